@@ -0,0 +1,24 @@
+//! Fixtures shared by the benches.
+
+// each bench compiles its own copy and uses only some of these
+#![allow(dead_code)]
+
+/// Deterministic filler, the same stream as the crate's self test uses.
+pub fn filler(len: usize, seed: u32) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (x >> 16) as u8
+        })
+        .collect()
+}
+
+/// `old` with one byte flipped every 97 blocks of `block` bytes.
+pub fn scattered_edits(old: &[u8], block: usize) -> Vec<u8> {
+    let mut new = old.to_vec();
+    for at in (0..new.len()).step_by(97 * block) {
+        new[at] ^= 0x5a;
+    }
+    new
+}
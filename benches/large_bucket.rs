@@ -0,0 +1,60 @@
+//! Times diffing against an old whose blocks all share one weak checksum,
+//! with a new made of further blocks from the same bucket that old lacks,
+//! so every block of new looks the bucket up and misses. An old of unrelated
+//! blocks, where those lookups find an empty bucket, is timed alongside.
+//!
+//! Run with `cargo bench --bench large_bucket`.
+
+mod common;
+
+use common::filler;
+use criterion::{criterion_group, criterion_main, Criterion};
+use xdelta::{apply_to, create_patch_with, ApplyOutput, PatchOptions};
+
+const BLOCK: usize = 64;
+
+/// `block` with `k, -2k, k` added from `at`, which keeps its weak checksum.
+fn perturb(block: &mut [u8], at: usize, k: i16) {
+    for (i, d) in [k, -2 * k, k].into_iter().enumerate() {
+        block[at + i] = (block[at + i] as i16 + d) as u8;
+    }
+}
+
+fn diff_and_apply(old: &[u8], new: &[u8], opts: &PatchOptions) {
+    let patch = create_patch_with(old, new, opts).expect("create");
+    let mut out = Vec::new();
+    apply_to(old, &patch, ApplyOutput::Grow(&mut out)).expect("apply");
+    assert_eq!(out, new);
+}
+
+fn large_bucket(c: &mut Criterion) {
+    // bytes kept within 8..248 so no perturbation wraps
+    let base: Vec<u8> = filler(BLOCK, 5).iter().map(|&b| b % 240 + 8).collect();
+    let mut old = base.clone();
+    for at in 0..BLOCK - 2 {
+        for k in [-3, -2, -1, 1, 2, 3] {
+            let mut block = base.clone();
+            perturb(&mut block, at, k);
+            old.extend_from_slice(&block);
+        }
+    }
+    let mut new = Vec::new();
+    for p in 0..BLOCK - 5 {
+        for q in p + 3..BLOCK - 2 {
+            let mut block = base.clone();
+            perturb(&mut block, p, 1);
+            perturb(&mut block, q, 1);
+            new.extend_from_slice(&block);
+        }
+    }
+    let unrelated = filler(old.len(), 9);
+    let opts = PatchOptions::new().block_size(BLOCK);
+
+    let mut group = c.benchmark_group("large_bucket");
+    group.bench_function("large bucket", |b| b.iter(|| diff_and_apply(&old, &new, &opts)));
+    group.bench_function("unrelated old", |b| b.iter(|| diff_and_apply(&unrelated, &new, &opts)));
+    group.finish();
+}
+
+criterion_group!(benches, large_bucket);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Times diffing files made of many near-duplicate blocks: every block of
+//! old is one pattern with a few bytes changed, so the blocks' weak
+//! checksums crowd together, and new is old with its blocks shuffled and
+//! every other one offset by a few bytes, so most windows are looked up
+//! and miss.
+//!
+//! Run with `cargo bench --bench near_duplicates`.
+
+mod common;
+
+use common::filler;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use xdelta::{apply_patch, create_patch_with, PatchOptions};
+
+const BLOCK: usize = 256;
+const BLOCKS: usize = 16 * 1024;
+
+fn near_duplicates(c: &mut Criterion) {
+    let pattern = filler(BLOCK, 1);
+    let noise = filler(BLOCKS * 4, 2);
+    let mut old = Vec::with_capacity(BLOCKS * BLOCK);
+    for i in 0..BLOCKS {
+        let mut block = pattern.clone();
+        for (j, &n) in noise[i * 4..i * 4 + 4].iter().enumerate() {
+            block[(n as usize + 61 * j) % BLOCK] ^= n | 1;
+        }
+        old.extend_from_slice(&block);
+    }
+    let mut new = Vec::with_capacity(old.len());
+    for i in 0..BLOCKS {
+        let from = (i * 7919 % BLOCKS) * BLOCK;
+        if i % 2 == 0 {
+            new.extend_from_slice(&old[from..from + BLOCK]);
+        } else {
+            new.extend_from_slice(&old[from + 3..from + BLOCK]);
+            new.extend_from_slice(&[0xa5; 3]);
+        }
+    }
+
+    let opts = PatchOptions::new().block_size(BLOCK);
+    let patch = create_patch_with(&old, &new, &opts).expect("create");
+    assert_eq!(apply_patch(&old, &patch).expect("apply"), new);
+
+    let mut group = c.benchmark_group("near_duplicates");
+    group.sample_size(10).throughput(Throughput::Bytes(new.len() as u64));
+    group.bench_function("diff", |b| b.iter(|| create_patch_with(&old, &new, &opts).expect("create")));
+    group.finish();
+}
+
+criterion_group!(benches, near_duplicates);
+criterion_main!(benches);
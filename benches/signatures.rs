@@ -0,0 +1,32 @@
+//! Times building the block signatures of a large old, alone and as part of
+//! a diff. Run it once without and once with the `parallel` feature, which
+//! hashes the blocks of olds of 4 MiB and more on every core; criterion
+//! reports the second run against the first:
+//!
+//! `cargo bench --bench signatures`
+//! `cargo bench --features parallel --bench signatures`
+
+mod common;
+
+use common::{filler, scattered_edits};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use xdelta::{apply_patch, create_patch_with, PatchOptions, Signature};
+
+const BLOCK: usize = 1024;
+
+fn signatures(c: &mut Criterion) {
+    let old = filler(64 << 20, 1);
+    let new = scattered_edits(&old, BLOCK);
+    let opts = PatchOptions::new().block_size(BLOCK);
+    let patch = create_patch_with(&old, &new, &opts).expect("create");
+    assert_eq!(apply_patch(&old, &patch).expect("apply"), new);
+
+    let mut group = c.benchmark_group("signatures");
+    group.sample_size(10).throughput(Throughput::Bytes(old.len() as u64));
+    group.bench_function("signatures", |b| b.iter(|| Signature::new(&old, BLOCK).expect("signature")));
+    group.bench_function("diff", |b| b.iter(|| create_patch_with(&old, &new, &opts).expect("create")));
+    group.finish();
+}
+
+criterion_group!(benches, signatures);
+criterion_main!(benches);
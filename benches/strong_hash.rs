@@ -0,0 +1,50 @@
+//! Times the block strong hash, SHA-256 against BLAKE3: hashing old in
+//! blocks as building signatures does, then whole diffs of a file with
+//! scattered edits, where every window whose weak checksum hits is hashed.
+//!
+//! Run with `cargo bench --features blake3 --bench strong_hash`.
+
+mod common;
+
+use common::{filler, scattered_edits};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use xdelta::{apply_patch, block_strong_hash, create_patch_with, HashAlgo, PatchOptions};
+
+const BLOCK: usize = 1024;
+
+fn strong_hash(c: &mut Criterion) {
+    let old = filler(32 << 20, 1);
+    let new = scattered_edits(&old, BLOCK);
+    let algos = [("SHA-256", HashAlgo::Sha256), ("BLAKE3", HashAlgo::Blake3)];
+
+    let patches: Vec<Vec<u8>> = algos
+        .iter()
+        .map(|&(_, algo)| {
+            let patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(BLOCK).strong_hash(algo))
+                .expect("create");
+            assert_eq!(apply_patch(&old, &patch).expect("apply"), new);
+            patch
+        })
+        .collect();
+    assert_eq!(patches[0].len(), patches[1].len(), "the hash changes how blocks are confirmed, not the records");
+
+    let mut group = c.benchmark_group("strong_hash");
+    group.sample_size(10).throughput(Throughput::Bytes(old.len() as u64));
+    for (name, algo) in algos {
+        group.bench_function(BenchmarkId::new("blocks", name), |b| {
+            b.iter(|| {
+                for block in old.chunks(BLOCK) {
+                    std::hint::black_box(block_strong_hash(block, algo));
+                }
+            })
+        });
+        let opts = PatchOptions::new().block_size(BLOCK).strong_hash(algo);
+        group.bench_function(BenchmarkId::new("diff", name), |b| {
+            b.iter(|| create_patch_with(&old, &new, &opts).expect("create"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, strong_hash);
+criterion_main!(benches);
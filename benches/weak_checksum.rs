@@ -0,0 +1,44 @@
+//! Times the rolling weak checksum of whole blocks, as building signatures
+//! computes it, scalar against the `simd` feature's 16-bytes-at-a-time sum,
+//! over a 64 MiB buffer cut into blocks of several sizes.
+//!
+//! Run with `cargo bench --features simd --bench weak_checksum`.
+
+mod common;
+
+use common::filler;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use xdelta::WeakAlgo;
+
+/// The checksum a byte at a time, as the crate computes it without `simd`.
+fn scalar(block: &[u8]) -> u32 {
+    let (mut a, mut b) = (0u32, 0u32);
+    for (i, &v) in block.iter().enumerate() {
+        a = a.wrapping_add(v as u32);
+        b = b.wrapping_add(((block.len() - i) as u32).wrapping_mul(v as u32));
+    }
+    ((b & 0xffff) << 16) | (a & 0xffff)
+}
+
+fn weak_checksum(c: &mut Criterion) {
+    let data = filler(64 << 20, 1);
+
+    let mut group = c.benchmark_group("weak_checksum");
+    group.sample_size(10).throughput(Throughput::Bytes(data.len() as u64));
+    for block in [64, 1024, 16 << 10, data.len()] {
+        let expected: Vec<u32> = data.chunks(block).map(scalar).collect();
+        let got: Vec<u32> = data.chunks(block).map(|b| WeakAlgo::Rolling.checksum(b)).collect();
+        assert_eq!(expected, got, "the vectorized sum must match the scalar one");
+
+        group.bench_function(BenchmarkId::new("scalar", block), |b| {
+            b.iter(|| data.chunks(block).map(scalar).collect::<Vec<_>>())
+        });
+        group.bench_function(BenchmarkId::new("simd", block), |b| {
+            b.iter(|| data.chunks(block).map(|chunk| WeakAlgo::Rolling.checksum(chunk)).collect::<Vec<_>>())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, weak_checksum);
+criterion_main!(benches);
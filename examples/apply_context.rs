@@ -0,0 +1,39 @@
+//! Times applying many small patches with a reused `ApplyContext` against a
+//! fresh output buffer per apply.
+//!
+//! Run with `cargo run --release --example apply_context`.
+
+use std::time::Instant;
+use xdelta::{apply_to, create_patch_with, ApplyContext, ApplyOutput, PatchOptions};
+
+const ROUNDS: usize = 100_000;
+
+fn main() {
+    let old: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+    let mut new = old.clone();
+    new[1000..1016].copy_from_slice(b"sixteen new byte");
+    let patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(256)).expect("create");
+
+    let start = Instant::now();
+    let mut total = 0usize;
+    for _ in 0..ROUNDS {
+        let mut out = Vec::new();
+        apply_to(&old, &patch, ApplyOutput::Grow(&mut out)).expect("apply");
+        total += out.len();
+    }
+    let fresh = start.elapsed();
+
+    let start = Instant::now();
+    let mut ctx = ApplyContext::new();
+    for _ in 0..ROUNDS {
+        let out = ctx.apply_patch(&old, &patch).expect("apply");
+        assert_eq!(out.len(), new.len());
+        total += out.len();
+    }
+    let reused = start.elapsed();
+
+    assert_eq!(ctx.apply_patch(&old, &patch).expect("apply"), &new[..]);
+    println!("{} applies of a {}-byte patch ({} output bytes in all)", ROUNDS, patch.len(), total);
+    println!("fresh buffer: {:?}/apply", fresh / ROUNDS as u32);
+    println!("context:      {:?}/apply", reused / ROUNDS as u32);
+}
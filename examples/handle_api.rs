@@ -0,0 +1,77 @@
+//! Drives the result-handle API the way a Python ctypes binding would:
+//! prototypes declared by hand, plain pointers in, one handle out, one free.
+//!
+//! The equivalent Python is roughly:
+//!
+//! ```text
+//! lib = ctypes.CDLL("libxdelta.so")
+//! lib.xdelta_create_patch_result.restype = ctypes.c_void_p
+//! h = lib.xdelta_create_patch_result(old, len(old), new, len(new), None)
+//! try:
+//!     if lib.xdelta_result_status(h) != 0:
+//!         raise RuntimeError(ctypes.string_at(lib.xdelta_result_error(h)))
+//!     patch = ctypes.string_at(lib.xdelta_result_data(h), lib.xdelta_result_len(h))
+//! finally:
+//!     lib.xdelta_result_free(h)
+//! ```
+
+extern crate xdelta;
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::{c_char, c_int};
+
+unsafe extern "C" {
+    fn xdelta_create_patch_result(
+        old_data: *const u8,
+        old_len: usize,
+        new_data: *const u8,
+        new_len: usize,
+        opts: *const c_void,
+    ) -> *mut c_void;
+    fn xdelta_apply_patch_result(
+        old_data: *const u8,
+        old_len: usize,
+        patch_data: *const u8,
+        patch_len: usize,
+    ) -> *mut c_void;
+    fn xdelta_result_status(result: *const c_void) -> c_int;
+    fn xdelta_result_data(result: *const c_void) -> *const u8;
+    fn xdelta_result_len(result: *const c_void) -> usize;
+    fn xdelta_result_error(result: *const c_void) -> *const c_char;
+    fn xdelta_result_free(result: *mut c_void);
+}
+
+/// Copy the bytes out of a handle and free it, like a binding's helper would.
+fn take(handle: *mut c_void) -> Result<Vec<u8>, String> {
+    unsafe {
+        let r = if xdelta_result_status(handle) == 0 {
+            Ok(std::slice::from_raw_parts(xdelta_result_data(handle), xdelta_result_len(handle)).to_vec())
+        } else {
+            Err(CStr::from_ptr(xdelta_result_error(handle)).to_string_lossy().into_owned())
+        };
+        xdelta_result_free(handle);
+        r
+    }
+}
+
+fn main() {
+    let old = b"the quick brown fox jumps over the lazy dog".repeat(100);
+    let mut new = old.clone();
+    new[2000..2005].copy_from_slice(b"HELLO");
+
+    let patch = take(unsafe {
+        xdelta_create_patch_result(old.as_ptr(), old.len(), new.as_ptr(), new.len(), std::ptr::null())
+    })
+    .expect("create");
+    let rebuilt = take(unsafe { xdelta_apply_patch_result(old.as_ptr(), old.len(), patch.as_ptr(), patch.len()) })
+        .expect("apply");
+    assert_eq!(rebuilt, new);
+
+    // a garbage patch fails through the same handle, with a code and a message
+    let truncated = [0x01u8, 0x00, 0x00];
+    let err = take(unsafe { xdelta_apply_patch_result(old.as_ptr(), old.len(), truncated.as_ptr(), truncated.len()) })
+        .unwrap_err();
+    assert!(err.contains("truncated"));
+
+    println!("patch {} bytes, round trip ok", patch.len());
+}
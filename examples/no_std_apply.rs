@@ -0,0 +1,32 @@
+//! Applies a hand-built patch using only the API that remains without the
+//! `std` feature: `apply_patch` and `validate_patch`.
+//!
+//! Run with `cargo run --example no_std_apply`. The cdylib can't be linked
+//! without std, so the `no_std` + `alloc` build of the library itself is
+//! checked with `cargo rustc --lib --no-default-features --crate-type rlib`.
+
+use xdelta::{apply_patch, validate_patch, PATCH_HEADER_VERSION};
+
+fn main() {
+    let old = b"the quick brown fox jumps over the lazy dog";
+
+    let mut patch = b"XDR1".to_vec();
+    patch.extend_from_slice(&[PATCH_HEADER_VERSION, 0, 0, 0]);
+    // COPY "the quick brown " from old
+    patch.push(0x01);
+    patch.extend_from_slice(&0u64.to_le_bytes());
+    patch.extend_from_slice(&16u32.to_le_bytes());
+    // ADD "cat"
+    patch.push(0x00);
+    patch.extend_from_slice(&3u32.to_le_bytes());
+    patch.extend_from_slice(b"cat");
+    // RUN of four '!'
+    patch.push(0x02);
+    patch.push(b'!');
+    patch.extend_from_slice(&4u32.to_le_bytes());
+
+    validate_patch(&patch).expect("validate");
+    let out = apply_patch(old, &patch).expect("apply");
+    assert_eq!(out, b"the quick brown cat!!!!");
+    println!("ok");
+}
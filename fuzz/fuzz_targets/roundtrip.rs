@@ -0,0 +1,23 @@
+//! Create a patch between two arbitrary buffers and apply it, which has to
+//! give back new or fail cleanly with an `XDeltaError`.
+//!
+//! The input is a 2-byte little-endian block size (0 picks one), a 2-byte
+//! little-endian split point, taken modulo what's left plus one, and then
+//! old followed by new.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let block_size = u16::from_le_bytes([data[0], data[1]]) as usize;
+    let rest = &data[4..];
+    let split = u16::from_le_bytes([data[2], data[3]]) as usize % (rest.len() + 1);
+    let (old, new) = rest.split_at(split);
+    if let Ok(same) = xdelta::roundtrip_check(old, new, block_size) {
+        assert!(same, "patch does not turn old back into new");
+    }
+});
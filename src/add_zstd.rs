@@ -0,0 +1,96 @@
+// src/add_zstd.rs
+//! ADD_ZSTD records: literal data compressed with zstd, see
+//! `PatchOptions::compress`.
+//!
+//! ADD_ZSTD layout: opcode 0x03, then
+//!   length: u32           // literal bytes the record produces
+//!   compressed_len: u32
+//!   [compressed_len] bytes // one zstd frame of exactly `length` bytes
+//!
+//! Literals flushed at different points of matching can still follow each
+//! other as separate ADD records; compressed one by one they would each
+//! start from an empty window. Consecutive ADD and ADD_SMALL records are
+//! compressed together instead, into records of at most `max_add_len`
+//! bytes, and a stretch that zstd doesn't shrink is left as it was.
+
+use crate::{read_record, Record, XDeltaError};
+use std::io::Read;
+
+/// zstd level literals are compressed at: its default, fast enough to keep
+/// creation dominated by matching.
+const LEVEL: i32 = 3;
+
+/// The bare records `patch` with every stretch of consecutive ADD and
+/// ADD_SMALL records, up to `max_len` literal bytes at a time, replaced by
+/// one ADD_ZSTD record where that is smaller.
+pub(crate) fn compress_adds(patch: &[u8], max_len: usize) -> Result<Vec<u8>, XDeltaError> {
+    let mut out = Vec::with_capacity(patch.len());
+    // literal bytes of the stretch so far, and where its records start
+    let mut literals = Vec::new();
+    let mut start = 0usize;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        match record {
+            Record::Add(data) if literals.len() + data.len() <= max_len => literals.extend_from_slice(data),
+            Record::Add(data) => {
+                flush(&mut out, &literals, &patch[start..pos])?;
+                literals.clear();
+                literals.extend_from_slice(data);
+                start = pos;
+            }
+            _ => {
+                flush(&mut out, &literals, &patch[start..pos])?;
+                literals.clear();
+                out.extend_from_slice(&patch[pos..next]);
+                start = next;
+            }
+        }
+        pos = next;
+    }
+    flush(&mut out, &literals, &patch[start..])?;
+    Ok(out)
+}
+
+/// Write `literals`, which the ADD records `raw` carry, as one ADD_ZSTD
+/// record if that is smaller than `raw`, or else `raw` itself.
+fn flush(out: &mut Vec<u8>, literals: &[u8], raw: &[u8]) -> Result<(), XDeltaError> {
+    if literals.is_empty() {
+        return Ok(());
+    }
+    let compressed = zstd::bulk::compress(literals, LEVEL)
+        .map_err(|e| XDeltaError::InvalidArg(format!("zstd compression failed: {}", e)))?;
+    if 9 + compressed.len() >= raw.len() {
+        out.extend_from_slice(raw);
+        return Ok(());
+    }
+    out.push(0x03); // ADD_ZSTD
+    out.extend_from_slice(&(literals.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    out.extend_from_slice(&compressed);
+    Ok(())
+}
+
+/// The `len` bytes the zstd frame `data` inflates to. The output grows only
+/// as the frame produces it, so a record claiming more than its data holds
+/// allocates no more than that data decompresses to (and at most `len`).
+pub(crate) fn inflate(len: u32, data: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    let malformed = |what: String| XDeltaError::MalformedPatch(format!("ADD_ZSTD {}", what));
+    match zstd::zstd_safe::find_frame_compressed_size(data) {
+        Ok(frame_len) if frame_len == data.len() => {}
+        Ok(_) => return Err(malformed("data is not exactly one zstd frame".into())),
+        Err(_) => return Err(malformed("data is not a zstd frame".into())),
+    }
+    let decoder = zstd::stream::read::Decoder::with_buffer(data)
+        .map_err(|e| malformed(format!("decoder: {}", e)))?
+        .single_frame();
+    let mut out = Vec::new();
+    decoder.take(len as u64 + 1).read_to_end(&mut out).map_err(|e| malformed(format!("data: {}", e)))?;
+    if out.len() > len as usize {
+        return Err(malformed(format!("data inflates to more than its {} bytes", len)));
+    }
+    if out.len() < len as usize {
+        return Err(malformed(format!("data inflates to {} bytes, not {}", out.len(), len)));
+    }
+    Ok(out)
+}
@@ -0,0 +1,71 @@
+//! The allocator behind every buffer the C functions hand out.
+//!
+//! Buffers are allocated with `libc::malloc` unless the embedder installs
+//! its own pair with `xdelta_set_allocator`, and `xdelta_free_data` and
+//! `xdelta_free_string` release them with the matching free. Swapping the
+//! pair while a buffer from the old one is still out would free it with the
+//! wrong function, so the swap is refused until every buffer is back.
+
+use crate::{ffi_status, XDeltaError};
+use std::os::raw::{c_int, c_void};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+/// Allocate `size` bytes, NULL on failure.
+pub type XdeltaMallocFn = unsafe extern "C" fn(size: usize) -> *mut c_void;
+
+/// Release a buffer the matching `XdeltaMallocFn` returned.
+pub type XdeltaFreeFn = unsafe extern "C" fn(ptr: *mut c_void);
+
+static ALLOCATOR: RwLock<(XdeltaMallocFn, XdeltaFreeFn)> = RwLock::new((libc::malloc, libc::free));
+
+/// Buffers handed out and not yet freed.
+static OUTSTANDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocate `size` bytes with the configured allocator.
+pub(crate) fn ffi_malloc(size: usize) -> *mut u8 {
+    let allocator = ALLOCATOR.read().unwrap_or_else(PoisonError::into_inner);
+    let ptr = unsafe { (allocator.0)(size) };
+    if !ptr.is_null() {
+        OUTSTANDING.fetch_add(1, Ordering::Relaxed);
+    }
+    ptr as *mut u8
+}
+
+/// Free `ptr`, from `ffi_malloc`, with the configured allocator; NULL is ignored.
+pub(crate) fn ffi_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let allocator = ALLOCATOR.read().unwrap_or_else(PoisonError::into_inner);
+    unsafe { (allocator.1)(ptr) };
+    OUTSTANDING.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// 设置库返回给调用方的所有缓冲区（补丁、输出、字符串等）使用的分配/释放函数，xdelta_free_data 与
+/// xdelta_free_string 随之改用 free_fn；两者都为 NULL 时恢复默认的 libc malloc/free，只有一个为 NULL 返回
+/// XDELTA_ERR_INVALID_ARG。应在初始化时、任何缓冲区分配之前调用：已分配的缓冲区尚未全部释放时拒绝切换，
+/// 返回 XDELTA_ERR_INVALID_ARG。可在任意线程调用，与其它调用并发时安全
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_set_allocator(malloc_fn: Option<XdeltaMallocFn>, free_fn: Option<XdeltaFreeFn>) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let pair: (XdeltaMallocFn, XdeltaFreeFn) = match (malloc_fn, free_fn) {
+            (Some(malloc_fn), Some(free_fn)) => (malloc_fn, free_fn),
+            (None, None) => (libc::malloc, libc::free),
+            _ => return Err(XDeltaError::InvalidArg("malloc_fn and free_fn must both be set or both be NULL".into())),
+        };
+        let mut allocator = ALLOCATOR.write().unwrap_or_else(PoisonError::into_inner);
+        let outstanding = OUTSTANDING.load(Ordering::Relaxed);
+        if outstanding != 0 {
+            return Err(XDeltaError::InvalidArg(format!(
+                "{} buffers from the current allocator are not freed yet",
+                outstanding
+            )));
+        }
+        *allocator = pair;
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
@@ -0,0 +1,221 @@
+// src/batch.rs
+//! Patches from one base to many targets, with shared content stored once.
+//!
+//! Each patch is cut at record boundaries into segments, and identical
+//! segments across the batch (or within one patch) are stored as a single
+//! blob. Cuts are content-defined, chosen from the bytes of each record, so
+//! two patches sharing a run of ADD/COPY records cut it the same way no
+//! matter what precedes it.
+//!
+//! Bundle layout (all integers little-endian):
+//!   magic:         b"XDBN"
+//!   patch_count:   u32
+//!   segment_count: u32
+//!   blob_count:    u32
+//!   patches:  [(first_segment: u32, segments: u32)...]
+//!   segments: [blob: u32...]
+//!   blobs:    [(offset: u64, len: u64)...]  // offset from bundle start
+//!   data:     blob contents, back to back
+
+use crate::{
+    create_patch_with, ffi_slice, options_from_ffi, patch_records, read_record, read_u32, read_u64, write_output,
+    PatchOptions, XDeltaError, XdeltaOptions,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::os::raw::c_int;
+
+const BUNDLE_MAGIC: &[u8; 4] = b"XDBN";
+const BUNDLE_HEADER_LEN: usize = 16;
+const PATCH_ENTRY_LEN: usize = 8;
+const SEGMENT_ENTRY_LEN: usize = 4;
+const BLOB_ENTRY_LEN: usize = 16;
+/// A segment ends after a record whose hash has these bits clear (about one
+/// record in 8), or once it reaches `MAX_SEGMENT`.
+const CUT_MASK: u32 = 7;
+const MAX_SEGMENT: usize = 64 * 1024;
+
+/// Create patches from `base` to each of `news` and bundle them, storing
+/// shared segments once. `batch_patch` gets each patch back.
+pub fn batch_create(base: &[u8], news: &[&[u8]], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+    let patch_count = u32::try_from(news.len()).map_err(|_| XDeltaError::InvalidArg("too many targets".into()))?;
+
+    let mut blobs: Vec<Vec<u8>> = Vec::new();
+    let mut blob_ids: HashMap<[u8; 32], u32> = HashMap::new();
+    let mut patches: Vec<(u32, u32)> = Vec::with_capacity(news.len());
+    let mut segments: Vec<u32> = Vec::new();
+    for new in news {
+        let patch = create_patch_with(base, new, opts)?;
+        let first = segments.len() as u32;
+        for segment in cut_segments(&patch)? {
+            let key: [u8; 32] = Sha256::digest(segment).into();
+            let id = *blob_ids.entry(key).or_insert_with(|| {
+                blobs.push(segment.to_vec());
+                (blobs.len() - 1) as u32
+            });
+            segments.push(id);
+        }
+        patches.push((first, segments.len() as u32 - first));
+    }
+
+    let tables_len = BUNDLE_HEADER_LEN
+        + patches.len() * PATCH_ENTRY_LEN
+        + segments.len() * SEGMENT_ENTRY_LEN
+        + blobs.len() * BLOB_ENTRY_LEN;
+    let data_len: usize = blobs.iter().map(Vec::len).sum();
+    let mut out = Vec::with_capacity(tables_len + data_len);
+    out.extend_from_slice(BUNDLE_MAGIC);
+    out.extend_from_slice(&patch_count.to_le_bytes());
+    out.extend_from_slice(&(segments.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(blobs.len() as u32).to_le_bytes());
+    for (first, count) in &patches {
+        out.extend_from_slice(&first.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+    }
+    for id in &segments {
+        out.extend_from_slice(&id.to_le_bytes());
+    }
+    let mut offset = tables_len as u64;
+    for blob in &blobs {
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(blob.len() as u64).to_le_bytes());
+        offset += blob.len() as u64;
+    }
+    for blob in &blobs {
+        out.extend_from_slice(blob);
+    }
+    Ok(out)
+}
+
+/// Split `patch` into its header, shared by patches with outputs of one
+/// length, and then content-defined runs of whole records.
+fn cut_segments(patch: &[u8]) -> Result<Vec<&[u8]>, XDeltaError> {
+    let header_len = patch.len() - patch_records(patch)?.len();
+    let mut segments = vec![&patch[..header_len]];
+    let mut start = header_len;
+    let mut pos = header_len;
+    while pos < patch.len() {
+        let (_, next) = read_record(patch, pos)?;
+        if fnv1a(&patch[pos..next]) & CUT_MASK == 0 || next - start >= MAX_SEGMENT {
+            segments.push(&patch[start..next]);
+            start = next;
+        }
+        pos = next;
+    }
+    if start < patch.len() {
+        segments.push(&patch[start..]);
+    }
+    Ok(segments)
+}
+
+fn fnv1a(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5u32, |h, &b| (h ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Reassemble patch `index` (in the order given to `batch_create`) from `bundle`.
+pub fn batch_patch(bundle: &[u8], index: usize) -> Result<Vec<u8>, XDeltaError> {
+    if bundle.len() < BUNDLE_HEADER_LEN || &bundle[..4] != BUNDLE_MAGIC {
+        return Err(XDeltaError::InvalidArg("not a batch bundle".into()));
+    }
+    let patch_count = read_u32(bundle, 4) as usize;
+    let segment_count = read_u32(bundle, 8) as usize;
+    let blob_count = read_u32(bundle, 12) as usize;
+    if index >= patch_count {
+        return Err(XDeltaError::InvalidArg(format!("patch {} not in bundle of {}", index, patch_count)));
+    }
+    let truncated = || XDeltaError::InvalidArg("truncated bundle tables".into());
+    let segments_at = patch_count
+        .checked_mul(PATCH_ENTRY_LEN)
+        .and_then(|n| n.checked_add(BUNDLE_HEADER_LEN))
+        .ok_or_else(truncated)?;
+    let blobs_at = segment_count
+        .checked_mul(SEGMENT_ENTRY_LEN)
+        .and_then(|n| n.checked_add(segments_at))
+        .ok_or_else(truncated)?;
+    blob_count
+        .checked_mul(BLOB_ENTRY_LEN)
+        .and_then(|n| n.checked_add(blobs_at))
+        .filter(|end| *end <= bundle.len())
+        .ok_or_else(truncated)?;
+
+    let entry = BUNDLE_HEADER_LEN + index * PATCH_ENTRY_LEN;
+    let first = read_u32(bundle, entry) as usize;
+    let count = read_u32(bundle, entry + 4) as usize;
+    if first.checked_add(count).is_none_or(|end| end > segment_count) {
+        return Err(XDeltaError::InvalidArg(format!("patch {} segments out of range", index)));
+    }
+
+    let mut patch = Vec::new();
+    for s in first..first + count {
+        let blob = read_u32(bundle, segments_at + s * SEGMENT_ENTRY_LEN) as usize;
+        if blob >= blob_count {
+            return Err(XDeltaError::InvalidArg(format!("blob {} not in bundle", blob)));
+        }
+        let blob_entry = blobs_at + blob * BLOB_ENTRY_LEN;
+        let offset = read_u64(bundle, blob_entry);
+        let len = read_u64(bundle, blob_entry + 8);
+        let end = offset
+            .checked_add(len)
+            .filter(|end| *end <= bundle.len() as u64)
+            .ok_or_else(|| XDeltaError::InvalidArg(format!("blob {} out of range", blob)))?;
+        patch.extend_from_slice(&bundle[offset as usize..end as usize]);
+    }
+    Ok(patch)
+}
+
+/// 批量创建从同一 base 到多个新版本的补丁，相同的补丁片段只保存一次；news/new_lens 为长度 count 的数组
+/// opts 为 NULL 时使用默认选项；用 xdelta_batch_patch 取出各个补丁
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_batch_create(
+    base: *const u8,
+    base_len: usize,
+    news: *const *const u8,
+    new_lens: *const usize,
+    count: usize,
+    opts: *const XdeltaOptions,
+    bundle_data: *mut *mut u8,
+    bundle_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if bundle_data.is_null() || bundle_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        if count > 0 && (news.is_null() || new_lens.is_null()) {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let base_bytes = unsafe { ffi_slice(base, base_len)? };
+        let mut targets = Vec::with_capacity(count);
+        for i in 0..count {
+            let (data, len) = unsafe { (*news.add(i), *new_lens.add(i)) };
+            targets.push(unsafe { ffi_slice(data, len)? });
+        }
+        batch_create(base_bytes, &targets, &options_from_ffi(opts)?)
+    })();
+
+    write_output(r, bundle_data, bundle_len)
+}
+
+/// 从批量补丁包中取出第 index 个补丁
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_batch_patch(
+    bundle: *const u8,
+    bundle_len: usize,
+    index: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let bundle_bytes = unsafe { ffi_slice(bundle, bundle_len)? };
+
+        batch_patch(bundle_bytes, index)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
@@ -0,0 +1,51 @@
+// src/cancel.rs
+//! Cooperative cancellation shared between a caller and running operations.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A cancellation flag that can be triggered from any thread.
+///
+/// Clones share the same flag. Operations holding a token check it
+/// periodically and stop with `XDeltaError::Cancelled` once it is set.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of every operation using this token.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+}
+
+/// 创建取消令牌，使用完毕后用 xdelta_cancel_token_free 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_cancel_token_new() -> *mut CancelToken {
+    Box::into_raw(Box::new(CancelToken::new()))
+}
+
+/// 请求取消使用该令牌的所有操作，可在任意线程调用
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_cancel_token_cancel(token: *const CancelToken) {
+    if let Some(token) = unsafe { token.as_ref() } {
+        token.cancel();
+    }
+}
+
+/// 释放取消令牌；调用前须确保没有操作仍在使用它
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_cancel_token_free(token: *mut CancelToken) {
+    if !token.is_null() {
+        drop(unsafe { Box::from_raw(token) });
+    }
+}
@@ -0,0 +1,129 @@
+// src/cas.rs
+//! Applying content-addressed patches.
+//!
+//! With `PatchOptions::content_addressed`, matched blocks are written as
+//! COPY_HASH records carrying the block's strong hash instead of an offset
+//! into old: SHA-256, or BLAKE3 if the patch header says so. Such a patch
+//! no longer depends on old's layout: any store that can look a block up by
+//! hash can reconstruct new.
+
+use crate::{
+    block_strong_hash, const_table, ffi_slice, inflate_add, is_identity, patch_hash_algo, patch_records, read_record,
+    write_output, OutputCheck, Record, XDeltaError,
+};
+use std::ffi::c_void;
+use std::os::raw::c_int;
+
+/// Apply a content-addressed patch, looking up each COPY_HASH block with `resolve`.
+///
+/// `resolve` gets the 32-byte hash of a block and returns its contents, or
+/// `None` if the store doesn't have it. Resolved blocks are checked against
+/// both the hash and the length recorded in the patch. Patches that also
+/// contain offset-based COPY or DIFF records are rejected, since there is no
+/// old to read them from.
+pub fn apply_cas<'s, F>(patch: &[u8], mut resolve: F) -> Result<Vec<u8>, XDeltaError>
+where
+    F: FnMut(&[u8; 32]) -> Option<&'s [u8]>,
+{
+    let records = patch_records(patch)?;
+    let algo = patch_hash_algo(patch)?;
+    if is_identity(patch) {
+        return Err(XDeltaError::InvalidArg(
+            "identity patch needs old, which a content-addressed apply does not have".into(),
+        ));
+    }
+    let mut check = OutputCheck::new(patch);
+    let patch = records;
+    let mut out = Vec::with_capacity(patch.len());
+    let mut consts = None;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        pos = next;
+        check.record(&record)?;
+        match record {
+            Record::Add(data) => out.extend_from_slice(data),
+            Record::AddZstd { len, data } => out.extend_from_slice(&inflate_add(len, data)?),
+            Record::Run { byte, len } => out.resize(out.len() + len as usize, byte),
+            Record::CopyTarget { offset, len } => {
+                let chunk = crate::copy_target(&out, offset, len)?;
+                out.extend_from_slice(&chunk);
+            }
+            Record::CopyHash { hash, len } => {
+                let mut key = [0u8; 32];
+                key.copy_from_slice(hash);
+                let block = resolve(&key)
+                    .ok_or_else(|| XDeltaError::InvalidArg(format!("block {} not found", hex(&key))))?;
+                if block.len() != len as usize || block_strong_hash(block, algo) != key {
+                    return Err(XDeltaError::InvalidArg(format!("block {} does not match its hash", hex(&key))));
+                }
+                out.extend_from_slice(block);
+            }
+            Record::CopyConst { index, len } => {
+                let tile = const_table::const_entry(consts, index)?;
+                out.extend_from_slice(&const_table::expand_const(tile, 0, len as usize));
+            }
+            Record::ConstTable(body) => consts = Some(body),
+            Record::Index(_)
+            | Record::OldHash(_)
+            | Record::OutputOffset(_)
+            | Record::Padding
+            | Record::BlockSize(_)
+            | Record::MinVersion
+            | Record::Check(_) => {}
+            Record::Copy { .. } | Record::Diff { .. } | Record::Xor { .. } => {
+                return Err(XDeltaError::InvalidArg("offset-based record in a content-addressed patch".into()));
+            }
+            Record::Skippable(opcode) => {
+                return Err(XDeltaError::InvalidArg(format!("unknown skippable opcode {:#x}", opcode)));
+            }
+        }
+    }
+    check.update(&out);
+    check.finish()?;
+    Ok(out)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// C callback looking up the block whose strong hash is `hash` (32 bytes).
+/// On success it stores the block in `*data`/`*len` and returns 0; the block
+/// must stay valid until the apply call returns. Nonzero means not found.
+pub type XdeltaResolveFn =
+    extern "C" fn(hash: *const u8, data: *mut *const u8, len: *mut usize, ctx: *mut c_void) -> c_int;
+
+/// 应用内容寻址补丁：COPY_HASH 记录通过 resolve 回调按强哈希（SHA-256，补丁头标明时为 BLAKE3）查找数据块
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_cas(
+    patch_data: *const u8,
+    patch_len: usize,
+    resolve: Option<XdeltaResolveFn>,
+    ctx: *mut c_void,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        let Some(resolve) = resolve else {
+            return Err(XDeltaError::InvalidArg("null callback".into()));
+        };
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_cas(patch_bytes, |hash| {
+            let mut data: *const u8 = std::ptr::null();
+            let mut len = 0usize;
+            if resolve(hash.as_ptr(), &mut data, &mut len, ctx) != 0 || data.is_null() {
+                return None;
+            }
+            Some(unsafe { std::slice::from_raw_parts(data, len) })
+        })
+    })();
+
+    write_output(r, new_data, new_len)
+}
@@ -0,0 +1,263 @@
+// src/chain.rs
+//! Applying one patch and diffing its output against a further version in
+//! the same pass, for cascading updates.
+//!
+//! The intermediate output is never built: patch A is indexed by output
+//! offset, one entry per record, and the matcher reads whatever ranges of the
+//! intermediate it needs straight from old and from A's literal data. Memory
+//! is that index plus the usual block signatures, however large the
+//! intermediate is.
+
+use crate::const_table::{const_entry, expand_const};
+use crate::stream::{apply_streaming, ApplyOptions, OldSource};
+#[cfg(debug_assertions)]
+use crate::with_header;
+use crate::{
+    build_signatures, check_strict, ffi_slice, finish_patch, inflate_add, is_identity, match_blocks, old_range,
+    patch_records, read_record, read_u32, write_output, xor_delta, MatchHooks, OldBytes, OutputCheck, PatchOptions,
+    Record, XDeltaError,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::os::raw::c_int;
+
+/// Bytes of the intermediate hashed at a time when verifying a CHECK record.
+const CHECK_CHUNK: usize = 1 << 20;
+
+/// Where the bytes of one record of patch A come from.
+enum Piece<'a> {
+    Old(Range<usize>),
+    /// Literal bytes, inflated first if they came from an ADD_ZSTD record.
+    Data(Cow<'a, [u8]>),
+    /// Old range patched with packed `(index: u32, delta: u8)` entries.
+    Diff(Range<usize>, &'a [u8]),
+    /// CONST_TABLE tile repeated to the piece's length.
+    Const(&'a [u8]),
+    /// Old range XORed with a validated XOR_DELTA body.
+    Xor(Range<usize>, &'a [u8]),
+    /// One byte repeated to the piece's length.
+    Run(u8),
+}
+
+/// The output of `patch` applied to `old`, read on demand.
+struct Applied<'a> {
+    old: &'a [u8],
+    /// Output offset each piece starts at, and the piece; empty pieces are left out.
+    pieces: Vec<(usize, Piece<'a>)>,
+    len: usize,
+}
+
+impl<'a> Applied<'a> {
+    /// Index `patch` by output offset, checking every record against `old`
+    /// up front so that reads cannot fail later. A CHECK record is verified
+    /// by reading the whole output once.
+    fn new(old: &'a [u8], patch: &'a [u8]) -> Result<Self, XDeltaError> {
+        let records = patch_records(patch)?;
+        if is_identity(patch) {
+            let pieces = if old.is_empty() { Vec::new() } else { vec![(0, Piece::Old(0..old.len()))] };
+            return Ok(Applied { old, pieces, len: old.len() });
+        }
+        let mut check = OutputCheck::new(patch);
+        let header = patch;
+        let patch = records;
+        let mut pieces = Vec::new();
+        let mut consts: Option<&[u8]> = None;
+        let mut len = 0usize;
+        let mut pos = 0usize;
+        while pos < patch.len() {
+            let (record, next) = read_record(patch, pos)?;
+            pos = next;
+            check.record(&record)?;
+            let (piece, piece_len) = match record {
+                Record::Add(data) => (Piece::Data(Cow::Borrowed(data)), data.len()),
+                Record::AddZstd { len, data } => (Piece::Data(Cow::Owned(inflate_add(len, data)?)), len as usize),
+                Record::Copy { offset, len } => {
+                    let range = old_range(old.len(), offset, len as u64, "COPY")?;
+                    (Piece::Old(range), len as usize)
+                }
+                Record::Diff { offset, len, deltas } => {
+                    let range = old_range(old.len(), offset, len as u64, "DIFF")?;
+                    if deltas.chunks_exact(5).any(|entry| read_u32(entry, 0) >= len) {
+                        return Err(XDeltaError::MalformedPatch("DIFF index out of range".into()));
+                    }
+                    (Piece::Diff(range, deltas), len as usize)
+                }
+                Record::Xor { offset, len, body } => {
+                    (Piece::Xor(old_range(old.len(), offset, len as u64, "XOR_DELTA")?, body), len as usize)
+                }
+                Record::CopyConst { index, len } => (Piece::Const(const_entry(consts, index)?), len as usize),
+                Record::Run { byte, len } => (Piece::Run(byte), len as usize),
+                Record::CopyTarget { .. } => return Err(crate::needs_output()),
+                Record::CopyHash { .. } => return Err(crate::needs_resolver()),
+                Record::ConstTable(body) => {
+                    consts = Some(body);
+                    continue;
+                }
+                Record::Skippable(opcode) => {
+                    return Err(XDeltaError::InvalidArg(format!("unknown skippable opcode {:#x}", opcode)));
+                }
+                Record::Index(_)
+                | Record::OldHash(_)
+                | Record::OutputOffset(_)
+                | Record::Padding
+                | Record::BlockSize(_)
+                | Record::MinVersion
+                | Record::Check(_) => continue,
+            };
+            if piece_len > 0 {
+                pieces.push((len, piece));
+                len = len
+                    .checked_add(piece_len)
+                    .ok_or_else(|| XDeltaError::InvalidArg("output length overflows".into()))?;
+            }
+        }
+        let applied = Applied { old, pieces, len };
+        check.records_done()?;
+        if crate::has_output_check(header) {
+            for start in (0..len).step_by(CHECK_CHUNK) {
+                check.update(&applied.bytes(start..usize::min(start + CHECK_CHUNK, len)));
+            }
+            check.finish()?;
+        }
+        Ok(applied)
+    }
+
+    /// Output offset the piece at `i` ends at.
+    fn piece_end(&self, i: usize) -> usize {
+        self.pieces.get(i + 1).map_or(self.len, |&(start, _)| start)
+    }
+}
+
+impl OldBytes for Applied<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn bytes(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        // the last piece starting at or before range.start
+        let mut i = self.pieces.partition_point(|&(start, _)| start <= range.start).saturating_sub(1);
+        let mut out = Vec::new();
+        while out.len() < range.len() {
+            let (start, ref piece) = self.pieces[i];
+            let end = self.piece_end(i);
+            let from = range.start + out.len() - start;
+            let to = usize::min(end, range.end) - start;
+            let whole = out.is_empty() && range.end <= end;
+            match piece {
+                Piece::Old(r) if whole => return Cow::Borrowed(&self.old[r.start + from..r.start + to]),
+                Piece::Data(data) if whole => return Cow::Borrowed(&data[from..to]),
+                Piece::Old(r) => out.extend_from_slice(&self.old[r.start + from..r.start + to]),
+                Piece::Data(data) => out.extend_from_slice(&data[from..to]),
+                Piece::Diff(r, deltas) => {
+                    let at = out.len();
+                    out.extend_from_slice(&self.old[r.start + from..r.start + to]);
+                    for entry in deltas.chunks_exact(5) {
+                        let idx = read_u32(entry, 0) as usize;
+                        if (from..to).contains(&idx) {
+                            out[at + idx - from] = out[at + idx - from].wrapping_add(entry[4]);
+                        }
+                    }
+                }
+                Piece::Const(tile) => out.extend_from_slice(&expand_const(tile, from, to)),
+                Piece::Run(byte) => out.resize(out.len() + (to - from), *byte),
+                Piece::Xor(r, body) => {
+                    let at = out.len();
+                    out.extend_from_slice(&self.old[r.start + from..r.start + to]);
+                    xor_delta::xor_into(&mut out[at..], body, from);
+                }
+            }
+            i += 1;
+        }
+        Cow::Owned(out)
+    }
+}
+
+impl OldSource for &Applied<'_> {
+    fn size(&self) -> u64 {
+        self.len as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+        let range = old_range(self.len, offset, buf.len() as u64, "read")?;
+        buf.copy_from_slice(&self.bytes(range));
+        Ok(())
+    }
+}
+
+/// Create a patch turning the output of `patch_a` applied to `old` into
+/// `new`, without building that output.
+///
+/// Memory is one index entry per record of `patch_a` plus the block
+/// signatures of its output, so it stays bounded for patches with few, long
+/// records; only ranges spanning several records are assembled, a window at
+/// a time. `patch_a` must not use COPY_HASH, and `old_hash` is not supported,
+/// as it would have to hash the whole intermediate; nor is `strict` with
+/// `content_addressed`, whose blocks only a materialized intermediate could
+/// resolve.
+pub fn apply_then_diff(old: &[u8], patch_a: &[u8], new: &[u8], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+    if opts.old_hash {
+        return Err(XDeltaError::InvalidArg("old_hash is not supported when diffing an applied patch".into()));
+    }
+    if opts.strict && opts.content_addressed {
+        return Err(XDeltaError::InvalidArg(
+            "strict content_addressed is not supported when diffing an applied patch".into(),
+        ));
+    }
+    let mid = Applied::new(old, patch_a)?;
+    let opts = &opts.resolve_block_size(mid.len);
+    let mut sigs = HashMap::new();
+    let (tail, weak) = (opts.effective_tail_policy(), opts.weak_checksum);
+    build_signatures(&mut sigs, &mid, opts.block_size, tail, opts.strong_hash, weak);
+    let mut patch = Vec::with_capacity(new.len() / 4);
+    match_blocks(&mid, new, opts, &sigs, &mut patch, &mut Vec::new(), MatchHooks::default())?;
+    #[cfg(debug_assertions)]
+    if !opts.content_addressed && opts.dirty_blocks.is_none() {
+        let mut out = Vec::new();
+        assert!(
+            apply_streaming(&mut &mid, &with_header(&patch), &mut out, &ApplyOptions::new()).is_ok() && out == new,
+            "patch does not reconstruct new (intermediate of {} bytes, {:?})",
+            mid.len,
+            opts
+        );
+    }
+    // old is only read for old_hash, which was refused above, and for
+    // strict, which is checked against the intermediate here instead
+    let patch = finish_patch(&[], new, patch, &opts.as_ref().clone().strict(false))?;
+    if opts.strict {
+        let mut out = Vec::new();
+        check_strict(apply_streaming(&mut &mid, &patch, &mut out, &ApplyOptions::new()).map(|_| out), new)?;
+    }
+    Ok(patch)
+}
+
+/// 将 patch_a 应用到旧数据，并在同一遍中把结果与 new_data 比较，生成补丁 B（B 的旧数据是 A 的输出）
+/// 不生成中间结果：内存为 A 的每条记录一个索引项加上块签名；A 不能含 COPY_HASH 记录
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_then_diff(
+    old_data: *const u8,
+    old_len: usize,
+    patch_a: *const u8,
+    patch_a_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    block_size: u32,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_a_bytes = unsafe { ffi_slice(patch_a, patch_a_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        let opts = PatchOptions::new().block_size(block_size as usize);
+        apply_then_diff(old_bytes, patch_a_bytes, new_bytes, &opts)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
@@ -0,0 +1,94 @@
+//! Which blocks of new are new or modified content, without building a patch.
+//!
+//! Backup and dedup tools that plan incremental work only need to know which
+//! blocks to send or store, not how to rebuild new from old. Each aligned
+//! block of new is looked up among the block signatures of old by its strong
+//! hash, so no window slides through new and nothing is encoded.
+
+use crate::allocator::ffi_malloc;
+use crate::{
+    block_signature, build_signatures, ffi_slice, set_last_error, HashAlgo, TailPolicy, WeakAlgo, XDELTA_ERR_NO_MEMORY,
+    XDeltaError,
+};
+use std::collections::HashMap;
+use std::os::raw::c_int;
+
+/// The indices of the `block_size`-byte blocks of `new` (the last one may be
+/// shorter) whose contents appear nowhere in `old` as a block of its own, in
+/// ascending order: the blocks a backup has to store.
+///
+/// Only whole blocks of old at multiples of `block_size` count, as they do
+/// for the signatures a patch is matched against, so content that moved in
+/// old by other than whole blocks is reported as changed. A short last block
+/// of new matches only old's equally short last block.
+///
+/// ```
+/// let old = b"aaaabbbbccccdddd";
+/// let new = b"bbbbaaaaXXXXdddd";
+/// assert_eq!(xdelta::changed_blocks(old, new, 4).unwrap(), [2]);
+/// ```
+pub fn changed_blocks(old: &[u8], new: &[u8], block_size: usize) -> Result<Vec<u64>, XDeltaError> {
+    if block_size == 0 {
+        return Err(XDeltaError::InvalidArg("block_size must be > 0".into()));
+    }
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, old, block_size, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let changed = new
+        .chunks(block_size)
+        .enumerate()
+        .filter(|&(i, block)| {
+            let (weak, sig) = block_signature(i as u64, block, HashAlgo::Sha256, WeakAlgo::Rolling);
+            !sigs.get(&weak).is_some_and(|bucket| {
+                bucket.iter().any(|e| e.len == sig.len && e.strong_hash == sig.strong_hash)
+            })
+        })
+        .map(|(i, _)| i as u64)
+        .collect();
+    Ok(changed)
+}
+
+/// 计算新数据中哪些块（按 block_size 对齐，最后一块可以较短）的内容在旧数据的任何块中都不存在，即新增或修改的块，
+/// 不生成补丁，供增量备份规划使用；只比较旧数据中按 block_size 对齐的整块
+/// 结果为 block_count 个升序块序号组成的数组，用 xdelta_free_data 释放；没有变化的块时 *blocks 可能为 NULL
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_changed_blocks(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    block_size: u32,
+    blocks: *mut *mut u64,
+    block_count: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u64>, XDeltaError> {
+        if blocks.is_null() || block_count.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        changed_blocks(old_bytes, new_bytes, block_size as usize)
+    })();
+
+    match r {
+        Ok(changed) => unsafe {
+            let out = ffi_malloc(changed.len() * std::mem::size_of::<u64>()) as *mut u64;
+            if out.is_null() && !changed.is_empty() {
+                set_last_error(&XDeltaError::OutOfMemory);
+                return XDELTA_ERR_NO_MEMORY;
+            }
+            for (i, &block) in changed.iter().enumerate() {
+                out.add(i).write(block);
+            }
+            *blocks = out;
+            *block_count = changed.len();
+            0
+        },
+        Err(e) => {
+            set_last_error(&e);
+            e.code()
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// src/compat.rs
+//! Checking a patch against the opcodes an older applier understands.
+//!
+//! Each known opcode has one `XDELTA_OPCODE_*` bit; a compatibility profile
+//! is the set of bits for the opcodes the oldest deployed applier supports.
+//! Unknown opcodes have no bit and are never allowed.
+//!
+//! Each opcode also has the format version that introduced it, so a patch
+//! can declare the oldest applier able to apply it in a MIN_VERSION record.
+//!
+//! MIN_VERSION layout: opcode 0x86, body length: u32 (4), then
+//!   version: u32  // lowest `XDELTA_FORMAT_VERSION` that can apply the patch
+//! `read_record` refuses a patch whose MIN_VERSION is above its own version.
+
+#[cfg(feature = "std")]
+use crate::{ffi_slice, ffi_status};
+use crate::{patch_records, read_record, XDeltaError};
+use alloc::format;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+use core::ffi::c_char;
+#[cfg(feature = "std")]
+use core::ffi::c_int;
+
+pub const XDELTA_OPCODE_ADD: u64 = 1 << 0;
+pub const XDELTA_OPCODE_COPY: u64 = 1 << 1;
+pub const XDELTA_OPCODE_NOP: u64 = 1 << 2;
+pub const XDELTA_OPCODE_DIFF: u64 = 1 << 3;
+pub const XDELTA_OPCODE_COPY_CONST: u64 = 1 << 4;
+pub const XDELTA_OPCODE_COPY_HASH: u64 = 1 << 5;
+pub const XDELTA_OPCODE_INDEX: u64 = 1 << 6;
+pub const XDELTA_OPCODE_OLD_HASH: u64 = 1 << 7;
+pub const XDELTA_OPCODE_CONST_TABLE: u64 = 1 << 8;
+pub const XDELTA_OPCODE_OUTPUT_OFFSET: u64 = 1 << 9;
+pub const XDELTA_OPCODE_PADDING: u64 = 1 << 10;
+pub const XDELTA_OPCODE_BLOCK_SIZE: u64 = 1 << 11;
+pub const XDELTA_OPCODE_XOR_DELTA: u64 = 1 << 12;
+pub const XDELTA_OPCODE_MIN_VERSION: u64 = 1 << 13;
+pub const XDELTA_OPCODE_RUN: u64 = 1 << 14;
+pub const XDELTA_OPCODE_COPY_TARGET: u64 = 1 << 15;
+pub const XDELTA_OPCODE_CHECK: u64 = 1 << 16;
+pub const XDELTA_OPCODE_ADD_SMALL: u64 = 1 << 17;
+pub const XDELTA_OPCODE_ADD_ZSTD: u64 = 1 << 18;
+
+/// Number of `XDELTA_OPCODE_*` bits, i.e. of known opcodes.
+pub const XDELTA_OPCODE_KINDS: usize = 19;
+
+/// The original format: ADD and COPY only.
+pub const XDELTA_OPCODES_BASELINE: u64 = XDELTA_OPCODE_ADD | XDELTA_OPCODE_COPY;
+
+/// The patch format version this library creates and applies: 1 is the
+/// original ADD/COPY format, 2 adds the opcodes up to MIN_VERSION, 3 adds RUN,
+/// 4 adds COPY_TARGET, 5 adds CHECK, 6 adds the output length to the header,
+/// 7 adds per-record CRCs, 8 adds ADD_SMALL, 9 adds the creation parameters
+/// to the header, 10 adds the forward-only header flag, 11 the sampled one,
+/// 12 adds ADD_ZSTD.
+pub const XDELTA_FORMAT_VERSION: u32 = 12;
+
+/// Format version that added the CRC after each record, see
+/// `PatchOptions::record_crc`.
+#[cfg(feature = "std")]
+pub(crate) const RECORD_CRC_VERSION: u32 = 7;
+
+/// Format version that added the creation parameters to the patch header,
+/// after the output length (6), both of which every created patch records.
+#[cfg(feature = "std")]
+pub(crate) const PARAMS_HEADER_VERSION: u32 = 9;
+
+/// Format version that added the forward-only header flag, see
+/// `PatchOptions::forward_only`.
+#[cfg(feature = "std")]
+pub(crate) const FORWARD_ONLY_VERSION: u32 = 10;
+
+/// Format version that added the sampled header flag, see
+/// `PatchOptions::sample_blocks`.
+#[cfg(feature = "std")]
+pub(crate) const SAMPLED_VERSION: u32 = 11;
+
+/// Name, `XDELTA_OPCODE_*` bit and introducing format version of a known opcode.
+pub(crate) fn opcode_info(opcode: u8) -> Option<(&'static str, u64, u32)> {
+    Some(match opcode {
+        0x00 => ("ADD", XDELTA_OPCODE_ADD, 1),
+        0x01 => ("COPY", XDELTA_OPCODE_COPY, 1),
+        0x02 => ("RUN", XDELTA_OPCODE_RUN, 3),
+        0x03 => ("ADD_ZSTD", XDELTA_OPCODE_ADD_ZSTD, 12),
+        0x04 => ("COPY_TARGET", XDELTA_OPCODE_COPY_TARGET, 4),
+        0x05 => ("ADD_SMALL", XDELTA_OPCODE_ADD_SMALL, 8),
+        0x06 => ("NOP", XDELTA_OPCODE_NOP, 2),
+        0x10 => ("DIFF", XDELTA_OPCODE_DIFF, 2),
+        0x11 => ("COPY_CONST", XDELTA_OPCODE_COPY_CONST, 2),
+        0x12 => ("COPY_HASH", XDELTA_OPCODE_COPY_HASH, 2),
+        0x13 => ("XOR_DELTA", XDELTA_OPCODE_XOR_DELTA, 2),
+        0x80 => ("INDEX", XDELTA_OPCODE_INDEX, 2),
+        0x81 => ("OLD_HASH", XDELTA_OPCODE_OLD_HASH, 2),
+        0x82 => ("CONST_TABLE", XDELTA_OPCODE_CONST_TABLE, 2),
+        0x83 => ("OUTPUT_OFFSET", XDELTA_OPCODE_OUTPUT_OFFSET, 2),
+        0x84 => ("PADDING", XDELTA_OPCODE_PADDING, 2),
+        0x85 => ("BLOCK_SIZE", XDELTA_OPCODE_BLOCK_SIZE, 2),
+        0x86 => ("MIN_VERSION", XDELTA_OPCODE_MIN_VERSION, 2),
+        0x7f => ("CHECK", XDELTA_OPCODE_CHECK, 5),
+        _ => return None,
+    })
+}
+
+/// Format version that introduced `opcode`; unknown opcodes count as newer
+/// than this library.
+#[cfg(feature = "std")]
+pub(crate) fn opcode_version(opcode: u8) -> u32 {
+    opcode_info(opcode).map_or(XDELTA_FORMAT_VERSION + 1, |(_, _, version)| version)
+}
+
+/// Lowest format version able to apply every record of `patch`.
+///
+/// MIN_VERSION records themselves don't count: an applier that doesn't know
+/// them can skip them (they are skippable) without misapplying anything.
+#[cfg(feature = "std")]
+pub(crate) fn required_version(patch: &[u8]) -> Result<u32, XDeltaError> {
+    let mut version = 1;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        if patch[pos] != 0x86 {
+            version = version.max(opcode_version(patch[pos]));
+        }
+        pos = read_record(patch, pos)?.1;
+    }
+    Ok(version)
+}
+
+/// Prepend a MIN_VERSION record declaring `version` to `patch`.
+#[cfg(feature = "std")]
+pub(crate) fn add_min_version(patch: &[u8], version: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(9 + patch.len());
+    out.push(0x86); // MIN_VERSION
+    out.extend_from_slice(&4u32.to_le_bytes());
+    out.extend_from_slice(&version.to_le_bytes());
+    out.extend_from_slice(patch);
+    out
+}
+
+/// Check that every record of `patch` uses an opcode whose `XDELTA_OPCODE_*`
+/// bit is set in `allowed`, failing on the first one that doesn't.
+///
+/// Skippable records count too: an older applier would skip them only if
+/// asked to, and the point is to gate on what it understands.
+pub fn patch_uses_only(patch: &[u8], allowed: u64) -> Result<(), XDeltaError> {
+    let patch = patch_records(patch)?;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let opcode = patch[pos];
+        match opcode_info(opcode) {
+            Some((_, bit, _)) if allowed & bit != 0 => {}
+            Some((name, _, _)) => {
+                return Err(XDeltaError::InvalidArg(format!(
+                    "opcode {:#x} ({}) at {} is not in the allowed set",
+                    opcode, name, pos
+                )));
+            }
+            None => {
+                return Err(XDeltaError::InvalidArg(format!("unknown opcode {:#x} at {}", opcode, pos)));
+            }
+        }
+        pos = read_record(patch, pos)?.1;
+    }
+    Ok(())
+}
+
+/// 检查补丁是否只使用 allowed_opcodes（XDELTA_OPCODE_* 位）中的操作码，用于兼容旧版本应用方
+/// 全部允许时返回0，否则返回负的错误码，xdelta_last_error 给出第一个不允许的操作码
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_patch_uses_only(patch_data: *const u8, patch_len: usize, allowed_opcodes: u64) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        patch_uses_only(patch_bytes, allowed_opcodes)
+    })();
+
+    ffi_status(r)
+}
+
+/// 返回本库实现的补丁格式版本（XDELTA_FORMAT_VERSION）；补丁的 MIN_VERSION 高于此值时应用会失败
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_format_version() -> u32 {
+    XDELTA_FORMAT_VERSION
+}
+
+/// NUL-terminated crate version returned by `xdelta_version`.
+const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+/// 返回本库的版本号（以 NUL 结尾的静态字符串，如 "0.1.0"），用于确认加载的是哪个构建
+/// 指针归库所有，不要释放；不分配内存，也不改变 xdelta_last_error
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_version() -> *const c_char {
+    VERSION.as_ptr() as *const c_char
+}
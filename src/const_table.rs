@@ -0,0 +1,159 @@
+// src/const_table.rs
+//! Constant table: short tiles that recur as literal runs, referenced by COPY_CONST.
+//!
+//! Only periodic runs are encoded: a tile of at most `MAX_TILE_LEN` bytes
+//! repeated back to back over at least `MIN_RUN_LEN` bytes. A short
+//! sequence that recurs only in isolated copies between other literals is
+//! left in its ADD records.
+//!
+//! CONST_TABLE layout: opcode 0x82, body length: u32, then the body:
+//!   count: u8
+//!   [(len: u8, bytes: [len])...]   // 1 <= len <= MAX_TILE_LEN
+//! COPY_CONST layout: opcode 0x11, index: u8, length: u32 (little-endian);
+//! the output is the tile repeated (and cut off) to `length` bytes.
+
+#[cfg(feature = "std")]
+use crate::{read_record, write_add, Record, MAX_RECORD_LEN};
+use crate::XDeltaError;
+use alloc::{format, vec::Vec};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+/// Longest tile considered for the table.
+#[cfg(feature = "std")]
+const MAX_TILE_LEN: usize = 8;
+/// Most entries the table may hold.
+#[cfg(feature = "std")]
+const MAX_TABLE_ENTRIES: usize = 16;
+/// Shortest literal run worth splitting an ADD for: COPY_CONST plus the
+/// extra ADD header cost 11 bytes.
+#[cfg(feature = "std")]
+const MIN_RUN_LEN: usize = 16;
+
+/// Find the best tile run at the start of `data`: `(tile_len, run_len)`.
+///
+/// Among periods covering at least `MIN_RUN_LEN` bytes the longest run wins,
+/// shortest period first, so "abab..." is reported as tile "ab".
+#[cfg(feature = "std")]
+fn tile_run(data: &[u8]) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    for p in 1..=usize::min(MAX_TILE_LEN, data.len() / 2) {
+        let mut run = p;
+        while run < data.len() && data[run] == data[run - p] {
+            run += 1;
+        }
+        if run >= MIN_RUN_LEN && best.is_none_or(|(_, best_run)| run > best_run) {
+            best = Some((p, run));
+        }
+    }
+    best
+}
+
+/// Split `data` into literal pieces and tile runs.
+#[cfg(feature = "std")]
+fn for_each_run(data: &[u8], mut f: impl FnMut(usize, usize, usize)) {
+    let mut i = 0usize;
+    while i < data.len() {
+        match tile_run(&data[i..]) {
+            Some((tile_len, run)) => {
+                f(i, tile_len, run);
+                i += run;
+            }
+            None => i += 1,
+        }
+    }
+}
+
+/// Look up entry `index` in a validated CONST_TABLE body.
+pub(crate) fn const_entry(table: Option<&[u8]>, index: u8) -> Result<&[u8], XDeltaError> {
+    let table = table.ok_or_else(|| XDeltaError::InvalidArg("COPY_CONST without CONST_TABLE".into()))?;
+    if index >= table[0] {
+        return Err(XDeltaError::MalformedPatch(format!("COPY_CONST index {} out of range", index)));
+    }
+    let mut pos = 1usize;
+    for _ in 0..index {
+        pos += 1 + table[pos] as usize;
+    }
+    let len = table[pos] as usize;
+    Ok(&table[pos + 1..pos + 1 + len])
+}
+
+/// Check that a CONST_TABLE body is well-formed.
+pub(crate) fn validate_table(body: &[u8]) -> bool {
+    let Some((&count, mut rest)) = body.split_first() else {
+        return false;
+    };
+    for _ in 0..count {
+        match rest.split_first() {
+            Some((&len, tail)) if len >= 1 && tail.len() >= len as usize => rest = &tail[len as usize..],
+            _ => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+/// Output bytes `from..to` of `tile` repeated.
+pub(crate) fn expand_const(tile: &[u8], from: usize, to: usize) -> Vec<u8> {
+    (from..to).map(|i| tile[i % tile.len()]).collect()
+}
+
+/// Rewrite `patch` so literal runs of frequent short tiles become COPY_CONST
+/// records backed by a CONST_TABLE record placed first.
+///
+/// Returns the patch unchanged if no tile run is worth it.
+#[cfg(feature = "std")]
+pub(crate) fn add_const_table(patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    // rank tiles by the literal bytes they would cover
+    let mut coverage: HashMap<&[u8], usize> = HashMap::new();
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        if let Record::Add(data) = record {
+            for_each_run(data, |at, tile_len, run| {
+                *coverage.entry(&data[at..at + tile_len]).or_default() += run;
+            });
+        }
+        pos = next;
+    }
+    if coverage.is_empty() {
+        return Ok(patch.to_vec());
+    }
+    let mut tiles: Vec<(&[u8], usize)> = coverage.into_iter().collect();
+    // most coverage first, ties broken by content so the table is deterministic
+    tiles.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    tiles.truncate(MAX_TABLE_ENTRIES);
+
+    let mut out = Vec::with_capacity(patch.len());
+    let mut body = vec![tiles.len() as u8];
+    for (tile, _) in &tiles {
+        body.push(tile.len() as u8);
+        body.extend_from_slice(tile);
+    }
+    out.push(0x82); // CONST_TABLE
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        match record {
+            Record::Add(data) => {
+                let mut literal_start = 0usize;
+                for_each_run(data, |at, tile_len, run| {
+                    let tile = &data[at..at + tile_len];
+                    if let Some(index) = tiles.iter().position(|(t, _)| *t == tile) {
+                        write_add(&mut out, &data[literal_start..at], MAX_RECORD_LEN);
+                        out.push(0x11); // COPY_CONST
+                        out.push(index as u8);
+                        out.extend_from_slice(&(run as u32).to_le_bytes());
+                        literal_start = at + run;
+                    }
+                });
+                write_add(&mut out, &data[literal_start..], MAX_RECORD_LEN);
+            }
+            _ => out.extend_from_slice(&patch[pos..next]),
+        }
+        pos = next;
+    }
+    Ok(out)
+}
@@ -0,0 +1,155 @@
+// src/context.rs
+//! Reusable diff and apply contexts for callers that create or apply many
+//! small patches.
+//!
+//! Every `create_patch_with` call builds a fresh signature map and output
+//! buffer. A `DiffContext` keeps those between calls and only clears them, so
+//! a loop over thousands of small diffs stops paying for the allocations. An
+//! `ApplyContext` does the same for the output buffer of apply.
+
+use crate::{
+    apply_to, create_patch_scratch, ffi_slice, ffi_status, finish_patch, identity_patch, write_output, ApplyOutput,
+    PatchOptions, Scratch, XDeltaError,
+};
+use std::os::raw::c_int;
+
+/// Scratch buffers reused across `create_patch` calls.
+///
+/// A context is not shared between threads; use one per thread.
+#[derive(Default)]
+pub struct DiffContext {
+    scratch: Scratch,
+}
+
+impl DiffContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Same as `create_patch_with`, but the patch is built in, and borrowed
+    /// from, this context's buffers. It stays valid until the next call.
+    pub fn create_patch(&mut self, old: &[u8], new: &[u8], opts: &PatchOptions) -> Result<&[u8], XDeltaError> {
+        if old == new && opts.identity_allowed() {
+            self.scratch.out.clear();
+            self.scratch.out.extend_from_slice(&identity_patch());
+            return Ok(&self.scratch.out);
+        }
+        let opts = &opts.resolve_block_size(old.len());
+        create_patch_scratch(old, new, opts, &mut self.scratch, None, None)?;
+        let patch = std::mem::take(&mut self.scratch.out);
+        self.scratch.out = finish_patch(old, new, patch, opts)?;
+        Ok(&self.scratch.out)
+    }
+}
+
+/// Output buffer reused across `apply_patch` calls.
+///
+/// A context is not shared between threads; use one per thread.
+#[derive(Default)]
+pub struct ApplyContext {
+    out: Vec<u8>,
+}
+
+impl ApplyContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `patch` to `old` into this context's buffer and borrow the
+    /// output from it. It stays valid until the next call; the buffer keeps
+    /// the largest capacity it has needed, so only growth allocates.
+    pub fn apply_patch(&mut self, old: &[u8], patch: &[u8]) -> Result<&[u8], XDeltaError> {
+        apply_to(old, patch, ApplyOutput::Grow(&mut self.out))?;
+        Ok(&self.out)
+    }
+}
+
+/// 创建可复用的差异上下文，用完后用 xdelta_context_free 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_context_new() -> *mut DiffContext {
+    Box::into_raw(Box::new(DiffContext::new()))
+}
+
+/// 释放差异上下文
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_context_free(ctx: *mut DiffContext) {
+    if !ctx.is_null() {
+        drop(unsafe { Box::from_raw(ctx) });
+    }
+}
+
+/// 使用上下文创建补丁数据，复用上下文中的缓冲区；同一上下文不可被多个线程同时使用
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_ctx(
+    ctx: *mut DiffContext,
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    block_size: u32,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let ctx = unsafe { ctx.as_mut() };
+    let r = (|| -> Result<&[u8], XDeltaError> {
+        let ctx = ctx.ok_or(XDeltaError::NullPointer)?;
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        ctx.create_patch(old_bytes, new_bytes, &PatchOptions::new().block_size(block_size as usize))
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 创建可复用的应用上下文，用完后用 xdelta_apply_context_free 释放
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_context_new() -> *mut ApplyContext {
+    Box::into_raw(Box::new(ApplyContext::new()))
+}
+
+/// 释放应用上下文，之前返回的输出随之失效
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_context_free(ctx: *mut ApplyContext) {
+    if !ctx.is_null() {
+        drop(unsafe { Box::from_raw(ctx) });
+    }
+}
+
+/// 使用上下文应用补丁：输出写入上下文内部的缓冲区，*new_data 指向该缓冲区（归上下文所有，不要释放）
+/// 输出在同一上下文的下一次调用或释放上下文之前有效；同一上下文不可被多个线程同时使用
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）（此时 *new_data、*new_len 不变）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_ctx(
+    ctx: *mut ApplyContext,
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *const u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let ctx = unsafe { ctx.as_mut() }.ok_or(XDeltaError::NullPointer)?;
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        let out = ctx.apply_patch(old_bytes, patch_bytes)?;
+        unsafe {
+            *new_data = out.as_ptr();
+            *new_len = out.len();
+        }
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
@@ -0,0 +1,89 @@
+// src/dictionary.rs
+//! Diffing small messages against a shared dictionary.
+//!
+//! Config blobs and protobuf or JSON messages are mostly field names and
+//! boilerplate that every message repeats, so they delta far better against
+//! a representative sample than against nothing. The dictionary is an
+//! ordinary base: it is signed and matched exactly as `old` is, and the
+//! patch format doesn't change. What this adds is the contract: the
+//! dictionary is only ever read, never changed or kept, so one buffer can
+//! serve any number of calls, from any number of threads at once.
+
+use crate::{apply_patch, create_patch, ffi_slice, write_output, XDeltaError};
+use std::os::raw::c_int;
+
+/// Create a patch that rebuilds `new` from the shared dictionary `dict`, at
+/// `block_size` (0 picks one from the dictionary's length).
+///
+/// Small messages want small blocks, as a block can only match when all of
+/// it repeats in the dictionary. The patch is applied with
+/// `apply_patch_with_dictionary` and the same dictionary, byte for byte.
+///
+/// ```
+/// let dict = br#"{"user_id": 0, "name": "", "email": "", "active": true, "roles": ["reader"]}"#;
+/// let msg = br#"{"user_id": 42, "name": "Ada", "email": "ada@example.com", "active": true, "roles": ["reader"]}"#;
+/// let patch = xdelta::create_patch_with_dictionary(dict, msg, 8).unwrap();
+/// assert!(patch.len() < xdelta::create_patch(b"", msg, 8).unwrap().len());
+/// assert_eq!(xdelta::apply_patch_with_dictionary(dict, &patch).unwrap(), msg);
+/// ```
+pub fn create_patch_with_dictionary(dict: &[u8], new: &[u8], block_size: usize) -> Result<Vec<u8>, XDeltaError> {
+    create_patch(dict, new, block_size)
+}
+
+/// Rebuild the message `patch` encodes against the shared dictionary `dict`.
+pub fn apply_patch_with_dictionary(dict: &[u8], patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    apply_patch(dict, patch)
+}
+
+/// 以共享字典为基准创建补丁，适合大量相似的小数据（配置、JSON、protobuf 消息）
+/// 字典只读，不会被修改或保留，同一字典可供任意多次调用（包括多线程同时调用）复用；
+/// 补丁用 xdelta_apply_patch_with_dictionary 和同一字典应用。block_size 为 0 时按字典长度自动选择
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_with_dictionary(
+    dict_data: *const u8,
+    dict_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size: u32,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let dict_bytes = unsafe { ffi_slice(dict_data, dict_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch_with_dictionary(dict_bytes, new_bytes, block_size as usize)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 用共享字典应用 xdelta_create_patch_with_dictionary 创建的补丁，字典只读
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_with_dictionary(
+    dict_data: *const u8,
+    dict_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let dict_bytes = unsafe { ffi_slice(dict_data, dict_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_patch_with_dictionary(dict_bytes, patch_bytes)
+    })();
+
+    write_output(r, new_data, new_len)
+}
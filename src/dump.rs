@@ -0,0 +1,127 @@
+// src/dump.rs
+//! A readable listing of a patch's operations, for seeing why a delta is as
+//! large as it is without writing a parser.
+//!
+//! The first line is the header, e.g. `XDR1 version=1 flags=0x28
+//! output_len=4096 block_size=1024`; then one line per operation, keyed by
+//! where in new its output starts, in the style of `patch_trace`, with an
+//! ADD's first bytes in hex; then `records=N`, the number of operations.
+//! Metadata records are not listed.
+
+use crate::{
+    declared_output_len, ffi_slice, header_params, without_record_crcs, write_output, PatchOp, PatchReader, XDELTA_OK,
+    XDeltaError,
+};
+use std::fmt::Write;
+use std::os::raw::c_int;
+
+/// Literal bytes of an ADD shown in the listing; longer ones end in `...`.
+const ADD_PREVIEW: usize = 16;
+
+/// List the operations of `patch`, checked as `PatchReader` checks them.
+/// Nothing is read from old.
+///
+/// ```
+/// let patch = xdelta::create_patch(b"hello world", b"hello there", 4).unwrap();
+/// let dump = xdelta::dump_patch(&patch).unwrap();
+/// assert!(dump.starts_with("XDR1 version=1") && dump.contains("\n0 COPY old=0 len=4\n"));
+/// assert!(dump.ends_with("records=2\n"));
+/// ```
+pub fn dump_patch(patch: &[u8]) -> Result<String, XDeltaError> {
+    let stripped = without_record_crcs(patch)?;
+    let reader = PatchReader::new(&stripped)?;
+    let mut dump = String::new();
+    // writing to a String cannot fail; the flags are shown as they are,
+    // the record CRC one included
+    let _ = write!(dump, "XDR1 version={} flags={:#04x}", patch[4], patch[5]);
+    let patch = &*stripped;
+    if let Some(len) = declared_output_len(patch) {
+        let _ = write!(dump, " output_len={}", len);
+    }
+    if let Some((block_size, _, _)) = header_params(patch) {
+        let _ = write!(dump, " block_size={}", block_size);
+    }
+    dump.push('\n');
+    if reader.is_identity() {
+        dump.push_str("identity\n");
+    }
+
+    let (mut out_pos, mut count) = (0u64, 0u64);
+    for op in reader {
+        let op = op?;
+        let _ = match op {
+            PatchOp::Add(data) => {
+                let preview: String = data.iter().take(ADD_PREVIEW).map(|b| format!("{:02x}", b)).collect();
+                let more = if data.len() > ADD_PREVIEW { "..." } else { "" };
+                writeln!(dump, "{} ADD len={} data={}{}", out_pos, data.len(), preview, more)
+            }
+            PatchOp::AddZstd { len, data } => {
+                writeln!(dump, "{} ADD_ZSTD len={} compressed={}", out_pos, len, data.len())
+            }
+            PatchOp::Copy { offset, len } => writeln!(dump, "{} COPY old={} len={}", out_pos, offset, len),
+            PatchOp::Run { byte, len } => writeln!(dump, "{} RUN byte={:#04x} len={}", out_pos, byte, len),
+            PatchOp::CopyTarget { offset, len } => writeln!(dump, "{} COPY_TARGET at={} len={}", out_pos, offset, len),
+            PatchOp::Diff { offset, len, deltas } => {
+                writeln!(dump, "{} DIFF old={} len={} deltas={}", out_pos, offset, len, deltas.len() / 5)
+            }
+            PatchOp::CopyConst { tile, len } => {
+                writeln!(dump, "{} COPY_CONST tile={} len={}", out_pos, tile.len(), len)
+            }
+            PatchOp::CopyHash { hash, len } => {
+                let prefix: String = hash[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(dump, "{} COPY_HASH hash={} len={}", out_pos, prefix, len)
+            }
+            PatchOp::Xor { offset, len, .. } => writeln!(dump, "{} XOR_DELTA old={} len={}", out_pos, offset, len),
+        };
+        out_pos += op_len(&op);
+        count += 1;
+    }
+    let _ = writeln!(dump, "records={}", count);
+    Ok(dump)
+}
+
+/// Output bytes `op` produces.
+fn op_len(op: &PatchOp<'_>) -> u64 {
+    match *op {
+        PatchOp::Add(data) => data.len() as u64,
+        PatchOp::AddZstd { len, .. }
+        | PatchOp::Copy { len, .. }
+        | PatchOp::Run { len, .. }
+        | PatchOp::CopyTarget { len, .. }
+        | PatchOp::Diff { len, .. }
+        | PatchOp::CopyConst { len, .. }
+        | PatchOp::CopyHash { len, .. }
+        | PatchOp::Xor { len, .. } => len as u64,
+    }
+}
+
+/// 不应用补丁、不需要旧数据，列出补丁的每个操作（类型、在输出中的位置、旧数据偏移、长度，ADD 附前几个字节），
+/// 供排查补丁为何偏大；第一行为补丁头，最后一行为 records=操作数，格式见 dump_patch
+/// *out 为以 NUL 结尾的字符串，*out_len 为其长度（不含 NUL），用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_dump_patch(
+    patch_data: *const u8,
+    patch_len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if out.is_null() || out_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        let mut text = dump_patch(patch_bytes)?.into_bytes();
+        text.push(0);
+        Ok(text)
+    })();
+
+    let status = write_output(r, out, out_len);
+    if status == XDELTA_OK {
+        // the length handed back leaves out the NUL
+        unsafe { *out_len -= 1 };
+    }
+    status
+}
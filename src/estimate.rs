@@ -0,0 +1,98 @@
+// src/estimate.rs
+//! Deciding whether a patch is worth making, by sampling new instead of
+//! running the matcher over all of it.
+
+use crate::{auto_block_size, ffi_slice, set_last_error, Rolling, WeakChecksum, XDeltaError};
+use std::collections::HashMap;
+use std::os::raw::c_int;
+
+/// How many windows of new `estimate_patch_size` samples.
+const SAMPLES: usize = 256;
+
+/// Bytes of record header a COPY or ADD costs.
+const RECORD_COST: u64 = 13;
+
+/// Estimate the size of the patch `create_patch_with` would make from `old`
+/// to `new` at `block_size` (0 for `auto_block_size`), without making it.
+///
+/// Old is indexed by the weak checksum of each block, which is cheap next to
+/// the strong hashes the matcher computes. Then up to `SAMPLES` evenly spaced
+/// stretches of new are each searched, one block's worth of positions, for a
+/// block of old; a stretch with a match is taken to be copied, one without
+/// to be sent literally, and each costs one record header. This is an
+/// approximation: it can miss matches shorter than a block or between
+/// samples, and charges a record per stretch where the matcher would merge
+/// adjacent copies.
+pub fn estimate_patch_size(old: &[u8], new: &[u8], block_size: usize) -> Result<u64, XDeltaError> {
+    let block_size = if block_size == 0 { auto_block_size(old.len()) } else { block_size };
+    if new.len() < block_size || old.len() < block_size {
+        return Ok(new.len() as u64 + RECORD_COST);
+    }
+    let mut blocks: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (idx, block) in old.chunks_exact(block_size).enumerate() {
+        blocks.entry(Rolling::from_slice(block).chksum()).or_default().push(idx * block_size);
+    }
+
+    let last = new.len() - block_size;
+    let samples = usize::min(SAMPLES, new.len() / block_size);
+    let mut matched = 0usize;
+    for i in 0..samples {
+        let start = i * last / samples;
+        let end = usize::min(start + block_size, last + 1);
+        let mut rolling = Rolling::from_slice(&new[start..start + block_size]);
+        for pos in start..end {
+            if pos > start {
+                rolling.roll(new[pos - 1], new[pos + block_size - 1]);
+            }
+            let window = &new[pos..pos + block_size];
+            let hit = blocks
+                .get(&rolling.chksum())
+                .is_some_and(|offsets| offsets.iter().any(|&o| old[o..o + block_size] == *window));
+            if hit {
+                matched += 1;
+                break;
+            }
+        }
+    }
+
+    let literal = (new.len() as u128 * (samples - matched) as u128 / samples as u128) as u64;
+    Ok(literal + samples as u64 * RECORD_COST)
+}
+
+/// Whether a patch from `old` to `new` is estimated (by
+/// `estimate_patch_size`) to be smaller than `new` by at least `threshold`,
+/// a fraction of `new.len()` between 0 and 1.
+pub fn should_patch(old: &[u8], new: &[u8], block_size: usize, threshold: f64) -> Result<bool, XDeltaError> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(XDeltaError::InvalidArg(format!("threshold {} is not between 0 and 1", threshold)));
+    }
+    let estimate = estimate_patch_size(old, new, block_size)?;
+    Ok((estimate as f64) <= new.len() as f64 * (1.0 - threshold))
+}
+
+/// 通过抽样估算补丁大小（不实际创建补丁），判断补丁是否比 new 至少小 threshold（0~1 的比例）
+/// 值得创建补丁时返回1，否则返回0，参数错误返回负的错误码；结果为近似值
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_should_patch(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    block_size: u32,
+    threshold: f64,
+) -> c_int {
+    let r = (|| -> Result<bool, XDeltaError> {
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        should_patch(old_bytes, new_bytes, block_size as usize, threshold)
+    })();
+
+    match r {
+        Ok(yes) => yes as c_int,
+        Err(e) => {
+            set_last_error(&e);
+            e.code()
+        }
+    }
+}
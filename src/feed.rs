@@ -0,0 +1,283 @@
+// src/feed.rs
+//! Push-style apply for patches arriving in pieces, e.g. over the network.
+//!
+//! Bytes are fed as they are received. Each record is parsed and checked as
+//! soon as all of it is present and its output is written straight away, so
+//! applying overlaps with the download; only a partial record at the end of
+//! a piece is buffered until the rest of it arrives.
+//!
+//! The format has no per-record checksum, so what can be verified is the
+//! structure of each record and, through an OLD_HASH record, the base: the
+//! whole of old before any output is written (`VerifyOld::Full`), or the old
+//! ranges actually read once the patch is finished (`VerifyOld::Partial`).
+//! A CHECK record, if the patch has one, is compared in `finish`, after all
+//! of the output has been written.
+
+use crate::stream::{CallbackWriter, XdeltaWriteFn, XDELTA_VERIFY_FULL, XDELTA_VERIFY_NONE, XDELTA_VERIFY_PARTIAL};
+use crate::{
+    apply_diff, const_table, ffi_slice, ffi_status, header_len, inflate_add, is_identity, old_range, patch_records,
+    read_record, record_size, xor_delta, OutputCheck, PATCH_HEADER_LEN, Record, VerifyOld, XDeltaError,
+};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::os::raw::{c_int, c_void};
+
+/// Applies a patch to `old` as its bytes are fed in, writing output to `W`.
+pub struct ApplyFeed<'a, W: Write> {
+    old: &'a [u8],
+    out: W,
+    verify: VerifyOld,
+    /// Whether the patch header has arrived and been checked.
+    header_seen: bool,
+    /// Whether that header is an identity patch's, after which nothing may follow.
+    identity: bool,
+    /// Received bytes not yet making up a whole record.
+    pending: Vec<u8>,
+    /// CONST_TABLE body, once seen.
+    consts: Option<Vec<u8>>,
+    /// OLD_HASH body, once seen.
+    expected: Option<[u8; 64]>,
+    referenced: Sha256,
+    /// The CHECK record the header announces, and the output hashed so far.
+    check: OutputCheck,
+    failed: bool,
+}
+
+impl<'a, W: Write> ApplyFeed<'a, W> {
+    /// Start applying a patch to `old`. With any `verify` other than `None`,
+    /// the patch must carry an OLD_HASH record before its first output.
+    pub fn new(old: &'a [u8], out: W, verify: VerifyOld) -> Self {
+        ApplyFeed {
+            old,
+            out,
+            verify,
+            header_seen: false,
+            identity: false,
+            pending: Vec::new(),
+            consts: None,
+            expected: None,
+            referenced: Sha256::new(),
+            check: OutputCheck::default(),
+            failed: false,
+        }
+    }
+
+    /// Feed the next `bytes` of the patch, applying every record they
+    /// complete. After an error, the feed is dead and every later call fails.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), XDeltaError> {
+        if self.failed {
+            return Err(XDeltaError::InvalidArg("feed already failed".into()));
+        }
+        let r = self.feed_inner(bytes);
+        self.failed = r.is_err();
+        r
+    }
+
+    fn feed_inner(&mut self, bytes: &[u8]) -> Result<(), XDeltaError> {
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(bytes);
+        let mut pos = 0usize;
+        if !self.header_seen {
+            if buf.len() < PATCH_HEADER_LEN || buf.len() < header_len(&buf) {
+                self.pending = buf;
+                return Ok(());
+            }
+            let header = &buf[..header_len(&buf)];
+            patch_records(header)?;
+            self.header_seen = true;
+            self.identity = is_identity(header);
+            self.check = OutputCheck::new(header);
+            pos = header.len();
+            if self.identity {
+                if self.verify != VerifyOld::None {
+                    return Err(XDeltaError::InvalidArg("patch carries no old hash to verify against".into()));
+                }
+                self.write(self.old)?;
+            }
+        }
+        if self.identity && pos < buf.len() {
+            return Err(XDeltaError::MalformedPatch("identity patch has records after its header".into()));
+        }
+        while pos < buf.len() {
+            match record_size(&buf, pos) {
+                Some(size) if size <= buf.len() - pos => {
+                    let (record, next) = read_record(&buf[..pos + size], pos)?;
+                    self.apply(record)?;
+                    pos = next;
+                }
+                _ => break,
+            }
+        }
+        buf.drain(..pos);
+        self.pending = buf;
+        Ok(())
+    }
+
+    /// Write the next `bytes` of output.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), XDeltaError> {
+        self.check.update(bytes);
+        self.out.write_all(bytes).map_err(io_error)
+    }
+
+    fn apply(&mut self, record: Record) -> Result<(), XDeltaError> {
+        self.check.record(&record)?;
+        if record.output_len() > 0 && self.verify != VerifyOld::None && self.expected.is_none() {
+            return Err(XDeltaError::InvalidArg("patch carries no old hash to verify against".into()));
+        }
+        let old = self.old;
+        let (verify, referenced) = (self.verify, &mut self.referenced);
+        let mut read = |offset: u64, len: u32, what: &str| -> Result<&'a [u8], XDeltaError> {
+            let range = &old[old_range(old.len(), offset, len as u64, what)?];
+            if verify == VerifyOld::Partial {
+                referenced.update(range);
+            }
+            Ok(range)
+        };
+        match record {
+            Record::Add(data) => self.write(data)?,
+            Record::AddZstd { len, data } => self.write(&inflate_add(len, data)?)?,
+            Record::Copy { offset, len } => {
+                let range = read(offset, len, "COPY")?;
+                self.write(range)?;
+            }
+            Record::Diff { offset, len, deltas } => {
+                read(offset, len, "DIFF")?;
+                self.write(&apply_diff(old, offset, len, deltas)?)?;
+            }
+            Record::Xor { offset, len, body } => {
+                let mut block = read(offset, len, "XOR_DELTA")?.to_vec();
+                xor_delta::xor_into(&mut block, body, 0);
+                self.write(&block)?;
+            }
+            Record::CopyConst { index, len } => {
+                let tile = const_table::const_entry(self.consts.as_deref(), index)?;
+                self.write(&const_table::expand_const(tile, 0, len as usize))?;
+            }
+            Record::Run { byte, len } => self.write(&vec![byte; len as usize])?,
+            Record::CopyTarget { .. } => return Err(crate::needs_output()),
+            Record::CopyHash { .. } => return Err(crate::needs_resolver()),
+            Record::ConstTable(body) => self.consts = Some(body.to_vec()),
+            Record::OldHash(body) => {
+                let mut hash = [0u8; 64];
+                hash.copy_from_slice(body);
+                if self.verify == VerifyOld::Full && Sha256::digest(old)[..] != hash[..32] {
+                    return Err(XDeltaError::OldHashMismatch("full hash of old differs".into()));
+                }
+                self.expected = Some(hash);
+            }
+            Record::Index(_)
+            | Record::OutputOffset(_)
+            | Record::Padding
+            | Record::BlockSize(_)
+            | Record::MinVersion
+            | Record::Check(_) => {}
+            Record::Skippable(opcode) => {
+                return Err(XDeltaError::InvalidArg(format!("unknown skippable opcode {:#x}", opcode)));
+            }
+        }
+        Ok(())
+    }
+
+    /// End of the patch: fail if it stopped inside the header or mid-record,
+    /// if the output doesn't match its CHECK record or, with
+    /// `VerifyOld::Partial`, if the old ranges read don't match its OLD_HASH.
+    /// Returns the writer.
+    pub fn finish(mut self) -> Result<W, XDeltaError> {
+        if self.failed {
+            return Err(XDeltaError::InvalidArg("feed already failed".into()));
+        }
+        if !self.header_seen {
+            patch_records(&self.pending)?;
+        }
+        if !self.pending.is_empty() {
+            return Err(XDeltaError::MalformedPatch(format!(
+                "patch ends {} bytes into an incomplete record",
+                self.pending.len()
+            )));
+        }
+        self.check.finish()?;
+        if let (VerifyOld::Partial, Some(expected)) = (self.verify, self.expected) {
+            if self.referenced.finalize()[..] != expected[32..] {
+                return Err(XDeltaError::OldHashMismatch("referenced ranges of old differ".into()));
+            }
+        }
+        Ok(self.out)
+    }
+}
+
+fn io_error(e: std::io::Error) -> XDeltaError {
+    XDeltaError::Io(e.to_string())
+}
+
+/// C handle of an `ApplyFeed` writing through a callback.
+pub struct XdeltaApplyFeed(ApplyFeed<'static, CallbackWriter>);
+
+/// 创建推送式应用句柄：补丁数据边接收边用 xdelta_apply_feed 送入，完整的记录立即校验并应用，输出交给 write_out
+/// old_data 在句柄释放前须保持有效；verify 为 XDELTA_VERIFY_*（需要补丁带有旧数据哈希）
+/// 用 xdelta_apply_feed_finish 结束（同时释放句柄），中途放弃用 xdelta_apply_feed_free；失败时返回 NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_feed_new(
+    old_data: *const u8,
+    old_len: usize,
+    write_out: Option<XdeltaWriteFn>,
+    ctx: *mut c_void,
+    verify: u32,
+) -> *mut XdeltaApplyFeed {
+    let r = (|| -> Result<XdeltaApplyFeed, XDeltaError> {
+        let Some(write) = write_out else {
+            return Err(XDeltaError::NullPointer);
+        };
+        let verify = match verify {
+            XDELTA_VERIFY_NONE => VerifyOld::None,
+            XDELTA_VERIFY_PARTIAL => VerifyOld::Partial,
+            XDELTA_VERIFY_FULL => VerifyOld::Full,
+            other => return Err(XDeltaError::InvalidArg(format!("unknown verify mode {}", other))),
+        };
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        Ok(XdeltaApplyFeed(ApplyFeed::new(old_bytes, CallbackWriter { write, ctx }, verify)))
+    })();
+
+    match r {
+        Ok(feed) => Box::into_raw(Box::new(feed)),
+        Err(e) => {
+            crate::set_last_error(&e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// 送入接下来收到的 len 字节补丁数据，应用其中完整的记录，不完整的记录缓存到下次
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）（之后该句柄的所有调用都会失败）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_feed(feed: *mut XdeltaApplyFeed, data: *const u8, len: usize) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let feed = unsafe { feed.as_mut() }.ok_or(XDeltaError::NullPointer)?;
+        feed.0.feed(unsafe { ffi_slice(data, len)? })
+    })();
+
+    ffi_status(r)
+}
+
+/// 补丁数据已全部送入：检查补丁没有在记录中间结束，并按 verify 校验旧数据；无论成功与否都释放句柄
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_feed_finish(feed: *mut XdeltaApplyFeed) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if feed.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        let feed = unsafe { Box::from_raw(feed) };
+        feed.0.finish().map(|_| ())
+    })();
+
+    ffi_status(r)
+}
+
+/// 放弃推送式应用并释放句柄
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_feed_free(feed: *mut XdeltaApplyFeed) {
+    if !feed.is_null() {
+        drop(unsafe { Box::from_raw(feed) });
+    }
+}
@@ -0,0 +1,235 @@
+// src/file.rs
+//! Creating and applying patches between files named by path, without
+//! loading the inputs into memory first.
+//!
+//! Create maps old and new into memory (on unix; elsewhere they are read in
+//! whole), so pages are read as the matcher touches them and the OS can drop
+//! them again under pressure. Apply streams: old is read on demand and the
+//! output goes straight to disk, see `apply_streaming`; `apply_patch_mmap`
+//! (feature `mmap`) maps old instead, for an in-memory patch. The patch
+//! itself is held in memory both ways. A mapped file must not change while
+//! the patch is being made or applied.
+//!
+//! I/O failures are `XDeltaError::Io` naming the path and the OS error.
+
+use crate::{apply_streaming, create_patch, ffi_status, ApplyOptions, OldSource, XDeltaError};
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+
+/// `XDeltaError::Io` for `err` on `path`.
+fn io_error(path: &Path, err: std::io::Error) -> XDeltaError {
+    XDeltaError::Io(format!("{}: {}", path.display(), err))
+}
+
+/// The contents of a file, mapped read-only where the platform allows.
+struct Mapped {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+    len: usize,
+}
+
+impl Mapped {
+    #[cfg(unix)]
+    fn open(path: &Path) -> Result<Self, XDeltaError> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path).map_err(|e| io_error(path, e))?;
+        let len = file.metadata().map_err(|e| io_error(path, e))?.len();
+        let len = usize::try_from(len).map_err(|_| XDeltaError::InvalidArg(format!("{} too large", path.display())))?;
+        if len == 0 {
+            // mmap refuses an empty mapping
+            return Ok(Mapped { ptr: std::ptr::null_mut(), len: 0 });
+        }
+        let ptr =
+            unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io_error(path, std::io::Error::last_os_error()));
+        }
+        Ok(Mapped { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    fn open(path: &Path) -> Result<Self, XDeltaError> {
+        let data = std::fs::read(path).map_err(|e| io_error(path, e))?;
+        Ok(Mapped { len: data.len(), data })
+    }
+}
+
+impl Deref for Mapped {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+/// Old as an open file, read where the patch asks.
+struct FileOld<'a> {
+    file: File,
+    len: u64,
+    path: &'a Path,
+}
+
+impl OldSource for FileOld<'_> {
+    fn size(&self) -> u64 {
+        self.len
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+        self.file.seek(SeekFrom::Start(offset)).map_err(|e| io_error(self.path, e))?;
+        self.file.read_exact(buf).map_err(|e| io_error(self.path, e))
+    }
+}
+
+/// Create a patch turning the file at `old_path` into the one at
+/// `new_path`, as `create_patch` does, and write it to `patch_path`.
+pub fn create_patch_file(
+    old_path: &Path,
+    new_path: &Path,
+    patch_path: &Path,
+    block_size: usize,
+) -> Result<(), XDeltaError> {
+    let old = Mapped::open(old_path)?;
+    let new = Mapped::open(new_path)?;
+    let patch = create_patch(&old, &new, block_size)?;
+    std::fs::write(patch_path, patch).map_err(|e| io_error(patch_path, e))
+}
+
+/// Apply the patch at `patch_path` to the file at `old_path`, writing the
+/// output to `out_path` as it is produced.
+///
+/// On failure `out_path` may hold part of the output. Patches with
+/// COPY_TARGET records, which read the output back, are refused.
+pub fn apply_patch_file(old_path: &Path, patch_path: &Path, out_path: &Path) -> Result<(), XDeltaError> {
+    let patch = std::fs::read(patch_path).map_err(|e| io_error(patch_path, e))?;
+    let file = File::open(old_path).map_err(|e| io_error(old_path, e))?;
+    let len = file.metadata().map_err(|e| io_error(old_path, e))?.len();
+    let mut old = FileOld { file, len, path: old_path };
+    let out = File::create(out_path).map_err(|e| io_error(out_path, e))?;
+    let mut out = BufWriter::new(out);
+    apply_streaming(&mut old, &patch, &mut out, &ApplyOptions::new())?;
+    out.flush().map_err(|e| io_error(out_path, e))
+}
+
+/// Apply `patch` to the file at `old_path`, mapped read-only so COPY
+/// records read straight from the page cache, writing the output to
+/// `out_path` as it is produced.
+///
+/// Where old can't be mapped (a device or filesystem without mmap support)
+/// it is read where the patch asks instead, as `apply_patch_file` does;
+/// either way old is never read into memory whole.
+/// The mapping is released on every return. On failure `out_path` may hold
+/// part of the output; a patch with a broken header fails before it is
+/// created. Patches with COPY_TARGET records are refused.
+#[cfg(feature = "mmap")]
+pub fn apply_patch_mmap(old_path: &Path, patch: &[u8], out_path: &Path) -> Result<(), XDeltaError> {
+    crate::patch_records(patch)?;
+    let file = File::open(old_path).map_err(|e| io_error(old_path, e))?;
+    let len = file.metadata().map_err(|e| io_error(old_path, e))?.len();
+    let out = File::create(out_path).map_err(|e| io_error(out_path, e))?;
+    let mut out = BufWriter::new(out);
+    // the caller's promise that old doesn't change under the mapping, see
+    // the module docs
+    match unsafe { memmap2::Mmap::map(&file) }.ok() {
+        Some(old) => apply_streaming(&mut &old[..], patch, &mut out, &ApplyOptions::new())?,
+        None => apply_streaming(&mut FileOld { file, len, path: old_path }, patch, &mut out, &ApplyOptions::new())?,
+    }
+    out.flush().map_err(|e| io_error(out_path, e))
+}
+
+/// The path in the NUL-terminated C string `path`.
+fn c_path<'a>(path: *const c_char) -> Result<&'a Path, XDeltaError> {
+    if path.is_null() {
+        return Err(XDeltaError::NullPointer);
+    }
+    let bytes = unsafe { CStr::from_ptr(path) }.to_bytes();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(Path::new(std::ffi::OsStr::from_bytes(bytes)))
+    }
+    #[cfg(not(unix))]
+    {
+        std::str::from_utf8(bytes).map(Path::new).map_err(|_| XDeltaError::InvalidArg("path is not UTF-8".into()))
+    }
+}
+
+/// 按路径创建补丁：旧文件与新文件以内存映射读取（非 unix 平台整体读入），补丁写入 patch_path
+/// 路径为以 NUL 结尾的字符串；文件读写失败返回 XDELTA_ERR_IO，xdelta_last_error 给出路径和系统错误信息
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_file(
+    old_path: *const c_char,
+    new_path: *const c_char,
+    patch_path: *const c_char,
+    block_size: u32,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        create_patch_file(c_path(old_path)?, c_path(new_path)?, c_path(patch_path)?, block_size as usize)
+    })();
+
+    ffi_status(r)
+}
+
+/// 按路径应用补丁：旧文件按需读取，输出边生成边写入 out_path，不在内存中保留整个输出
+/// 失败时 out_path 可能只有部分输出；含 COPY_TARGET 记录的补丁会被拒绝
+/// 文件读写失败返回 XDELTA_ERR_IO，xdelta_last_error 给出路径和系统错误信息
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_file(
+    old_path: *const c_char,
+    patch_path: *const c_char,
+    out_path: *const c_char,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        apply_patch_file(c_path(old_path)?, c_path(patch_path)?, c_path(out_path)?)
+    })();
+
+    ffi_status(r)
+}
+
+/// 按路径应用内存中的补丁：旧文件以只读内存映射读取（COPY 记录直接读映射，不整体读入内存），输出边生成边写入 out_path
+/// 旧文件无法映射时（不支持映射的设备或文件系统）改为按需读取；映射在所有返回路径上释放
+/// 补丁头无效时不创建 out_path；其他失败时 out_path 可能只有部分输出；含 COPY_TARGET 记录的补丁会被拒绝
+/// 文件读写失败返回 XDELTA_ERR_IO，xdelta_last_error 给出路径和系统错误信息
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "mmap")]
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_mmap(
+    old_path: *const c_char,
+    patch_data: *const u8,
+    patch_len: usize,
+    out_path: *const c_char,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let patch_bytes = unsafe { crate::ffi_slice(patch_data, patch_len)? };
+
+        apply_patch_mmap(c_path(old_path)?, patch_bytes, c_path(out_path)?)
+    })();
+
+    ffi_status(r)
+}
@@ -0,0 +1,107 @@
+// src/fuzzy.rs
+//! SimHash index of old's blocks, for finding near-miss DIFF candidates that
+//! are not on the current diagonal.
+//!
+//! A block's SimHash is built from hashes of its 4-byte shingles, so a few
+//! changed bytes flip only a few of its 64 bits. The hash is split into bands
+//! and a block is a candidate when any band matches exactly and the whole
+//! hash is close; candidates are then verified byte by byte like any other
+//! near miss.
+
+use crate::OldBytes;
+use std::collections::HashMap;
+
+const BANDS: u32 = 8;
+const BAND_BITS: u32 = 64 / BANDS;
+/// Blocks looked at per band, so that a band shared by many blocks (runs of
+/// zeros, say) cannot turn a lookup into a scan.
+const MAX_SCAN: usize = 256;
+/// Largest Hamming distance between SimHashes still worth verifying.
+const MAX_DISTANCE: u32 = 20;
+const SHINGLE: usize = 4;
+
+pub(crate) struct FuzzyIndex {
+    block_size: usize,
+    /// SimHash of each full block of old.
+    hashes: Vec<u64>,
+    /// Blocks of old (by index) keyed by band number and that band's bits.
+    bands: HashMap<(u32, u64), Vec<u32>>,
+}
+
+impl FuzzyIndex {
+    /// Index every full `block_size` block of `old`, except those in holes.
+    pub(crate) fn new<O: OldBytes + ?Sized>(old: &O, block_size: usize) -> Self {
+        let blocks = usize::min(old.len() / block_size, u32::MAX as usize);
+        let mut hashes = Vec::with_capacity(blocks);
+        let mut bands: HashMap<(u32, u64), Vec<u32>> = HashMap::new();
+        // blocks past u32::MAX are not indexed; they can still match exactly
+        for idx in 0..blocks {
+            let range = idx * block_size..(idx + 1) * block_size;
+            let hash = if old.is_hole(range.clone()) {
+                None
+            } else {
+                simhash(&old.bytes(range))
+            };
+            hashes.push(hash.unwrap_or(0));
+            if let Some(hash) = hash {
+                for band in 0..BANDS {
+                    bands.entry((band, band_bits(hash, band))).or_default().push(idx as u32);
+                }
+            }
+        }
+        FuzzyIndex {
+            block_size,
+            hashes,
+            bands,
+        }
+    }
+
+    /// Offsets in old of blocks that may be similar to `window`.
+    pub(crate) fn candidates<'a>(&'a self, window: &[u8]) -> impl Iterator<Item = usize> + 'a {
+        let hash = simhash(window);
+        (0..BANDS)
+            .filter_map(move |band| Some((hash?, self.bands.get(&(band, band_bits(hash?, band)))?)))
+            .flat_map(move |(hash, blocks)| {
+                blocks
+                    .iter()
+                    .take(MAX_SCAN)
+                    .filter(move |&&idx| (self.hashes[idx as usize] ^ hash).count_ones() <= MAX_DISTANCE)
+            })
+            .map(|&idx| idx as usize * self.block_size)
+    }
+}
+
+fn band_bits(hash: u64, band: u32) -> u64 {
+    (hash >> (band * BAND_BITS)) & ((1 << BAND_BITS) - 1)
+}
+
+/// SimHash of `block`, or `None` if it is too short to have any shingles.
+///
+/// Large blocks only use the shingles whose hash has its low 3 bits clear;
+/// the choice depends on content alone, so similar blocks sample alike.
+fn simhash(block: &[u8]) -> Option<u64> {
+    let sample_mask = if block.len() >= 512 { 7 } else { 0 };
+    let mut counts = [0i32; 64];
+    let mut used = 0;
+    for shingle in block.windows(SHINGLE) {
+        let h = mix(u32::from_le_bytes([shingle[0], shingle[1], shingle[2], shingle[3]]) as u64);
+        if h & sample_mask != 0 {
+            continue;
+        }
+        used += 1;
+        for (bit, count) in counts.iter_mut().enumerate() {
+            *count += if h >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    if used == 0 {
+        return None;
+    }
+    Some(counts.iter().enumerate().fold(0u64, |acc, (bit, &c)| acc | ((c > 0) as u64) << bit))
+}
+
+/// splitmix64 finalizer, spreading a shingle over all 64 bits.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,102 @@
+// src/gzip.rs
+//! Whole patches in a gzip container (RFC 1952), for distribution channels
+//! that want one compressed artifact (only with the `gzip` feature).
+//!
+//! The container is plain gzip, so `gzip -d` unpacks it; its magic, 1f 8b,
+//! is what `apply_patch` tells it from a bare patch by. DEFLATE is flate2's:
+//! writing uses its default level, reading takes any number of members and
+//! checks the CRC-32 and length of each.
+
+use crate::{ffi_slice, write_output, XDeltaError};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use std::os::raw::c_int;
+
+/// ID1 and ID2 opening every gzip member.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Whether `blob` starts like a gzip member rather than a bare patch.
+pub(crate) fn is_gzip(blob: &[u8]) -> bool {
+    blob.starts_with(&GZIP_MAGIC)
+}
+
+/// `patch` in a gzip container: one member with no name or timestamp, so the
+/// same patch always gives the same bytes.
+///
+/// ```
+/// let patch = xdelta::create_patch(b"", &b"one line after another\n".repeat(100), 16).unwrap();
+/// let blob = xdelta::compress_patch(&patch);
+/// assert!(blob.len() < patch.len() / 4);
+/// assert_eq!(xdelta::decompress_patch(&blob).unwrap(), patch);
+/// ```
+pub fn compress_patch(patch: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(patch.len() / 2 + 32), Compression::default());
+    // writing into a Vec never fails
+    encoder.write_all(patch).expect("gzip into memory");
+    encoder.finish().expect("gzip into memory")
+}
+
+/// The patch in the gzip container `blob`, the members' contents one after
+/// another. Anything but gzip members, a corrupt stream or a CRC-32 or
+/// length that doesn't match what it inflates to is
+/// `XDeltaError::MalformedPatch`.
+pub fn decompress_patch(blob: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    if !is_gzip(blob) {
+        return Err(XDeltaError::MalformedPatch("gzip container: not a gzip member".into()));
+    }
+    let mut out = Vec::new();
+    MultiGzDecoder::new(blob)
+        .read_to_end(&mut out)
+        .map_err(|e| XDeltaError::MalformedPatch(format!("gzip container: {}", e)))?;
+    Ok(out)
+}
+
+/// 将整个补丁封装为 gzip 容器（需启用 gzip 特性），输出可用 gzip -d 解开，
+/// 也可直接交给 xdelta_apply_patch_data 等应用函数（按 gzip 魔数自动识别并解压）
+/// 输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_compress_patch(
+    patch_data: *const u8,
+    patch_len: usize,
+    blob_data: *mut *mut u8,
+    blob_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if blob_data.is_null() || blob_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        Ok(compress_patch(patch_bytes))
+    })();
+
+    write_output(r, blob_data, blob_len)
+}
+
+/// 解开 gzip 容器，取出其中的补丁（需启用 gzip 特性）；接受任意 gzip 数据（含多个成员），
+/// 校验每个成员的 CRC-32 与长度，不符时返回 XDELTA_ERR_MALFORMED_PATCH
+/// 输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_decompress_patch(
+    blob_data: *const u8,
+    blob_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let blob_bytes = unsafe { ffi_slice(blob_data, blob_len)? };
+
+        decompress_patch(blob_bytes)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
@@ -0,0 +1,318 @@
+// src/histogram.rs
+//! Per-opcode record counts of a patch, for analyzing patch corpora: which
+//! records dominate, and whether there are many tiny ones. Also a summary of
+//! a single patch, for auditing an update before it is deployed, and of a
+//! patch as it is created, for deciding whether to ship it at all.
+
+use crate::compat::{opcode_info, XDELTA_OPCODE_KINDS};
+use crate::{
+    create_patch_reporting, ffi_slice, ffi_status, header_params, is_identity, patch_records, read_record, write_output,
+    write_sized, PATCH_FLAG_SAMPLED, PatchOptions, Record, RecordWalker, XDeltaError,
+};
+use std::os::raw::c_int;
+
+/// The records of one opcode in a patch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpcodeStat {
+    pub opcode: u8,
+    /// Opcode name, or "unknown" for an unrecognized skippable opcode.
+    pub name: &'static str,
+    pub count: u64,
+    /// Bytes of patch the records take, headers included.
+    pub patch_bytes: u64,
+    /// Bytes of output they produce.
+    pub output_bytes: u64,
+}
+
+/// Tally the records of `patch` by opcode: one entry per opcode that occurs,
+/// in opcode order. Nothing is read from old.
+pub fn opcode_histogram(patch: &[u8]) -> Result<Vec<OpcodeStat>, XDeltaError> {
+    let patch = patch_records(patch)?;
+    let mut stats: Vec<OpcodeStat> = Vec::new();
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let opcode = patch[pos];
+        let (record, next) = read_record(patch, pos)?;
+        let at = match stats.binary_search_by_key(&opcode, |s| s.opcode) {
+            Ok(at) => at,
+            Err(at) => {
+                let name = opcode_info(opcode).map_or("unknown", |(name, _, _)| name);
+                stats.insert(at, OpcodeStat { opcode, name, ..OpcodeStat::default() });
+                at
+            }
+        };
+        stats[at].count += 1;
+        stats[at].patch_bytes += (next - pos) as u64;
+        stats[at].output_bytes += record.output_len();
+        pos = next;
+    }
+    Ok(stats)
+}
+
+/// C layout of one `OpcodeStat`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct XdeltaOpcodeStat {
+    pub count: u64,
+    pub patch_bytes: u64,
+    pub output_bytes: u64,
+}
+
+/// 按操作码统计补丁中的记录：记录数、占用的补丁字节数（含记录头）及产生的输出字节数
+/// stats 为 stat_count 个元素的数组，stats[i] 对应 XDELTA_OPCODE_* 中的 (1ull << i)，未出现的操作码为 0；
+/// 超出 stat_count 的项及未知的可跳过操作码不报告。成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_opcode_histogram(
+    patch_data: *const u8,
+    patch_len: usize,
+    stats: *mut XdeltaOpcodeStat,
+    stat_count: usize,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if stats.is_null() && stat_count > 0 {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        let mut table = [XdeltaOpcodeStat::default(); XDELTA_OPCODE_KINDS];
+        for s in opcode_histogram(patch_bytes)? {
+            if let Some((_, bit, _)) = opcode_info(s.opcode) {
+                table[bit.trailing_zeros() as usize] =
+                    XdeltaOpcodeStat { count: s.count, patch_bytes: s.patch_bytes, output_bytes: s.output_bytes };
+            }
+        }
+        for (i, entry) in table.iter().take(stat_count).enumerate() {
+            unsafe { stats.add(i).write(*entry) };
+        }
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
+
+/// What a patch does, as far as can be told without old.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PatchInfo {
+    /// Patch header version.
+    pub version: u8,
+    /// Identity patch: new is old, whose length the patch does not record.
+    pub identity: bool,
+    /// ADD, ADD_SMALL and ADD_ZSTD records, and the literal bytes they carry (inflated).
+    pub num_add: u64,
+    pub literal_bytes: u64,
+    /// COPY, DIFF and XOR_DELTA records, and the bytes they take from old.
+    pub num_copy: u64,
+    pub copied_bytes: u64,
+    /// Length of new. RUN, COPY_CONST, COPY_HASH and COPY_TARGET records count
+    /// here but in neither of the above.
+    pub output_len: u64,
+    /// Block size the patch was made with, 0 if its header doesn't record
+    /// it, as identity patches and those made before format 9 don't.
+    pub block_size: u32,
+    /// `XDELTA_WEAK_*` and `XDELTA_HASH_*` ids of the weak checksum and
+    /// strong hash it was made with, if `block_size` isn't 0.
+    pub weak_checksum: u32,
+    pub strong_hash: u32,
+    /// Made from signatures of only some blocks of old, see
+    /// `PatchOptions::sample_blocks`, so compression may be a little worse.
+    pub sampled: bool,
+}
+
+/// Summarize `patch`, checking it as `validate_patch` does. Nothing is read
+/// from old, so COPY ranges are not checked against it.
+pub fn patch_info(patch: &[u8]) -> Result<PatchInfo, XDeltaError> {
+    let mut records = RecordWalker::for_patch(patch, false)?;
+    let mut info = PatchInfo {
+        version: patch[4],
+        identity: is_identity(patch),
+        sampled: patch[5] & PATCH_FLAG_SAMPLED != 0,
+        ..PatchInfo::default()
+    };
+    if let Some((block_size, weak, strong)) = header_params(patch) {
+        (info.block_size, info.weak_checksum, info.strong_hash) = (block_size, weak as u32, strong as u32);
+    }
+    while let Some(record) = records.next_record()? {
+        match record {
+            Record::Add(_) | Record::AddZstd { .. } => {
+                info.num_add += 1;
+                info.literal_bytes += record.output_len();
+            }
+            Record::Copy { len, .. } | Record::Diff { len, .. } | Record::Xor { len, .. } => {
+                info.num_copy += 1;
+                info.copied_bytes += len as u64;
+            }
+            _ => {}
+        }
+        info.output_len += record.output_len();
+    }
+    Ok(info)
+}
+
+/// C layout of `PatchInfo`; the caller sets `size` before the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XdeltaPatchInfo {
+    pub size: u32,
+    pub version: u8,
+    pub identity: u8,
+    pub num_add: u64,
+    pub num_copy: u64,
+    pub literal_bytes: u64,
+    pub copied_bytes: u64,
+    pub output_len: u64,
+    pub block_size: u32,
+    pub weak_checksum: u32,
+    pub strong_hash: u32,
+    pub sampled: u8,
+}
+
+/// 不应用补丁、不需要旧数据，统计补丁内容：ADD（含 ADD_SMALL）记录数及字面字节数、COPY/DIFF/XOR_DELTA 记录数及
+/// 从旧数据复制的字节数、输出长度，以及补丁头记录的块大小、弱校验和（XDELTA_WEAK_*）与强哈希（XDELTA_HASH_*）；
+/// 补丁的校验同 xdelta_validate_patch
+/// sampled 为 1 时补丁由只索引部分旧数据块的签名生成（见 XdeltaOptions::sample_blocks），压缩率可能略差
+/// identity 为 1 时输出即旧数据，output_len 为 0
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_patch_info(patch_data: *const u8, patch_len: usize, info: *mut XdeltaPatchInfo) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if info.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        let i = patch_info(patch_bytes)?;
+        let value = XdeltaPatchInfo {
+            size: 0,
+            version: i.version,
+            identity: i.identity as u8,
+            num_add: i.num_add,
+            num_copy: i.num_copy,
+            literal_bytes: i.literal_bytes,
+            copied_bytes: i.copied_bytes,
+            output_len: i.output_len,
+            block_size: i.block_size,
+            weak_checksum: i.weak_checksum,
+            strong_hash: i.strong_hash,
+            sampled: i.sampled as u8,
+        };
+        // callers built before the parameters were appended still get the rest
+        write_sized(info, value, std::mem::offset_of!(XdeltaPatchInfo, block_size))
+    })();
+
+    ffi_status(r)
+}
+
+/// How much of new a patch takes from old, reported by
+/// `create_patch_with_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PatchStats {
+    /// Bytes of new copied from old (COPY, DIFF and XOR_DELTA records, or
+    /// all of new for an identity patch).
+    pub matched_bytes: u64,
+    /// Bytes of new sent literally in ADD records.
+    pub literal_bytes: u64,
+    /// Length of the patch.
+    pub patch_size: u64,
+    /// `matched_bytes` as a percentage of new's length, 100 for an empty new.
+    pub similarity: f64,
+    /// Whether matching outgrew `PatchOptions::max_ratio`, so the patch is
+    /// all of new as a literal.
+    pub fell_back: bool,
+}
+
+/// Create a patch from `old` to `new` at `block_size` as `create_patch`
+/// does, along with how similar the two turned out to be, so a pipeline can
+/// ship a full download instead when too little of new is in old.
+///
+/// ```
+/// let old = b"the quick brown fox jumps over the lazy dog".repeat(10);
+/// let (patch, stats) = xdelta::create_patch_with_stats(&old, &old, 16).unwrap();
+/// assert_eq!((stats.matched_bytes, stats.similarity), (old.len() as u64, 100.0));
+/// assert_eq!(stats.patch_size, patch.len() as u64);
+/// ```
+pub fn create_patch_with_stats(
+    old: &[u8],
+    new: &[u8],
+    block_size: usize,
+) -> Result<(Vec<u8>, PatchStats), XDeltaError> {
+    create_patch_stats_with(old, new, &PatchOptions::new().block_size(block_size))
+}
+
+/// `create_patch_with_stats` with `opts`, as `create_patch_with` makes it.
+///
+/// ```
+/// let old = b"the quick brown fox jumps over the lazy dog".repeat(10);
+/// let new: Vec<u8> = (0..400u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+/// let opts = xdelta::PatchOptions::new().block_size(16).max_ratio(1.0);
+/// let (patch, stats) = xdelta::create_patch_stats_with(&old, &new, &opts).unwrap();
+/// assert!(stats.fell_back && stats.literal_bytes == new.len() as u64);
+/// assert_eq!(xdelta::apply_patch(&old, &patch).unwrap(), new);
+/// ```
+pub fn create_patch_stats_with(
+    old: &[u8],
+    new: &[u8],
+    opts: &PatchOptions,
+) -> Result<(Vec<u8>, PatchStats), XDeltaError> {
+    let (patch, fell_back) = create_patch_reporting(old, new, opts)?;
+    let info = patch_info(&patch)?;
+    let matched_bytes = if info.identity { new.len() as u64 } else { info.copied_bytes };
+    let similarity = if new.is_empty() { 100.0 } else { matched_bytes as f64 * 100.0 / new.len() as f64 };
+    let stats = PatchStats {
+        matched_bytes,
+        literal_bytes: info.literal_bytes,
+        patch_size: patch.len() as u64,
+        similarity,
+        fell_back,
+    };
+    Ok((patch, stats))
+}
+
+/// C layout of `PatchStats`; the caller sets `size` before the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XdeltaStats {
+    pub size: u32,
+    pub matched_bytes: u64,
+    pub literal_bytes: u64,
+    pub patch_size: u64,
+    pub similarity: f64,
+}
+
+/// 创建补丁数据，并把相似度统计写入 *stats：从旧数据复制的字节数、字面字节数、补丁长度，
+/// 以及复制字节占新数据的百分比（similarity，0~100），供构建流程判断分发补丁还是完整文件
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_stats(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    block_size: u32,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    stats: *mut XdeltaStats,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() || stats.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        let (patch, s) = create_patch_with_stats(old_bytes, new_bytes, block_size as usize)?;
+        let value = XdeltaStats {
+            size: 0,
+            matched_bytes: s.matched_bytes,
+            literal_bytes: s.literal_bytes,
+            patch_size: s.patch_size,
+            similarity: s.similarity,
+        };
+        write_sized(stats, value, std::mem::size_of::<XdeltaStats>())?;
+        Ok(patch)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
@@ -1,57 +1,361 @@
 // src/lib.rs
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::c_int;
+use core::fmt;
+use core::ops::Range;
 use sha2::{Digest, Sha256};
+#[cfg(feature = "std")]
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "std")]
 use std::collections::HashMap;
-use std::ffi::CString;
-use std::os::raw::{c_char, c_int};
-use thiserror::Error;
-use std::cell::RefCell;
+#[cfg(feature = "std")]
+use std::ffi::{c_char, CString};
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+#[cfg(feature = "zstd")]
+mod add_zstd;
+#[cfg(feature = "std")]
+mod allocator;
+#[cfg(feature = "std")]
+mod batch;
+#[cfg(feature = "std")]
+mod cancel;
+#[cfg(feature = "std")]
+mod cas;
+#[cfg(feature = "std")]
+mod chain;
+#[cfg(feature = "std")]
+mod changed;
+mod compat;
+mod const_table;
+mod crc32;
+#[cfg(feature = "std")]
+mod context;
+#[cfg(feature = "std")]
+mod dictionary;
+#[cfg(feature = "std")]
+mod dump;
+#[cfg(feature = "std")]
+mod estimate;
+#[cfg(feature = "std")]
+mod feed;
+#[cfg(feature = "std")]
+mod file;
+#[cfg(feature = "std")]
+mod fuzzy;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "std")]
+mod histogram;
+#[cfg(feature = "std")]
+mod output;
+#[cfg(feature = "std")]
+mod overlap;
+#[cfg(feature = "std")]
+mod pack;
+#[cfg(feature = "std")]
+mod result;
+#[cfg(feature = "std")]
+mod reverse;
+#[cfg(feature = "std")]
+mod self_test;
+#[cfg(feature = "std")]
+mod signature;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(feature = "std")]
+mod sparse;
+#[cfg(feature = "std")]
+mod split;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "std")]
+mod stream_create;
+#[cfg(all(test, feature = "std"))]
+mod tests;
+#[cfg(feature = "match-trace")]
+mod trace;
+#[cfg(feature = "vcdiff")]
+mod vcdiff;
+mod xor_delta;
+
+#[cfg(feature = "std")]
+pub use batch::{batch_create, batch_patch};
+#[cfg(feature = "std")]
+pub use cancel::CancelToken;
+#[cfg(feature = "std")]
+pub use cas::apply_cas;
+#[cfg(feature = "std")]
+pub use chain::apply_then_diff;
+#[cfg(feature = "std")]
+pub use changed::changed_blocks;
+pub use compat::{
+    patch_uses_only, XDELTA_FORMAT_VERSION, XDELTA_OPCODES_BASELINE, XDELTA_OPCODE_ADD, XDELTA_OPCODE_ADD_SMALL,
+    XDELTA_OPCODE_ADD_ZSTD,
+    XDELTA_OPCODE_BLOCK_SIZE, XDELTA_OPCODE_CHECK, XDELTA_OPCODE_CONST_TABLE, XDELTA_OPCODE_COPY,
+    XDELTA_OPCODE_COPY_CONST, XDELTA_OPCODE_COPY_HASH, XDELTA_OPCODE_COPY_TARGET, XDELTA_OPCODE_DIFF,
+    XDELTA_OPCODE_INDEX, XDELTA_OPCODE_KINDS, XDELTA_OPCODE_MIN_VERSION, XDELTA_OPCODE_NOP, XDELTA_OPCODE_OLD_HASH,
+    XDELTA_OPCODE_OUTPUT_OFFSET, XDELTA_OPCODE_PADDING, XDELTA_OPCODE_RUN, XDELTA_OPCODE_XOR_DELTA,
+};
+#[cfg(feature = "std")]
+pub use context::{ApplyContext, DiffContext};
+#[cfg(feature = "std")]
+pub use dictionary::{apply_patch_with_dictionary, create_patch_with_dictionary};
+#[cfg(feature = "std")]
+pub use dump::dump_patch;
+#[cfg(feature = "std")]
+pub use estimate::{estimate_patch_size, should_patch};
+#[cfg(feature = "std")]
+pub use feed::ApplyFeed;
+#[cfg(feature = "std")]
+pub use file::{apply_patch_file, create_patch_file};
+#[cfg(feature = "mmap")]
+pub use file::apply_patch_mmap;
+#[cfg(feature = "gzip")]
+pub use gzip::{compress_patch, decompress_patch};
+#[cfg(feature = "std")]
+pub use histogram::{
+    create_patch_stats_with, create_patch_with_stats, opcode_histogram, patch_info, OpcodeStat, PatchInfo, PatchStats,
+};
+#[cfg(feature = "std")]
+pub use output::{apply_to, ApplyOutput};
+#[cfg(feature = "std")]
+pub use overlap::{copy_overlap, old_ranges_merged, OverlapStats};
+#[cfg(feature = "std")]
+pub use pack::{build_pack, pack_diff, pack_revision};
+#[cfg(feature = "std")]
+pub use reverse::create_patch_bidirectional;
+#[cfg(feature = "std")]
+pub use signature::{
+    apply_with_signature, build_signature_bytes, create_patch_from_signature, Signature, SignatureStats,
+};
+#[cfg(feature = "std")]
+pub use sparse::{apply_sparse, create_patch_sparse, SparseOld, XdeltaExtent};
+#[cfg(feature = "std")]
+pub use split::{split_patch, sub_patch_offset};
+#[cfg(feature = "std")]
+pub use stream::{apply_compare, apply_streaming, ApplyOptions, CompareResult, OldSource, VerifyOld};
+#[cfg(feature = "std")]
+pub use stream_create::create_patch_streaming;
+#[cfg(feature = "match-trace")]
+pub use trace::{match_trace, patch_trace};
+#[cfg(feature = "vcdiff")]
+pub use vcdiff::{apply_patch_vcdiff, create_patch_vcdiff};
 
+#[cfg(feature = "std")]
 thread_local! {
-    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+    /// Code and message of the last failure on this thread, set together.
+    static LAST_ERROR: RefCell<Option<(c_int, CString)>> = const { RefCell::new(None) };
 }
 
-fn set_last_error(err: &str) {
+#[cfg(feature = "std")]
+fn set_last_error(err: &XDeltaError) {
+    let msg = CString::new(err.to_string()).unwrap_or_else(|_| CString::new("internal error").unwrap());
     LAST_ERROR.with(|cell| {
-        *cell.borrow_mut() = Some(CString::new(err).unwrap_or_else(|_| CString::new("internal error").unwrap()));
+        *cell.borrow_mut() = Some((err.code(), msg));
     });
 }
 
+/// 返回本线程最近一次失败的错误信息（以 NUL 结尾），尚无失败或已清除时返回 NULL
+/// 指针归库所有，只在本线程下一次可能失败的调用（或 xdelta_clear_last_error）之前有效，需要保留时请复制
+#[cfg(feature = "std")]
 #[unsafe(no_mangle)]
 pub extern "C" fn xdelta_last_error() -> *const c_char {
     LAST_ERROR.with(|cell| {
         cell.borrow()
             .as_ref()
-            .map(|s| s.as_ptr())
+            .map(|(_, s)| s.as_ptr())
             .unwrap_or(std::ptr::null())
     })
 }
 
-#[derive(Error, Debug)]
-enum XDeltaError {
-    #[error("invalid argument: {0}")]
+/// 返回 xdelta_last_error 所指错误信息的字节数（不含结尾的 NUL），没有错误信息时返回 0
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_last_error_len() -> usize {
+    LAST_ERROR.with(|cell| cell.borrow().as_ref().map_or(0, |(_, s)| s.as_bytes().len()))
+}
+
+/// 清除本线程的错误信息，之后 xdelta_last_error 返回 NULL，直到下一次失败；
+/// 此前取得的错误信息指针随之失效
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_clear_last_error() {
+    LAST_ERROR.with(|cell| {
+        *cell.borrow_mut() = None;
+    });
+}
+
+/// 返回本线程最近一次失败的错误信息，并把对应的错误码（XDELTA_ERR_*）写入 *code_out
+/// 两者来自同一次失败；尚无失败时返回 NULL 并写入 XDELTA_OK；code_out 可为 NULL
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_last_error_detail(code_out: *mut c_int) -> *const c_char {
+    LAST_ERROR.with(|cell| {
+        let last = cell.borrow();
+        let (code, msg) = match last.as_ref() {
+            Some((code, s)) => (*code, s.as_ptr()),
+            None => (XDELTA_OK, std::ptr::null()),
+        };
+        if !code_out.is_null() {
+            unsafe { *code_out = code };
+        }
+        msg
+    })
+}
+
+/// Error of every fallible function; the C functions return the code from
+/// `code` and report the message, its `Display`, through `xdelta_last_error`.
+/// Without the `std` feature it is all a caller gets: there is no
+/// thread-local last error to read back.
+#[derive(Debug)]
+pub enum XDeltaError {
     InvalidArg(String),
+    /// A required pointer argument of a C function was null.
+    NullPointer,
+    /// The patch can't be parsed: a bad header, a truncated record, or a
+    /// record whose fields contradict each other. A patch that may just be
+    /// newer than this library (a higher version, an unknown opcode) is
+    /// `InvalidArg` instead.
+    MalformedPatch(String),
+    /// A record reads old past its end.
+    OldOutOfRange(String),
+    Io(String),
+    OldHashMismatch(String),
+    Cancelled,
+    OutOfMemory,
+    OldReadBudgetExceeded(u64),
+    BufferTooSmall { needed: u64, cap: u64 },
+    /// The output doesn't hash to the patch's CHECK record: the patch was
+    /// corrupted in a way that still parses, or old isn't the base it was
+    /// made against.
+    ChecksumMismatch(String),
+    /// The patch would produce more output than the given limit, see
+    /// `apply_patch_limited`.
+    OutputLimitExceeded(u64),
+    /// The output doesn't hash to the SHA-256 the caller expects of new,
+    /// see `apply_patch_verify`.
+    NewHashMismatch,
+}
+
+impl fmt::Display for XDeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XDeltaError::InvalidArg(msg) => write!(f, "invalid argument: {}", msg),
+            XDeltaError::NullPointer => f.write_str("null pointer"),
+            XDeltaError::MalformedPatch(msg) => write!(f, "malformed patch: {}", msg),
+            XDeltaError::OldOutOfRange(msg) => write!(f, "old range out of bounds: {}", msg),
+            XDeltaError::Io(msg) => write!(f, "I/O error: {}", msg),
+            XDeltaError::OldHashMismatch(msg) => write!(f, "old data does not match the patch: {}", msg),
+            XDeltaError::Cancelled => f.write_str("operation cancelled"),
+            XDeltaError::OutOfMemory => f.write_str("failed to allocate memory"),
+            XDeltaError::OldReadBudgetExceeded(budget) => {
+                write!(f, "patch reads more than the {}-byte budget from old", budget)
+            }
+            XDeltaError::BufferTooSmall { needed, cap } => {
+                write!(f, "output is {} bytes, more than the buffer's {}", needed, cap)
+            }
+            XDeltaError::ChecksumMismatch(msg) => write!(f, "output checksum mismatch: {}", msg),
+            XDeltaError::OutputLimitExceeded(limit) => write!(f, "patch output is over the {}-byte limit", limit),
+            XDeltaError::NewHashMismatch => f.write_str("output does not match the expected SHA-256 of new"),
+        }
+    }
+}
+
+impl core::error::Error for XDeltaError {}
+
+/// Status codes returned by the C functions on failure (and reported by the
+/// result-handle API and `xdelta_last_error_detail`), one per `XDeltaError`
+/// variant. Published codes never change meaning; new ones are appended.
+pub const XDELTA_OK: c_int = 0;
+pub const XDELTA_ERR_INVALID_ARG: c_int = -1;
+pub const XDELTA_ERR_IO: c_int = -2;
+pub const XDELTA_ERR_OLD_HASH_MISMATCH: c_int = -3;
+pub const XDELTA_ERR_CANCELLED: c_int = -4;
+pub const XDELTA_ERR_NO_MEMORY: c_int = -5;
+pub const XDELTA_ERR_OLD_READ_BUDGET: c_int = -6;
+pub const XDELTA_ERR_BUFFER_TOO_SMALL: c_int = -7;
+pub const XDELTA_ERR_NULL_POINTER: c_int = -8;
+pub const XDELTA_ERR_MALFORMED_PATCH: c_int = -9;
+pub const XDELTA_ERR_OLD_OUT_OF_RANGE: c_int = -10;
+pub const XDELTA_ERR_CHECKSUM_MISMATCH: c_int = -11;
+pub const XDELTA_ERR_OUTPUT_LIMIT: c_int = -12;
+pub const XDELTA_ERR_NEW_HASH_MISMATCH: c_int = -13;
+
+impl XDeltaError {
+    /// The `XDELTA_ERR_*` code for this error.
+    pub fn code(&self) -> c_int {
+        match self {
+            XDeltaError::InvalidArg(_) => XDELTA_ERR_INVALID_ARG,
+            XDeltaError::NullPointer => XDELTA_ERR_NULL_POINTER,
+            XDeltaError::MalformedPatch(_) => XDELTA_ERR_MALFORMED_PATCH,
+            XDeltaError::OldOutOfRange(_) => XDELTA_ERR_OLD_OUT_OF_RANGE,
+            XDeltaError::Io(_) => XDELTA_ERR_IO,
+            XDeltaError::OldHashMismatch(_) => XDELTA_ERR_OLD_HASH_MISMATCH,
+            XDeltaError::Cancelled => XDELTA_ERR_CANCELLED,
+            XDeltaError::OutOfMemory => XDELTA_ERR_NO_MEMORY,
+            XDeltaError::OldReadBudgetExceeded(_) => XDELTA_ERR_OLD_READ_BUDGET,
+            XDeltaError::BufferTooSmall { .. } => XDELTA_ERR_BUFFER_TOO_SMALL,
+            XDeltaError::ChecksumMismatch(_) => XDELTA_ERR_CHECKSUM_MISMATCH,
+            XDeltaError::OutputLimitExceeded(_) => XDELTA_ERR_OUTPUT_LIMIT,
+            XDeltaError::NewHashMismatch => XDELTA_ERR_NEW_HASH_MISMATCH,
+        }
+    }
+}
+
+/// A weak checksum of a window that can slide along the data a byte at a
+/// time, see `WeakAlgo`.
+#[cfg(feature = "std")]
+pub(crate) trait WeakChecksum: Copy {
+    fn from_slice(buf: &[u8]) -> Self;
+
+    /// Slide the window one byte: `prev` leaves at the front, `next` joins
+    /// at the back. The result equals `from_slice` of the shifted window.
+    fn roll(&mut self, prev: u8, next: u8);
+
+    fn chksum(&self) -> u32;
+}
+
+/// The `(a, b)` of `Rolling::from_slice`, a byte at a time; the `simd`
+/// feature replaces it with `simd::rolling_sums`, which must agree.
+#[cfg(all(feature = "std", any(test, not(feature = "simd"))))]
+fn rolling_sums(buf: &[u8]) -> (u32, u32) {
+    let mut a: u32 = 0;
+    let mut b: u32 = 0;
+    for (i, &v) in buf.iter().enumerate() {
+        a = a.wrapping_add(v as u32);
+        b = b.wrapping_add(((buf.len() - i) as u32).wrapping_mul(v as u32));
+    }
+    (a, b)
 }
 
 /// A simple rsync-style rolling checksum (a,b) described in rsync tech report.
-/// Weak checksum is (b << 16) | a (u32).
+/// Weak checksum is (b << 16) | a (u32), both halves taken mod 65536.
+#[cfg(feature = "std")]
 #[derive(Clone, Copy, Debug)]
-struct Rolling {
+pub(crate) struct Rolling {
     a: u32,
     b: u32,
     len: usize,
 }
-impl Rolling {
-    fn new() -> Self {
-        Rolling { a: 0, b: 0, len: 0 }
-    }
 
+#[cfg(feature = "std")]
+impl WeakChecksum for Rolling {
     fn from_slice(buf: &[u8]) -> Self {
-        let mut a: u32 = 0;
-        let mut b: u32 = 0;
-        for (i, &v) in buf.iter().enumerate() {
-            a = a.wrapping_add(v as u32);
-            b = b.wrapping_add((buf.len() - i) as u32 * v as u32);
-        }
+        #[cfg(feature = "simd")]
+        let (a, b) = simd::rolling_sums(buf);
+        #[cfg(not(feature = "simd"))]
+        let (a, b) = rolling_sums(buf);
         Rolling {
             a,
             b,
@@ -60,282 +364,4927 @@ impl Rolling {
     }
 
     /// roll window: remove `prev` byte, add `next` byte
+    ///
+    /// The result equals `from_slice` of the window shifted by one byte: each
+    /// byte that stays moves one place to the front and gains one unit of
+    /// weight, `prev` leaves with weight `len` and `next` joins with weight 1,
+    /// so `b' = b - len * prev + a'` with `a' = a - prev + next`.
     fn roll(&mut self, prev: u8, next: u8) {
         let len = self.len as u32;
         // based on rsync-style weak checksum updates
         self.a = self.a.wrapping_sub(prev as u32).wrapping_add(next as u32);
-        self.b = self.b.wrapping_sub((len) * (prev as u32)).wrapping_add(self.a);
+        self.b = self.b.wrapping_sub(len.wrapping_mul(prev as u32)).wrapping_add(self.a);
+    }
+
+    /// `a` and `b` wrap mod 2^32, which keeps them right mod 65536, so each
+    /// contributes its full low 16 bits.
+    fn chksum(&self) -> u32 {
+        ((self.b & 0xffff) << 16) | (self.a & 0xffff)
+    }
+}
+
+/// Largest prime below 2^16, the modulus of both Adler-32 sums.
+#[cfg(feature = "std")]
+const ADLER_MOD: u32 = 65521;
+
+/// Bytes summed before reducing: the most for which `b` can't overflow a
+/// u32 from sums below `ADLER_MOD`, as zlib's NMAX.
+#[cfg(feature = "std")]
+const ADLER_NMAX: usize = 5552;
+
+/// Adler-32 (RFC 1950): `a` is 1 plus the sum of the bytes and `b` the sum
+/// of the successive values of `a`, both mod 65521; the checksum is
+/// `(b << 16) | a`, the same value zlib's `adler32` gives.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+struct Adler32 {
+    a: u32,
+    b: u32,
+    len: usize,
+}
+
+#[cfg(feature = "std")]
+impl WeakChecksum for Adler32 {
+    fn from_slice(buf: &[u8]) -> Self {
+        let (mut a, mut b) = (1u32, 0u32);
+        for chunk in buf.chunks(ADLER_NMAX) {
+            for &v in chunk {
+                a += v as u32;
+                b += a;
+            }
+            a %= ADLER_MOD;
+            b %= ADLER_MOD;
+        }
+        Adler32 { a, b, len: buf.len() }
+    }
+
+    /// As for `Rolling`, `prev` leaves with weight `len` and every byte
+    /// that stays gains one, but the initial 1 in `a` is not part of the
+    /// window, so `b' = b - len * prev + a' - 1`; all mod 65521.
+    fn roll(&mut self, prev: u8, next: u8) {
+        let out = (self.len as u64 % ADLER_MOD as u64 * prev as u64 % ADLER_MOD as u64) as u32;
+        self.a = (self.a + ADLER_MOD - prev as u32 + next as u32) % ADLER_MOD;
+        self.b = (self.b + ADLER_MOD - out + self.a + ADLER_MOD - 1) % ADLER_MOD;
     }
 
     fn chksum(&self) -> u32 {
-        ((self.b & 0xffff_ffff) << 16) ^ (self.a & 0xffff)
+        (self.b << 16) | self.a
     }
 }
 
 /// Block signature entry
-struct SigEntry {
-    block_index: u64,
-    strong_hash: [u8; 32], // sha256
+#[cfg(feature = "std")]
+pub(crate) struct SigEntry {
+    pub(crate) block_index: u64,
+    pub(crate) strong_hash: [u8; 32], // sha256
+    /// bytes hashed: a block, but for a short last one kept as it is
+    pub(crate) len: usize,
+}
+
+/// The view of old the matcher reads through: a plain slice, or a sparse
+/// description whose holes read as zeros (see `sparse`). Shared across
+/// threads when the `parallel` feature hashes blocks concurrently.
+#[cfg(feature = "std")]
+pub(crate) trait OldBytes: Sync {
+    fn len(&self) -> usize;
+
+    /// Bytes `range` of old, which must lie within it.
+    fn bytes(&self, range: Range<usize>) -> Cow<'_, [u8]>;
+
+    /// Whether `range` lies entirely in a hole.
+    fn is_hole(&self, _range: Range<usize>) -> bool {
+        false
+    }
+
+    /// Whether `bytes` can be called at all. An old known only by its
+    /// signature can't, and is matched by whole blocks alone.
+    fn has_bytes(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "std")]
+impl OldBytes for [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn bytes(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self[range])
+    }
+}
+
+/// Build signatures for the "old" file into `map`, which is cleared first
+///
+/// Each weak bucket lists its entries by ascending `block_index`: of blocks
+/// with the same contents the matcher confirms the lowest-numbered first, so
+/// patches come out the same whatever the build or thread count.
+///
+/// Blocks lying wholly in a hole of a sparse old are all zeros; only the first
+/// is indexed, so a huge hole costs one entry rather than one per block.
+#[cfg(feature = "std")]
+pub(crate) fn build_signatures<O: OldBytes + ?Sized>(
+    map: &mut HashMap<u32, Vec<SigEntry>>,
+    old: &O,
+    block_size: usize,
+    tail: TailPolicy,
+    algo: HashAlgo,
+    weak: WeakAlgo,
+) {
+    build_signatures_on(map, old, block_size, tail, algo, weak, 1, signing_threads(old.len()));
+}
+
+/// `build_signatures` indexing only every `every`-th block of old, the
+/// first included, see `PatchOptions::sample_blocks`.
+#[cfg(feature = "std")]
+pub(crate) fn build_sampled_signatures<O: OldBytes + ?Sized>(
+    map: &mut HashMap<u32, Vec<SigEntry>>,
+    old: &O,
+    block_size: usize,
+    tail: TailPolicy,
+    algo: HashAlgo,
+    weak: WeakAlgo,
+    every: usize,
+) {
+    build_signatures_on(map, old, block_size, tail, algo, weak, every, signing_threads(old.len()));
+}
+
+/// Olds shorter than this are hashed on the calling thread even with the
+/// `parallel` feature; spawning costs more than it saves.
+#[cfg(feature = "parallel")]
+const PARALLEL_SIGN_MIN: usize = 4 << 20;
+
+/// Runs to share the hashing of the blocks of an old of `len` bytes out in,
+/// one per thread of rayon's pool.
+#[cfg(feature = "std")]
+fn signing_threads(len: usize) -> usize {
+    #[cfg(feature = "parallel")]
+    if len >= PARALLEL_SIGN_MIN {
+        return rayon::current_num_threads();
+    }
+    let _ = len;
+    1
 }
 
-/// Build signatures for the "old" file
-fn build_signatures(old: &[u8], block_size: usize) -> HashMap<u32, Vec<SigEntry>> {
-    let mut map: HashMap<u32, Vec<SigEntry>> = HashMap::new();
+/// `build_signatures` indexing every `every`-th block, hashing in `threads`
+/// runs on rayon's pool (on the calling thread without the `parallel`
+/// feature). Which blocks are indexed
+/// is settled first, in order; the hashing is then shared out in contiguous
+/// runs and collected back in block order, so every weak bucket lists its
+/// blocks ascending (the matcher takes the first that confirms, unless a
+/// later one continues the pending COPY) and the map is the same whatever
+/// the thread count.
+#[cfg(feature = "std")]
+#[allow(clippy::too_many_arguments)]
+fn build_signatures_on<O: OldBytes + ?Sized>(
+    map: &mut HashMap<u32, Vec<SigEntry>>,
+    old: &O,
+    block_size: usize,
+    tail: TailPolicy,
+    algo: HashAlgo,
+    weak: WeakAlgo,
+    every: usize,
+    threads: usize,
+) {
+    map.clear();
+    // an empty old has no blocks, and a block size of 0 would never get past
+    // the first one
+    if old.len() == 0 || block_size == 0 {
+        return;
+    }
+    let mut blocks = Vec::with_capacity(old.len().div_ceil(block_size));
     let mut idx: u64 = 0;
     let mut offset = 0usize;
+    let mut hole_indexed = false;
     while offset < old.len() {
         let end = usize::min(offset + block_size, old.len());
-        let slice = &old[offset..end];
-        let weak = Rolling::from_slice(slice).chksum();
-        let mut hasher = Sha256::new();
-        hasher.update(slice);
-        let strong = hasher.finalize();
-        let mut arr = [0u8; 32];
-        arr.copy_from_slice(&strong);
-        map.entry(weak).or_default().push(SigEntry {
-            block_index: idx,
-            strong_hash: arr,
-        });
+        let sampled_out = !idx.is_multiple_of(every as u64);
+        if sampled_out {
+            // left for the matcher to reach by extending a neighbour
+        } else if end - offset == block_size && old.is_hole(offset..end) {
+            if !hole_indexed {
+                blocks.push((idx, offset..end));
+                hole_indexed = true;
+            }
+        } else if end - offset == block_size || tail != TailPolicy::Skip {
+            blocks.push((idx, offset..end));
+        }
         idx += 1;
         offset += block_size;
     }
-    map
+
+    let sign = |&(idx, ref range): &(u64, Range<usize>)| {
+        let bytes = old.bytes(range.clone());
+        if bytes.len() < block_size && tail == TailPolicy::Pad {
+            let mut padded = bytes.into_owned();
+            padded.resize(block_size, TAIL_PAD);
+            block_signature(idx, &padded, algo, weak)
+        } else {
+            block_signature(idx, &bytes, algo, weak)
+        }
+    };
+    #[cfg(feature = "parallel")]
+    let signed: Vec<(u32, SigEntry)> = if threads > 1 && blocks.len() > 1 {
+        use rayon::prelude::*;
+        let run = blocks.len().div_ceil(threads);
+        blocks.par_chunks(run).flat_map_iter(|run| run.iter().map(sign)).collect()
+    } else {
+        blocks.iter().map(sign).collect()
+    };
+    #[cfg(not(feature = "parallel"))]
+    let signed: Vec<(u32, SigEntry)> = {
+        let _ = threads;
+        blocks.iter().map(sign).collect()
+    };
+    for (weak, entry) in signed {
+        map.entry(weak).or_default().push(entry);
+    }
+    // already so as folded; sorted anyway (a pass over each bucket) so that
+    // a change to how blocks are shared out can't move which duplicate a
+    // COPY points at
+    for bucket in map.values_mut() {
+        bucket.sort_by_key(|e| e.block_index);
+    }
 }
 
-/// Patch format (simple custom):
-/// [records...] where each record is:
-/// opcode: u8 (0x00 = ADD, 0x01 = COPY)
-/// If ADD:
-///   length: u32 (little-endian)
-///   data: [length] bytes
-/// If COPY:
-///   offset: u64 (little-endian)  // offset in old file
-///   length: u32 (little-endian)
+/// The weak checksum and signature of block number `idx`, whose contents
+/// are `block`.
+#[cfg(feature = "std")]
+pub(crate) fn block_signature(idx: u64, block: &[u8], algo: HashAlgo, weak: WeakAlgo) -> (u32, SigEntry) {
+    let weak = weak.checksum(block);
+    (weak, SigEntry { block_index: idx, strong_hash: block_strong_hash(block, algo), len: block.len() })
+}
+
+/// Add the signature of block number `idx`, whose contents are `block`.
+#[cfg(feature = "std")]
+fn add_block_signature(
+    map: &mut HashMap<u32, Vec<SigEntry>>,
+    idx: u64,
+    block: &[u8],
+    algo: HashAlgo,
+    weak: WeakAlgo,
+) {
+    let (weak, entry) = block_signature(idx, block, algo, weak);
+    map.entry(weak).or_default().push(entry);
+}
+
+/// Weak checksum of block signatures, which picks the candidate blocks a
+/// window of new may match before the strong hash confirms one.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WeakAlgo {
+    /// The rsync-style sum, both halves mod 65536.
+    #[default]
+    Rolling,
+    /// Canonical Adler-32, both halves mod 65521, as zlib computes it; for
+    /// comparing against tools such as xdelta3 that use it. Recorded in
+    /// serialized signatures, see `PatchOptions::weak_checksum`.
+    Adler32,
+}
+
+#[cfg(feature = "std")]
+impl WeakAlgo {
+    /// The weak checksum of `block`, as signatures record it.
+    pub fn checksum(self, block: &[u8]) -> u32 {
+        match self {
+            WeakAlgo::Rolling => Rolling::from_slice(block).chksum(),
+            WeakAlgo::Adler32 => Adler32::from_slice(block).chksum(),
+        }
+    }
+}
+
+/// Strong hash algorithm of block signatures.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgo {
+    /// SHA-256 (FIPS 180-4).
+    #[default]
+    Sha256,
+    /// BLAKE3, from the `blake3` crate: faster than SHA-256 on CPUs lacking
+    /// SHA instructions, slower on blocks of a chunk (1 KiB) or less than
+    /// the hardware SHA-256 `sha2` uses where they exist
+    /// (`examples/strong_hash` times both). Recorded only where a patch or
+    /// signature carries the hashes themselves, see
+    /// `PatchOptions::strong_hash`.
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+/// The strong hash the signatures keep for a block of old, and that windows
+/// of new are compared against, so signatures can be built elsewhere.
 ///
-/// This is simple, versionable, and easy to apply.
-fn create_patch_bytes(old: &[u8], new: &[u8], block_size: usize) -> Result<Vec<u8>, XDeltaError> {
-    if block_size == 0 {
-        return Err(XDeltaError::InvalidArg("block_size must be > 0".into()));
+/// `data` is the block as indexed: `block_size` bytes of old at a multiple of
+/// `block_size`, or the shorter last block as it is (`TailPolicy::AsIs`) or
+/// padded to `block_size` with `TAIL_PAD` (`TailPolicy::Pad`). The result is
+/// the raw 32-byte digest, in the order the algorithm outputs it; serialized
+/// signatures and COPY_HASH records carry the same bytes.
+#[cfg(feature = "std")]
+pub fn block_strong_hash(data: &[u8], algo: HashAlgo) -> [u8; 32] {
+    match algo {
+        HashAlgo::Sha256 => Sha256::digest(data).into(),
+        #[cfg(feature = "blake3")]
+        HashAlgo::Blake3 => blake3::hash(data).into(),
     }
-    let sigs = build_signatures(old, block_size);
+}
 
-    let mut out: Vec<u8> = Vec::with_capacity(new.len() / 4);
-    let mut pos: usize = 0;
-    let mut pending_add: Vec<u8> = Vec::new();
+/// How the last block of old is indexed when it is shorter than `block_size`.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TailPolicy {
+    /// Index it at its own length. It matches the same bytes at the very end
+    /// of new, which is where an unchanged tail ends up.
+    #[default]
+    AsIs,
+    /// Index it padded to `block_size` with `TAIL_PAD` bytes, so it matches a
+    /// full window of new holding the tail followed by that padding (a file
+    /// grown by zero-filling, say). The tail is copied and the padding is
+    /// sent as ADD. A short window at the very end of new is looked up padded
+    /// the same way, so an unchanged tail still matches. Treated as `AsIs`
+    /// with `content_addressed`.
+    Pad,
+    /// Don't index it, nor let a COPY run on into it; the tail is always
+    /// sent as ADD.
+    Skip,
+}
 
-    // helper to flush pending adds
-    let flush_add = |out: &mut Vec<u8>, pending: &mut Vec<u8>| {
-        if !pending.is_empty() {
-            out.push(0x00); // ADD
-            let len = pending.len() as u32;
-            out.extend_from_slice(&len.to_le_bytes());
-            out.extend_from_slice(&pending[..]);
-            pending.clear();
-        }
-    };
+/// Byte `TailPolicy::Pad` fills the short last block with.
+#[cfg(feature = "std")]
+pub const TAIL_PAD: u8 = 0;
 
-    while pos < new.len() {
-        let remaining = new.len() - pos;
-        let try_len = usize::min(block_size, remaining);
-        if try_len < 1 {
-            // shouldn't happen, but safety
-            pending_add.push(new[pos]);
-            pos += 1;
-            continue;
-        }
+/// How hard the matcher looks for the best block match at each position.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quality {
+    /// Take the first block of old that matches the window, in old order.
+    #[default]
+    Fast,
+    /// Among all blocks of old that match the window, take the one whose
+    /// match runs on furthest past it (looking at most `MATCH_RUN_PROBE`
+    /// bytes ahead), the first of them on a tie. Fewer, longer COPY records
+    /// for data with many repeated blocks, at the cost of comparing every
+    /// candidate. Same as `Fast` with `content_addressed`.
+    Best,
+    /// Like `Fast`, but while sliding through unmatched data only one
+    /// position in `k` is looked up in the signatures (the rolling checksum
+    /// still advances byte by byte), which ones rotating from one block's
+    /// worth of positions to the next so that every alignment is tried in
+    /// turn. Lookups, and the strong hashes confirming them, drop to about
+    /// 1/k; the price is that matching data can be noticed up to `k` blocks
+    /// late, those bytes going out as ADD, so each return from novel to
+    /// matching data costs at most `k * block_size` more patch bytes. For
+    /// interactive use where latency matters more than size. `Skim(0)` and
+    /// `Skim(1)` are `Fast`.
+    Skim(usize),
+}
 
-        if pos + try_len <= new.len() {
-            let window = &new[pos..pos + try_len];
-            let weak = Rolling::from_slice(window).chksum();
-            let candidates = sigs.get(&weak);
-            let mut matched = false;
-            if let Some(vec) = candidates {
-                // Compute strong for this window and compare
-                let mut hasher = Sha256::new();
-                hasher.update(window);
-                let strong = hasher.finalize();
-
-                for e in vec {
-                    if e.strong_hash[..] == strong[..] {
-                        // Found a match. Flush any pending adds.
-                        flush_add(&mut out, &mut pending_add);
-                        out.push(0x01); // COPY
-                        let offset_in_old: u64 = e.block_index * (block_size as u64);
-                        out.extend_from_slice(&offset_in_old.to_le_bytes());
-                        let copy_len = try_len as u32;
-                        out.extend_from_slice(&copy_len.to_le_bytes());
-                        pos += try_len;
-                        matched = true;
-                        break;
-                    }
-                }
-            }
+/// How far past the window `Quality::Best` compares each candidate.
+#[cfg(feature = "std")]
+pub const MATCH_RUN_PROBE: usize = 1 << 20;
 
-            if !matched {
-                // sliding by 1 byte: add first byte to pending_add and continue
-                pending_add.push(new[pos]);
-                pos += 1;
-                // To avoid pathological O(n^2) behavior for huge pending_add, flush periodically:
-                if pending_add.len() >= block_size {
-                    flush_add(&mut out, &mut pending_add);
-                }
-            }
-        } else {
-            // leftover bytes less than try_len (end of file)
-            pending_add.extend_from_slice(&new[pos..]);
-            break;
+/// Weak-checksum buckets with more entries than this get a secondary index
+/// by strong-hash prefix, so that looking a window up in them is not a scan.
+#[cfg(feature = "std")]
+const LARGE_BUCKET: usize = 16;
+
+/// Bytes of the strong hash that key the secondary index.
+#[cfg(feature = "std")]
+const STRONG_PREFIX: usize = 8;
+
+/// Large buckets by weak checksum, then their entries by strong-hash prefix.
+#[cfg(feature = "std")]
+pub(crate) type LargeBuckets<'a> = HashMap<u32, HashMap<[u8; STRONG_PREFIX], Vec<&'a SigEntry>>>;
+
+/// The large buckets of `sigs`, each split by strong-hash prefix. Entries
+/// keep their bucket order, so a lookup finds the same hits a scan would.
+#[cfg(feature = "std")]
+pub(crate) fn index_large_buckets(sigs: &HashMap<u32, Vec<SigEntry>>) -> LargeBuckets<'_> {
+    let mut large = LargeBuckets::new();
+    for (&weak, bucket) in sigs.iter().filter(|(_, bucket)| bucket.len() > LARGE_BUCKET) {
+        let mut by_prefix: HashMap<[u8; STRONG_PREFIX], Vec<&SigEntry>> = HashMap::new();
+        for e in bucket {
+            by_prefix.entry(strong_prefix(&e.strong_hash)).or_default().push(e);
         }
+        large.insert(weak, by_prefix);
     }
+    large
+}
 
-    // flush remaining adds
-    if !pending_add.is_empty() {
-        out.push(0x00);
-        let len = pending_add.len() as u32;
-        out.extend_from_slice(&len.to_le_bytes());
-        out.extend_from_slice(&pending_add[..]);
-    }
+#[cfg(feature = "std")]
+pub(crate) fn strong_prefix(hash: &[u8; 32]) -> [u8; STRONG_PREFIX] {
+    let mut prefix = [0u8; STRONG_PREFIX];
+    prefix.copy_from_slice(&hash[..STRONG_PREFIX]);
+    prefix
+}
 
-    Ok(out)
+/// The signature map behind a first level keyed by 16 bits of the weak
+/// checksum: a bitmap of the values some block of old has there. Most
+/// windows of unmatched data miss on a bit test, without hashing the full
+/// checksum into the map, and a window is only strong hashed once a bucket
+/// with its full checksum is found. The bits are the `b` half: `a` is a
+/// plain byte sum, which near-duplicate blocks all but share, while `b`
+/// weighs bytes by position (`benches/near_duplicates` times such a file).
+#[cfg(feature = "std")]
+pub(crate) struct WeakIndex<'a> {
+    sigs: &'a HashMap<u32, Vec<SigEntry>>,
+    low: Vec<u64>,
 }
 
-/// Apply the simple patch format to `old` -> produces reconstructed `new`.
-fn apply_patch_bytes(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
-    let mut pos = 0usize;
-    let mut out: Vec<u8> = Vec::new();
-    while pos < patch.len() {
-        let opcode = patch[pos];
-        pos += 1;
-        match opcode {
-            0x00 => {
-                if pos + 4 > patch.len() {
-                    return Err(XDeltaError::InvalidArg("truncated ADD length".into()));
-                }
-                let mut lenb = [0u8; 4];
-                lenb.copy_from_slice(&patch[pos..pos + 4]);
-                pos += 4;
-                let len = u32::from_le_bytes(lenb) as usize;
-                if pos + len > patch.len() {
-                    return Err(XDeltaError::InvalidArg("truncated ADD data".into()));
-                }
-                out.extend_from_slice(&patch[pos..pos + len]);
-                pos += len;
-            }
-            0x01 => {
-                if pos + 8 + 4 > patch.len() {
-                    return Err(XDeltaError::InvalidArg("truncated COPY entry".into()));
-                }
-                let mut offb = [0u8; 8];
-                offb.copy_from_slice(&patch[pos..pos + 8]);
-                pos += 8;
-                let offset = u64::from_le_bytes(offb) as usize;
-                let mut lenb = [0u8; 4];
-                lenb.copy_from_slice(&patch[pos..pos + 4]);
-                pos += 4;
-                let len = u32::from_le_bytes(lenb) as usize;
-                if offset + len > old.len() {
-                    return Err(XDeltaError::InvalidArg("COPY out of range".into()));
-                }
-                out.extend_from_slice(&old[offset..offset + len]);
-            }
-            other => {
-                return Err(XDeltaError::InvalidArg(format!("unknown opcode {:#x}", other)));
-            }
+#[cfg(feature = "std")]
+impl<'a> WeakIndex<'a> {
+    pub(crate) fn new(sigs: &'a HashMap<u32, Vec<SigEntry>>) -> Self {
+        let mut low = vec![0u64; (1 << 16) / 64];
+        for &weak in sigs.keys() {
+            let half = (weak >> 16) as usize;
+            low[half / 64] |= 1 << (half % 64);
         }
+        WeakIndex { sigs, low }
     }
-    Ok(out)
-}
 
-/// 创建补丁数据（内存版本）
-/// 成功时返回0，失败返回-1
-#[unsafe(no_mangle)]
-pub extern "C" fn xdelta_create_patch_data(
-    old_data: *const u8,
-    old_len: usize,
-    new_data: *const u8,
-    new_len: usize,
-    patch_data: *mut *mut u8,
-    patch_len: *mut usize,
-    block_size: u32,
-) -> c_int {
-    let r = (|| -> Result<Vec<u8>, XDeltaError> {
-        if old_data.is_null() || new_data.is_null() || patch_data.is_null() || patch_len.is_null() {
-            return Err(XDeltaError::InvalidArg("null pointer".into()));
+    /// The bucket of blocks whose weak checksum is `weak`, as `sigs.get`.
+    pub(crate) fn get(&self, weak: u32) -> Option<&'a Vec<SigEntry>> {
+        let half = (weak >> 16) as usize;
+        if self.low[half / 64] & (1 << (half % 64)) == 0 {
+            return None;
         }
+        self.sigs.get(&weak)
+    }
+}
 
-        let old_bytes = unsafe { std::slice::from_raw_parts(old_data, old_len) };
-        let new_bytes = unsafe { std::slice::from_raw_parts(new_data, new_len) };
+/// Buffers the matcher allocates, kept between calls by `DiffContext`.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Scratch {
+    sigs: HashMap<u32, Vec<SigEntry>>,
+    pending_add: Vec<u8>,
+    pub(crate) out: Vec<u8>,
+    /// whether `out` is all of new as literal bytes, see `PatchOptions::max_ratio`
+    pub(crate) fell_back: bool,
+}
 
-        create_patch_bytes(old_bytes, new_bytes, block_size as usize)
-    })();
+/// Options controlling how a patch is created.
+///
+/// Start from `PatchOptions::default()` (or `new()`) and adjust with the
+/// builder methods:
+///
+/// ```
+/// let opts = xdelta::PatchOptions::new().block_size(512).near_miss_diff(true);
+/// let patch = xdelta::create_patch_with(b"hello world", b"hello there", &opts).unwrap();
+/// assert!(!patch.is_empty());
+/// ```
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct PatchOptions {
+    block_size: usize,
+    near_miss_diff: bool,
+    index_granularity: Option<u64>,
+    old_hash: bool,
+    const_table: bool,
+    content_addressed: bool,
+    dirty_blocks: Option<Vec<u8>>,
+    pad_to: Option<usize>,
+    embed_block_size: bool,
+    novel_skip: Option<usize>,
+    min_match: Option<usize>,
+    fuzzy_index: bool,
+    tail_policy: TailPolicy,
+    quality: Quality,
+    xor_delta: bool,
+    min_version: bool,
+    copy_target: bool,
+    output_check: bool,
+    strong_hash: HashAlgo,
+    weak_checksum: WeakAlgo,
+    strict: bool,
+    record_crc: bool,
+    forward_only: bool,
+    crc_precheck: bool,
+    max_ratio: Option<f64>,
+    sample_every: Option<usize>,
+    max_candidates: usize,
+    aligned: bool,
+    alignment: Option<usize>,
+    max_add_len: usize,
+    #[cfg(feature = "zstd")]
+    compress: bool,
+    cancel: Option<CancelToken>,
+}
 
-    match r {
-        Ok(data) => {
-            unsafe {
-                *patch_len = data.len();
-                *patch_data = libc::malloc(data.len()) as *mut u8;
-                if (*patch_data).is_null() {
-                    set_last_error("failed to allocate memory");
-                    return -1;
-                }
-                std::ptr::copy_nonoverlapping(data.as_ptr(), *patch_data, data.len());
-            }
-            0
-        },
-        Err(e) => {
-            set_last_error(&format!("{}", e));
-            -1
+#[cfg(feature = "std")]
+impl Default for PatchOptions {
+    fn default() -> Self {
+        PatchOptions {
+            block_size: 1024,
+            near_miss_diff: false,
+            index_granularity: None,
+            old_hash: false,
+            const_table: false,
+            content_addressed: false,
+            dirty_blocks: None,
+            pad_to: None,
+            embed_block_size: false,
+            novel_skip: None,
+            min_match: None,
+            fuzzy_index: false,
+            tail_policy: TailPolicy::AsIs,
+            quality: Quality::Fast,
+            xor_delta: false,
+            min_version: false,
+            copy_target: false,
+            output_check: false,
+            strong_hash: HashAlgo::Sha256,
+            weak_checksum: WeakAlgo::Rolling,
+            strict: false,
+            record_crc: false,
+            forward_only: false,
+            crc_precheck: false,
+            max_ratio: None,
+            sample_every: None,
+            max_candidates: DEFAULT_MAX_CANDIDATES,
+            aligned: false,
+            alignment: None,
+            max_add_len: DEFAULT_MAX_ADD_LEN,
+            #[cfg(feature = "zstd")]
+            compress: false,
+            cancel: None,
         }
     }
 }
 
-/// 应用补丁数据（内存版本）
-/// 成功时返回0，失败返回-1
-#[unsafe(no_mangle)]
-pub extern "C" fn xdelta_apply_patch_data(
-    old_data: *const u8,
-    old_len: usize,
-    patch_data: *const u8,
-    patch_len: usize,
-    new_data: *mut *mut u8,
-    new_len: *mut usize,
-) -> c_int {
-    let r = (|| -> Result<Vec<u8>, XDeltaError> {
-        if old_data.is_null() || patch_data.is_null() || new_data.is_null() || new_len.is_null() {
-            return Err(XDeltaError::InvalidArg("null pointer".into()));
-        }
+#[cfg(feature = "std")]
+impl PatchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        let old_bytes = unsafe { std::slice::from_raw_parts(old_data, old_len) };
-        let patch_bytes = unsafe { std::slice::from_raw_parts(patch_data, patch_len) };
+    /// Block size used for signatures and matching; 0 picks one from old's
+    /// length, see `auto_block_size`.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
 
-        apply_patch_bytes(old_bytes, patch_bytes)
-    })();
+    /// Emit DIFF records for blocks that differ from old in only a few bytes.
+    pub fn near_miss_diff(mut self, enabled: bool) -> Self {
+        self.near_miss_diff = enabled;
+        self
+    }
 
-    match r {
-        Ok(data) => {
-            unsafe {
-                *new_len = data.len();
-                *new_data = libc::malloc(data.len()) as *mut u8;
-                if (*new_data).is_null() {
-                    set_last_error("failed to allocate memory");
-                    return -1;
-                }
-                std::ptr::copy_nonoverlapping(data.as_ptr(), *new_data, data.len());
-            }
-            0
-        },
-        Err(e) => {
-            set_last_error(&format!("{}", e));
-            -1
-        }
+    /// Prepend an output index with an entry every `granularity` output bytes.
+    pub fn index_granularity(mut self, granularity: u64) -> Self {
+        self.index_granularity = Some(granularity);
+        self
     }
-}
 
-/// 释放通过xdelta_create_patch_data或xdelta_apply_patch_data分配的内存
-#[unsafe(no_mangle)]
-pub extern "C" fn xdelta_free_data(data: *mut u8) {
-    if !data.is_null() {
-        unsafe {
-            libc::free(data as *mut libc::c_void);
-        }
+    /// Record hashes of old so a streaming apply can verify its base.
+    pub fn old_hash(mut self, enabled: bool) -> Self {
+        self.old_hash = enabled;
+        self
+    }
+
+    /// Encode literal runs of frequent short tiles via a constant table: a
+    /// tile of up to 8 bytes repeated back to back over 16 bytes or more
+    /// ("abababab..."). Isolated copies of a short sequence between other
+    /// literals are not encoded.
+    pub fn const_table(mut self, enabled: bool) -> Self {
+        self.const_table = enabled;
+        self
+    }
+
+    /// Reference matched blocks of old by their strong hash (COPY_HASH,
+    /// SHA-256 unless `strong_hash` says otherwise) instead of by offset, so
+    /// the patch can be applied against a content-addressed store with
+    /// `apply_cas` rather than against old itself. Each block is referenced
+    /// separately and near-miss DIFF records are not emitted.
+    pub fn content_addressed(mut self, enabled: bool) -> Self {
+        self.content_addressed = enabled;
+        self
+    }
+
+    /// Only run the matcher where the caller says something changed.
+    ///
+    /// Bit `i` of `bitmap` (least significant bit first) covers block `i`,
+    /// i.e. bytes `i * block_size..(i + 1) * block_size` of both old and new.
+    /// A clear bit promises the block is unchanged at the same position, so
+    /// it becomes a COPY without being hashed; blocks past the end of the
+    /// bitmap count as dirty. Ignored with `content_addressed`.
+    pub fn dirty_blocks(mut self, bitmap: &[u8]) -> Self {
+        self.dirty_blocks = Some(bitmap.to_vec());
+        self
+    }
+
+    /// Pad the finished patch with NOP/PADDING records to exactly `size`
+    /// bytes, so every patch for a known update has the same length. Creation
+    /// fails if the patch is already larger.
+    pub fn pad_to(mut self, size: usize) -> Self {
+        self.pad_to = Some(size);
+        self
+    }
+
+    /// Record the block size in the patch (BLOCK_SIZE record), so that
+    /// `apply_with_signature` can sign the output at the same block size.
+    pub fn embed_block_size(mut self, enabled: bool) -> Self {
+        self.embed_block_size = enabled;
+        self
+    }
+
+    /// With `near_miss_diff`, also look for near-miss blocks anywhere in old
+    /// through a SimHash index of its blocks, not only on the current
+    /// diagonal. Lookups happen where a near miss is tried: at block
+    /// boundaries after a match or a flushed literal run.
+    pub fn fuzzy_index(mut self, enabled: bool) -> Self {
+        self.fuzzy_index = enabled;
+        self
+    }
+
+    /// How to index the last block of old when it is shorter than a block.
+    pub fn tail_policy(mut self, policy: TailPolicy) -> Self {
+        self.tail_policy = policy;
+        self
+    }
+
+    /// When old and new have the same length and differ in at most 1/16th of
+    /// their bytes, emit a single XOR_DELTA record (see `xor_delta`) instead
+    /// of running the matcher. Far smaller for a few scattered changed bytes.
+    /// Ignored with `content_addressed` or past 4 GiB.
+    pub fn xor_delta(mut self, enabled: bool) -> Self {
+        self.xor_delta = enabled;
+        self
+    }
+
+    /// Declare in the patch (MIN_VERSION record) the lowest format version
+    /// whose applier understands every record used, so an older applier that
+    /// knows MIN_VERSION refuses it up front by version rather than failing
+    /// on the first opcode it doesn't know.
+    pub fn min_version(mut self, enabled: bool) -> Self {
+        self.min_version = enabled;
+        self
+    }
+
+    /// Also match blocks against the part of new already encoded, emitting
+    /// COPY_TARGET records for repetition within new that old lacks. Such a
+    /// patch needs an applier that keeps its output (see `needs_output`).
+    /// This includes short-period repetition (a tile of up to
+    /// `MAX_REPEAT_PERIOD` bytes over and over), sent as one record copying
+    /// from a period back that overlaps what it writes itself.
+    /// Ignored with `content_addressed`.
+    pub fn copy_target(mut self, enabled: bool) -> Self {
+        self.copy_target = enabled;
+        self
+    }
+
+    /// End the patch with a CHECK record, the SHA-256 of all of new, which
+    /// every full apply compares its output against, failing with
+    /// `ChecksumMismatch`. This catches a corrupt patch that still parses,
+    /// at the cost of hashing new on both sides. Needs format 5.
+    pub fn output_check(mut self, enabled: bool) -> Self {
+        self.output_check = enabled;
+        self
+    }
+
+    /// Strong hash confirming weak-checksum hits. Patches come out the same
+    /// either way unless `content_addressed`, whose COPY_HASH records carry
+    /// the hashes; the patch header then records the algorithm for the
+    /// applier to check blocks with.
+    pub fn strong_hash(mut self, algo: HashAlgo) -> Self {
+        self.strong_hash = algo;
+        self
+    }
+
+    /// Weak checksum finding candidate blocks. Either finds the same
+    /// matches bar collisions, so patches rarely differ; it matters for
+    /// comparing with other tools, and a `Signature` records its own.
+    pub fn weak_checksum(mut self, algo: WeakAlgo) -> Self {
+        self.weak_checksum = algo;
+        self
+    }
+
+    /// How hard to look for the best match at each position.
+    pub fn quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Stop probing the signatures in wholly novel data: after `misses`
+    /// consecutive unmatched bytes (at least one block's worth), the next
+    /// `misses` bytes go straight to ADD without hashing, then one block's
+    /// worth of positions is probed again before the next skip. A later matching region is still found, at the
+    /// cost of up to `misses + block_size` of its bytes being sent as ADD.
+    pub fn novel_skip(mut self, misses: usize) -> Self {
+        self.novel_skip = Some(misses);
+        self
+    }
+
+    /// Send matches shorter than `len` bytes as literal data rather than as
+    /// COPY or COPY_TARGET records. A COPY record is 13 bytes, so a short
+    /// one can cost more than the bytes it replaces, and it splits the
+    /// literal run around it into two ADD records. The default is
+    /// `DEFAULT_MIN_MATCH` or the block size, whichever is smaller, so
+    /// whole-block matches are always kept; 0 keeps every match.
+    pub fn min_match(mut self, len: usize) -> Self {
+        self.min_match = Some(len);
+        self
+    }
+
+    /// Apply the finished patch to old and compare the output with new
+    /// before returning it, failing with `XDeltaError::InvalidArg` if they
+    /// differ: a self-checking encoder, at the cost of an apply per patch.
+    /// Unlike the check debug builds make, this one runs in release builds,
+    /// covers every optional record and also catches `dirty_blocks` that
+    /// wrongly claim a block is unchanged.
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// Follow every record with the CRC-32 of its bytes, so corruption of
+    /// the patch itself, such as bit rot in flash, is caught before anything
+    /// is applied and located: `apply_patch`, `apply_to`, `apply_streaming`
+    /// and `validate_patch` check each record in turn and fail with
+    /// `ChecksumMismatch` naming the index of the first bad one. This is
+    /// apart from `output_check`, which catches a wrong output. Other
+    /// readers (`apply_iter`, `PatchReader`, range applies) refuse such a
+    /// patch. Costs 4 bytes a record; needs format 7.
+    pub fn record_crc(mut self, enabled: bool) -> Self {
+        self.record_crc = enabled;
+        self
+    }
+
+    /// Read old front to back only: every record reading old (COPY, DIFF,
+    /// XOR_DELTA) starts at or after where the one before it ended, so an
+    /// applier can stream the base once, without seeking back or reading a
+    /// byte twice. The matcher passes over blocks of old behind that point,
+    /// which costs compression when new reorders or repeats old. The header
+    /// says so, and appliers refuse such a patch that breaks it; needs
+    /// format 10. Content-addressed patches have no offsets and refuse it.
+    pub fn forward_only(mut self, enabled: bool) -> Self {
+        self.forward_only = enabled;
+        self
+    }
+
+    /// Before strong-hashing a window whose weak checksum some blocks of old
+    /// share, compare its CRC-32 with theirs, and skip the strong hash when
+    /// none agrees. Pays for itself when weak checksums collide often, as
+    /// in data of many near-identical blocks, at the cost of a CRC of every
+    /// block of old up front. Large buckets, narrowed by the strong hash
+    /// itself, are not prechecked. The patch is the same either way.
+    pub fn crc_precheck(mut self, enabled: bool) -> Self {
+        self.crc_precheck = enabled;
+        self
     }
+
+    /// Give up matching once the records outgrow `ratio` times new's length,
+    /// and send all of new as literals instead, in ADD records of at most
+    /// `max_add_len` bytes: for near-random or wholly
+    /// changed inputs, the patch is then never much larger than new itself
+    /// (its header and a few bytes a record on top), and the matcher stops
+    /// early. `create_patch_stats_with` tells whether it happened. A ratio
+    /// that isn't above 0 fails with `InvalidArg`.
+    pub fn max_ratio(mut self, ratio: f64) -> Self {
+        self.max_ratio = Some(ratio);
+        self
+    }
+
+    /// Index only every `every`-th block of old, the first included, so the
+    /// signatures of a huge old take 1/`every` of the memory (an entry is
+    /// over 40 bytes a block) and the hashing. A window of new matching an
+    /// indexed block is extended in both directions over the blocks around
+    /// it, which is most of what the rest would have found; what it misses
+    /// goes out as literals, so compression may be a little worse, which
+    /// the header says (format 11, see `PatchInfo::sampled`). 1 indexes
+    /// every block as usual; 0 fails with `InvalidArg`.
+    pub fn sample_blocks(mut self, every: usize) -> Self {
+        self.sample_every = Some(every);
+        self
+    }
+
+    /// Compare a window of new with at most the first `max` blocks of old
+    /// sharing its weak checksum (and, in a large bucket, its strong-hash
+    /// prefix), and treat it as unmatched if none of them confirms. Without
+    /// a cap, data of many identical or colliding blocks costs a scan of
+    /// them all at every lookup, quadratic in the worst case; with one, some
+    /// match (the longest with `Quality::Best`) may be missed. Defaults to
+    /// `DEFAULT_MAX_CANDIDATES`; `usize::MAX` lifts the cap, 0 fails with
+    /// `InvalidArg`. Appliers don't see it.
+    pub fn max_candidates(mut self, max: usize) -> Self {
+        self.max_candidates = max;
+        self
+    }
+
+    /// Look for matches only at multiples of `alignment` in new: where a
+    /// window doesn't match, skip to the next aligned position instead of
+    /// sliding on by one byte, sending the bytes in between as literals.
+    /// For sector-aligned disk images or page-aligned memory dumps, where
+    /// content only ever moves by whole sectors or pages, this cuts the
+    /// weak checksums and lookups by that factor, at the cost of missing
+    /// unaligned matches. Appliers don't see it.
+    pub fn aligned(mut self, enabled: bool) -> Self {
+        self.aligned = enabled;
+        self
+    }
+
+    /// The alignment `aligned` matches at, the block size by default; 0
+    /// fails with `InvalidArg`.
+    pub fn alignment(mut self, bytes: usize) -> Self {
+        self.alignment = Some(bytes);
+        self
+    }
+
+    /// Write literal regions as ADD records of at most `max` bytes each,
+    /// several in a row for a longer one, and hold at most that many
+    /// unmatched bytes before writing them. Defaults to
+    /// `DEFAULT_MAX_ADD_LEN`; above `u32::MAX`, a record's most, it is
+    /// `u32::MAX`, and 0 fails with `InvalidArg`.
+    pub fn max_add_len(mut self, max: usize) -> Self {
+        self.max_add_len = max;
+        self
+    }
+
+    /// Compress literal data with zstd into ADD_ZSTD records (format 12),
+    /// consecutive ADD records together up to `max_add_len` bytes at a
+    /// time, where that makes them smaller: text, source trees and JSON
+    /// shrink several times over, already compressed data stays as ADD.
+    /// Appliers need the `zstd` feature too.
+    #[cfg(feature = "zstd")]
+    pub fn compress(mut self, enabled: bool) -> Self {
+        self.compress = enabled;
+        self
+    }
+
+    /// Abort creation with `XDeltaError::Cancelled` once `token` is cancelled.
+    pub fn cancel_token(mut self, token: &CancelToken) -> Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl PatchOptions {
+    /// `tail_policy`, except that a padded block cannot be content-addressed.
+    pub(crate) fn effective_tail_policy(&self) -> TailPolicy {
+        match self.tail_policy {
+            TailPolicy::Pad if self.content_addressed => TailPolicy::AsIs,
+            policy => policy,
+        }
+    }
+
+    /// `min_match`, defaulted against the block size.
+    pub(crate) fn effective_min_match(&self) -> usize {
+        self.min_match.unwrap_or(usize::min(DEFAULT_MIN_MATCH, self.block_size))
+    }
+
+    /// Longest ADD record to write, see `max_add_len`.
+    pub(crate) fn effective_max_add_len(&self) -> usize {
+        self.max_add_len.min(MAX_RECORD_LEN)
+    }
+
+    /// Whether `sample_blocks` leaves blocks of old out of the signatures.
+    pub(crate) fn sampled(&self) -> bool {
+        self.sample_every.is_some_and(|every| every > 1)
+    }
+
+    /// Build the signatures of `old` into `map`, as `sample_blocks` asks.
+    fn build_signatures<O: OldBytes + ?Sized>(&self, map: &mut HashMap<u32, Vec<SigEntry>>, old: &O) {
+        let (tail, every) = (self.effective_tail_policy(), self.sample_every.unwrap_or(1).max(1));
+        build_sampled_signatures(map, old, self.block_size, tail, self.strong_hash, self.weak_checksum, every);
+    }
+
+    /// Patch header flags of a patch made with these options.
+    fn header_flags(&self) -> u8 {
+        let mut flags = 0;
+        #[cfg(feature = "blake3")]
+        if self.content_addressed && self.strong_hash == HashAlgo::Blake3 {
+            flags |= PATCH_FLAG_BLAKE3;
+        }
+        if self.output_check {
+            flags |= PATCH_FLAG_CHECK;
+        }
+        if self.record_crc {
+            flags |= PATCH_FLAG_RECORD_CRC;
+        }
+        if self.forward_only {
+            flags |= PATCH_FLAG_FORWARD_ONLY;
+        }
+        if self.sampled() {
+            flags |= PATCH_FLAG_SAMPLED;
+        }
+        flags
+    }
+
+    /// The parameters the header of a patch made with these options, and a
+    /// block size already resolved, records.
+    pub(crate) fn header_params(&self) -> Result<[u8; PATCH_PARAMS_LEN], XDeltaError> {
+        let block_size = u32::try_from(self.block_size)
+            .map_err(|_| XDeltaError::InvalidArg("block_size does not fit the patch header".into()))?;
+        let weak = match self.weak_checksum {
+            WeakAlgo::Rolling => XDELTA_WEAK_ROLLING,
+            WeakAlgo::Adler32 => XDELTA_WEAK_ADLER32,
+        };
+        let strong = match self.strong_hash {
+            HashAlgo::Sha256 => XDELTA_HASH_SHA256,
+            #[cfg(feature = "blake3")]
+            HashAlgo::Blake3 => XDELTA_HASH_BLAKE3,
+        };
+        let mut params = [0u8; PATCH_PARAMS_LEN];
+        params[..4].copy_from_slice(&block_size.to_le_bytes());
+        params[4] = weak as u8;
+        params[5] = strong as u8;
+        Ok(params)
+    }
+
+    /// These options with a block size of 0 replaced by the one
+    /// `auto_block_size` picks for `old_len`, which the patch then records
+    /// in a BLOCK_SIZE record.
+    pub(crate) fn resolve_block_size(&self, old_len: usize) -> Cow<'_, PatchOptions> {
+        if self.block_size != 0 {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(self.clone().block_size(auto_block_size(old_len)).embed_block_size(true))
+    }
+
+    /// Whether `old == new` may be answered with an identity patch: not when
+    /// records it cannot carry are asked for, nor for a content-addressed
+    /// patch, whose applier has no old to hand back.
+    pub(crate) fn identity_allowed(&self) -> bool {
+        !self.content_addressed
+            && !self.old_hash
+            && !self.embed_block_size
+            && !self.min_version
+            && !self.output_check
+            && self.pad_to.is_none()
+    }
+}
+
+/// Default `PatchOptions::min_match`: below this a COPY record and the ADD
+/// header it costs to resume the literal run outweigh the bytes it saves.
+#[cfg(feature = "std")]
+pub const DEFAULT_MIN_MATCH: usize = 16;
+
+/// Default `PatchOptions::max_candidates`: more than a bucket holds before
+/// it counts as large, so only large ones are ever cut short.
+#[cfg(feature = "std")]
+pub const DEFAULT_MAX_CANDIDATES: usize = 32;
+
+/// Default `PatchOptions::max_add_len`: a record a streaming applier can
+/// hold whole and a record CRC covers cheaply, against a 5-byte header in
+/// every 4 MiB of literals.
+#[cfg(feature = "std")]
+pub const DEFAULT_MAX_ADD_LEN: usize = 4 << 20;
+
+/// Range of the block sizes `auto_block_size` picks from.
+#[cfg(feature = "std")]
+const AUTO_BLOCK_SIZE_RANGE: (usize, usize) = (512, 64 * 1024);
+
+/// Block size used for an old of `old_len` bytes when `PatchOptions` asks
+/// for 0: the square root of `old_len` rounded up to a power of two, within
+/// 512..=65536. That keeps about as many signatures as there are bytes in a
+/// block, so neither the signature map nor the missed matches dominate.
+///
+/// ```
+/// assert_eq!(xdelta::auto_block_size(1000), 512);
+/// assert_eq!(xdelta::auto_block_size(16 << 20), 4096);
+/// assert_eq!(xdelta::auto_block_size(1 << 40), 65536);
+/// ```
+#[cfg(feature = "std")]
+pub fn auto_block_size(old_len: usize) -> usize {
+    let (min, max) = AUTO_BLOCK_SIZE_RANGE;
+    old_len.isqrt().next_power_of_two().clamp(min, max)
+}
+
+/// Longest period of repetition within new that `copy_target` looks for
+/// behind an unmatched position, e.g. a repeating 4-byte tile. Period 1 is
+/// left to RUN records, which are smaller.
+#[cfg(feature = "std")]
+pub(crate) const MAX_REPEAT_PERIOD: usize = 32;
+
+/// Fewest bytes a short-period repetition must run for before it is sent as
+/// a COPY_TARGET (13 bytes) rather than as literal bytes.
+#[cfg(feature = "std")]
+pub(crate) const MIN_REPEAT_LEN: usize = 32;
+
+/// How many bytes of new the matcher consumes between cancellation checks
+/// (and progress reports).
+#[cfg(feature = "std")]
+pub(crate) const CANCEL_CHECK_INTERVAL: usize = 64 * 1024;
+
+#[cfg(feature = "std")]
+fn check_cancel(token: Option<&CancelToken>) -> Result<(), XDeltaError> {
+    match token {
+        Some(t) if t.is_cancelled() => Err(XDeltaError::Cancelled),
+        _ => Ok(()),
+    }
+}
+
+/// Create a patch turning `old` into `new` with default options and the
+/// given block size; see `create_patch_with` for the rest of the options.
+///
+/// ```
+/// let old = b"the quick brown fox jumps over the lazy dog".repeat(10);
+/// let mut new = old.clone();
+/// new[100..105].copy_from_slice(b"HELLO");
+/// let patch = xdelta::create_patch(&old, &new, 16).unwrap();
+/// assert_eq!(xdelta::apply_patch(&old, &patch).unwrap(), new);
+/// ```
+#[cfg(feature = "std")]
+pub fn create_patch(old: &[u8], new: &[u8], block_size: usize) -> Result<Vec<u8>, XDeltaError> {
+    create_patch_with(old, new, &PatchOptions::new().block_size(block_size))
+}
+
+/// Create a patch turning `old` into `new` and apply it, returning whether
+/// it gave back `new`: the property every patch has to keep, as a fuzz
+/// target checks it, see `fuzz/`. Errors creating the patch are returned as
+/// they are; a patch that is created but fails to apply is false.
+///
+/// ```
+/// assert!(xdelta::roundtrip_check(b"hello world", b"hello there", 4).unwrap());
+/// ```
+#[cfg(feature = "std")]
+pub fn roundtrip_check(old: &[u8], new: &[u8], block_size: usize) -> Result<bool, XDeltaError> {
+    let patch = create_patch(old, new, block_size)?;
+    Ok(apply_patch(old, &patch).is_ok_and(|out| out == new))
+}
+
+/// Create a patch turning `old` into `new` using `opts`.
+///
+/// If `old == new` the patch is an identity patch, a bare header that apply
+/// answers with old, unless `opts` asks for records it could not carry.
+#[cfg(feature = "std")]
+pub fn create_patch_with(old: &[u8], new: &[u8], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+    create_patch_reporting(old, new, opts).map(|(patch, _)| patch)
+}
+
+/// `create_patch_with`, also telling whether the records were replaced by
+/// all of new as a literal, see `PatchOptions::max_ratio`.
+#[cfg(feature = "std")]
+pub(crate) fn create_patch_reporting(
+    old: &[u8],
+    new: &[u8],
+    opts: &PatchOptions,
+) -> Result<(Vec<u8>, bool), XDeltaError> {
+    if old == new && opts.identity_allowed() {
+        return Ok((identity_patch(), false));
+    }
+    let opts = opts.resolve_block_size(old.len());
+    let mut scratch = Scratch { out: Vec::with_capacity(new.len() / 4), ..Scratch::default() };
+    create_patch_scratch(old, new, &opts, &mut scratch, None, None)?;
+    Ok((finish_patch(old, new, scratch.out, &opts)?, scratch.fell_back))
+}
+
+/// Like `create_patch_with`, also calling `on_progress(processed, new.len())`
+/// as the matcher works through new, for progress bars on large inputs.
+///
+/// Calls come at most once per 64 KiB of new, with `processed` increasing
+/// from one to the next; the last one has `processed == new.len()`.
+#[cfg(feature = "std")]
+pub fn create_patch_with_progress(
+    old: &[u8],
+    new: &[u8],
+    opts: &PatchOptions,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<u8>, XDeltaError> {
+    create_patch_hooked(old, new, opts, Some(&mut on_progress), None)
+}
+
+/// Like `create_patch_with`, stopping with `XDeltaError::Cancelled` once
+/// `cancel` is set, for callers that keep their own flag rather than a
+/// `CancelToken`. The flag is checked, as the token is, every 64 KiB of new
+/// the matcher works through; whatever was built so far is dropped.
+#[cfg(feature = "std")]
+pub fn create_patch_cancelable(
+    old: &[u8],
+    new: &[u8],
+    opts: &PatchOptions,
+    cancel: &AtomicBool,
+) -> Result<Vec<u8>, XDeltaError> {
+    create_patch_hooked(old, new, opts, None, Some(&|| cancel.load(Ordering::Relaxed)))
+}
+
+/// `create_patch_with`, reporting progress to `on_progress` and asking
+/// `is_cancelled` whether to stop, before starting and at every
+/// cancellation check. An identity patch reports all of new at once.
+#[cfg(feature = "std")]
+fn create_patch_hooked(
+    old: &[u8],
+    new: &[u8],
+    opts: &PatchOptions,
+    on_progress: Option<&mut dyn FnMut(usize, usize)>,
+    is_cancelled: Option<&dyn Fn() -> bool>,
+) -> Result<Vec<u8>, XDeltaError> {
+    if is_cancelled.is_some_and(|f| f()) {
+        return Err(XDeltaError::Cancelled);
+    }
+    if old == new && opts.identity_allowed() {
+        if let Some(f) = on_progress {
+            f(new.len(), new.len());
+        }
+        return Ok(identity_patch());
+    }
+    let opts = opts.resolve_block_size(old.len());
+    let mut scratch = Scratch { out: Vec::with_capacity(new.len() / 4), ..Scratch::default() };
+    create_patch_scratch(old, new, &opts, &mut scratch, on_progress, is_cancelled)?;
+    finish_patch(old, new, scratch.out, &opts)
+}
+
+/// One COPY record of a patch, with the position in new that the patch
+/// itself leaves implicit.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CopyMatch {
+    pub new_offset: u64,
+    pub old_offset: u64,
+    pub len: u64,
+}
+
+/// Like `create_patch_with`, also reporting every COPY record to `on_match`
+/// as it is emitted, in patch order, for tools that visualize the alignment.
+#[cfg(feature = "std")]
+pub fn create_patch_with_matches(
+    old: &[u8],
+    new: &[u8],
+    opts: &PatchOptions,
+    mut on_match: impl FnMut(CopyMatch),
+) -> Result<Vec<u8>, XDeltaError> {
+    let opts = opts.resolve_block_size(old.len());
+    let mut sigs = HashMap::new();
+    opts.build_signatures(&mut sigs, old);
+    let mut patch = Vec::with_capacity(new.len() / 4);
+    let hooks = MatchHooks { on_match: Some(&mut on_match), ..MatchHooks::default() };
+    match_blocks(old, new, &opts, &sigs, &mut patch, &mut Vec::new(), hooks)?;
+    #[cfg(debug_assertions)]
+    debug_check_patch(old, new, &patch, &opts, &sigs);
+    finish_patch(old, new, patch, &opts)
+}
+
+/// Add the optional records `opts` asks for, and the header, to a bare patch
+/// turning `old` into `new`.
+#[cfg(feature = "std")]
+pub(crate) fn finish_patch(
+    old: &[u8],
+    new: &[u8],
+    mut patch: Vec<u8>,
+    opts: &PatchOptions,
+) -> Result<Vec<u8>, XDeltaError> {
+    if opts.const_table {
+        patch = const_table::add_const_table(&patch)?;
+    }
+    #[cfg(feature = "zstd")]
+    if opts.compress {
+        patch = add_zstd::compress_adds(&patch, opts.effective_max_add_len())?;
+    }
+    if opts.old_hash {
+        patch = add_old_hash(old, &patch)?;
+    }
+    if opts.embed_block_size {
+        let block_size = u32::try_from(opts.block_size)
+            .map_err(|_| XDeltaError::InvalidArg("block_size does not fit the BLOCK_SIZE record".into()))?;
+        let mut with_size = Vec::with_capacity(patch.len() + 9);
+        with_size.push(0x85); // BLOCK_SIZE
+        with_size.extend_from_slice(&4u32.to_le_bytes());
+        with_size.extend_from_slice(&block_size.to_le_bytes());
+        with_size.extend_from_slice(&patch);
+        patch = with_size;
+    }
+    if opts.output_check {
+        add_output_check(&mut patch, new);
+    }
+    if opts.min_version {
+        // INDEX and padding come later but count too; both are in format 2,
+        // CRCs after records are in 7, the header with the output length
+        // and parameters in 9, the forward-only flag in 10 and the sampled
+        // one in 11
+        let mut version = compat::required_version(&patch)?.max(compat::PARAMS_HEADER_VERSION);
+        if opts.record_crc {
+            version = version.max(compat::RECORD_CRC_VERSION);
+        }
+        if opts.forward_only {
+            version = version.max(compat::FORWARD_ONLY_VERSION);
+        }
+        if opts.sampled() {
+            version = version.max(compat::SAMPLED_VERSION);
+        }
+        if opts.index_granularity.is_some() || opts.pad_to.is_some() {
+            version = version.max(compat::opcode_version(0x80));
+        }
+        patch = compat::add_min_version(&patch, version);
+    }
+    if let Some(granularity) = opts.index_granularity {
+        patch = add_index(&patch, granularity)?;
+    }
+    let output_len = output_len(&patch)?;
+    debug_assert_eq!(output_len, new.len() as u64, "records don't produce new");
+    if opts.record_crc {
+        patch = add_record_crcs(&patch)?;
+    }
+    patch = with_params_header(&patch, output_len, &opts.header_params()?);
+    patch[5] |= opts.header_flags();
+    if let Some(size) = opts.pad_to {
+        add_padding(&mut patch, size, opts.record_crc)?;
+    }
+    if opts.strict {
+        check_strict(apply_finished(old, &patch, opts), new)?;
+    }
+    Ok(patch)
+}
+
+/// Apply the finished `patch`, made with `opts`, to `old`; a
+/// content-addressed one looks its blocks up among old's.
+#[cfg(feature = "std")]
+fn apply_finished(old: &[u8], patch: &[u8], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+    if !opts.content_addressed {
+        return apply_patch_bytes(old, patch);
+    }
+    let patch = &*without_record_crcs(patch)?;
+    let blocks: HashMap<[u8; 32], &[u8]> =
+        old.chunks(opts.block_size).map(|block| (block_strong_hash(block, opts.strong_hash), block)).collect();
+    cas::apply_cas(patch, |hash| blocks.get(hash).copied())
+}
+
+/// The `PatchOptions::strict` verdict on `rebuilt`, what applying a patch
+/// meant to give `new` gave instead.
+#[cfg(feature = "std")]
+pub(crate) fn check_strict(rebuilt: Result<Vec<u8>, XDeltaError>, new: &[u8]) -> Result<(), XDeltaError> {
+    match reconstruction_error(rebuilt, new) {
+        Some(msg) => Err(XDeltaError::InvalidArg(format!("strict check failed: {}", msg))),
+        None => Ok(()),
+    }
+}
+
+/// What is wrong with `rebuilt`, the output of applying a patch meant to
+/// give `new`, if anything.
+#[cfg(feature = "std")]
+fn reconstruction_error(rebuilt: Result<Vec<u8>, XDeltaError>, new: &[u8]) -> Option<String> {
+    match rebuilt {
+        Ok(out) if out == new => None,
+        Ok(out) => {
+            let first = out.iter().zip(new).position(|(a, b)| a != b).unwrap_or(out.len().min(new.len()));
+            Some(format!(
+                "patch does not reconstruct new: {} bytes instead of {}, first difference at {}",
+                out.len(),
+                new.len(),
+                first
+            ))
+        }
+        Err(e) => Some(format!("patch does not apply: {} (new {} bytes)", e, new.len())),
+    }
+}
+
+/// Pad `patch` to exactly `size` bytes.
+///
+/// Padding goes at the end: one PADDING record (opcode 0x84, body length:
+/// u32, zero bytes) when there is room for its header, otherwise single-byte
+/// NOP records (opcode 0x06). Appliers skip both. With `record_crc` the
+/// PADDING record is followed by its CRC like any other, and there is no
+/// room for NOPs.
+#[cfg(feature = "std")]
+fn add_padding(patch: &mut Vec<u8>, size: usize, record_crc: bool) -> Result<(), XDeltaError> {
+    if patch.len() > size {
+        return Err(XDeltaError::InvalidArg(format!(
+            "patch is {} bytes, more than the pad size {}",
+            patch.len(),
+            size
+        )));
+    }
+    let gap = size - patch.len();
+    let crc_len = if record_crc { 4 } else { 0 };
+    if gap >= 5 + crc_len {
+        let body = u32::try_from(gap - 5 - crc_len).map_err(|_| XDeltaError::InvalidArg("pad size too large".into()))?;
+        let start = patch.len();
+        patch.push(0x84); // PADDING
+        patch.extend_from_slice(&body.to_le_bytes());
+        patch.resize(size - crc_len, 0);
+        if record_crc {
+            let crc = crc32::crc32(&patch[start..]);
+            patch.extend_from_slice(&crc.to_le_bytes());
+        }
+    } else if record_crc && gap > 0 {
+        return Err(XDeltaError::InvalidArg(format!(
+            "{} bytes short of the pad size, too few for a PADDING record and its CRC",
+            gap
+        )));
+    } else {
+        patch.resize(size, 0x06); // NOP
+    }
+    Ok(())
+}
+
+/// Upper-bound estimate of the peak heap memory `create_patch_with` uses for
+/// inputs of the given sizes, not counting `old` and `new` themselves.
+///
+/// It adds up the signature map (one `SigEntry` per block of old, allocated
+/// in a per-bucket `Vec`, plus the hash table and its resize), the patch
+/// buffer (which may be over-allocated up to 2x while growing, and copied once
+/// more if an index or old hash is prepended) and the pending literal buffer.
+/// Actual use is normally well below this; it is meant for deciding whether
+/// to proceed or to pick a larger block size.
+#[cfg(feature = "std")]
+pub fn estimate_memory(old_len: u64, new_len: u64, opts: &PatchOptions) -> u64 {
+    let block_size = opts.block_size.max(1) as u64;
+    let blocks = old_len.div_ceil(block_size);
+    // a fresh Vec holds at least 4 entries; the table stores key + Vec + control
+    // byte at 7/8 load and briefly holds old and new tables while resizing
+    let entry = 4 * std::mem::size_of::<SigEntry>() as u64;
+    let bucket = (4 + std::mem::size_of::<Vec<SigEntry>>() as u64 + 1) * 8 / 7 * 2;
+    let mut signatures = blocks.saturating_mul(entry + bucket);
+    if opts.fuzzy_index && opts.near_miss_diff {
+        // a SimHash per block, and its index in 8 band buckets that may be
+        // over-allocated up to 2x while growing
+        signatures = signatures.saturating_add(blocks.saturating_mul(8 + 8 * 4 * 2));
+    }
+
+    // worst case every block is a literal ADD (or DIFF) with its record header
+    let records = new_len.div_ceil(block_size).saturating_add(1);
+    // a COPY_HASH record may be larger than the (short) block it replaces
+    let per_record = if opts.content_addressed { 37 } else { 17 } + if opts.record_crc { 4 } else { 0 };
+    let mut patch = new_len.saturating_add(records.saturating_mul(per_record));
+    if let Some(granularity) = opts.index_granularity {
+        patch = patch.saturating_add((new_len / granularity.max(1) + 1).saturating_mul(16) + 13);
+    }
+    if opts.old_hash {
+        patch = patch.saturating_add(69);
+    }
+    if opts.min_version {
+        patch = patch.saturating_add(9);
+    }
+    patch = patch.saturating_add(PATCH_HEADER_MAX_LEN as u64);
+    if let Some(size) = opts.pad_to {
+        patch = patch.max(size as u64);
+    }
+    let copies = 2 + opts.old_hash as u64 + opts.index_granularity.is_some() as u64;
+
+    signatures
+        .saturating_add(patch.saturating_mul(copies))
+        .saturating_add(block_size)
+}
+
+/// Patch format (simple custom):
+/// header: magic b"XDR1", version: u8 (`PATCH_HEADER_VERSION`), flags: u8, reserved: [u8; 2] (zero)
+///   flags bit 0: identity patch, the output is old and no records follow
+///   flags bit 1: COPY_HASH records carry BLAKE3 hashes instead of SHA-256
+///   flags bit 2: the patch ends in a CHECK record, see `add_output_check`
+///   flags bit 3: output_len: u64 follows, the total output of the records
+///     (checked once they run out), so the output can be allocated once
+///   flags bit 4: every record is followed by crc32: u32, the CRC-32 of its
+///     bytes, see `without_record_crcs`
+///   flags bit 5: block_size: u32, weak: u8, strong: u8, reserved: [u8; 2]
+///     follow the output length, the parameters the patch was made with;
+///     applying doesn't need them, see `header_params`
+/// then [records...] where each record is:
+/// opcode: u8 (0x00 = ADD, 0x01 = COPY)
+/// If ADD:
+///   length: u32 (little-endian)
+///   data: [length] bytes
+/// If ADD_SMALL (0x05, for literals of at most 255 bytes, see `write_add`):
+///   length: u8, data: [length] bytes
+/// If COPY:
+///   offset: u64 (little-endian)  // offset in old file
+///   length: u32 (little-endian)
+/// If RUN (0x02):
+///   byte: u8, length: u32          // `length` copies of `byte`, see `write_literal`
+/// If ADD_ZSTD (0x03, only emitted with `PatchOptions::compress`):
+///   length: u32, compressed_len: u32, [compressed_len] bytes  // zstd frame of the literals, see `add_zstd`
+/// If COPY_TARGET (0x04, only emitted with `PatchOptions::copy_target`):
+///   offset: u64, length: u32      // from the output so far, see `copy_target`
+/// If DIFF (0x10, only emitted with `PatchOptions::near_miss_diff`):
+///   offset: u64, length: u32      // block in old, as for COPY
+///   count: u32
+///   [(index: u32, delta: u8)...]  // out[index] = old[offset + index] + delta
+/// If COPY_CONST (0x11, only emitted with `PatchOptions::const_table`):
+///   index: u8, length: u32         // tile from the CONST_TABLE record, see `const_table`
+/// If COPY_HASH (0x12, only emitted with `PatchOptions::content_addressed`):
+///   hash: [u8; 32], length: u32    // block of old with this SHA-256 (header flag bit 1: BLAKE3), see `cas`
+/// If XOR_DELTA (0x13, only emitted with `PatchOptions::xor_delta`):
+///   offset: u64, length: u32, body_len: u32, body  // old range XOR body, see `xor_delta`
+/// An optional INDEX record (opcode 0x80) may precede the others, see `add_index`.
+/// NOP (0x06) and PADDING (0x84) records produce nothing, see `add_padding`.
+/// A MIN_VERSION record (0x86) may declare the oldest applier able to apply
+/// the patch, see `compat`.
+///
+/// Opcodes with the high bit (0x80) set are always followed by a u32 body
+/// length, so appliers that don't know them can skip them if asked to.
+/// Opcodes below 0x80 are critical and must be understood.
+///
+/// This is simple, versionable, and easy to apply.
+#[cfg(all(feature = "std", any(test, feature = "match-trace", feature = "vcdiff")))]
+fn create_patch_bytes(old: &[u8], new: &[u8], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+    let mut scratch = Scratch {
+        out: Vec::with_capacity(new.len() / 4),
+        ..Scratch::default()
+    };
+    create_patch_scratch(old, new, opts, &mut scratch, None, None)?;
+    Ok(scratch.out)
+}
+
+/// `create_patch_bytes` into `scratch.out`, reusing whatever `scratch` has
+/// already allocated, reporting progress to `on_progress` and asking
+/// `is_cancelled` whether to stop as `match_blocks` does.
+#[cfg(feature = "std")]
+pub(crate) fn create_patch_scratch(
+    old: &[u8],
+    new: &[u8],
+    opts: &PatchOptions,
+    scratch: &mut Scratch,
+    on_progress: Option<&mut dyn FnMut(usize, usize)>,
+    is_cancelled: Option<&dyn Fn() -> bool>,
+) -> Result<(), XDeltaError> {
+    let block_size = opts.block_size;
+    if block_size == 0 {
+        return Err(XDeltaError::InvalidArg("block_size must be > 0".into()));
+    }
+    if whole_copy_allowed(old, new, opts) {
+        // identical inputs the caller didn't take an identity patch for: one
+        // COPY of everything, without signing old or walking new
+        scratch.out.clear();
+        write_copy(&mut scratch.out, 0, new.len(), |_, _| {});
+        if let Some(f) = on_progress {
+            f(new.len(), new.len());
+        }
+        return Ok(());
+    }
+    if let Some(body) = xor_delta_body(old, new, opts) {
+        scratch.out.clear();
+        xor_delta::write_record(&mut scratch.out, 0, new.len() as u32, &body);
+        #[cfg(debug_assertions)]
+        debug_check_patch(old, new, &scratch.out, opts, &scratch.sigs);
+        if let Some(f) = on_progress {
+            f(new.len(), new.len());
+        }
+        return Ok(());
+    }
+    opts.build_signatures(&mut scratch.sigs, old);
+    let mut fell_back = false;
+    let mut on_fallback = || fell_back = true;
+    let on_progress = on_progress.map(|f| f as &mut dyn FnMut(usize, usize));
+    let hooks = MatchHooks { on_progress, on_fallback: Some(&mut on_fallback), is_cancelled, ..MatchHooks::default() };
+    match_blocks(old, new, opts, &scratch.sigs, &mut scratch.out, &mut scratch.pending_add, hooks)?;
+    scratch.fell_back = fell_back;
+    #[cfg(debug_assertions)]
+    debug_check_patch(old, new, &scratch.out, opts, &scratch.sigs);
+    Ok(())
+}
+
+/// Whether `new` is `old` and goes out as one COPY of all of it: not for a
+/// content-addressed patch, which has no offsets into old, nor below
+/// `min_match`. The slice comparison checks the lengths first, so inputs of
+/// different lengths cost nothing.
+#[cfg(feature = "std")]
+fn whole_copy_allowed(old: &[u8], new: &[u8], opts: &PatchOptions) -> bool {
+    !opts.content_addressed && !new.is_empty() && new.len() >= opts.effective_min_match() && old == new
+}
+
+/// The XOR_DELTA body turning `old` into `new` if `opts.xor_delta` asks for
+/// one and the inputs qualify.
+#[cfg(feature = "std")]
+fn xor_delta_body(old: &[u8], new: &[u8], opts: &PatchOptions) -> Option<Vec<u8>> {
+    if !opts.xor_delta || opts.content_addressed || old.len() != new.len() || u32::try_from(new.len()).is_err() {
+        return None;
+    }
+    xor_delta::encode(old, new, new.len() / 16).filter(|body| u32::try_from(body.len()).is_ok())
+}
+
+/// Debug builds apply every bare patch to `old` as soon as it is made and
+/// panic if it doesn't give back `new`, so a matcher bug shows up at the
+/// input that triggers it rather than as a corrupt output downstream.
+///
+/// Skipped with `dirty_blocks`, whose clean blocks are the caller's word
+/// rather than the matcher's.
+#[cfg(all(feature = "std", debug_assertions))]
+fn debug_check_patch(old: &[u8], new: &[u8], patch: &[u8], opts: &PatchOptions, sigs: &HashMap<u32, Vec<SigEntry>>) {
+    if opts.dirty_blocks.is_some() {
+        return;
+    }
+    let mut patch = with_header(patch);
+    // the records alone, without the CHECK record and CRCs finish_patch adds
+    patch[5] |= opts.header_flags() & !(PATCH_FLAG_CHECK | PATCH_FLAG_RECORD_CRC);
+    let patch = &patch;
+    let rebuilt = if opts.content_addressed {
+        let blocks: HashMap<&[u8; 32], u64> =
+            sigs.values().flatten().map(|e| (&e.strong_hash, e.block_index)).collect();
+        cas::apply_cas(patch, |hash| {
+            let start = *blocks.get(hash)? as usize * opts.block_size;
+            old.get(start..usize::min(start + opts.block_size, old.len()))
+        })
+    } else {
+        apply_patch_bytes(old, patch)
+    };
+    if let Some(msg) = reconstruction_error(rebuilt, new) {
+        panic!("{} (old {} bytes, {:?})", msg, old.len(), opts);
+    }
+}
+
+/// Callers' views into `match_blocks` as it runs.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct MatchHooks<'a> {
+    /// Told about every COPY record as it is emitted.
+    pub(crate) on_match: Option<&'a mut dyn FnMut(CopyMatch)>,
+    /// Told `(processed, new.len())` at every cancellation check, and once
+    /// more when all of new is encoded.
+    pub(crate) on_progress: Option<&'a mut dyn FnMut(usize, usize)>,
+    /// Told every time a window of new is looked up by its weak checksum.
+    pub(crate) on_weak_lookup: Option<&'a mut dyn FnMut()>,
+    /// Told every time a window of new is strong-hashed.
+    pub(crate) on_strong_hash: Option<&'a mut dyn FnMut()>,
+    /// Told about every block of old a strong-hashed window is compared with.
+    pub(crate) on_candidate: Option<&'a mut dyn FnMut()>,
+    /// Told if the records outgrow `PatchOptions::max_ratio` and are
+    /// replaced by all of new as a literal.
+    pub(crate) on_fallback: Option<&'a mut dyn FnMut()>,
+    /// Asked at every cancellation check, along with `PatchOptions::cancel_token`,
+    /// whether to stop with `XDeltaError::Cancelled`.
+    pub(crate) is_cancelled: Option<&'a dyn Fn() -> bool>,
+}
+
+/// The matcher proper: encode `new` into `out` against `old`, whose block
+/// signatures at `opts.block_size` are `sigs`, telling `hooks` about it.
+#[cfg(feature = "std")]
+fn match_blocks<O: OldBytes + ?Sized>(
+    old: &O,
+    new: &[u8],
+    opts: &PatchOptions,
+    sigs: &HashMap<u32, Vec<SigEntry>>,
+    out: &mut Vec<u8>,
+    pending_add: &mut Vec<u8>,
+    hooks: MatchHooks,
+) -> Result<(), XDeltaError> {
+    match opts.weak_checksum {
+        WeakAlgo::Rolling => match_blocks_with::<O, Rolling>(old, new, opts, sigs, out, pending_add, hooks),
+        WeakAlgo::Adler32 => match_blocks_with::<O, Adler32>(old, new, opts, sigs, out, pending_add, hooks),
+    }
+}
+
+/// `match_blocks` with `W`, the weak checksum `sigs` were built with, rolled
+/// through unmatched data.
+#[cfg(feature = "std")]
+fn match_blocks_with<O: OldBytes + ?Sized, W: WeakChecksum>(
+    old: &O,
+    new: &[u8],
+    opts: &PatchOptions,
+    sigs: &HashMap<u32, Vec<SigEntry>>,
+    out: &mut Vec<u8>,
+    pending_add: &mut Vec<u8>,
+    hooks: MatchHooks,
+) -> Result<(), XDeltaError> {
+    let MatchHooks {
+        mut on_match,
+        mut on_progress,
+        mut on_weak_lookup,
+        mut on_strong_hash,
+        mut on_candidate,
+        on_fallback,
+        is_cancelled,
+    } = hooks;
+    let block_size = opts.block_size;
+    let min_match = opts.effective_min_match();
+    if block_size > MAX_RECORD_LEN {
+        return Err(XDeltaError::InvalidArg("block_size must fit a record length (u32)".into()));
+    }
+    if opts.forward_only && opts.content_addressed {
+        return Err(XDeltaError::InvalidArg("forward_only needs the offsets content_addressed drops".into()));
+    }
+    if opts.max_ratio.is_some_and(|ratio| ratio.is_nan() || ratio <= 0.0) {
+        return Err(XDeltaError::InvalidArg("max_ratio must be above 0".into()));
+    }
+    if opts.sample_every == Some(0) {
+        return Err(XDeltaError::InvalidArg("sample_blocks must be at least 1".into()));
+    }
+    if opts.max_candidates == 0 {
+        return Err(XDeltaError::InvalidArg("max_candidates must be at least 1".into()));
+    }
+    if opts.alignment == Some(0) {
+        return Err(XDeltaError::InvalidArg("alignment must be at least 1".into()));
+    }
+    if opts.max_add_len == 0 {
+        return Err(XDeltaError::InvalidArg("max_add_len must be at least 1".into()));
+    }
+    let max_add = opts.effective_max_add_len();
+    // with `aligned`, how far apart the positions probed for a match are
+    let alignment = if opts.aligned { opts.alignment.unwrap_or(block_size) } else { 1 };
+    // with `sample_blocks`, a hit extends back over the unindexed blocks
+    // before it, into the literals still pending
+    let sampled = opts.sampled() && !opts.content_addressed && old.has_bytes();
+    // with `max_ratio`, the most bytes of records before matching stops
+    let budget = opts.max_ratio.map(|ratio| (new.len() as f64 * ratio) as usize);
+    out.clear();
+    let mut pos: usize = 0;
+    pending_add.clear();
+    // (offset in old, length) of a COPY that may still be extended
+    let mut pending_copy: Option<(u64, usize)> = None;
+    // old offset minus new offset of the last COPY/DIFF, where a near-miss is looked for
+    let mut diag: i64 = 0;
+    let mut next_cancel_check: usize = 0;
+    // weak checksum state of the full window at the given position, kept
+    // while sliding through unmatched data so each step is O(1)
+    let mut rolling: Option<(usize, W)> = None;
+    // a skipped tail block is not continued into either
+    let copyable_len = match opts.effective_tail_policy() {
+        TailPolicy::Skip => old.len() - old.len() % block_size,
+        _ => old.len(),
+    };
+    let fuzzy = (opts.fuzzy_index && opts.near_miss_diff && !opts.content_addressed && old.has_bytes())
+        .then(|| fuzzy::FuzzyIndex::new(old, block_size));
+    let weak_index = WeakIndex::new(sigs);
+    let large_buckets = index_large_buckets(sigs);
+    // with `crc_precheck`, the CRC-32 of every block of old as it was hashed
+    let block_crcs: Vec<u32> = if opts.crc_precheck && old.has_bytes() {
+        (0..old.len())
+            .step_by(block_size)
+            .map(|start| {
+                let block = old.bytes(start..usize::min(start + block_size, old.len()));
+                if block.len() < block_size && opts.effective_tail_policy() == TailPolicy::Pad {
+                    let mut padded = block.into_owned();
+                    padded.resize(block_size, TAIL_PAD);
+                    crc32::crc32(&padded)
+                } else {
+                    crc32::crc32(&block)
+                }
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    // consecutive unmatched positions, and where a novel_skip run ends
+    let mut misses: usize = 0;
+    // literal bytes slid over since a near-miss DIFF was last looked for
+    let mut unprobed: usize = 0;
+    let mut skip_until: usize = 0;
+    // whole blocks of new behind pos, by weak checksum, and where the next
+    // one to index starts; COPY_TARGET records copy from them
+    let copy_target = opts.copy_target && !opts.content_addressed;
+    let mut target_blocks: HashMap<u32, Vec<usize>> = HashMap::new();
+    let mut target_indexed: usize = 0;
+
+    // where in `out` the length of the last RUN record is, so a run carrying
+    // on past a periodic flush extends it
+    let last_run: Cell<Option<usize>> = Cell::new(None);
+    // where in old the last record reading it ended; with `forward_only`
+    // nothing may read old before that, nor before the pending COPY's end
+    let old_read_end: Cell<u64> = Cell::new(0);
+    let reachable = |offset: u64, pending: Option<(u64, usize)>| {
+        !opts.forward_only || offset >= pending.map_or(old_read_end.get(), |(start, len)| start + len as u64)
+    };
+    // helper to flush pending adds
+    let flush_add = |out: &mut Vec<u8>, pending: &mut Vec<u8>| {
+        let mut run = last_run.get();
+        write_literal(out, pending, &mut run, max_add);
+        last_run.set(run);
+        pending.clear();
+    };
+
+    // helper to flush a pending copy, which ends at `end` in new, after the
+    // literal bytes before it; one shorter than `min_match` joins them instead
+    let mut flush_copy = |out: &mut Vec<u8>, pending: &mut Option<(u64, usize)>, adds: &mut Vec<u8>, end: usize| {
+        if let Some((offset, len)) = pending.take() {
+            if len < min_match {
+                adds.extend_from_slice(&new[end - len..end]);
+                return;
+            }
+            flush_add(out, adds);
+            debug_assert!(
+                offset.checked_add(len as u64).is_some_and(|end| end <= old.len() as u64),
+                "COPY of {} bytes at {} runs past the end of old ({} bytes)",
+                len,
+                offset,
+                old.len()
+            );
+            old_read_end.set(offset + len as u64);
+            write_copy(out, offset, len, |done, n| {
+                if let Some(f) = on_match.as_mut() {
+                    f(CopyMatch {
+                        new_offset: (end - len + done) as u64,
+                        old_offset: offset + done as u64,
+                        len: n as u64,
+                    });
+                }
+            });
+        }
+    };
+
+    // caller-declared clean block at `pos`, if the dirty bitmap says so
+    let clean_at = |pos: usize| match &opts.dirty_blocks {
+        Some(bitmap) if !opts.content_addressed && pos.is_multiple_of(block_size) => {
+            let block = pos / block_size;
+            bitmap.get(block / 8).is_some_and(|b| b & (1 << (block % 8)) == 0)
+        }
+        _ => false,
+    };
+
+    // set once the records outgrow `budget`; what is left pending then
+    // may flush smaller (a run of literals as one RUN), so the size is not
+    // checked again
+    let mut over_budget = false;
+    while pos < new.len() {
+        if budget.is_some_and(|budget| out.len() + pending_add.len() > budget) {
+            over_budget = true;
+            break;
+        }
+        if pos >= next_cancel_check {
+            check_cancel(opts.cancel.as_ref())?;
+            if is_cancelled.is_some_and(|f| f()) {
+                return Err(XDeltaError::Cancelled);
+            }
+            if let Some(f) = on_progress.as_mut() {
+                f(pos, new.len());
+            }
+            next_cancel_check = pos + CANCEL_CHECK_INTERVAL;
+        }
+        let remaining = new.len() - pos;
+        let try_len = usize::min(block_size, remaining);
+
+        if clean_at(pos) && pos + try_len <= old.len() && reachable(pos as u64, pending_copy) {
+            match pending_copy {
+                Some((offset, len)) if offset as usize + len == pos => {
+                    pending_copy = Some((offset, len + try_len));
+                }
+                _ => {
+                    flush_copy(out, &mut pending_copy, pending_add, pos);
+                    pending_copy = Some((pos as u64, try_len));
+                }
+            }
+            diag = 0;
+            misses = 0;
+            pos += try_len;
+            continue;
+        }
+        if pos < skip_until {
+            // novel data: straight to ADD, up to the longest ADD record
+            flush_copy(out, &mut pending_copy, pending_add, pos);
+            if pending_add.len() >= max_add {
+                flush_add(out, pending_add);
+            }
+            let n = usize::min(skip_until, new.len()) - pos;
+            let n = usize::min(n, max_add - pending_add.len());
+            pending_add.extend_from_slice(&new[pos..pos + n]);
+            pos += n;
+            if pending_add.len() >= max_add {
+                flush_add(out, pending_add);
+            }
+            continue;
+        }
+        if try_len < 1 {
+            // shouldn't happen, but safety
+            pending_add.push(new[pos]);
+            pos += 1;
+            continue;
+        }
+
+        let window = &new[pos..pos + try_len];
+
+        // Cheap path: the previous COPY continues in old. Checking this
+        // first lets a run of matched blocks become a single COPY, no
+        // matter where in old the run starts.
+        if let (Some((offset, len)), false, true) = (pending_copy, opts.content_addressed, old.has_bytes()) {
+            let cont = offset as usize + len;
+            if cont + try_len <= copyable_len && *old.bytes(cont..cont + try_len) == *window {
+                pending_copy = Some((offset, len + try_len));
+                pos += try_len;
+                continue;
+            }
+        }
+
+        // a short window at the end of new is looked up the way the short
+        // last block of old was indexed: as it is, or padded to a block
+        let padded;
+        let key = if try_len < block_size && opts.effective_tail_policy() == TailPolicy::Pad {
+            padded = [window, &vec![TAIL_PAD; block_size - try_len]].concat();
+            &padded[..]
+        } else {
+            window
+        };
+        let weak = match rolling {
+            Some((at, r)) if at == pos && try_len == block_size => r.chksum(),
+            _ => {
+                let r = W::from_slice(key);
+                rolling = Some((pos, r));
+                r.chksum()
+            }
+        };
+        if let Some(f) = on_weak_lookup.as_mut() {
+            f();
+        }
+        let candidates = match opts.quality {
+            Quality::Skim(k) if k > 1 && (misses % block_size) % k != (misses / block_size) % k => None,
+            _ => weak_index.get(weak),
+        };
+        // a bucket with no block of the window's length, or with
+        // `crc_precheck` none whose CRC agrees, can't confirm the window,
+        // which then isn't strong-hashed; a large bucket is narrowed by the
+        // strong hash instead
+        let candidates = candidates.filter(|bucket| {
+            if large_buckets.contains_key(&weak) {
+                return true;
+            }
+            let crc = (!block_crcs.is_empty()).then(|| crc32::crc32(key));
+            bucket.iter().any(|e| e.len == key.len() && crc.is_none_or(|crc| block_crcs[e.block_index as usize] == crc))
+        });
+        let mut matched = false;
+        if let Some(vec) = candidates {
+            // Compute strong for this window and compare
+            let strong = block_strong_hash(key, opts.strong_hash);
+            if let Some(f) = on_strong_hash.as_mut() {
+                f();
+            }
+
+            // a large bucket is narrowed to the entries sharing the hash prefix first
+            let (scanned, narrowed): (&[SigEntry], &[&SigEntry]) = match large_buckets.get(&weak) {
+                Some(by_prefix) => (&[], by_prefix.get(&strong_prefix(&strong)).map_or(&[], Vec::as_slice)),
+                None => (vec, &[]),
+            };
+            let mut hits = scanned
+                .iter()
+                .chain(narrowed.iter().copied())
+                .take(opts.max_candidates)
+                .inspect(|_| {
+                    if let Some(f) = on_candidate.as_mut() {
+                        f();
+                    }
+                })
+                .filter(|e| e.strong_hash[..] == strong[..])
+                .filter(|e| reachable(e.block_index * block_size as u64, pending_copy));
+            // a block of old where the pending COPY ends, so it extends that
+            // COPY rather than starting one of its own; without old's bytes
+            // the continuation check above can't find it
+            let continues = |e: &SigEntry| {
+                pending_copy.is_some_and(|(offset, len)| offset + len as u64 == e.block_index * block_size as u64)
+            };
+            let hit = match opts.quality {
+                Quality::Best if !opts.content_addressed && old.has_bytes() => {
+                    // the first of the hits that runs on furthest past the
+                    // window, or of those running as far, the one continuing
+                    let mut best: Option<(&SigEntry, usize)> = None;
+                    for e in hits {
+                        let start = e.block_index as usize * block_size + try_len;
+                        let run = match_run(old, start, copyable_len, &new[pos + try_len..]);
+                        let better = |(b, longest): (&SigEntry, usize)| {
+                            run > longest || run == longest && continues(e) && !continues(b)
+                        };
+                        if best.is_none_or(better) {
+                            best = Some((e, run));
+                        }
+                    }
+                    best.map(|(e, _)| e)
+                }
+                // the hit continuing the pending COPY, or else the first
+                _ => match hits.next() {
+                    Some(first) if pending_copy.is_some() && !continues(first) => {
+                        Some(hits.find(|e| continues(e)).unwrap_or(first))
+                    }
+                    first => first,
+                },
+            };
+            if let Some(e) = hit {
+                // Found a match, which may jump anywhere in old, including
+                // backwards. Pending adds are flushed along with the COPY,
+                // or joined by it if it ends up shorter than min_match.
+                if opts.content_addressed {
+                    flush_add(out, pending_add);
+                    out.push(0x12); // COPY_HASH
+                    out.extend_from_slice(&e.strong_hash);
+                    out.extend_from_slice(&record_len(try_len));
+                } else {
+                    let offset_in_old: u64 = e.block_index * (block_size as u64);
+                    // a padded tail block copies only what old has
+                    let len = usize::min(try_len, old.len() - offset_in_old as usize);
+                    // a block starting where the pending COPY ends in old
+                    // extends it (pending adds all come before a pending
+                    // COPY, so no ADD goes between them)
+                    match pending_copy {
+                        Some((offset, pending_len)) if offset + pending_len as u64 == offset_in_old => {
+                            pending_copy = Some((offset, pending_len + len));
+                        }
+                        _ => {
+                            flush_copy(out, &mut pending_copy, pending_add, pos);
+                            // the literal bytes just before the hit that old
+                            // has just before the block, unindexed, join it
+                            let mut back = 0;
+                            if sampled {
+                                while let Some(&byte) = pending_add.get(pending_add.len().wrapping_sub(back + 1)) {
+                                    let at = offset_in_old as usize - back;
+                                    if at == 0 || !reachable(at as u64 - 1, None) || old.bytes(at - 1..at)[0] != byte {
+                                        break;
+                                    }
+                                    back += 1;
+                                }
+                                pending_add.truncate(pending_add.len() - back);
+                            }
+                            pending_copy = Some((offset_in_old - back as u64, back + len));
+                        }
+                    }
+                    if len < try_len {
+                        flush_copy(out, &mut pending_copy, pending_add, pos + len);
+                        pending_add.extend_from_slice(&window[len..]);
+                    }
+                    diag = offset_in_old as i64 - pos as i64;
+                }
+                pos += try_len;
+                misses = 0;
+                matched = true;
+            }
+        }
+
+        if !matched
+            && opts.near_miss_diff
+            && !opts.content_addressed
+            && old.has_bytes()
+            && (pending_add.is_empty() || pending_copy.is_some() || unprobed >= block_size)
+            && try_len == block_size
+        {
+            // bsdiff-style: the block on the current diagonal (or one the
+            // fuzzy index suggests) may differ in only a few bytes, which
+            // is far cheaper to send as deltas. Looked for once a block
+            // while sliding, not at every byte.
+            unprobed = 0;
+            let on_diag = usize::try_from(pos as i64 + diag).ok();
+            let near = on_diag
+                .into_iter()
+                .chain(fuzzy.iter().flat_map(|f| f.candidates(window)))
+                .filter(|&cand| cand + try_len <= old.len() && reachable(cand as u64, pending_copy))
+                .find_map(|cand| near_miss_deltas(&old.bytes(cand..cand + try_len), window).map(|d| (cand, d)));
+            if let Some((cand, deltas)) = near {
+                flush_copy(out, &mut pending_copy, pending_add, pos);
+                flush_add(out, pending_add);
+                out.push(0x10); // DIFF
+                out.extend_from_slice(&(cand as u64).to_le_bytes());
+                out.extend_from_slice(&record_len(try_len));
+                out.extend_from_slice(&((deltas.len() / 5) as u32).to_le_bytes());
+                out.extend_from_slice(&deltas);
+                old_read_end.set((cand + try_len) as u64);
+                diag = cand as i64 - pos as i64;
+                pos += try_len;
+                misses = 0;
+                continue;
+            }
+        }
+
+        if !matched && copy_target {
+            // new repeating itself with a short period right here: a
+            // COPY_TARGET from one period back, which apply expands from its
+            // own output. Only MIN_REPEAT_LEN bytes are compared per period
+            // before the shortest one repeating that far is followed to its
+            // end, and runs of one byte are left to RUN records.
+            let rest = &new[pos..usize::min(new.len(), pos.saturating_add(MAX_RECORD_LEN))];
+            let repeats = |period: usize, most: usize| {
+                rest.iter().zip(&new[pos - period..]).take(most).take_while(|(a, b)| a == b).count()
+            };
+            let periodic = (rest.len() >= MIN_REPEAT_LEN && rest[..MIN_REPEAT_LEN].iter().any(|&b| b != rest[0]))
+                .then(|| (2..=usize::min(MAX_REPEAT_PERIOD, pos)).find(|&p| repeats(p, MIN_REPEAT_LEN) == MIN_REPEAT_LEN))
+                .flatten()
+                .map(|period| (period, repeats(period, rest.len())))
+                .filter(|&(_, len)| len >= min_match);
+            if let Some((period, len)) = periodic {
+                flush_copy(out, &mut pending_copy, pending_add, pos);
+                flush_add(out, pending_add);
+                out.push(0x04); // COPY_TARGET
+                out.extend_from_slice(&((pos - period) as u64).to_le_bytes());
+                out.extend_from_slice(&record_len(len));
+                pos += len;
+                misses = 0;
+                continue;
+            }
+        }
+
+        if !matched && copy_target && try_len == block_size {
+            while target_indexed + block_size <= pos {
+                let block = &new[target_indexed..target_indexed + block_size];
+                target_blocks.entry(W::from_slice(block).chksum()).or_default().push(target_indexed);
+                target_indexed += block_size;
+            }
+            let earlier =
+                target_blocks.get(&weak).into_iter().flatten().find(|&&at| new[at..at + try_len] == *window);
+            // carry on past the block, reaching into what the record itself
+            // writes when new repeats with a short period
+            let repeat = earlier.map(|&from| {
+                let len = try_len
+                    + new[pos + try_len..]
+                        .iter()
+                        .zip(&new[from + try_len..])
+                        .take(MAX_RECORD_LEN - try_len)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                (from, len)
+            });
+            if let Some((from, len)) = repeat.filter(|&(_, len)| len >= min_match) {
+                flush_copy(out, &mut pending_copy, pending_add, pos);
+                flush_add(out, pending_add);
+                out.push(0x04); // COPY_TARGET
+                out.extend_from_slice(&(from as u64).to_le_bytes());
+                out.extend_from_slice(&record_len(len));
+                pos += len;
+                misses = 0;
+                continue;
+            }
+        }
+
+        if !matched && alignment > 1 {
+            // on to the next aligned position, the bytes up to it literal
+            flush_copy(out, &mut pending_copy, pending_add, pos);
+            let next = usize::min(new.len(), (pos / alignment + 1).saturating_mul(alignment));
+            pending_add.extend_from_slice(&new[pos..next]);
+            misses += next - pos;
+            unprobed += next - pos;
+            pos = next;
+            if pending_add.len() >= max_add {
+                flush_add(out, pending_add);
+            }
+        } else if !matched {
+            // sliding by 1 byte: add first byte to pending_add and continue
+            flush_copy(out, &mut pending_copy, pending_add, pos);
+            pending_add.push(new[pos]);
+            if let Some((at, r)) = rolling.as_mut() {
+                if *at == pos && pos + block_size < new.len() {
+                    r.roll(new[pos], new[pos + block_size]);
+                    *at = pos + 1;
+                }
+            }
+            pos += 1;
+            misses += 1;
+            unprobed += 1;
+            if let Some(limit) = opts.novel_skip {
+                // probe at least a block's worth of consecutive positions
+                // between skips, or a match could fall between the probes
+                let threshold = usize::max(limit, block_size);
+                if misses >= threshold {
+                    skip_until = pos + limit;
+                    misses = threshold - block_size;
+                }
+            }
+            // literals accumulate up to the longest ADD record, then go out
+            if pending_add.len() >= max_add {
+                flush_add(out, pending_add);
+            }
+        }
+    }
+
+    flush_copy(out, &mut pending_copy, pending_add, pos);
+
+    // flush remaining adds
+    flush_add(out, pending_add);
+
+    if over_budget || budget.is_some_and(|budget| out.len() > budget) {
+        // not worth it: all of new as literals instead
+        out.clear();
+        write_add(out, new, max_add);
+        if let Some(f) = on_fallback {
+            f();
+        }
+    }
+
+    if let Some(f) = on_progress {
+        f(new.len(), new.len());
+    }
+    Ok(())
+}
+
+/// How many bytes of old from `start` (but not past `end`) equal the start of
+/// `rest`, looking at most `MATCH_RUN_PROBE` bytes ahead.
+#[cfg(feature = "std")]
+fn match_run<O: OldBytes + ?Sized>(old: &O, start: usize, end: usize, rest: &[u8]) -> usize {
+    let n = usize::min(end.saturating_sub(start), usize::min(rest.len(), MATCH_RUN_PROBE));
+    if n == 0 {
+        return 0;
+    }
+    old.bytes(start..start + n).iter().zip(rest).take_while(|(a, b)| a == b).count()
+}
+
+/// Encode `window` as per-byte deltas against `base` if only a few bytes differ.
+///
+/// Returns the packed `(index: u32, delta: u8)` entries of a DIFF record, or
+/// `None` when more than 1/16th of the bytes differ and an ADD is the better deal.
+#[cfg(feature = "std")]
+fn near_miss_deltas(base: &[u8], window: &[u8]) -> Option<Vec<u8>> {
+    let max_diffs = window.len() / 16;
+    let mut deltas = Vec::new();
+    for (i, (&o, &n)) in base.iter().zip(window).enumerate() {
+        if o != n {
+            if deltas.len() / 5 >= max_diffs {
+                return None;
+            }
+            deltas.extend_from_slice(&(i as u32).to_le_bytes());
+            deltas.push(n.wrapping_sub(o));
+        }
+    }
+    Some(deltas)
+}
+
+/// A single parsed patch record.
+// The fields only the std-side tools read (histograms, signatures) go unused without std.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+enum Record<'a> {
+    Add(&'a [u8]),
+    /// `len` literal bytes compressed as the zstd frame `data`, see `add_zstd`.
+    AddZstd { len: u32, data: &'a [u8] },
+    Copy { offset: u64, len: u32 },
+    /// `len` copies of `byte`.
+    Run { byte: u8, len: u32 },
+    /// `len` bytes of the output so far from `offset` on, see `copy_target`.
+    CopyTarget { offset: u64, len: u32 },
+    /// COPY of `len` bytes patched with packed `(index: u32, delta: u8)` entries.
+    Diff { offset: u64, len: u32, deltas: &'a [u8] },
+    /// `len` bytes of a repeated CONST_TABLE entry.
+    CopyConst { index: u8, len: u32 },
+    /// `len`-byte block identified by its SHA-256 rather than its place in old.
+    CopyHash { hash: &'a [u8], len: u32 },
+    /// COPY of `len` bytes XORed with the validated run-length encoded `body`.
+    Xor { offset: u64, len: u32, body: &'a [u8] },
+    /// Output-offset index; the body is kept raw and decoded on demand.
+    Index(&'a [u8]),
+    /// SHA-256 of all of old, then SHA-256 of the old bytes read by COPY/DIFF/XOR_DELTA records.
+    OldHash(&'a [u8]),
+    /// Validated CONST_TABLE body.
+    ConstTable(&'a [u8]),
+    /// Where a sub-patch's output starts in the full output, see `split`.
+    OutputOffset(u64),
+    /// NOP or PADDING, see `add_padding`.
+    Padding,
+    /// Block size the patch was created with.
+    BlockSize(u32),
+    /// Lowest format version able to apply the patch, already checked against ours.
+    MinVersion,
+    /// SHA-256 of the whole output, see `add_output_check`.
+    Check(&'a [u8]),
+    /// Unrecognized opcode with the skippable bit (0x80) set.
+    Skippable(u8),
+}
+
+impl Record<'_> {
+    /// Number of output bytes this record produces.
+    fn output_len(&self) -> u64 {
+        match self {
+            Record::Add(data) => data.len() as u64,
+            Record::AddZstd { len, .. }
+            | Record::Copy { len, .. }
+            | Record::Diff { len, .. }
+            | Record::CopyConst { len, .. }
+            | Record::CopyHash { len, .. }
+            | Record::Xor { len, .. }
+            | Record::Run { len, .. }
+            | Record::CopyTarget { len, .. } => *len as u64,
+            Record::Index(_)
+            | Record::OldHash(_)
+            | Record::ConstTable(_)
+            | Record::OutputOffset(_)
+            | Record::Padding
+            | Record::BlockSize(_)
+            | Record::MinVersion
+            | Record::Check(_)
+            | Record::Skippable(_) => 0,
+        }
+    }
+
+    /// Offset and length of the bytes of old this record reads, if any.
+    fn old_read(&self) -> Option<(u64, u32)> {
+        match *self {
+            Record::Copy { offset, len } | Record::Diff { offset, len, .. } | Record::Xor { offset, len, .. } => {
+                Some((offset, len))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn read_u32(patch: &[u8], pos: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&patch[pos..pos + 4]);
+    u32::from_le_bytes(b)
+}
+
+fn read_u64(patch: &[u8], pos: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&patch[pos..pos + 8]);
+    u64::from_le_bytes(b)
+}
+
+/// Whether `patch` holds `n` more bytes from `pos`. Lengths come from the
+/// patch, so `pos + n` is checked rather than left to wrap on 32-bit targets.
+fn fits(patch: &[u8], pos: usize, n: usize) -> bool {
+    pos.checked_add(n).is_some_and(|end| end <= patch.len())
+}
+
+/// Parse the record starting at `pos`, returning it and the position of the next one.
+fn read_record(patch: &[u8], mut pos: usize) -> Result<(Record<'_>, usize), XDeltaError> {
+    let opcode = patch[pos];
+    pos += 1;
+    match opcode {
+        0x00 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated ADD length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if len == 0 {
+                return Err(XDeltaError::MalformedPatch("zero-length ADD".into()));
+            }
+            if !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch("truncated ADD data".into()));
+            }
+            Ok((Record::Add(&patch[pos..pos + len]), pos + len))
+        }
+        0x05 => {
+            if !fits(patch, pos, 1) {
+                return Err(XDeltaError::MalformedPatch("truncated ADD_SMALL length".into()));
+            }
+            let len = patch[pos] as usize;
+            pos += 1;
+            if len == 0 {
+                return Err(XDeltaError::MalformedPatch("zero-length ADD_SMALL".into()));
+            }
+            if !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch("truncated ADD_SMALL data".into()));
+            }
+            Ok((Record::Add(&patch[pos..pos + len]), pos + len))
+        }
+        0x03 => {
+            if !fits(patch, pos, 8) {
+                return Err(XDeltaError::MalformedPatch("truncated ADD_ZSTD lengths".into()));
+            }
+            let len = read_u32(patch, pos);
+            let compressed_len = read_u32(patch, pos + 4) as usize;
+            pos += 8;
+            if len == 0 {
+                return Err(XDeltaError::MalformedPatch("zero-length ADD_ZSTD".into()));
+            }
+            if compressed_len == 0 || !fits(patch, pos, compressed_len) {
+                return Err(XDeltaError::MalformedPatch("truncated ADD_ZSTD data".into()));
+            }
+            Ok((Record::AddZstd { len, data: &patch[pos..pos + compressed_len] }, pos + compressed_len))
+        }
+        0x01 => {
+            if !fits(patch, pos, 8 + 4) {
+                return Err(XDeltaError::MalformedPatch("truncated COPY entry".into()));
+            }
+            let offset = read_u64(patch, pos);
+            let len = read_u32(patch, pos + 8);
+            if len == 0 {
+                return Err(XDeltaError::MalformedPatch("zero-length COPY".into()));
+            }
+            Ok((Record::Copy { offset, len }, pos + 12))
+        }
+        0x02 => {
+            if !fits(patch, pos, 1 + 4) {
+                return Err(XDeltaError::MalformedPatch("truncated RUN entry".into()));
+            }
+            Ok((Record::Run { byte: patch[pos], len: read_u32(patch, pos + 1) }, pos + 5))
+        }
+        0x04 => {
+            if !fits(patch, pos, 8 + 4) {
+                return Err(XDeltaError::MalformedPatch("truncated COPY_TARGET entry".into()));
+            }
+            let offset = read_u64(patch, pos);
+            let len = read_u32(patch, pos + 8);
+            Ok((Record::CopyTarget { offset, len }, pos + 12))
+        }
+        0x10 => {
+            if !fits(patch, pos, 8 + 4 + 4) {
+                return Err(XDeltaError::MalformedPatch("truncated DIFF entry".into()));
+            }
+            let offset = read_u64(patch, pos);
+            let len = read_u32(patch, pos + 8);
+            let count = read_u32(patch, pos + 12) as usize;
+            pos += 16;
+            if !count.checked_mul(5).is_some_and(|n| fits(patch, pos, n)) {
+                return Err(XDeltaError::MalformedPatch("truncated DIFF data".into()));
+            }
+            let deltas = &patch[pos..pos + count * 5];
+            Ok((Record::Diff { offset, len, deltas }, pos + count * 5))
+        }
+        0x11 => {
+            if !fits(patch, pos, 1 + 4) {
+                return Err(XDeltaError::MalformedPatch("truncated COPY_CONST entry".into()));
+            }
+            let index = patch[pos];
+            let len = read_u32(patch, pos + 1);
+            Ok((Record::CopyConst { index, len }, pos + 5))
+        }
+        0x12 => {
+            if !fits(patch, pos, 32 + 4) {
+                return Err(XDeltaError::MalformedPatch("truncated COPY_HASH entry".into()));
+            }
+            let len = read_u32(patch, pos + 32);
+            Ok((Record::CopyHash { hash: &patch[pos..pos + 32], len }, pos + 36))
+        }
+        0x13 => {
+            if !fits(patch, pos, 8 + 4 + 4) {
+                return Err(XDeltaError::MalformedPatch("truncated XOR_DELTA entry".into()));
+            }
+            let offset = read_u64(patch, pos);
+            let len = read_u32(patch, pos + 8);
+            let body_len = read_u32(patch, pos + 12) as usize;
+            pos += 16;
+            if !fits(patch, pos, body_len) || !xor_delta::validate(&patch[pos..pos + body_len], len) {
+                return Err(XDeltaError::MalformedPatch("malformed XOR_DELTA record".into()));
+            }
+            Ok((Record::Xor { offset, len, body: &patch[pos..pos + body_len] }, pos + body_len))
+        }
+        0x80 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated INDEX length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if !fits(patch, pos, len) || len < 8 || !(len - 8).is_multiple_of(16) {
+                return Err(XDeltaError::MalformedPatch("malformed INDEX record".into()));
+            }
+            Ok((Record::Index(&patch[pos..pos + len]), pos + len))
+        }
+        0x81 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated OLD_HASH length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if len != 64 || !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch("malformed OLD_HASH record".into()));
+            }
+            Ok((Record::OldHash(&patch[pos..pos + len]), pos + len))
+        }
+        0x82 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated CONST_TABLE length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if !fits(patch, pos, len) || !const_table::validate_table(&patch[pos..pos + len]) {
+                return Err(XDeltaError::MalformedPatch("malformed CONST_TABLE record".into()));
+            }
+            Ok((Record::ConstTable(&patch[pos..pos + len]), pos + len))
+        }
+        0x06 => Ok((Record::Padding, pos)),
+        0x7f => {
+            if !fits(patch, pos, 32) {
+                return Err(XDeltaError::MalformedPatch("truncated CHECK record".into()));
+            }
+            Ok((Record::Check(&patch[pos..pos + 32]), pos + 32))
+        }
+        0x83 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated OUTPUT_OFFSET length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if len != 8 || !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch("malformed OUTPUT_OFFSET record".into()));
+            }
+            Ok((Record::OutputOffset(read_u64(patch, pos)), pos + len))
+        }
+        0x84 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated PADDING length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch("truncated PADDING body".into()));
+            }
+            Ok((Record::Padding, pos + len))
+        }
+        0x85 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated BLOCK_SIZE length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if len != 4 || !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch("malformed BLOCK_SIZE record".into()));
+            }
+            Ok((Record::BlockSize(read_u32(patch, pos)), pos + len))
+        }
+        0x86 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch("truncated MIN_VERSION length".into()));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if len != 4 || !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch("malformed MIN_VERSION record".into()));
+            }
+            let version = read_u32(patch, pos);
+            if version > XDELTA_FORMAT_VERSION {
+                return Err(XDeltaError::InvalidArg(format!(
+                    "patch requires applier >= {}, this is {}",
+                    version, XDELTA_FORMAT_VERSION
+                )));
+            }
+            Ok((Record::MinVersion, pos + len))
+        }
+        other if other & 0x80 != 0 => {
+            if !fits(patch, pos, 4) {
+                return Err(XDeltaError::MalformedPatch(format!("truncated length of opcode {:#x}", other)));
+            }
+            let len = read_u32(patch, pos) as usize;
+            pos += 4;
+            if !fits(patch, pos, len) {
+                return Err(XDeltaError::MalformedPatch(format!("truncated body of opcode {:#x}", other)));
+            }
+            Ok((Record::Skippable(other), pos + len))
+        }
+        other => Err(XDeltaError::InvalidArg(format!("unknown opcode {:#x}", other))),
+    }
+}
+
+/// Total size of the record starting at `pos`, or `None` while `patch` ends
+/// before the length fields that tell it. Says nothing about whether the
+/// record is well-formed; `read_record` checks that once it is all present.
+#[cfg(feature = "std")]
+fn record_size(patch: &[u8], pos: usize) -> Option<usize> {
+    let field = |at: usize| (pos + at + 4 <= patch.len()).then(|| read_u32(patch, pos + at) as usize);
+    match patch[pos] {
+        0x00 => field(1).map(|len| 5usize.saturating_add(len)),
+        0x01 => Some(13),
+        0x02 => Some(6),
+        0x03 => field(5).map(|len| 9usize.saturating_add(len)),
+        0x04 => Some(13),
+        0x05 => patch.get(pos + 1).map(|&len| 2 + len as usize),
+        0x10 => field(13).map(|count| count.saturating_mul(5).saturating_add(17)),
+        0x11 => Some(6),
+        0x12 => Some(37),
+        0x13 => field(13).map(|len| 17usize.saturating_add(len)),
+        0x06 => Some(1),
+        0x7f => Some(33),
+        opcode if opcode & 0x80 != 0 => field(1).map(|len| 5usize.saturating_add(len)),
+        // unknown critical opcode: read_record rejects it as it stands
+        _ => Some(1),
+    }
+}
+
+/// Longest region one record can cover: lengths are u32 fields. Longer
+/// copies and literals are written as several consecutive records.
+#[cfg(feature = "std")]
+pub(crate) const MAX_RECORD_LEN: usize = u32::MAX as usize;
+
+/// The length field of a record covering `len` bytes. Callers split longer
+/// regions first, so this never truncates.
+#[cfg(feature = "std")]
+fn record_len(len: usize) -> [u8; 4] {
+    u32::try_from(len).expect("record length over MAX_RECORD_LEN").to_le_bytes()
+}
+
+/// Write a COPY of `len` bytes of old from `offset` as consecutive COPY
+/// records of at most `MAX_RECORD_LEN` bytes, calling `on_record` with where
+/// in the copy each one starts and how long it is.
+#[cfg(feature = "std")]
+pub(crate) fn write_copy(out: &mut Vec<u8>, offset: u64, len: usize, mut on_record: impl FnMut(usize, usize)) {
+    let mut done = 0usize;
+    while done < len {
+        let n = usize::min(len - done, MAX_RECORD_LEN);
+        on_record(done, n);
+        out.push(0x01); // COPY
+        out.extend_from_slice(&(offset + done as u64).to_le_bytes());
+        out.extend_from_slice(&record_len(n));
+        done += n;
+    }
+}
+
+/// Shortest repeat of one byte written as a RUN record. Anything shorter
+/// stays in the surrounding ADD, which splitting would cost a RUN record and
+/// a second ADD header.
+#[cfg(feature = "std")]
+const MIN_RUN: usize = 16;
+
+/// Write the literal bytes `data` as consecutive records of at most `max`
+/// (and `MAX_RECORD_LEN`) bytes each: ADD_SMALL, with its one-byte length,
+/// for one of at most 255, as scattered small edits leave, or else ADD.
+/// Writes nothing for no bytes.
+#[cfg(feature = "std")]
+pub(crate) fn write_add(out: &mut Vec<u8>, data: &[u8], max: usize) {
+    for chunk in data.chunks(max.clamp(1, MAX_RECORD_LEN)) {
+        if let Ok(len) = u8::try_from(chunk.len()) {
+            out.push(0x05); // ADD_SMALL
+            out.push(len);
+        } else {
+            out.push(0x00); // ADD
+            out.extend_from_slice(&record_len(chunk.len()));
+        }
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Write the literal bytes `data` with `write_add`, in records of at most
+/// `max_add` bytes, with every run of a single byte at least `MIN_RUN` long
+/// as a RUN record in between.
+///
+/// `last_run` is where the length of the last RUN record written to `out`
+/// is kept; if nothing has been written since, a run of the same byte at the
+/// start of `data` is added to it rather than starting a record of its own.
+#[cfg(feature = "std")]
+pub(crate) fn write_literal(out: &mut Vec<u8>, data: &[u8], last_run: &mut Option<usize>, max_add: usize) {
+    // start of the literal bytes not written yet
+    let mut start = 0usize;
+    let mut pos = 0usize;
+    while pos < data.len() {
+        let byte = data[pos];
+        let run = data[pos..].iter().take(MAX_RECORD_LEN).take_while(|&&b| b == byte).count();
+        let continued = last_run
+            .filter(|&at| pos == 0 && at + 4 == out.len() && out[at - 1] == byte)
+            .and_then(|at| read_u32(out, at).checked_add(run as u32).map(|len| (at, len)));
+        if let Some((at, len)) = continued {
+            out[at..].copy_from_slice(&len.to_le_bytes());
+            start = run;
+        } else if run >= MIN_RUN {
+            write_add(out, &data[start..pos], max_add);
+            out.push(0x02); // RUN
+            out.push(byte);
+            *last_run = Some(out.len());
+            out.extend_from_slice(&record_len(run));
+            start = pos + run;
+        }
+        pos += run;
+    }
+    write_add(out, &data[start..], max_add);
+}
+
+/// Follow each record of the bare records `patch` with its CRC-32, see
+/// `PatchOptions::record_crc`.
+#[cfg(feature = "std")]
+fn add_record_crcs(patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    let mut out = Vec::with_capacity(patch.len() + patch.len() / 4);
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let next = read_record(patch, pos)?.1;
+        out.extend_from_slice(&patch[pos..next]);
+        out.extend_from_slice(&crc32::crc32(&patch[pos..next]).to_le_bytes());
+        pos = next;
+    }
+    Ok(out)
+}
+
+/// `patch` with the CRC after each of its records checked, in order, and
+/// dropped, and the header flag announcing them cleared, for an applier to
+/// read as any other patch; one without them as it is. A record that fails
+/// its CRC, or is cut short or garbled so it can't be framed, is named by
+/// its index among the records.
+pub(crate) fn without_record_crcs(patch: &[u8]) -> Result<Cow<'_, [u8]>, XDeltaError> {
+    match crc_checked_prefix(patch)? {
+        (out, None) => Ok(out),
+        (_, Some((e, _))) => Err(e),
+    }
+}
+
+/// An error applying a patch, with the byte offset in the patch where it was found.
+type PatchFailure = (XDeltaError, usize);
+
+/// `without_record_crcs`, stopping at the first record that is cut short or
+/// fails its CRC: the patch of the records before it, and that record's
+/// error with the byte offset in `patch` where it starts.
+fn crc_checked_prefix(patch: &[u8]) -> Result<(Cow<'_, [u8]>, Option<PatchFailure>), XDeltaError> {
+    let records = header_records(patch)?;
+    if patch[5] & PATCH_FLAG_RECORD_CRC == 0 {
+        return Ok((Cow::Borrowed(patch), None));
+    }
+    let header_len = patch.len() - records.len();
+    let mut out = Vec::with_capacity(patch.len());
+    out.extend_from_slice(&patch[..header_len]);
+    out[5] &= !PATCH_FLAG_RECORD_CRC;
+    let (mut pos, mut index) = (0usize, 0u64);
+    while pos < records.len() {
+        let at = header_len + pos;
+        let next = match read_record(records, pos) {
+            Ok((_, next)) => next,
+            Err(e) => {
+                let e = XDeltaError::MalformedPatch(format!("record {} at byte {}: {}", index, at, e));
+                return Ok((Cow::Owned(out), Some((e, at))));
+            }
+        };
+        let Some(crc) = records.get(next..next + 4) else {
+            let e = XDeltaError::MalformedPatch(format!("record {} at byte {} has no CRC", index, at));
+            return Ok((Cow::Owned(out), Some((e, at))));
+        };
+        if crc != crc32::crc32(&records[pos..next]).to_le_bytes() {
+            let e = XDeltaError::ChecksumMismatch(format!("record {} at byte {} fails its CRC", index, at));
+            return Ok((Cow::Owned(out), Some((e, at))));
+        }
+        out.extend_from_slice(&records[pos..next]);
+        pos = next + 4;
+        index += 1;
+    }
+    Ok((Cow::Owned(out), None))
+}
+
+/// Magic opening every patch.
+const PATCH_MAGIC: &[u8; 4] = b"XDR1";
+
+/// Length of the patch header: magic, version, flags, two reserved bytes.
+pub(crate) const PATCH_HEADER_LEN: usize = 8;
+
+/// Header flag of an identity patch: the output is old itself, and no
+/// records follow.
+const PATCH_FLAG_IDENTITY: u8 = 1 << 0;
+
+/// Header flag of a content-addressed patch whose COPY_HASH records carry
+/// BLAKE3 rather than SHA-256 hashes, see `PatchOptions::strong_hash`.
+pub(crate) const PATCH_FLAG_BLAKE3: u8 = 1 << 1;
+
+/// Header flag of a patch ending in a CHECK record, see `add_output_check`.
+/// It lets an applier tell a patch cut short just before the record from
+/// one that never had it.
+pub(crate) const PATCH_FLAG_CHECK: u8 = 1 << 2;
+
+/// Header flag of a patch whose header goes on with the length of its
+/// output, a u64, so appliers can allocate the output once. Every created
+/// patch but an identity patch or one `create_patch_streaming` writes has
+/// it; needs format 6.
+const PATCH_FLAG_LENGTH: u8 = 1 << 3;
+
+/// Header flag of a patch whose records are each followed by their CRC-32,
+/// see `PatchOptions::record_crc`; needs format 7.
+const PATCH_FLAG_RECORD_CRC: u8 = 1 << 4;
+
+/// Header flag of a patch whose header goes on, after the output length,
+/// with the block size, weak checksum and strong hash it was made with.
+/// Every created patch but an identity patch has it; needs format 9.
+const PATCH_FLAG_PARAMS: u8 = 1 << 5;
+
+/// Header flag of a patch whose records read old front to back, see
+/// `PatchOptions::forward_only`; appliers check that they do. Needs format 10.
+const PATCH_FLAG_FORWARD_ONLY: u8 = 1 << 6;
+
+/// Header flag of a patch made from signatures of only some blocks of old,
+/// see `PatchOptions::sample_blocks`. Appliers don't need it; it tells why
+/// compression may be worse. Needs format 11.
+pub(crate) const PATCH_FLAG_SAMPLED: u8 = 1 << 7;
+
+/// Length of the parameters: block_size: u32, the `XDELTA_WEAK_*` id of the
+/// weak checksum and the `XDELTA_HASH_*` id of the strong hash as a u8
+/// each, and two reserved zero bytes.
+const PATCH_PARAMS_LEN: usize = 8;
+
+/// Length of the patch header with the output length and the parameters.
+#[cfg(feature = "std")]
+pub(crate) const PATCH_HEADER_MAX_LEN: usize = PATCH_HEADER_LEN + 8 + PATCH_PARAMS_LEN;
+
+/// Version of the patch header and record framing. It changes only if they
+/// do; which opcodes a patch needs is declared by MIN_VERSION, see `compat`.
+pub const PATCH_HEADER_VERSION: u8 = 1;
+
+/// `records` with the patch header in front.
+#[cfg(feature = "std")]
+pub(crate) fn with_header(records: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(PATCH_HEADER_LEN + records.len());
+    out.extend_from_slice(PATCH_MAGIC);
+    out.extend_from_slice(&[PATCH_HEADER_VERSION, 0, 0, 0]);
+    out.extend_from_slice(records);
+    out
+}
+
+/// `records`, which produce `output_len` bytes, with a patch header
+/// declaring that length and recording `params` in front.
+#[cfg(feature = "std")]
+fn with_params_header(records: &[u8], output_len: u64, params: &[u8; PATCH_PARAMS_LEN]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(PATCH_HEADER_MAX_LEN + records.len());
+    out.extend_from_slice(PATCH_MAGIC);
+    out.extend_from_slice(&[PATCH_HEADER_VERSION, PATCH_FLAG_LENGTH | PATCH_FLAG_PARAMS, 0, 0]);
+    out.extend_from_slice(&output_len.to_le_bytes());
+    out.extend_from_slice(params);
+    out.extend_from_slice(records);
+    out
+}
+
+/// The header of a patch recording `params` but no output length, for one
+/// written out before its records are all known, see `create_patch_streaming`.
+#[cfg(feature = "std")]
+pub(crate) fn params_header(params: &[u8; PATCH_PARAMS_LEN]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(PATCH_HEADER_LEN + PATCH_PARAMS_LEN);
+    out.extend_from_slice(PATCH_MAGIC);
+    out.extend_from_slice(&[PATCH_HEADER_VERSION, PATCH_FLAG_PARAMS, 0, 0]);
+    out.extend_from_slice(params);
+    out
+}
+
+/// The patch for `old == new`: a header with the identity flag and nothing else.
+#[cfg(feature = "std")]
+pub(crate) fn identity_patch() -> Vec<u8> {
+    let mut patch = with_header(&[]);
+    patch[5] = PATCH_FLAG_IDENTITY;
+    patch
+}
+
+/// Whether `patch`, whose header `patch_records` has accepted, is an
+/// identity patch. Its records are empty; appliers hand back old instead.
+pub(crate) fn is_identity(patch: &[u8]) -> bool {
+    patch[5] & PATCH_FLAG_IDENTITY != 0
+}
+
+/// Length of the header of `patch`, of which the first `PATCH_HEADER_LEN`
+/// bytes are there: longer if its flags say the output length or the
+/// parameters follow.
+pub(crate) fn header_len(patch: &[u8]) -> usize {
+    let mut len = PATCH_HEADER_LEN;
+    if patch[5] & PATCH_FLAG_LENGTH != 0 {
+        len += 8;
+    }
+    if patch[5] & PATCH_FLAG_PARAMS != 0 {
+        len += PATCH_PARAMS_LEN;
+    }
+    len
+}
+
+/// The output length the header of `patch`, which `patch_records` has
+/// accepted, declares, if it does.
+pub(crate) fn declared_output_len(patch: &[u8]) -> Option<u64> {
+    (patch[5] & PATCH_FLAG_LENGTH != 0).then(|| read_u64(patch, PATCH_HEADER_LEN))
+}
+
+/// The block size and the `XDELTA_WEAK_*` and `XDELTA_HASH_*` ids the
+/// header of `patch`, all of which is there, records, if it does.
+///
+/// Applying needs none of them: they are only checked against the rest of
+/// the patch, and ids this build doesn't know are let through.
+pub(crate) fn header_params(patch: &[u8]) -> Option<(u32, u8, u8)> {
+    let at = (patch[5] & PATCH_FLAG_PARAMS != 0).then(|| header_len(patch) - PATCH_PARAMS_LEN)?;
+    Some((read_u32(patch, at), patch[at + 4], patch[at + 5]))
+}
+
+/// Whether `patch`, whose header `patch_records` has accepted, ends in a
+/// CHECK record its output has to match.
+pub(crate) fn has_output_check(patch: &[u8]) -> bool {
+    patch[5] & PATCH_FLAG_CHECK != 0
+}
+
+/// The strong hash of the COPY_HASH records of `patch`, whose header
+/// `patch_records` has accepted.
+#[cfg(feature = "std")]
+pub(crate) fn patch_hash_algo(patch: &[u8]) -> Result<HashAlgo, XDeltaError> {
+    if patch[5] & PATCH_FLAG_BLAKE3 == 0 {
+        return Ok(HashAlgo::Sha256);
+    }
+    #[cfg(feature = "blake3")]
+    {
+        Ok(HashAlgo::Blake3)
+    }
+    #[cfg(not(feature = "blake3"))]
+    {
+        Err(XDeltaError::InvalidArg("patch hashes blocks with BLAKE3, which this build lacks (feature blake3)".into()))
+    }
+}
+
+/// Check the header of `patch` and return the records following it; those
+/// of a patch with per-record CRCs are refused, as their framing differs,
+/// see `without_record_crcs`.
+pub(crate) fn patch_records(patch: &[u8]) -> Result<&[u8], XDeltaError> {
+    let records = header_records(patch)?;
+    if patch[5] & PATCH_FLAG_RECORD_CRC != 0 {
+        return Err(XDeltaError::InvalidArg(
+            "patch has per-record CRCs, which only apply_patch, apply_to, apply_streaming and validate_patch read"
+                .into(),
+        ));
+    }
+    Ok(records)
+}
+
+/// Check the header of `patch` and return what follows it.
+fn header_records(patch: &[u8]) -> Result<&[u8], XDeltaError> {
+    if patch.len() < PATCH_HEADER_LEN {
+        return Err(XDeltaError::MalformedPatch(format!(
+            "truncated patch header: {} bytes, need {}",
+            patch.len(),
+            PATCH_HEADER_LEN
+        )));
+    }
+    if patch.starts_with(&[0x1f, 0x8b]) {
+        return Err(XDeltaError::MalformedPatch(
+            "patch is in a gzip container: apply_patch or decompress_patch (feature gzip) inflate it".into(),
+        ));
+    }
+    if &patch[..4] != PATCH_MAGIC {
+        return Err(XDeltaError::MalformedPatch(
+            "not an xdelta patch: no XDR1 header (patches made before the header was added must be re-created)".into(),
+        ));
+    }
+    if patch[4] != PATCH_HEADER_VERSION {
+        return Err(XDeltaError::InvalidArg(format!(
+            "unsupported patch header version {} (this applier reads {})",
+            patch[4], PATCH_HEADER_VERSION
+        )));
+    }
+    let known = PATCH_FLAG_IDENTITY
+        | PATCH_FLAG_BLAKE3
+        | PATCH_FLAG_CHECK
+        | PATCH_FLAG_LENGTH
+        | PATCH_FLAG_RECORD_CRC
+        | PATCH_FLAG_PARAMS
+        | PATCH_FLAG_FORWARD_ONLY
+        | PATCH_FLAG_SAMPLED;
+    if patch[5] & !known != 0 {
+        return Err(XDeltaError::MalformedPatch(format!("unknown patch header flags {:#x}", patch[5])));
+    }
+    if patch[6..PATCH_HEADER_LEN] != [0, 0] {
+        return Err(XDeltaError::MalformedPatch("reserved patch header bytes are not zero".into()));
+    }
+    let header_len = header_len(patch);
+    if patch.len() < header_len {
+        return Err(XDeltaError::MalformedPatch(format!(
+            "truncated patch header: {} bytes, need {} with the fields its flags announce",
+            patch.len(),
+            header_len
+        )));
+    }
+    if let Some((block_size, _, strong)) = header_params(patch) {
+        if patch[header_len - 2..header_len] != [0, 0] {
+            return Err(XDeltaError::MalformedPatch("reserved patch header bytes are not zero".into()));
+        }
+        if block_size == 0 {
+            return Err(XDeltaError::MalformedPatch("patch header records a block size of 0".into()));
+        }
+        if patch[5] & PATCH_FLAG_BLAKE3 != 0 && strong as u32 != XDELTA_HASH_BLAKE3 {
+            return Err(XDeltaError::MalformedPatch(format!(
+                "patch header flags BLAKE3 COPY_HASH records but records strong hash {}",
+                strong
+            )));
+        }
+    }
+    if is_identity(patch) && patch.len() > PATCH_HEADER_LEN {
+        return Err(XDeltaError::MalformedPatch(
+            "identity patch has records or an output length after its header".into(),
+        ));
+    }
+    Ok(&patch[header_len..])
+}
+
+/// Bounds-check `offset..offset + len` against an old of `old_len` bytes.
+///
+/// The arithmetic stays in u64 and the result is converted with
+/// `usize::try_from`, so offsets past 4 GiB are neither truncated on 32-bit
+/// targets nor allowed to wrap.
+pub(crate) fn old_range(old_len: usize, offset: u64, len: u64, what: &str) -> Result<Range<usize>, XDeltaError> {
+    let end = offset
+        .checked_add(len)
+        .ok_or_else(|| XDeltaError::OldOutOfRange(format!("{} range overflows", what)))?;
+    let (Ok(start), Ok(end)) = (usize::try_from(offset), usize::try_from(end)) else {
+        return Err(XDeltaError::OldOutOfRange(format!(
+            "{} offset {} does not fit in usize on this target",
+            what, offset
+        )));
+    };
+    if end > old_len {
+        return Err(XDeltaError::OldOutOfRange(format!("{} out of range", what)));
+    }
+    Ok(start..end)
+}
+
+/// Reconstruct the output of a DIFF record.
+fn apply_diff(old: &[u8], offset: u64, len: u32, deltas: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    let mut block = old[old_range(old.len(), offset, len as u64, "DIFF")?].to_vec();
+    apply_deltas(&mut block, deltas)?;
+    Ok(block)
+}
+
+/// Add the packed `(index: u32, delta: u8)` entries of a DIFF record to `block`.
+fn apply_deltas(block: &mut [u8], deltas: &[u8]) -> Result<(), XDeltaError> {
+    for entry in deltas.chunks_exact(5) {
+        let idx = read_u32(entry, 0) as usize;
+        if idx >= block.len() {
+            return Err(XDeltaError::MalformedPatch("DIFF index out of range".into()));
+        }
+        block[idx] = block[idx].wrapping_add(entry[4]);
+    }
+    Ok(())
+}
+
+/// Lazily applies a patch, yielding one output chunk per record.
+///
+/// COPY chunks borrow from `old` and ADD chunks borrow from `patch`, so the
+/// reconstructed output can be written out without buffering it or copying
+/// it an extra time. Iteration stops after the first error.
+pub fn apply_iter<'a>(
+    old: &'a [u8],
+    patch: &'a [u8],
+) -> impl Iterator<Item = Result<Cow<'a, [u8]>, XDeltaError>> {
+    let (iter, header_error) = match ApplyIter::for_patch(old, patch, false) {
+        Ok(iter) => (iter, None),
+        Err(e) => (ApplyIter::new(old, &[], false), Some(Err(e))),
+    };
+    header_error.into_iter().chain(iter)
+}
+
+/// The `len` bytes a COPY_TARGET record at the end of `written`, the output
+/// so far, produces: `written[offset..]` and on into its own output when
+/// `offset + len` reaches past the end of `written`, so a short `offset`
+/// back repeats the bytes in between like a run.
+fn copy_target(written: &[u8], offset: u64, len: u32) -> Result<Vec<u8>, XDeltaError> {
+    let start = usize::try_from(offset).ok().filter(|&start| start < written.len()).ok_or_else(|| {
+        XDeltaError::MalformedPatch(format!(
+            "COPY_TARGET offset {} not before output position {}",
+            offset,
+            written.len()
+        ))
+    })?;
+    let len = len as usize;
+    let mut out = Vec::with_capacity(len);
+    out.extend_from_slice(&written[start..usize::min(start.saturating_add(len), written.len())]);
+    // past the end of written, the source is what this record has produced
+    while out.len() < len {
+        let n = usize::min(out.len(), len - out.len());
+        out.extend_from_within(..n);
+    }
+    Ok(out)
+}
+
+/// Error for a COPY_TARGET record met by an apply path that doesn't keep the
+/// output it has produced.
+pub(crate) fn needs_output() -> XDeltaError {
+    XDeltaError::InvalidArg("COPY_TARGET record needs the output so far (apply_patch, apply_to into memory)".into())
+}
+
+/// Error for a COPY_HASH record met by an apply path without a block store.
+pub(crate) fn needs_resolver() -> XDeltaError {
+    XDeltaError::InvalidArg("COPY_HASH record needs a block resolver (apply_cas)".into())
+}
+
+/// Walks the records of a patch the way every in-memory apply does, checking
+/// everything that doesn't need old: framing, COPY_CONST against the
+/// CONST_TABLE in effect, unknown skippable opcodes, DIFF indexes and where
+/// COPY_TARGET reads from. Metadata records are consumed here; only records
+/// producing output are returned.
+pub(crate) struct RecordWalker<'a> {
+    patch: &'a [u8],
+    pos: usize,
+    /// Where the last record read, or failing to read, starts.
+    record_start: usize,
+    /// Ignore unknown skippable opcodes instead of failing on them.
+    skip_unknown: bool,
+    /// CONST_TABLE body, once seen.
+    consts: Option<&'a [u8]>,
+    /// Output produced by the records returned so far.
+    out_pos: u64,
+    /// The CHECK record the header may announce.
+    check: OutputCheck,
+}
+
+impl<'a> RecordWalker<'a> {
+    /// Walk the bare records `patch`.
+    pub(crate) fn new(patch: &'a [u8], skip_unknown: bool) -> Self {
+        RecordWalker {
+            patch,
+            pos: 0,
+            record_start: 0,
+            skip_unknown,
+            consts: None,
+            out_pos: 0,
+            check: OutputCheck::default(),
+        }
+    }
+
+    /// Walk the records of the whole `patch`, checking its header first.
+    pub(crate) fn for_patch(patch: &'a [u8], skip_unknown: bool) -> Result<Self, XDeltaError> {
+        let mut walker = RecordWalker::new(patch_records(patch)?, skip_unknown);
+        walker.check = OutputCheck::new(patch);
+        Ok(walker)
+    }
+
+    /// The next record producing output, checked.
+    pub(crate) fn next_record(&mut self) -> Result<Option<Record<'a>>, XDeltaError> {
+        while self.pos < self.patch.len() {
+            self.record_start = self.pos;
+            let (record, next) = read_record(self.patch, self.pos)?;
+            self.pos = next;
+            self.check.record(&record)?;
+            match record {
+                Record::ConstTable(body) => {
+                    self.consts = Some(body);
+                    continue;
+                }
+                Record::Index(_)
+                | Record::OldHash(_)
+                | Record::OutputOffset(_)
+                | Record::Padding
+                | Record::BlockSize(_)
+                | Record::MinVersion
+                | Record::Check(_) => continue,
+                Record::Skippable(opcode) => {
+                    if !self.skip_unknown {
+                        return Err(XDeltaError::InvalidArg(format!(
+                            "unknown skippable opcode {:#x} (skipping disabled)",
+                            opcode
+                        )));
+                    }
+                    continue;
+                }
+                Record::CopyConst { index, .. } => {
+                    const_table::const_entry(self.consts, index)?;
+                }
+                Record::Diff { len, deltas, .. } => {
+                    if deltas.chunks_exact(5).any(|entry| read_u32(entry, 0) >= len) {
+                        return Err(XDeltaError::MalformedPatch("DIFF index out of range".into()));
+                    }
+                }
+                Record::CopyTarget { offset, .. } => {
+                    if offset >= self.out_pos {
+                        return Err(XDeltaError::MalformedPatch(format!(
+                            "COPY_TARGET offset {} not before output position {}",
+                            offset, self.out_pos
+                        )));
+                    }
+                }
+                Record::Add(_)
+                | Record::AddZstd { .. }
+                | Record::Copy { .. }
+                | Record::Run { .. }
+                | Record::CopyHash { .. }
+                | Record::Xor { .. } => {}
+            }
+            self.out_pos += record.output_len();
+            return Ok(Some(record));
+        }
+        self.record_start = self.pos;
+        self.check.records_done()?;
+        Ok(None)
+    }
+}
+
+/// Check that `patch` is well-formed without old: the header, the framing
+/// of every record and the consistency checks applying makes before it
+/// reads old. Whether COPY ranges fit in old is left to apply.
+///
+/// ```
+/// let patch = xdelta::create_patch(b"hello world", b"hello there", 4).unwrap();
+/// assert!(xdelta::validate_patch(&patch).is_ok());
+/// assert!(xdelta::validate_patch(&patch[..patch.len() - 1]).is_err());
+/// ```
+pub fn validate_patch(patch: &[u8]) -> Result<(), XDeltaError> {
+    let patch = &*without_record_crcs(patch)?;
+    let mut records = RecordWalker::for_patch(patch, false)?;
+    while records.next_record()?.is_some() {}
+    Ok(())
+}
+
+/// One output-producing record of a patch, as `PatchReader` yields it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatchOp<'a> {
+    /// Literal bytes.
+    Add(&'a [u8]),
+    /// `len` literal bytes compressed as the zstd frame `data`; `inflate_add`
+    /// gives them back.
+    AddZstd { len: u32, data: &'a [u8] },
+    /// `len` bytes of old from `offset` on.
+    Copy { offset: u64, len: u32 },
+    /// `len` copies of `byte`.
+    Run { byte: u8, len: u32 },
+    /// `len` bytes of the output so far from `offset` on, which may run
+    /// into the bytes this record produces.
+    CopyTarget { offset: u64, len: u32 },
+    /// COPY of `len` bytes, with the packed `(index: u32, delta: u8)`
+    /// entries of `deltas` added to the bytes they index.
+    Diff { offset: u64, len: u32, deltas: &'a [u8] },
+    /// `len` bytes of `tile` repeated, the CONST_TABLE entry the record names.
+    CopyConst { tile: &'a [u8], len: u32 },
+    /// `len`-byte block known by its strong hash, see `apply_cas`.
+    CopyHash { hash: &'a [u8], len: u32 },
+    /// COPY of `len` bytes XORed with the run-length encoded `body`, see
+    /// the XOR_DELTA layout.
+    Xor { offset: u64, len: u32, body: &'a [u8] },
+}
+
+/// The `len` literal bytes of an ADD_ZSTD record whose zstd frame is `data`,
+/// as `PatchOp::AddZstd` gives them, failing with `MalformedPatch` unless
+/// the frame is well-formed and inflates to exactly `len` bytes. Builds
+/// without the `zstd` feature fail with `InvalidArg` on every such record.
+pub fn inflate_add(len: u32, data: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    #[cfg(feature = "zstd")]
+    {
+        add_zstd::inflate(len, data)
+    }
+    #[cfg(not(feature = "zstd"))]
+    {
+        let _ = (len, data);
+        Err(XDeltaError::InvalidArg("patch has ADD_ZSTD records, which this build lacks (feature zstd)".into()))
+    }
+}
+
+/// Iterator over the operations of a patch, borrowing from it.
+///
+/// Records are checked as every apply checks them, short of reading old:
+/// a malformed or unknown record ends the iteration with its error.
+/// Metadata records (index, hashes, constant table, padding, CHECK) are
+/// consumed on the way and never yielded. An identity patch yields nothing;
+/// see `is_identity`.
+///
+/// ```
+/// use xdelta::{PatchOp, PatchReader};
+///
+/// let patch = xdelta::create_patch(b"hello world", b"hello there", 4).unwrap();
+/// let mut copied = 0;
+/// for op in PatchReader::new(&patch).unwrap() {
+///     if let PatchOp::Copy { len, .. } = op.unwrap() {
+///         copied += len;
+///     }
+/// }
+/// assert_eq!(copied, 4); // "hell"
+/// ```
+pub struct PatchReader<'a> {
+    records: RecordWalker<'a>,
+    identity: bool,
+    failed: bool,
+}
+
+impl<'a> PatchReader<'a> {
+    /// Read the whole `patch`, checking its header first.
+    pub fn new(patch: &'a [u8]) -> Result<Self, XDeltaError> {
+        PatchReader::for_patch(patch, false)
+    }
+
+    /// Read the bare records `patch`.
+    fn records(patch: &'a [u8], skip_unknown: bool) -> Self {
+        PatchReader { records: RecordWalker::new(patch, skip_unknown), identity: false, failed: false }
+    }
+
+    fn for_patch(patch: &'a [u8], skip_unknown: bool) -> Result<Self, XDeltaError> {
+        let records = RecordWalker::for_patch(patch, skip_unknown)?;
+        Ok(PatchReader { records, identity: is_identity(patch), failed: false })
+    }
+
+    /// Whether the patch is an identity patch, whose output is old itself.
+    pub fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    /// The next operation, or `None` after the last one.
+    fn next_op(&mut self) -> Result<Option<PatchOp<'a>>, XDeltaError> {
+        let Some(record) = self.records.next_record()? else {
+            return Ok(None);
+        };
+        Ok(Some(match record {
+            Record::Add(data) => PatchOp::Add(data),
+            Record::AddZstd { len, data } => PatchOp::AddZstd { len, data },
+            Record::Copy { offset, len } => PatchOp::Copy { offset, len },
+            Record::Run { byte, len } => PatchOp::Run { byte, len },
+            Record::CopyTarget { offset, len } => PatchOp::CopyTarget { offset, len },
+            Record::Diff { offset, len, deltas } => PatchOp::Diff { offset, len, deltas },
+            Record::CopyConst { index, len } => {
+                PatchOp::CopyConst { tile: const_table::const_entry(self.records.consts, index)?, len }
+            }
+            Record::CopyHash { hash, len } => PatchOp::CopyHash { hash, len },
+            Record::Xor { offset, len, body } => PatchOp::Xor { offset, len, body },
+            Record::ConstTable(_)
+            | Record::Index(_)
+            | Record::OldHash(_)
+            | Record::OutputOffset(_)
+            | Record::Padding
+            | Record::BlockSize(_)
+            | Record::MinVersion
+            | Record::Check(_)
+            | Record::Skippable(_) => unreachable!("RecordWalker consumes metadata records"),
+        }))
+    }
+}
+
+impl<'a> Iterator for PatchReader<'a> {
+    type Item = Result<PatchOp<'a>, XDeltaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let r = self.next_op();
+        self.failed = r.is_err();
+        r.transpose()
+    }
+}
+
+struct ApplyIter<'a> {
+    old: &'a [u8],
+    ops: PatchReader<'a>,
+    failed: bool,
+    /// An identity patch whose single chunk, all of old, is still to come.
+    identity: bool,
+}
+
+impl<'a> ApplyIter<'a> {
+    /// Iterate over the bare records `patch`.
+    fn new(old: &'a [u8], patch: &'a [u8], skip_unknown: bool) -> Self {
+        ApplyIter {
+            old,
+            ops: PatchReader::records(patch, skip_unknown),
+            failed: false,
+            identity: false,
+        }
+    }
+
+    /// Iterate over the whole `patch`, header included.
+    fn for_patch(old: &'a [u8], patch: &'a [u8], skip_unknown: bool) -> Result<Self, XDeltaError> {
+        let ops = PatchReader::for_patch(patch, skip_unknown)?;
+        Ok(ApplyIter { old, identity: ops.is_identity(), ops, failed: false })
+    }
+
+    /// The next chunk of output. `written` is the output so far, which a
+    /// COPY_TARGET record reads; without it one fails with `needs_output`.
+    /// After the last chunk, the output is checked against the patch's CHECK
+    /// record, if it has one.
+    fn next_chunk(&mut self, written: Option<&[u8]>) -> Result<Option<Cow<'a, [u8]>>, XDeltaError> {
+        let chunk = self.next_unchecked(written)?;
+        match &chunk {
+            Some(chunk) => self.ops.records.check.update(chunk),
+            None => self.ops.records.check.finish()?,
+        }
+        Ok(chunk)
+    }
+
+    fn next_unchecked(&mut self, written: Option<&[u8]>) -> Result<Option<Cow<'a, [u8]>>, XDeltaError> {
+        if core::mem::take(&mut self.identity) {
+            return Ok(Some(Cow::Borrowed(self.old)));
+        }
+        let Some(op) = self.ops.next_op()? else {
+            return Ok(None);
+        };
+        let chunk = match op {
+            PatchOp::Add(data) => Cow::Borrowed(data),
+            PatchOp::AddZstd { len, data } => Cow::Owned(inflate_add(len, data)?),
+            PatchOp::Copy { offset, len } => {
+                let range = old_range(self.old.len(), offset, len as u64, "COPY")?;
+                Cow::Borrowed(&self.old[range])
+            }
+            PatchOp::Run { byte, len } => Cow::Owned(vec![byte; len as usize]),
+            PatchOp::CopyTarget { offset, len } => {
+                let written = written.ok_or_else(needs_output)?;
+                Cow::Owned(copy_target(written, offset, len)?)
+            }
+            PatchOp::Diff { offset, len, deltas } => Cow::Owned(apply_diff(self.old, offset, len, deltas)?),
+            PatchOp::Xor { offset, len, body } => {
+                let mut block = self.old[old_range(self.old.len(), offset, len as u64, "XOR_DELTA")?].to_vec();
+                xor_delta::xor_into(&mut block, body, 0);
+                Cow::Owned(block)
+            }
+            PatchOp::CopyConst { tile, len } => Cow::Owned(const_table::expand_const(tile, 0, len as usize)),
+            PatchOp::CopyHash { .. } => return Err(needs_resolver()),
+        };
+        Ok(Some(chunk))
+    }
+}
+
+impl<'a> Iterator for ApplyIter<'a> {
+    type Item = Result<Cow<'a, [u8]>, XDeltaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let r = self.next_chunk(None);
+        self.failed = r.is_err();
+        r.transpose()
+    }
+}
+
+/// Apply `patch` to `old`, returning the reconstructed new.
+///
+/// With the `gzip` feature a patch in a gzip container, see
+/// `compress_patch`, is inflated first. An empty patch, not even a header,
+/// gives an empty output whatever old is.
+///
+/// Errors are returned as they are, without touching the thread-local last
+/// error the C functions report through.
+///
+/// ```
+/// let patch = xdelta::create_patch(b"hello world", b"hello there", 4).unwrap();
+/// assert_eq!(xdelta::apply_patch(b"hello world", &patch).unwrap(), b"hello there");
+/// assert!(matches!(xdelta::apply_patch(b"hello world", b"junk"), Err(xdelta::XDeltaError::MalformedPatch(_))));
+/// ```
+pub fn apply_patch(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    #[cfg(feature = "gzip")]
+    if gzip::is_gzip(patch) {
+        return apply_patch_bytes(old, &gzip::decompress_patch(patch)?);
+    }
+    apply_patch_bytes(old, patch)
+}
+
+/// Like `apply_patch`, but failing with `XDeltaError::OutputLimitExceeded`
+/// before producing any output if the patch makes more than `max_output`
+/// bytes, so a small patch of huge RUN or COPY records can't exhaust memory.
+///
+/// ```
+/// let patch = xdelta::create_patch(b"hello world", b"hello there", 4).unwrap();
+/// assert_eq!(xdelta::apply_patch_limited(b"hello world", &patch, 11).unwrap(), b"hello there");
+/// let too_long = xdelta::apply_patch_limited(b"hello world", &patch, 10);
+/// assert!(matches!(too_long, Err(xdelta::XDeltaError::OutputLimitExceeded(10))));
+/// ```
+pub fn apply_patch_limited(old: &[u8], patch: &[u8], max_output: u64) -> Result<Vec<u8>, XDeltaError> {
+    apply_patch_bytes_ex(old, patch, false, Some(max_output))
+}
+
+/// Apply `patch` to `old` as `apply_patch` does, then check the output
+/// against `expected`, the SHA-256 of new obtained apart from the patch
+/// (from a signed manifest, say), failing with
+/// `XDeltaError::NewHashMismatch` if it differs. Unlike a CHECK record,
+/// which whoever wrote the patch wrote too, this doesn't trust the patch.
+///
+/// ```
+/// use sha2::{Digest, Sha256};
+///
+/// let patch = xdelta::create_patch(b"hello world", b"hello there", 4).unwrap();
+/// let expected: [u8; 32] = Sha256::digest(b"hello there").into();
+/// assert_eq!(xdelta::apply_patch_verify(b"hello world", &patch, expected).unwrap(), b"hello there");
+/// let wrong = xdelta::apply_patch_verify(b"hello world", &patch, [0; 32]);
+/// assert!(matches!(wrong, Err(xdelta::XDeltaError::NewHashMismatch)));
+/// ```
+pub fn apply_patch_verify(old: &[u8], patch: &[u8], expected: [u8; 32]) -> Result<Vec<u8>, XDeltaError> {
+    let out = apply_patch(old, patch)?;
+    if Sha256::digest(&out)[..] != expected {
+        return Err(XDeltaError::NewHashMismatch);
+    }
+    Ok(out)
+}
+
+/// Apply the patch at the start of `patch`, which may be followed by other
+/// bytes, returning the output and how many bytes of `patch` it took up.
+///
+/// The end is found from the header alone: an identity patch ends with it,
+/// a patch with an output check at its CHECK record, and any other patch
+/// once its records have produced the output length its header declares.
+/// A patch with neither a CHECK record nor a length is refused, as is one in
+/// a gzip container. Padding after the end is not counted: it can't be told
+/// from the bytes that follow. For an empty output, records that produce
+/// nothing are taken as long as they read as the patch's leading ones
+/// (INDEX, MIN_VERSION, BLOCK_SIZE, OLD_HASH, CONST_TABLE). An empty
+/// `patch` is, as for `apply_patch`, an empty output, taking up no bytes.
+///
+/// ```
+/// let patch = xdelta::create_patch(b"hello world", b"hello there", 4).unwrap();
+/// let mut stream = patch.clone();
+/// stream.extend_from_slice(b"trailing");
+/// let (new, consumed) = xdelta::apply_patch_prefix(b"hello world", &stream).unwrap();
+/// assert_eq!((new.as_slice(), consumed), (&b"hello there"[..], patch.len()));
+/// ```
+pub fn apply_patch_prefix(old: &[u8], patch: &[u8]) -> Result<(Vec<u8>, usize), XDeltaError> {
+    let len = patch_prefix_len(patch)?;
+    Ok((apply_patch_bytes(old, &patch[..len])?, len))
+}
+
+/// Apply `patch` to `old` as `apply_patch` does, but when a record turns out
+/// bad part way through, keep the output of the records before it rather
+/// than discarding it: the output reconstructed so far, and the error, or
+/// `None` if the whole patch applied. Meant for diagnosing failed updates;
+/// output returned with an error is at best a prefix of new and must not be
+/// used as new.
+///
+/// ```
+/// let patch = xdelta::create_patch(b"hello world", b"hello world!", 4).unwrap();
+/// let (out, err) = xdelta::apply_patch_lenient(b"hello world", &patch[..patch.len() - 1]);
+/// assert_eq!(out, b"hello wo"); // the COPY before the cut ADD "rld!"
+/// assert!(matches!(err, Some(xdelta::XDeltaError::MalformedPatch(_))));
+/// ```
+pub fn apply_patch_lenient(old: &[u8], patch: &[u8]) -> (Vec<u8>, Option<XDeltaError>) {
+    let (out, failure) = apply_patch_partial(old, patch);
+    (out, failure.map(|(e, _)| e))
+}
+
+/// `apply_patch_lenient`, with the error the byte offset in `patch` of the
+/// record it failed at: 0 for a bad header, the length of the patch for a
+/// failure found after the last record (a missing or mismatched CHECK). For
+/// a patch in a gzip container it is the offset in the inflated patch.
+fn apply_patch_partial(old: &[u8], patch: &[u8]) -> (Vec<u8>, Option<PatchFailure>) {
+    #[cfg(feature = "gzip")]
+    if gzip::is_gzip(patch) {
+        return match gzip::decompress_patch(patch) {
+            Ok(inflated) => apply_patch_partial(old, &inflated),
+            Err(e) => (Vec::new(), Some((e, 0))),
+        };
+    }
+    if patch.is_empty() {
+        return (Vec::new(), None);
+    }
+    let (records, bad_crc) = match crc_checked_prefix(patch) {
+        Ok(prefix) => prefix,
+        Err(e) => return (Vec::new(), Some((e, 0))),
+    };
+    let header_len = header_len(patch);
+    let mut iter = match ApplyIter::for_patch(old, &records, false) {
+        Ok(iter) => iter,
+        Err(e) => return (Vec::new(), Some((e, 0))),
+    };
+    let mut out = Vec::new();
+    if let Some(len) = declared_output_len(patch) {
+        let _ = usize::try_from(len).map(|len| out.try_reserve_exact(len));
+    }
+    loop {
+        match iter.next_chunk(Some(&out)) {
+            Ok(Some(chunk)) => out.extend_from_slice(&chunk),
+            Ok(None) => return (out, bad_crc),
+            Err(e) => {
+                let walker = &iter.ops.records;
+                // running out of records where a bad one was cut off is that record's failure
+                if walker.record_start == walker.patch.len() && bad_crc.is_some() {
+                    return (out, bad_crc);
+                }
+                let at = if patch[5] & PATCH_FLAG_RECORD_CRC != 0 {
+                    let crcs = 4 * count_records(&walker.patch[..walker.record_start]);
+                    header_len + walker.record_start + crcs
+                } else {
+                    header_len + walker.record_start
+                };
+                return (out, Some((e, at)));
+            }
+        }
+    }
+}
+
+/// How many records the bare, well-formed records `patch` hold.
+fn count_records(patch: &[u8]) -> usize {
+    let (mut pos, mut count) = (0usize, 0usize);
+    while pos < patch.len() {
+        let Ok((_, next)) = read_record(patch, pos) else { break };
+        pos = next;
+        count += 1;
+    }
+    count
+}
+
+/// Length of the patch at the start of `patch`, see `apply_patch_prefix`.
+fn patch_prefix_len(patch: &[u8]) -> Result<usize, XDeltaError> {
+    if patch.is_empty() {
+        return Ok(0);
+    }
+    if patch.len() < PATCH_HEADER_LEN {
+        // no flags for header_len to read; this says what's wrong
+        header_records(patch)?;
+    }
+    let header_len = header_len(patch);
+    header_records(&patch[..header_len.min(patch.len())])?;
+    if is_identity(patch) {
+        return Ok(header_len);
+    }
+    let checked = patch[5] & PATCH_FLAG_CHECK != 0;
+    let declared = declared_output_len(patch);
+    if !checked && declared.is_none() {
+        return Err(XDeltaError::InvalidArg(
+            "patch has neither an output length nor a CHECK record to find its end by".into(),
+        ));
+    }
+    let crc_len = if patch[5] & PATCH_FLAG_RECORD_CRC != 0 { 4 } else { 0 };
+    let mut pos = header_len;
+    let mut produced = 0u64;
+    loop {
+        if !checked && declared == Some(produced) {
+            let leading = produced == 0
+                && pos < patch.len()
+                && matches!(
+                    read_record(patch, pos),
+                    Ok((
+                        Record::Index(_)
+                            | Record::MinVersion
+                            | Record::BlockSize(_)
+                            | Record::OldHash(_)
+                            | Record::ConstTable(_),
+                        _
+                    ))
+                );
+            if !leading {
+                return Ok(pos);
+            }
+        }
+        if pos >= patch.len() {
+            return Err(XDeltaError::MalformedPatch(if checked {
+                "patch ends before its CHECK record".into()
+            } else {
+                format!("patch ends after {} of the {} output bytes it declares", produced, declared.unwrap_or(0))
+            }));
+        }
+        let (record, next) = read_record(patch, pos)?;
+        if !fits(patch, next, crc_len) {
+            return Err(XDeltaError::MalformedPatch(format!("record at byte {} has no CRC", pos)));
+        }
+        produced = produced
+            .checked_add(record.output_len())
+            .ok_or_else(|| XDeltaError::InvalidArg("output length overflows".into()))?;
+        pos = next + crc_len;
+        if matches!(record, Record::Check(_)) {
+            return Ok(pos);
+        }
+        if declared.is_some_and(|len| produced > len) {
+            return Err(XDeltaError::MalformedPatch(format!(
+                "records produce more than the {} output bytes the patch declares",
+                declared.unwrap_or(0)
+            )));
+        }
+    }
+}
+
+/// Apply the simple patch format to `old` -> produces reconstructed `new`.
+fn apply_patch_bytes(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    apply_patch_bytes_ex(old, patch, false, None)
+}
+
+/// `apply_patch_bytes`, optionally skipping unknown opcodes that are marked
+/// skippable, and refusing patches whose output is over `max_output` bytes.
+fn apply_patch_bytes_ex(
+    old: &[u8],
+    patch: &[u8],
+    skip_unknown: bool,
+    max_output: Option<u64>,
+) -> Result<Vec<u8>, XDeltaError> {
+    if patch.is_empty() {
+        return Ok(Vec::new());
+    }
+    let patch = &*without_record_crcs(patch)?;
+    let mut iter = ApplyIter::for_patch(old, patch, skip_unknown)?;
+    if let Some(limit) = max_output {
+        let len = if is_identity(patch) { old.len() as u64 } else { output_len(patch_records(patch)?)? };
+        if len > limit {
+            return Err(XDeltaError::OutputLimitExceeded(limit));
+        }
+    }
+    let mut out: Vec<u8> = Vec::new();
+    if let Some(len) = declared_output_len(patch) {
+        if let Some(limit) = max_output.filter(|&limit| len > limit) {
+            return Err(XDeltaError::OutputLimitExceeded(limit));
+        }
+        // a length the allocator refuses is a lie the records will expose;
+        // until they do, the output grows as it would without one
+        let _ = usize::try_from(len).map(|len| out.try_reserve_exact(len));
+    }
+    while let Some(chunk) = iter.next_chunk(Some(&out))? {
+        out.extend_from_slice(&chunk);
+    }
+    Ok(out)
+}
+
+/// Total output length of `patch`, bare records, from its records alone.
+pub(crate) fn output_len(patch: &[u8]) -> Result<u64, XDeltaError> {
+    let mut total = 0u64;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        total = total
+            .checked_add(record.output_len())
+            .ok_or_else(|| XDeltaError::InvalidArg("output length overflows".into()))?;
+        pos = next;
+    }
+    Ok(total)
+}
+
+/// Prepend an OLD_HASH record to `patch`.
+///
+/// OLD_HASH layout: opcode 0x81, body length: u32 (64), then
+///   full: [u8; 32]        // SHA-256 of all of old
+///   referenced: [u8; 32]  // SHA-256 of the old ranges read by COPY/DIFF/XOR_DELTA, in record order
+/// The second hash lets an applier that only reads copied ranges verify them.
+#[cfg(feature = "std")]
+fn add_old_hash(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    let mut referenced = Sha256::new();
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        if let Record::Copy { offset, len } | Record::Diff { offset, len, .. } | Record::Xor { offset, len, .. } = record {
+            referenced.update(&old[old_range(old.len(), offset, len as u64, "COPY")?]);
+        }
+        pos = next;
+    }
+
+    let mut out = Vec::with_capacity(5 + 64 + patch.len());
+    out.push(0x81); // OLD_HASH
+    out.extend_from_slice(&64u32.to_le_bytes());
+    out.extend_from_slice(&Sha256::digest(old));
+    out.extend_from_slice(&referenced.finalize());
+    out.extend_from_slice(patch);
+    Ok(out)
+}
+
+/// Append a CHECK record for the output `new` to `patch`.
+///
+/// CHECK layout: opcode 0x7f, then
+///   hash: [u8; 32]  // SHA-256 of the whole output
+/// It follows every record producing output; only records that produce
+/// none, such as INDEX or padding, may come after it. The opcode is critical
+/// rather than skippable, so an applier too old to verify the hash refuses
+/// the patch instead of ignoring it.
+#[cfg(feature = "std")]
+fn add_output_check(patch: &mut Vec<u8>, new: &[u8]) {
+    patch.push(0x7f); // CHECK
+    patch.extend_from_slice(&Sha256::digest(new));
+}
+
+/// The CHECK record of a patch being applied: where it may stand among the
+/// records, and the hash of the output to compare with it at the end. Every
+/// apply path feeds it each record it parses and, if it produces the whole
+/// output, each chunk of output.
+#[derive(Default)]
+pub(crate) struct OutputCheck {
+    /// Whether the header announces a CHECK record.
+    expected: bool,
+    /// Its hash, once seen.
+    hash: Option<[u8; 32]>,
+    /// SHA-256 of the output so far, until it is compared.
+    hasher: Option<Sha256>,
+    /// Output length the header declares, if it does.
+    declared_len: Option<u64>,
+    /// Block size the header records, if it does.
+    block_size: Option<u32>,
+    /// Of a forward-only patch, where in old the last record reading it ended.
+    old_read_end: Option<u64>,
+    /// Output of the records seen so far.
+    records_len: u64,
+}
+
+impl OutputCheck {
+    /// The check `patch`, whose header `patch_records` has accepted, asks for.
+    pub(crate) fn new(patch: &[u8]) -> Self {
+        let expected = has_output_check(patch);
+        OutputCheck {
+            expected,
+            hash: None,
+            hasher: expected.then(Sha256::new),
+            declared_len: declared_output_len(patch),
+            block_size: header_params(patch).map(|(block_size, _, _)| block_size),
+            old_read_end: (patch[5] & PATCH_FLAG_FORWARD_ONLY != 0).then_some(0),
+            records_len: 0,
+        }
+    }
+
+    /// Check that `record` is the announced CHECK record, seen once, or a
+    /// record that may come before or after it, that a BLOCK_SIZE record
+    /// agrees with the header, and that a forward-only patch doesn't read
+    /// old behind where it last did.
+    pub(crate) fn record(&mut self, record: &Record) -> Result<(), XDeltaError> {
+        self.records_len = self.records_len.saturating_add(record.output_len());
+        if let (Some(end), Some((offset, len))) = (self.old_read_end, record.old_read()) {
+            if offset < end {
+                return Err(XDeltaError::MalformedPatch(format!(
+                    "forward-only patch reads old at {}, before {} where its previous read ended",
+                    offset, end
+                )));
+            }
+            self.old_read_end = Some(offset.saturating_add(len as u64));
+        }
+        if let (Record::BlockSize(size), Some(header)) = (record, self.block_size) {
+            if *size != header {
+                return Err(XDeltaError::MalformedPatch(format!(
+                    "BLOCK_SIZE record says {}, the patch header {}",
+                    size, header
+                )));
+            }
+        }
+        if let Record::Check(hash) = record {
+            if !self.expected || self.hash.is_some() {
+                return Err(XDeltaError::MalformedPatch("CHECK record the header doesn't announce".into()));
+            }
+            let mut body = [0u8; 32];
+            body.copy_from_slice(hash);
+            self.hash = Some(body);
+        } else if self.hash.is_some() && record.output_len() > 0 {
+            return Err(XDeltaError::MalformedPatch("output record after the CHECK record".into()));
+        }
+        Ok(())
+    }
+
+    /// Fail if the records have run out before the announced CHECK record,
+    /// or have produced other than the output length the header declares.
+    pub(crate) fn records_done(&self) -> Result<(), XDeltaError> {
+        if self.expected && self.hash.is_none() {
+            return Err(XDeltaError::MalformedPatch("patch ends before its CHECK record".into()));
+        }
+        match self.declared_len {
+            Some(len) if len != self.records_len => Err(XDeltaError::MalformedPatch(format!(
+                "records produce {} bytes, the patch header declares {}",
+                self.records_len, len
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Account for the next `output` bytes.
+    pub(crate) fn update(&mut self, output: &[u8]) {
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(output);
+        }
+    }
+
+    /// After the last record and all of its output: compare the hash.
+    pub(crate) fn finish(&mut self) -> Result<(), XDeltaError> {
+        self.records_done()?;
+        if let (Some(hasher), Some(hash)) = (self.hasher.take(), self.hash) {
+            if hasher.finalize()[..] != hash[..] {
+                return Err(XDeltaError::ChecksumMismatch("output does not hash to the patch's CHECK record".into()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prepend an INDEX record to `patch` for random-access apply.
+///
+/// INDEX layout: opcode 0x80, body length: u32, then the body:
+///   granularity: u64
+///   entries: [(output_offset: u64, record_pos: u64)...]
+/// An entry is written for the first record starting at or past each
+/// multiple of `granularity` bytes of output. `record_pos` is relative to the
+/// end of the INDEX record, so the index does not depend on its own size.
+#[cfg(feature = "std")]
+fn add_index(patch: &[u8], granularity: u64) -> Result<Vec<u8>, XDeltaError> {
+    if granularity == 0 {
+        return Err(XDeltaError::InvalidArg("index granularity must be > 0".into()));
+    }
+    let mut body: Vec<u8> = Vec::new();
+    body.extend_from_slice(&granularity.to_le_bytes());
+    let mut pos = 0usize;
+    let mut out_pos: u64 = 0;
+    let mut next_mark: u64 = 0;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        if let Record::Index(_) = record {
+            return Err(XDeltaError::InvalidArg("patch is already indexed".into()));
+        }
+        if out_pos >= next_mark {
+            body.extend_from_slice(&out_pos.to_le_bytes());
+            body.extend_from_slice(&(pos as u64).to_le_bytes());
+            next_mark = (out_pos / granularity + 1) * granularity;
+        }
+        out_pos += record.output_len();
+        pos = next;
+    }
+
+    let mut out = Vec::with_capacity(5 + body.len() + patch.len());
+    out.push(0x80); // INDEX
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    out.extend_from_slice(patch);
+    Ok(out)
+}
+
+/// Find the CONST_TABLE among the metadata records that precede any output.
+#[cfg(feature = "std")]
+fn leading_const_table(patch: &[u8]) -> Result<Option<&[u8]>, XDeltaError> {
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        match record {
+            Record::ConstTable(body) => return Ok(Some(body)),
+            Record::Index(_)
+            | Record::OldHash(_)
+            | Record::OutputOffset(_)
+            | Record::Padding
+            | Record::BlockSize(_)
+            | Record::MinVersion
+            | Record::Skippable(_) => pos = next,
+            _ => break,
+        }
+    }
+    Ok(None)
+}
+
+/// Reconstruct only `len` bytes of the output starting at output offset `start`.
+///
+/// If the patch begins with an INDEX record, the scan starts at the closest
+/// indexed record at or before `start` instead of at the first record.
+#[cfg(feature = "std")]
+fn apply_range_bytes(old: &[u8], patch: &[u8], start: u64, len: usize) -> Result<Vec<u8>, XDeltaError> {
+    let records = patch_records(patch)?;
+    let end = start
+        .checked_add(len as u64)
+        .ok_or_else(|| XDeltaError::InvalidArg("range overflows".into()))?;
+    if is_identity(patch) {
+        if end > old.len() as u64 {
+            return Err(XDeltaError::InvalidArg("range exceeds patch output".into()));
+        }
+        return Ok(old[start as usize..end as usize].to_vec());
+    }
+    let patch = records;
+    let mut pos = 0usize;
+    let mut out_pos: u64 = 0;
+    let consts = leading_const_table(patch)?;
+
+    if !patch.is_empty() {
+        if let (Record::Index(body), records_start) = read_record(patch, 0)? {
+            pos = records_start;
+            let entries = &body[8..];
+            let count = entries.len() / 16;
+            // last entry whose output offset is <= start
+            let (mut lo, mut hi) = (0usize, count);
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                if read_u64(entries, mid * 16) <= start {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            if lo > 0 {
+                let entry = (lo - 1) * 16;
+                out_pos = read_u64(entries, entry);
+                let record_pos = usize::try_from(read_u64(entries, entry + 8)).ok();
+                pos = record_pos
+                    .and_then(|p| records_start.checked_add(p))
+                    .filter(|p| *p <= patch.len())
+                    .ok_or_else(|| XDeltaError::MalformedPatch("INDEX entry out of range".into()))?;
+            }
+        }
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    while out_pos < end && pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        pos = next;
+        let rec_len = record.output_len();
+        let rec_end = out_pos
+            .checked_add(rec_len)
+            .ok_or_else(|| XDeltaError::InvalidArg("output offset overflows".into()))?;
+        if rec_end > start {
+            let from = (start.max(out_pos) - out_pos) as usize;
+            let to = (end.min(rec_end) - out_pos) as usize;
+            match record {
+                Record::Add(data) => out.extend_from_slice(&data[from..to]),
+                Record::AddZstd { len, data } => out.extend_from_slice(&inflate_add(len, data)?[from..to]),
+                Record::Copy { offset, len } => {
+                    let range = old_range(old.len(), offset, len as u64, "COPY")?;
+                    out.extend_from_slice(&old[range.start + from..range.start + to]);
+                }
+                Record::Run { byte, .. } => out.resize(out.len() + (to - from), byte),
+                // the output before `start` is not reconstructed
+                Record::CopyTarget { .. } => return Err(needs_output()),
+                Record::Diff { offset, len, deltas } => {
+                    out.extend_from_slice(&apply_diff(old, offset, len, deltas)?[from..to]);
+                }
+                Record::Xor { offset, len, body } => {
+                    let range = old_range(old.len(), offset, len as u64, "XOR_DELTA")?;
+                    let at = out.len();
+                    out.extend_from_slice(&old[range.start + from..range.start + to]);
+                    xor_delta::xor_into(&mut out[at..], body, from);
+                }
+                Record::CopyConst { index, .. } => {
+                    let tile = const_table::const_entry(consts, index)?;
+                    out.extend_from_slice(&const_table::expand_const(tile, from, to));
+                }
+                Record::CopyHash { .. } => return Err(needs_resolver()),
+                Record::Index(_)
+                | Record::OldHash(_)
+                | Record::ConstTable(_)
+                | Record::OutputOffset(_)
+                | Record::Padding
+                | Record::BlockSize(_)
+                | Record::MinVersion
+                | Record::Check(_)
+                | Record::Skippable(_) => {}
+            }
+        }
+        if let Record::Skippable(opcode) = record {
+            return Err(XDeltaError::InvalidArg(format!("unknown skippable opcode {:#x}", opcode)));
+        }
+        out_pos = rec_end;
+    }
+    if (out.len() as u64) < len as u64 {
+        return Err(XDeltaError::InvalidArg("range exceeds patch output".into()));
+    }
+    Ok(out)
+}
+
+/// `XdeltaOptions::flags` bit: emit DIFF records for near-miss blocks.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_NEAR_MISS_DIFF: u32 = 1 << 0;
+
+/// `XdeltaOptions::flags` bit: record hashes of old for base verification.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_OLD_HASH: u32 = 1 << 1;
+
+/// `XdeltaOptions::flags` bit: encode literal tile runs via a constant table.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_CONST_TABLE: u32 = 1 << 2;
+
+/// `XdeltaOptions::flags` bit: reference old blocks by hash (COPY_HASH).
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_CONTENT_ADDRESSED: u32 = 1 << 3;
+
+/// `XdeltaOptions::flags` bit: record the block size (BLOCK_SIZE record).
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_EMBED_BLOCK_SIZE: u32 = 1 << 4;
+
+/// `XdeltaOptions::flags` bit: find near-miss blocks through a SimHash index.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_FUZZY_INDEX: u32 = 1 << 5;
+
+/// `XdeltaOptions::flags` bit: emit an XOR_DELTA for same-length, nearly equal inputs.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_XOR_DELTA: u32 = 1 << 6;
+
+/// `XdeltaOptions::flags` bit: declare the lowest applier version (MIN_VERSION record).
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_MIN_VERSION: u32 = 1 << 7;
+
+/// `XdeltaOptions::flags` bit: also COPY from the new encoded so far (COPY_TARGET records).
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_COPY_TARGET: u32 = 1 << 8;
+
+/// `XdeltaOptions::flags` bit: end with the SHA-256 of new (CHECK record), verified on apply.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_OUTPUT_CHECK: u32 = 1 << 9;
+
+/// `XdeltaOptions::flags` bit: find candidate blocks by Adler-32, see `WeakAlgo::Adler32`.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_ADLER32: u32 = 1 << 10;
+
+/// `XdeltaOptions::flags` bit: verify the patch reconstructs new before returning it, see `PatchOptions::strict`.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_STRICT: u32 = 1 << 11;
+
+/// `XdeltaOptions::flags` bit: follow every record with its CRC-32, see `PatchOptions::record_crc`.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_RECORD_CRC: u32 = 1 << 12;
+
+/// `XdeltaOptions::flags` bit: read old front to back only, see `PatchOptions::forward_only`.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_FORWARD_ONLY: u32 = 1 << 13;
+
+/// `XdeltaOptions::flags` bit: compare CRC-32s before strong hashes, see `PatchOptions::crc_precheck`.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_CRC_PRECHECK: u32 = 1 << 14;
+
+/// `XdeltaOptions::flags` bit: match only at multiples of `XdeltaOptions::alignment`, see `PatchOptions::aligned`.
+#[cfg(feature = "std")]
+pub const XDELTA_OPT_ALIGNED: u32 = 1 << 15;
+
+/// `XdeltaOptions::tail_policy` values, see `TailPolicy`.
+#[cfg(feature = "std")]
+pub const XDELTA_TAIL_AS_IS: u32 = 0;
+#[cfg(feature = "std")]
+pub const XDELTA_TAIL_PAD: u32 = 1;
+#[cfg(feature = "std")]
+pub const XDELTA_TAIL_SKIP: u32 = 2;
+
+/// `XdeltaOptions::quality` values, see `Quality`.
+#[cfg(feature = "std")]
+pub const XDELTA_QUALITY_FAST: u32 = 0;
+#[cfg(feature = "std")]
+pub const XDELTA_QUALITY_BEST: u32 = 1;
+/// `Quality::Skim` with `XdeltaOptions::probe_stride` as k.
+#[cfg(feature = "std")]
+pub const XDELTA_QUALITY_SKIM: u32 = 2;
+
+/// `xdelta_block_strong_hash` algorithms, see `HashAlgo`; also the strong
+/// hash patch headers record.
+pub const XDELTA_HASH_SHA256: u32 = 0;
+/// Needs the `blake3` feature; refused without it.
+pub const XDELTA_HASH_BLAKE3: u32 = 1;
+
+/// Weak checksums as patch headers record them, see `WeakAlgo`.
+pub const XDELTA_WEAK_ROLLING: u32 = 0;
+pub const XDELTA_WEAK_ADLER32: u32 = 1;
+
+/// `xdelta_apply_patch_data_ex` flag: skip unknown opcodes marked skippable.
+#[cfg(feature = "std")]
+pub const XDELTA_APPLY_SKIP_UNKNOWN: u32 = 1 << 0;
+
+/// `xdelta_create_patch_data_ex` flag: zstd-compress ADD data into ADD_ZSTD
+/// records, see `PatchOptions::compress`. Needs the `zstd` feature; refused
+/// without it.
+#[cfg(feature = "std")]
+pub const XDELTA_CREATE_COMPRESS: u32 = 1 << 0;
+
+/// `xdelta_create_patch_data_ex` flag: confirm block matches with BLAKE3
+/// instead of SHA-256, see `PatchOptions::strong_hash`. Needs the `blake3`
+/// feature; refused without it.
+#[cfg(feature = "std")]
+pub const XDELTA_CREATE_BLAKE3: u32 = 1 << 1;
+
+/// The `HashAlgo` of `XDELTA_HASH_*` id `algo`.
+#[cfg(feature = "std")]
+fn hash_algo_from_ffi(algo: u32) -> Result<HashAlgo, XDeltaError> {
+    match algo {
+        XDELTA_HASH_SHA256 => Ok(HashAlgo::Sha256),
+        #[cfg(feature = "blake3")]
+        XDELTA_HASH_BLAKE3 => Ok(HashAlgo::Blake3),
+        #[cfg(not(feature = "blake3"))]
+        XDELTA_HASH_BLAKE3 => {
+            Err(XDeltaError::InvalidArg("BLAKE3 needs the blake3 feature, which this build lacks".into()))
+        }
+        other => Err(XDeltaError::InvalidArg(format!("unknown hash algorithm {}", other))),
+    }
+}
+
+/// C mirror of `PatchOptions`. New fields are only ever appended.
+#[cfg(feature = "std")]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct XdeltaOptions {
+    /// sizeof(XdeltaOptions) as seen by the caller.
+    pub size: u32,
+    /// 0 to pick one from the old data's length, see `auto_block_size`.
+    pub block_size: u32,
+    /// XDELTA_OPT_* bits.
+    pub flags: u32,
+    /// Output index interval in bytes, 0 for no index.
+    pub index_granularity: u64,
+    /// Optional token from `xdelta_cancel_token_new`.
+    pub cancel: *const CancelToken,
+    /// Optional dirty-block bitmap, see `PatchOptions::dirty_blocks`.
+    pub dirty_bitmap: *const u8,
+    pub dirty_bitmap_len: usize,
+    /// Exact patch size to pad to, 0 for no padding.
+    pub pad_to: u64,
+    /// See `PatchOptions::novel_skip`, 0 to always probe.
+    pub novel_skip: u64,
+    /// XDELTA_TAIL_* value, see `TailPolicy`.
+    pub tail_policy: u32,
+    /// XDELTA_QUALITY_* value, see `Quality`.
+    pub quality: u32,
+    /// Probe every this many positions with XDELTA_QUALITY_SKIM.
+    pub probe_stride: u32,
+    /// See `PatchOptions::min_match`, 0 for the default.
+    pub min_match: u32,
+    /// See `PatchOptions::max_ratio`, 0 for no limit.
+    pub max_ratio: f64,
+    /// See `PatchOptions::sample_blocks`, 0 to index every block.
+    pub sample_blocks: u32,
+    /// See `PatchOptions::max_candidates`, 0 for the default.
+    pub max_candidates: u32,
+    /// See `PatchOptions::alignment`, 0 for the block size.
+    pub alignment: u32,
+    /// See `PatchOptions::max_add_len`, 0 for the default.
+    pub max_add_len: u32,
+}
+
+#[cfg(feature = "std")]
+impl Default for XdeltaOptions {
+    fn default() -> Self {
+        XdeltaOptions {
+            size: std::mem::size_of::<XdeltaOptions>() as u32,
+            block_size: 0,
+            flags: 0,
+            index_granularity: 0,
+            cancel: std::ptr::null(),
+            dirty_bitmap: std::ptr::null(),
+            dirty_bitmap_len: 0,
+            pad_to: 0,
+            novel_skip: 0,
+            tail_policy: XDELTA_TAIL_AS_IS,
+            quality: XDELTA_QUALITY_FAST,
+            probe_stride: 0,
+            min_match: 0,
+            max_ratio: 0.0,
+            sample_blocks: 0,
+            max_candidates: 0,
+            alignment: 0,
+            max_add_len: 0,
+        }
+    }
+}
+
+/// Largest `size` accepted for a caller-provided struct; anything bigger is
+/// almost certainly an uninitialized field rather than a newer layout.
+#[cfg(feature = "std")]
+const MAX_FFI_STRUCT_SIZE: u32 = 4096;
+
+/// `std::ptr::copy_nonoverlapping` of `n` bytes, with debug builds checking
+/// what it requires of caller-provided pointers: neither is null, and the
+/// ranges don't overlap.
+///
+/// # Safety
+///
+/// As for `copy_nonoverlapping`: `src` readable and `dst` writable for `n` bytes.
+#[cfg(feature = "std")]
+unsafe fn copy_bytes(src: *const u8, dst: *mut u8, n: usize) {
+    debug_assert!(!src.is_null() && !dst.is_null(), "null pointer in a copy of {} bytes", n);
+    debug_assert!(
+        (src as usize).saturating_add(n) <= dst as usize || (dst as usize).saturating_add(n) <= src as usize,
+        "overlapping copy of {} bytes from {:p} to {:p}",
+        n,
+        src,
+        dst
+    );
+    unsafe { std::ptr::copy_nonoverlapping(src, dst, n) }
+}
+
+/// The `len` bytes at a caller-provided `ptr`. An empty buffer may be
+/// passed as (NULL, 0); NULL with a nonzero length is a null-pointer error.
+///
+/// # Safety
+///
+/// Unless `len` is 0, `ptr` must be readable for `len` bytes for `'a`.
+#[cfg(feature = "std")]
+unsafe fn ffi_slice<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8], XDeltaError> {
+    if len == 0 {
+        Ok(&[])
+    } else if ptr.is_null() {
+        Err(XDeltaError::NullPointer)
+    } else {
+        Ok(unsafe { std::slice::from_raw_parts(ptr, len) })
+    }
+}
+
+/// Read a C struct whose first field is `size: u32` set by the caller.
+///
+/// Only the first `min(size, size_of::<T>())` bytes are read, so a struct from
+/// an older caller leaves the newer fields zeroed (zero means "default" for
+/// every appended field), and a newer caller's extra fields are ignored.
+/// `min_size` is the size of the first published layout of `T`.
+#[cfg(feature = "std")]
+fn read_sized<T: Copy + Default>(ptr: *const T, min_size: usize) -> Result<T, XDeltaError> {
+    let size = unsafe { std::ptr::read_unaligned(ptr as *const u32) };
+    if (size as usize) < min_size || size > MAX_FFI_STRUCT_SIZE {
+        return Err(XDeltaError::InvalidArg(format!("implausible struct size {}", size)));
+    }
+    let mut value = T::default();
+    let n = usize::min(size as usize, std::mem::size_of::<T>());
+    unsafe {
+        copy_bytes(ptr as *const u8, &mut value as *mut T as *mut u8, n);
+    }
+    Ok(value)
+}
+
+/// Write `value` into a caller-provided C struct whose first field is `size: u32`.
+///
+/// The counterpart of `read_sized` for output structs: only the first
+/// `min(size, size_of::<T>())` bytes are written, and `size` is left as the
+/// caller set it.
+#[cfg(feature = "std")]
+fn write_sized<T: Copy>(ptr: *mut T, value: T, min_size: usize) -> Result<(), XDeltaError> {
+    let size = unsafe { std::ptr::read_unaligned(ptr as *const u32) };
+    if (size as usize) < min_size || size > MAX_FFI_STRUCT_SIZE {
+        return Err(XDeltaError::InvalidArg(format!("implausible struct size {}", size)));
+    }
+    let n = usize::min(size as usize, std::mem::size_of::<T>());
+    unsafe {
+        copy_bytes((&value as *const T as *const u8).add(4), (ptr as *mut u8).add(4), n - 4);
+    }
+    Ok(())
+}
+
+/// Convert C options to `PatchOptions`; a null pointer means defaults.
+#[cfg(feature = "std")]
+fn options_from_ffi(opts: *const XdeltaOptions) -> Result<PatchOptions, XDeltaError> {
+    // a zero block_size, like the flat API's, asks for auto_block_size
+    if opts.is_null() {
+        return Ok(PatchOptions::default().block_size(0));
+    }
+    // size and block_size are the minimum a caller has to provide
+    let o = read_sized(opts, 8)?;
+    let mut p = PatchOptions::new()
+        .near_miss_diff(o.flags & XDELTA_OPT_NEAR_MISS_DIFF != 0)
+        .old_hash(o.flags & XDELTA_OPT_OLD_HASH != 0)
+        .const_table(o.flags & XDELTA_OPT_CONST_TABLE != 0)
+        .content_addressed(o.flags & XDELTA_OPT_CONTENT_ADDRESSED != 0)
+        .embed_block_size(o.flags & XDELTA_OPT_EMBED_BLOCK_SIZE != 0)
+        .fuzzy_index(o.flags & XDELTA_OPT_FUZZY_INDEX != 0)
+        .xor_delta(o.flags & XDELTA_OPT_XOR_DELTA != 0)
+        .min_version(o.flags & XDELTA_OPT_MIN_VERSION != 0)
+        .copy_target(o.flags & XDELTA_OPT_COPY_TARGET != 0)
+        .output_check(o.flags & XDELTA_OPT_OUTPUT_CHECK != 0)
+        .strict(o.flags & XDELTA_OPT_STRICT != 0)
+        .record_crc(o.flags & XDELTA_OPT_RECORD_CRC != 0)
+        .forward_only(o.flags & XDELTA_OPT_FORWARD_ONLY != 0)
+        .crc_precheck(o.flags & XDELTA_OPT_CRC_PRECHECK != 0)
+        .aligned(o.flags & XDELTA_OPT_ALIGNED != 0);
+    if o.flags & XDELTA_OPT_ADLER32 != 0 {
+        p = p.weak_checksum(WeakAlgo::Adler32);
+    }
+    p = p.block_size(o.block_size as usize);
+    if o.index_granularity != 0 {
+        p = p.index_granularity(o.index_granularity);
+    }
+    if let Some(token) = unsafe { o.cancel.as_ref() } {
+        p = p.cancel_token(token);
+    }
+    if o.pad_to != 0 {
+        let size = usize::try_from(o.pad_to).map_err(|_| XDeltaError::InvalidArg("pad size too large".into()))?;
+        p = p.pad_to(size);
+    }
+    p = p.tail_policy(match o.tail_policy {
+        XDELTA_TAIL_AS_IS => TailPolicy::AsIs,
+        XDELTA_TAIL_PAD => TailPolicy::Pad,
+        XDELTA_TAIL_SKIP => TailPolicy::Skip,
+        other => return Err(XDeltaError::InvalidArg(format!("unknown tail policy {}", other))),
+    });
+    p = p.quality(match o.quality {
+        XDELTA_QUALITY_FAST => Quality::Fast,
+        XDELTA_QUALITY_BEST => Quality::Best,
+        XDELTA_QUALITY_SKIM => Quality::Skim(o.probe_stride as usize),
+        other => return Err(XDeltaError::InvalidArg(format!("unknown quality {}", other))),
+    });
+    if o.novel_skip != 0 {
+        let misses = usize::try_from(o.novel_skip).map_err(|_| XDeltaError::InvalidArg("novel_skip too large".into()))?;
+        p = p.novel_skip(misses);
+    }
+    if o.min_match != 0 {
+        p = p.min_match(o.min_match as usize);
+    }
+    if o.max_ratio != 0.0 {
+        p = p.max_ratio(o.max_ratio);
+    }
+    if o.sample_blocks != 0 {
+        p = p.sample_blocks(o.sample_blocks as usize);
+    }
+    if o.max_candidates != 0 {
+        p = p.max_candidates(o.max_candidates as usize);
+    }
+    if o.alignment != 0 {
+        p = p.alignment(o.alignment as usize);
+    }
+    if o.max_add_len != 0 {
+        p = p.max_add_len(o.max_add_len as usize);
+    }
+    if !o.dirty_bitmap.is_null() {
+        p = p.dirty_blocks(unsafe { std::slice::from_raw_parts(o.dirty_bitmap, o.dirty_bitmap_len) });
+    }
+    Ok(p)
+}
+
+/// Hand a result buffer to the caller as a libc-allocated copy.
+#[cfg(feature = "std")]
+fn write_output<T: AsRef<[u8]>>(r: Result<T, XDeltaError>, out_data: *mut *mut u8, out_len: *mut usize) -> c_int {
+    // malloc(0) may return NULL, which is not a failure; ask for a byte
+    write_output_with(r, out_data, out_len, |len| allocator::ffi_malloc(len.max(1)))
+}
+
+/// `write_output` allocating through `alloc`. The out-params are written
+/// only once the copy is complete; on any failure the pointer is set to
+/// NULL and the length to 0 (where they aren't null themselves) and nothing
+/// is left allocated.
+#[cfg(feature = "std")]
+fn write_output_with<T: AsRef<[u8]>>(
+    r: Result<T, XDeltaError>,
+    out_data: *mut *mut u8,
+    out_len: *mut usize,
+    alloc: impl FnOnce(usize) -> *mut u8,
+) -> c_int {
+    let r = r.and_then(|data| {
+        let data = data.as_ref();
+        if out_data.is_null() || out_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        let buf = alloc(data.len());
+        if buf.is_null() {
+            return Err(XDeltaError::OutOfMemory);
+        }
+        unsafe {
+            copy_bytes(data.as_ptr(), buf, data.len());
+            *out_data = buf;
+            *out_len = data.len();
+        }
+        Ok(())
+    });
+    if r.is_err() {
+        unsafe {
+            if !out_data.is_null() {
+                *out_data = std::ptr::null_mut();
+            }
+            if !out_len.is_null() {
+                *out_len = 0;
+            }
+        }
+    }
+    ffi_status(r)
+}
+
+/// Report a result without an output buffer to the caller.
+#[cfg(feature = "std")]
+fn ffi_status(r: Result<(), XDeltaError>) -> c_int {
+    match r {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(&e);
+            e.code()
+        }
+    }
+}
+
+/// 创建补丁数据（内存版本）
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size: u32,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch(old_bytes, new_bytes, block_size as usize)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 创建补丁数据并写入调用方提供的缓冲区 out_buf（容量 out_cap 字节），不为输出分配内存
+/// 成功时 *out_written 为补丁长度；缓冲区不足时不写入任何数据，*out_written 为所需长度，
+/// 错误码为 XDELTA_ERR_BUFFER_TOO_SMALL
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data_into(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    out_buf: *mut u8,
+    out_cap: usize,
+    out_written: *mut usize,
+    block_size: u32,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if out_written.is_null() || (out_buf.is_null() && out_cap > 0) {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        let patch = create_patch(old_bytes, new_bytes, block_size as usize)?;
+        unsafe { *out_written = patch.len() };
+        if patch.len() > out_cap {
+            return Err(XDeltaError::BufferTooSmall { needed: patch.len() as u64, cap: out_cap as u64 });
+        }
+        unsafe { copy_bytes(patch.as_ptr(), out_buf, patch.len()) };
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
+
+/// 创建补丁数据，flags 为 XDELTA_CREATE_* 位；未知的位会被拒绝
+/// XDELTA_CREATE_COMPRESS 用 zstd 压缩新增数据为 ADD_ZSTD 记录（格式版本 12），需以 zstd 特性编译，否则设置时返回失败
+/// XDELTA_CREATE_BLAKE3 用 BLAKE3 代替 SHA-256 确认匹配块（补丁内容不变），需以 blake3 特性编译
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data_ex(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size: u32,
+    flags: u32,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        let known = XDELTA_CREATE_COMPRESS | XDELTA_CREATE_BLAKE3;
+        if flags & !known != 0 {
+            return Err(XDeltaError::InvalidArg(format!("unknown create flags {:#x}", flags & !known)));
+        }
+        #[cfg(not(feature = "zstd"))]
+        if flags & XDELTA_CREATE_COMPRESS != 0 {
+            return Err(XDeltaError::InvalidArg(
+                "XDELTA_CREATE_COMPRESS needs the zstd feature, which this build lacks".into(),
+            ));
+        }
+        let algo = if flags & XDELTA_CREATE_BLAKE3 != 0 {
+            hash_algo_from_ffi(XDELTA_HASH_BLAKE3)?
+        } else {
+            HashAlgo::Sha256
+        };
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        let opts = PatchOptions::new().block_size(block_size as usize).strong_hash(algo);
+        #[cfg(feature = "zstd")]
+        let opts = opts.compress(flags & XDELTA_CREATE_COMPRESS != 0);
+        create_patch_with(old_bytes, new_bytes, &opts)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 创建补丁数据，对仅有少量字节不同的块输出逐字节差值（DIFF）而不是整块新增
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data_diff(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size: u32,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        let opts = PatchOptions::new().block_size(block_size as usize).near_miss_diff(true);
+        create_patch_with(old_bytes, new_bytes, &opts)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 应用补丁数据（内存版本）
+/// 以 gzip 特性构建时，gzip 容器中的补丁（xdelta_compress_patch）先自动解压再应用；0 字节的补丁输出为空
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_apply_patch_data(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_patch(old_bytes, patch_bytes)
+    })();
+
+    write_output(r, new_data, new_len)
+}
+
+/// 使用选项结构体创建补丁数据，opts 为 NULL 时使用默认选项；opts->block_size 为 0（或 opts 为 NULL）时按旧数据长度自动选择块大小
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data_opts(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    opts: *const XdeltaOptions,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch_with(old_bytes, new_bytes, &options_from_ffi(opts)?)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 创建补丁数据，opts->block_size 为 0（或 opts 为 NULL）时按旧数据长度自动选择块大小（见 auto_block_size），
+/// 并以 BLOCK_SIZE 记录写入补丁；实际使用的块大小写入 *block_size_used（可为 NULL）
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_auto(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    opts: *const XdeltaOptions,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size_used: *mut u32,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+        let p = options_from_ffi(opts)?;
+        let used = p.resolve_block_size(old_len).block_size;
+        let used = u32::try_from(used).map_err(|_| XDeltaError::InvalidArg("block_size does not fit a u32".into()))?;
+
+        let patch = create_patch_with(old_bytes, new_bytes, &p)?;
+        if !block_size_used.is_null() {
+            unsafe { *block_size_used = used };
+        }
+        Ok(patch)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// C callback receiving one COPY record: where its bytes go in new, where
+/// they come from in old, and how many there are.
+#[cfg(feature = "std")]
+pub type XdeltaMatchFn = extern "C" fn(new_offset: u64, old_offset: u64, len: u64, ctx: *mut std::ffi::c_void);
+
+/// C callback receiving how many bytes of new the matcher has worked through
+/// out of `total`.
+#[cfg(feature = "std")]
+pub type XdeltaProgressFn = extern "C" fn(processed: usize, total: usize, ctx: *mut std::ffi::c_void);
+
+/// 创建补丁数据，并在匹配过程中调用 progress_cb（已处理的新数据字节数、新数据总长度），供界面显示进度
+/// 每 64KiB 新数据至多调用一次，processed 递增，最后一次为 processed == new_len；progress_cb 可为 NULL
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data_progress(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size: u32,
+    progress_cb: Option<XdeltaProgressFn>,
+    ctx: *mut std::ffi::c_void,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+        let opts = PatchOptions::new().block_size(block_size as usize);
+
+        match progress_cb {
+            Some(f) => create_patch_with_progress(old_bytes, new_bytes, &opts, |processed, total| {
+                f(processed, total, ctx)
+            }),
+            None => create_patch_with(old_bytes, new_bytes, &opts),
+        }
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 创建补丁数据，可由其它线程取消：cancel_flag 非 0 时在匹配过程中（每 64KiB 新数据检查一次）停止，
+/// 丢弃已生成的部分并返回 XDELTA_ERR_CANCELLED；cancel_flag 须以原子操作写入（C11 atomic_store 或
+/// __atomic_store_n），可为 NULL 表示不可取消
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data_cancelable(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size: u32,
+    cancel_flag: *const c_int,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+        let opts = PatchOptions::new().block_size(block_size as usize);
+        // the caller's int, which another thread stores to, read atomically
+        let flag = unsafe { (cancel_flag as *const AtomicI32).as_ref() };
+
+        let is_cancelled = || flag.is_some_and(|f| f.load(Ordering::Relaxed) != 0);
+        create_patch_hooked(old_bytes, new_bytes, &opts, None, Some(&is_cancelled))
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 创建补丁，并在生成每条 COPY 记录时调用 on_match（新数据偏移、旧数据偏移、长度），供调试/可视化工具使用
+/// on_match 为 NULL 时与 xdelta_create_patch_data_opts 相同；opts 为 NULL 时使用默认选项
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_matches(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    opts: *const XdeltaOptions,
+    on_match: Option<XdeltaMatchFn>,
+    ctx: *mut std::ffi::c_void,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+        let opts = options_from_ffi(opts)?;
+
+        match on_match {
+            Some(f) => create_patch_with_matches(old_bytes, new_bytes, &opts, |m| {
+                f(m.new_offset, m.old_offset, m.len, ctx)
+            }),
+            None => create_patch_with(old_bytes, new_bytes, &opts),
+        }
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 应用补丁数据，flags 为 XDELTA_APPLY_* 位
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_apply_patch_data_ex(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    flags: u32,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_patch_bytes_ex(old_bytes, patch_bytes, flags & XDELTA_APPLY_SKIP_UNKNOWN != 0, None)
+    })();
+
+    write_output(r, new_data, new_len)
+}
+
+/// 应用补丁数据，输出超过 max_output 字节时在生成任何输出之前返回 XDELTA_ERR_OUTPUT_LIMIT，
+/// 防止很小的补丁（巨大的 RUN/COPY 长度）耗尽内存
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_apply_patch_data_limited(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    max_output: u64,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_patch_limited(old_bytes, patch_bytes, max_output)
+    })();
+
+    write_output(r, new_data, new_len)
+}
+
+/// 应用补丁后计算输出的 SHA-256，与调用方另行获得（如来自签名清单）的 expected_sha256（32 字节）比较，
+/// 不符时返回 XDELTA_ERR_NEW_HASH_MISMATCH 且不返回输出；与补丁自带的 CHECK 记录不同，不依赖补丁本身可信
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_apply_patch_verify(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    expected_sha256: *const u8,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if expected_sha256.is_null() || new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+        let expected = unsafe { std::ptr::read_unaligned(expected_sha256 as *const [u8; 32]) };
+
+        apply_patch_verify(old_bytes, patch_bytes, expected)
+    })();
+
+    write_output(r, new_data, new_len)
+}
+
+/// 应用位于 patch_data 开头的补丁（其后可以跟着其它数据），并在 consumed 中写入补丁占用的字节数；
+/// 补丁的结尾由补丁头确定：带 CHECK 记录的补丁到 CHECK 记录为止，否则到记录产生补丁头声明的输出长度为止，
+/// 两者都没有的补丁返回 XDELTA_ERR_INVALID_ARG；结尾之后的填充不计入 consumed
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*），失败时 consumed 不被修改
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_apply_patch_prefix(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+    consumed: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<(Vec<u8>, usize), XDeltaError> {
+        if new_data.is_null() || new_len.is_null() || consumed.is_null()
+        {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_patch_prefix(old_bytes, patch_bytes)
+    })();
+
+    // consumed is only written once the output has been handed over
+    let used = r.as_ref().map_or(0, |&(_, used)| used);
+    let status = write_output(r.map(|(out, _)| out), new_data, new_len);
+    if status == XDELTA_OK {
+        unsafe { *consumed = used };
+    }
+    status
+}
+
+/// 宽松地应用补丁：中途遇到损坏的记录时不丢弃已重建的输出，供排查更新失败使用
+/// 成功时返回0；失败返回负的错误码（XDELTA_ERR_*），此时 new_data/new_len 仍为出错记录之前重建的输出（可能为空，
+/// 仅供诊断，不能当作新数据使用），failed_at 为出错记录在补丁中的字节偏移（补丁头出错为 0，
+/// 所有记录之后才发现的错误如 CHECK 不符为 patch_len）；输出都用 xdelta_free_data 释放
+/// 参数为 NULL 或内存不足时返回对应错误码，不返回输出，也不修改 failed_at
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_apply_patch_lenient(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+    failed_at: *mut usize,
+) -> c_int {
+    let inputs = (|| unsafe {
+        if failed_at.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        Ok((ffi_slice(old_data, old_len)?, ffi_slice(patch_data, patch_len)?))
+    })();
+    let (old_bytes, patch_bytes) = match inputs {
+        Ok(inputs) => inputs,
+        Err(e) => return write_output(Err::<Vec<u8>, _>(e), new_data, new_len),
+    };
+
+    let (out, failure) = apply_patch_partial(old_bytes, patch_bytes);
+    let status = write_output(Ok(out), new_data, new_len);
+    if status != XDELTA_OK {
+        return status;
+    }
+    match failure {
+        Some((e, at)) => {
+            unsafe { *failed_at = at };
+            set_last_error(&e);
+            e.code()
+        }
+        None => XDELTA_OK,
+    }
+}
+
+/// 检查补丁结构是否有效（不需要旧数据，不生成输出）：补丁头、每条记录的格式，
+/// 以及应用时不读旧数据就能做的一致性检查；COPY 范围是否超出旧数据留到应用时检查
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_validate_patch(patch_data: *const u8, patch_len: usize) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        validate_patch(patch_bytes)
+    })();
+
+    ffi_status(r)
+}
+
+/// 估算以给定参数创建补丁所需的峰值内存（字节，上限估计，不含输入数据本身）
+/// block_size 非 0 时覆盖 opts 中的块大小；opts 可为 NULL，参数错误时返回 0
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_estimate_memory(
+    old_len: u64,
+    new_len: u64,
+    block_size: u32,
+    opts: *const XdeltaOptions,
+) -> u64 {
+    match options_from_ffi(opts) {
+        Ok(mut o) => {
+            if block_size != 0 {
+                o = o.block_size(block_size as usize);
+            }
+            estimate_memory(old_len, new_len, &o)
+        }
+        Err(e) => {
+            set_last_error(&e);
+            0
+        }
+    }
+}
+
+/// 计算一个数据块的强哈希（与签名、COPY_HASH 中保存的相同），供其他实现生成兼容的签名
+/// algo 为 XDELTA_HASH_*；hash_out 须能容纳 32 字节，写入原始摘要字节
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_block_strong_hash(data: *const u8, len: usize, algo: u32, hash_out: *mut u8) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if hash_out.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        let algo = hash_algo_from_ffi(algo)?;
+
+        let bytes = unsafe { ffi_slice(data, len)? };
+        let hash = block_strong_hash(bytes, algo);
+        unsafe { copy_bytes(hash.as_ptr(), hash_out, hash.len()) };
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
+
+/// 创建带输出索引的补丁数据，index_granularity 为索引间隔（输出字节数）
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_create_patch_data_indexed(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+    block_size: u32,
+    index_granularity: u64,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        let opts = PatchOptions::new()
+            .block_size(block_size as usize)
+            .index_granularity(index_granularity);
+        create_patch_with(old_bytes, new_bytes, &opts)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 只还原输出中 [start, start + len) 范围的数据；补丁带索引时可直接定位
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn xdelta_apply_patch_range(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    start: u64,
+    len: usize,
+    out_data: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if out_data.is_null() || out_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_range_bytes(old_bytes, patch_bytes, start, len)
+    })();
+
+    write_output(r, out_data, out_len)
+}
+
+/// 释放通过xdelta_create_patch_data或xdelta_apply_patch_data分配的内存
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_free_data(data: *mut u8) {
+    allocator::ffi_free(data as *mut libc::c_void);
+}
+
+/// 释放库返回的字符串（以 NUL 结尾的 char*）
+/// 不要用于 xdelta_last_error 返回的指针，那是线程局部存储，不归调用方所有
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_free_string(s: *mut c_char) {
+    allocator::ffi_free(s as *mut libc::c_void);
 }
@@ -0,0 +1,114 @@
+// src/output.rs
+//! One apply entry point with the output allocation chosen per call.
+//!
+//! The specialized apply functions each fix a strategy (growing a `Vec`,
+//! streaming to a writer); `apply_to` makes the choice explicit, and adds
+//! reserving the exact output size up front and writing into a caller
+//! buffer.
+
+use crate::{ffi_slice, ffi_status, is_identity, output_len, patch_records, without_record_crcs, ApplyIter, XDeltaError};
+use std::io::Write;
+use std::os::raw::c_int;
+
+/// Where `apply_to` puts the output, and how it allocates for it.
+pub enum ApplyOutput<'a> {
+    /// Replace the contents of the `Vec`, reserving exactly the output size
+    /// (summed from the patch records first) so it never reallocates.
+    ExactReserve(&'a mut Vec<u8>),
+    /// Replace the contents of the `Vec`, growing it as output is produced.
+    Grow(&'a mut Vec<u8>),
+    /// Write each chunk to the writer as it is produced, buffering nothing.
+    /// Fails on COPY_TARGET records, which read the output back.
+    Streaming(&'a mut dyn Write),
+    /// Write into the start of the buffer, failing before any output if it
+    /// is too small. Nothing is allocated for the output.
+    IntoBuffer(&'a mut [u8]),
+}
+
+/// Apply `patch` to `old`, putting the output where `output` says. Returns
+/// the number of output bytes.
+///
+/// The output is the same whichever strategy is chosen; on error a `Vec` or
+/// buffer may hold part of it.
+pub fn apply_to(old: &[u8], patch: &[u8], output: ApplyOutput<'_>) -> Result<u64, XDeltaError> {
+    let patch = &*without_record_crcs(patch)?;
+    let mut iter = ApplyIter::for_patch(old, patch, false)?;
+    let total_len = || if is_identity(patch) { Ok(old.len() as u64) } else { output_len(patch_records(patch)?) };
+    let mut written = 0u64;
+    match output {
+        ApplyOutput::ExactReserve(vec) => {
+            let len = usize::try_from(total_len()?)
+                .map_err(|_| XDeltaError::InvalidArg("output does not fit in memory".into()))?;
+            vec.clear();
+            vec.try_reserve_exact(len).map_err(|_| XDeltaError::OutOfMemory)?;
+            while let Some(chunk) = iter.next_chunk(Some(vec))? {
+                vec.extend_from_slice(&chunk);
+            }
+            written = vec.len() as u64;
+        }
+        ApplyOutput::Grow(vec) => {
+            vec.clear();
+            while let Some(chunk) = iter.next_chunk(Some(vec))? {
+                vec.extend_from_slice(&chunk);
+            }
+            written = vec.len() as u64;
+        }
+        ApplyOutput::Streaming(out) => {
+            for chunk in iter {
+                let chunk = chunk?;
+                out.write_all(&chunk).map_err(|e| XDeltaError::Io(e.to_string()))?;
+                written += chunk.len() as u64;
+            }
+        }
+        ApplyOutput::IntoBuffer(buf) => {
+            let len = total_len()?;
+            if len > buf.len() as u64 {
+                return Err(XDeltaError::BufferTooSmall { needed: len, cap: buf.len() as u64 });
+            }
+            while let Some(chunk) = iter.next_chunk(Some(&buf[..written as usize]))? {
+                let at = written as usize;
+                buf[at..at + chunk.len()].copy_from_slice(&chunk);
+                written += chunk.len() as u64;
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// 应用补丁，输出写入调用方提供的缓冲区 out_buf（容量 out_cap 字节），不为输出分配内存
+/// 成功时 *out_len 为输出长度；缓冲区不足时不写入任何输出，*out_len 为所需长度，
+/// 错误码为 XDELTA_ERR_BUFFER_TOO_SMALL
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_into(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    out_buf: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if out_len.is_null() || (out_buf.is_null() && out_cap > 0) {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+        let buf: &mut [u8] =
+            if out_cap == 0 { &mut [] } else { unsafe { std::slice::from_raw_parts_mut(out_buf, out_cap) } };
+
+        match apply_to(old_bytes, patch_bytes, ApplyOutput::IntoBuffer(buf)) {
+            Ok(written) => unsafe { *out_len = written as usize },
+            Err(XDeltaError::BufferTooSmall { needed, cap }) => {
+                unsafe { *out_len = usize::try_from(needed).unwrap_or(usize::MAX) };
+                return Err(XDeltaError::BufferTooSmall { needed, cap });
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
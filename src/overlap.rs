@@ -0,0 +1,183 @@
+// src/overlap.rs
+//! Detecting COPY/DIFF records that read overlapping ranges of old.
+//!
+//! Overlapping reads are perfectly legal and apply doesn't care, but they
+//! matter for in-place apply (a range may be needed again after it has been
+//! overwritten), for deciding what is worth caching, and for prefetching old
+//! from slow storage in a few large reads.
+
+use crate::allocator::ffi_malloc;
+use crate::sparse::XdeltaExtent;
+use crate::{
+    ffi_slice, ffi_status, patch_records, read_record, set_last_error, write_sized, Record, XDELTA_ERR_NO_MEMORY,
+    XDeltaError,
+};
+use std::ops::Range;
+use std::os::raw::c_int;
+
+/// How the old ranges read by a patch overlap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OverlapStats {
+    /// Non-empty COPY, DIFF and XOR_DELTA records.
+    pub ranges: u64,
+    /// Of those, how many share at least one byte of old with another.
+    pub overlapping_ranges: u64,
+    /// Bytes of old read more than once, counting every extra read.
+    pub reread_bytes: u64,
+}
+
+/// Find the COPY/DIFF records of `patch` whose old ranges overlap.
+///
+/// Purely analytical: nothing is read from old and the patch is unchanged.
+pub fn copy_overlap(patch: &[u8]) -> Result<OverlapStats, XDeltaError> {
+    let ranges = old_ranges(patch)?;
+    let mut stats = OverlapStats {
+        ranges: ranges.len() as u64,
+        ..OverlapStats::default()
+    };
+    let mut overlapping = vec![false; ranges.len()];
+    // range reaching furthest so far, and where the current merged run started
+    let mut furthest: Option<usize> = None;
+    let mut run_start = 0u64;
+    let mut union = 0u64;
+    let mut total = 0u64;
+    for (i, &(start, end)) in ranges.iter().enumerate() {
+        total += end - start;
+        match furthest {
+            Some(f) if start < ranges[f].1 => {
+                overlapping[i] = true;
+                overlapping[f] = true;
+                if end > ranges[f].1 {
+                    furthest = Some(i);
+                }
+            }
+            _ => {
+                if let Some(f) = furthest {
+                    union += ranges[f].1 - run_start;
+                }
+                run_start = start;
+                furthest = Some(i);
+            }
+        }
+    }
+    if let Some(f) = furthest {
+        union += ranges[f].1 - run_start;
+    }
+    stats.overlapping_ranges = overlapping.iter().filter(|&&o| o).count() as u64;
+    stats.reread_bytes = total - union;
+    Ok(stats)
+}
+
+/// The old ranges read by the non-empty COPY, DIFF and XOR_DELTA records of
+/// `patch`, as sorted `(start, end)` pairs.
+fn old_ranges(patch: &[u8]) -> Result<Vec<(u64, u64)>, XDeltaError> {
+    let patch = patch_records(patch)?;
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        if let Record::Copy { offset, len } | Record::Diff { offset, len, .. } | Record::Xor { offset, len, .. } = record {
+            if len > 0 {
+                ranges.push((offset, offset.saturating_add(len as u64)));
+            }
+        }
+        pos = next;
+    }
+    ranges.sort_unstable();
+    Ok(ranges)
+}
+
+/// The old ranges `patch` reads, sorted and merged wherever they overlap,
+/// touch, or are at most `gap_tolerance` bytes apart, for prefetching old in
+/// fewer, larger reads. A larger tolerance trades reading unused gap bytes
+/// for fewer reads.
+pub fn old_ranges_merged(patch: &[u8], gap_tolerance: u64) -> Result<Vec<Range<u64>>, XDeltaError> {
+    let mut merged: Vec<Range<u64>> = Vec::new();
+    for (start, end) in old_ranges(patch)? {
+        match merged.last_mut() {
+            Some(last) if start <= last.end.saturating_add(gap_tolerance) => last.end = last.end.max(end),
+            _ => merged.push(start..end),
+        }
+    }
+    Ok(merged)
+}
+
+/// C layout of `OverlapStats`; the caller sets `size` before the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XdeltaOverlapStats {
+    pub size: u32,
+    pub ranges: u64,
+    pub overlapping_ranges: u64,
+    pub reread_bytes: u64,
+}
+
+/// 分析补丁中 COPY/DIFF 读取的旧数据范围是否重叠（仅统计，不影响应用结果）
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_copy_overlap_stats(
+    patch_data: *const u8,
+    patch_len: usize,
+    stats: *mut XdeltaOverlapStats,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if stats.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        let s = copy_overlap(patch_bytes)?;
+        let value = XdeltaOverlapStats {
+            size: 0,
+            ranges: s.ranges,
+            overlapping_ranges: s.overlapping_ranges,
+            reread_bytes: s.reread_bytes,
+        };
+        write_sized(stats, value, std::mem::size_of::<XdeltaOverlapStats>())
+    })();
+
+    ffi_status(r)
+}
+
+/// 列出补丁读取的旧数据范围（COPY/DIFF/XOR_DELTA），排序后合并重叠、相邻及间隔不超过 gap_tolerance 字节的范围，供预读使用
+/// 结果为 range_count 个 XdeltaExtent 组成的数组，用 xdelta_free_data 释放；没有范围时 *ranges 可能为 NULL
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_patch_old_ranges_merged(
+    patch_data: *const u8,
+    patch_len: usize,
+    gap_tolerance: u64,
+    ranges: *mut *mut XdeltaExtent,
+    range_count: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<Range<u64>>, XDeltaError> {
+        if ranges.is_null() || range_count.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        old_ranges_merged(patch_bytes, gap_tolerance)
+    })();
+
+    match r {
+        Ok(merged) => unsafe {
+            let out = ffi_malloc(merged.len() * std::mem::size_of::<XdeltaExtent>()) as *mut XdeltaExtent;
+            if out.is_null() && !merged.is_empty() {
+                set_last_error(&XDeltaError::OutOfMemory);
+                return XDELTA_ERR_NO_MEMORY;
+            }
+            for (i, range) in merged.iter().enumerate() {
+                out.add(i).write(XdeltaExtent { offset: range.start, len: range.end - range.start });
+            }
+            *ranges = out;
+            *range_count = merged.len();
+            0
+        },
+        Err(e) => {
+            set_last_error(&e);
+            e.code()
+        }
+    }
+}
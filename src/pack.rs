@@ -0,0 +1,137 @@
+// src/pack.rs
+//! A minimal revision store: several revisions of one file in a single blob.
+//!
+//! Pack layout (all integers little-endian):
+//!   magic: b"XDPK"
+//!   count: u32
+//!   index: [(rev_id: u64, offset: u64, len: u64)...]  // offset from pack start
+//!   data:  revision contents, back to back
+//!
+//! Revisions are stored whole; patches between any two of them are computed
+//! on demand with the regular diff core.
+
+use crate::{
+    create_patch_with, ffi_slice, options_from_ffi, read_u32, read_u64, write_output, PatchOptions, XDeltaError,
+    XdeltaOptions,
+};
+use std::os::raw::c_int;
+
+const PACK_MAGIC: &[u8; 4] = b"XDPK";
+const PACK_HEADER_LEN: usize = 8;
+const PACK_ENTRY_LEN: usize = 24;
+
+/// Build a pack from `(rev_id, contents)` pairs. Revision ids must be unique.
+pub fn build_pack(revs: &[(u64, &[u8])]) -> Result<Vec<u8>, XDeltaError> {
+    for (i, (id, _)) in revs.iter().enumerate() {
+        if revs[..i].iter().any(|(other, _)| other == id) {
+            return Err(XDeltaError::InvalidArg(format!("duplicate revision {}", id)));
+        }
+    }
+    let count = u32::try_from(revs.len())
+        .map_err(|_| XDeltaError::InvalidArg("too many revisions".into()))?;
+
+    let data_len: usize = revs.iter().map(|(_, d)| d.len()).sum();
+    let mut out = Vec::with_capacity(PACK_HEADER_LEN + revs.len() * PACK_ENTRY_LEN + data_len);
+    out.extend_from_slice(PACK_MAGIC);
+    out.extend_from_slice(&count.to_le_bytes());
+    let mut offset = (PACK_HEADER_LEN + revs.len() * PACK_ENTRY_LEN) as u64;
+    for (id, data) in revs {
+        out.extend_from_slice(&id.to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        offset += data.len() as u64;
+    }
+    for (_, data) in revs {
+        out.extend_from_slice(data);
+    }
+    Ok(out)
+}
+
+/// Look up the contents of revision `rev_id` in `pack`.
+pub fn pack_revision(pack: &[u8], rev_id: u64) -> Result<&[u8], XDeltaError> {
+    if pack.len() < PACK_HEADER_LEN || &pack[..4] != PACK_MAGIC {
+        return Err(XDeltaError::InvalidArg("not a pack".into()));
+    }
+    let count = read_u32(pack, 4) as usize;
+    let index_end = count
+        .checked_mul(PACK_ENTRY_LEN)
+        .and_then(|n| n.checked_add(PACK_HEADER_LEN))
+        .filter(|n| *n <= pack.len())
+        .ok_or_else(|| XDeltaError::InvalidArg("truncated pack index".into()))?;
+
+    for entry in pack[PACK_HEADER_LEN..index_end].chunks_exact(PACK_ENTRY_LEN) {
+        if read_u64(entry, 0) != rev_id {
+            continue;
+        }
+        let offset = read_u64(entry, 8);
+        let len = read_u64(entry, 16);
+        let end = offset
+            .checked_add(len)
+            .filter(|end| *end <= pack.len() as u64)
+            .ok_or_else(|| XDeltaError::InvalidArg(format!("revision {} out of range", rev_id)))?;
+        return Ok(&pack[offset as usize..end as usize]);
+    }
+    Err(XDeltaError::InvalidArg(format!("revision {} not in pack", rev_id)))
+}
+
+/// Create a patch from revision `from_rev` to revision `to_rev` of `pack`.
+pub fn pack_diff(pack: &[u8], from_rev: u64, to_rev: u64, opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+    let old = pack_revision(pack, from_rev)?;
+    let new = pack_revision(pack, to_rev)?;
+    create_patch_with(old, new, opts)
+}
+
+/// 由多个版本构建 pack：rev_ids/datas/lens 均为长度 count 的数组
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_pack_build(
+    rev_ids: *const u64,
+    datas: *const *const u8,
+    lens: *const usize,
+    count: usize,
+    pack_data: *mut *mut u8,
+    pack_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if pack_data.is_null() || pack_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        if count > 0 && (rev_ids.is_null() || datas.is_null() || lens.is_null()) {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let mut revs = Vec::with_capacity(count);
+        for i in 0..count {
+            let (id, data, len) = unsafe { (*rev_ids.add(i), *datas.add(i), *lens.add(i)) };
+            revs.push((id, unsafe { ffi_slice(data, len)? }));
+        }
+        build_pack(&revs)
+    })();
+
+    write_output(r, pack_data, pack_len)
+}
+
+/// 计算 pack 中两个版本之间的补丁，opts 为 NULL 时使用默认选项
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_pack_diff(
+    pack: *const u8,
+    pack_len: usize,
+    from_rev: u64,
+    to_rev: u64,
+    opts: *const XdeltaOptions,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let pack_bytes = unsafe { ffi_slice(pack, pack_len)? };
+
+        pack_diff(pack_bytes, from_rev, to_rev, &options_from_ffi(opts)?)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
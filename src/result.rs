@@ -0,0 +1,230 @@
+// src/result.rs
+//! Result-handle API for bindings that find out-parameters awkward (ctypes,
+//! cffi, ...).
+//!
+//! Each call returns one opaque handle that owns everything it produced: the
+//! output bytes on success, the status code and error message on failure.
+//! The caller reads what it needs through accessors and releases it all with
+//! the single `xdelta_result_free`. A batch of patches can also be written
+//! into plain entries in a caller's array, released with `xdelta_free_batch`.
+
+use crate::{
+    apply_patch, create_patch_with, ffi_slice, ffi_status, options_from_ffi, write_output, xdelta_free_data,
+    PatchOptions, XDELTA_OK, XDeltaError, XdeltaOptions,
+};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+
+/// Outcome of a result-handle call.
+pub struct XdeltaResult {
+    status: c_int,
+    data: Vec<u8>,
+    error: Option<CString>,
+}
+
+impl XdeltaResult {
+    fn from_result(r: Result<Vec<u8>, XDeltaError>) -> *mut XdeltaResult {
+        let result = match r {
+            Ok(data) => XdeltaResult {
+                status: XDELTA_OK,
+                data,
+                error: None,
+            },
+            Err(e) => XdeltaResult {
+                status: e.code(),
+                data: Vec::new(),
+                error: Some(CString::new(e.to_string()).unwrap_or_else(|_| CString::new("internal error").unwrap())),
+            },
+        };
+        Box::into_raw(Box::new(result))
+    }
+}
+
+/// 创建补丁，结果通过句柄返回；opts 为 NULL 时使用默认选项
+/// 总是返回非 NULL 句柄，须用 xdelta_result_free 释放；可在多个线程中并发调用
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_result(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    opts: *const XdeltaOptions,
+) -> *mut XdeltaResult {
+    XdeltaResult::from_result((|| {
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch_with(old_bytes, new_bytes, &options_from_ffi(opts)?)
+    })())
+}
+
+/// 应用补丁，结果通过句柄返回
+/// 总是返回非 NULL 句柄，须用 xdelta_result_free 释放；可在多个线程中并发调用
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_result(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+) -> *mut XdeltaResult {
+    XdeltaResult::from_result((|| {
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_patch(old_bytes, patch_bytes)
+    })())
+}
+
+/// One (old, new) input of `xdelta_create_patch_batch_handles`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XdeltaPatchPair {
+    pub old_data: *const u8,
+    pub old_len: usize,
+    pub new_data: *const u8,
+    pub new_len: usize,
+}
+
+/// Create a patch for each pair, in order; a `None` pair (a null pointer
+/// from C) fails on its own. With the `parallel` feature the pairs are shared
+/// out over rayon's pool; the results stay in pair order.
+fn create_patches(pairs: &[Option<(&[u8], &[u8])>], opts: &PatchOptions) -> Vec<Result<Vec<u8>, XDeltaError>> {
+    let create = |pair: &Option<(&[u8], &[u8])>| match pair {
+        Some((old, new)) => create_patch_with(old, new, opts),
+        None => Err(XDeltaError::NullPointer),
+    };
+    #[cfg(feature = "parallel")]
+    if pairs.len() > 1 {
+        use rayon::prelude::*;
+        return pairs.par_iter().map(create).collect();
+    }
+    pairs.iter().map(create).collect()
+}
+
+/// 批量创建补丁：pairs 为长度 count 的 (旧, 新) 数组，results 为调用方提供的长度 count 的句柄数组
+/// 第 i 个补丁（或其错误）写入 results[i]，各对互不影响；每个句柄须用 xdelta_result_free 释放
+/// 启用 parallel 特性时在内部多线程并行；参数本身无效时返回负的错误码且不写入 results，否则返回0
+/// 不需要句柄时见 xdelta_create_patch_batch
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_batch_handles(
+    pairs: *const XdeltaPatchPair,
+    count: usize,
+    block_size: usize,
+    results: *mut *mut XdeltaResult,
+) -> c_int {
+    ffi_status((|| -> Result<(), XDeltaError> {
+        if results.is_null() || (count > 0 && pairs.is_null()) {
+            return Err(XDeltaError::NullPointer);
+        }
+        let opts = PatchOptions::new().block_size(block_size);
+
+        let inputs = pair_inputs(pairs, count);
+        for (i, r) in create_patches(&inputs, &opts).into_iter().enumerate() {
+            unsafe { *results.add(i) = XdeltaResult::from_result(r) };
+        }
+        Ok(())
+    })())
+}
+
+/// The `count` pairs at `pairs` as slices, `None` for one with a null
+/// pointer to a nonempty buffer.
+fn pair_inputs<'a>(pairs: *const XdeltaPatchPair, count: usize) -> Vec<Option<(&'a [u8], &'a [u8])>> {
+    (0..count)
+        .map(|i| {
+            let pair = unsafe { *pairs.add(i) };
+            unsafe {
+                Some((ffi_slice(pair.old_data, pair.old_len).ok()?, ffi_slice(pair.new_data, pair.new_len).ok()?))
+            }
+        })
+        .collect()
+}
+
+/// One patch of `xdelta_create_patch_batch`: its status, and on success the
+/// patch in a buffer from the C allocator, as `xdelta_create_patch_data`
+/// hands it out.
+#[repr(C)]
+pub struct XdeltaBatchEntry {
+    pub status: c_int,
+    pub patch_data: *mut u8,
+    pub patch_len: usize,
+}
+
+/// 批量创建补丁，不使用句柄（使用句柄的版本见 xdelta_create_patch_batch_handles）：pairs 为长度 count 的 (旧, 新) 数组，out 为调用方提供的长度 count 的数组
+/// out[i].status 为第 i 对的状态（XDELTA_OK 或 XDELTA_ERR_*），成功时 patch_data/patch_len 为补丁，失败时为 NULL/0；
+/// 各对互不影响，xdelta_last_error 给出最后一个失败的对的错误信息；用 xdelta_free_batch 一次释放全部补丁
+/// 启用 parallel 特性时在内部多线程并行；参数本身无效时返回负的错误码且不写入 out，否则返回0
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_batch(
+    pairs: *const XdeltaPatchPair,
+    count: usize,
+    out: *mut XdeltaBatchEntry,
+    block_size: usize,
+) -> c_int {
+    ffi_status((|| -> Result<(), XDeltaError> {
+        if out.is_null() || (count > 0 && pairs.is_null()) {
+            return Err(XDeltaError::NullPointer);
+        }
+        let opts = PatchOptions::new().block_size(block_size);
+
+        let inputs = pair_inputs(pairs, count);
+        for (i, r) in create_patches(&inputs, &opts).into_iter().enumerate() {
+            let entry = unsafe { &mut *out.add(i) };
+            entry.status = write_output(r, &mut entry.patch_data, &mut entry.patch_len);
+        }
+        Ok(())
+    })())
+}
+
+/// 释放 xdelta_create_patch_batch 写入 entries（长度 count）的全部补丁，并把各项置为 NULL/0；
+/// entries 数组本身归调用方；entries 可为 NULL，重复释放无害
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_free_batch(entries: *mut XdeltaBatchEntry, count: usize) {
+    if entries.is_null() {
+        return;
+    }
+    for i in 0..count {
+        let entry = unsafe { &mut *entries.add(i) };
+        xdelta_free_data(std::mem::replace(&mut entry.patch_data, std::ptr::null_mut()));
+        entry.patch_len = 0;
+    }
+}
+
+/// 结果状态：XDELTA_OK（0）或 XDELTA_ERR_*；result 为 NULL 时返回 XDELTA_ERR_INVALID_ARG
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_result_status(result: *const XdeltaResult) -> c_int {
+    match unsafe { result.as_ref() } {
+        Some(r) => r.status,
+        None => crate::XDELTA_ERR_INVALID_ARG,
+    }
+}
+
+/// 结果数据指针，归句柄所有，在 xdelta_result_free 之前有效；失败时为 NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_result_data(result: *const XdeltaResult) -> *const u8 {
+    match unsafe { result.as_ref() } {
+        Some(r) if r.status == XDELTA_OK => r.data.as_ptr(),
+        _ => std::ptr::null(),
+    }
+}
+
+/// 结果数据长度；失败时为 0
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_result_len(result: *const XdeltaResult) -> usize {
+    unsafe { result.as_ref() }.map_or(0, |r| r.data.len())
+}
+
+/// 错误信息，归句柄所有，在 xdelta_result_free 之前有效；成功时为 NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_result_error(result: *const XdeltaResult) -> *const c_char {
+    unsafe { result.as_ref() }
+        .and_then(|r| r.error.as_ref())
+        .map_or(std::ptr::null(), |e| e.as_ptr())
+}
+
+/// 释放结果句柄及其数据和错误信息；result 可为 NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_result_free(result: *mut XdeltaResult) {
+    if !result.is_null() {
+        drop(unsafe { Box::from_raw(result) });
+    }
+}
@@ -0,0 +1,96 @@
+// src/reverse.rs
+//! Creating the forward and reverse patches between two versions together,
+//! for rollback tooling that ships both.
+//!
+//! Both directions go through one `DiffContext`, so the signature map and
+//! output buffers allocated for the forward patch are cleared and refilled
+//! for the reverse one rather than allocated twice, and each file is signed
+//! exactly once: old for the forward patch, new for the reverse.
+
+use crate::{ffi_slice, ffi_status, write_output, DiffContext, PatchOptions, XDeltaError};
+use std::os::raw::c_int;
+
+/// Create the patch turning `old` into `new` and the patch turning `new`
+/// back into `old`, at `block_size` (0 picks one from each base's length).
+///
+/// The reverse patch is an ordinary patch with `new` as its base, applied
+/// with `apply_patch(new, &reverse)`.
+///
+/// ```
+/// let old = b"the quick brown fox jumps over the lazy dog. ".repeat(20);
+/// let mut new = old.clone();
+/// new[200..210].copy_from_slice(b"0123456789");
+/// let (forward, reverse) = xdelta::create_patch_bidirectional(&old, &new, 16).unwrap();
+/// let rebuilt = xdelta::apply_patch(&old, &forward).unwrap();
+/// assert_eq!(xdelta::apply_patch(&rebuilt, &reverse).unwrap(), old);
+/// ```
+pub fn create_patch_bidirectional(
+    old: &[u8],
+    new: &[u8],
+    block_size: usize,
+) -> Result<(Vec<u8>, Vec<u8>), XDeltaError> {
+    let opts = PatchOptions::new().block_size(block_size);
+    let mut ctx = DiffContext::new();
+    let forward = ctx.create_patch(old, new, &opts)?.to_vec();
+    let reverse = ctx.create_patch(new, old, &opts)?.to_vec();
+    Ok((forward, reverse))
+}
+
+/// 同时创建正向补丁（旧→新）和反向补丁（新→旧），用于回滚；两个方向共用签名表和缓冲区
+/// 反向补丁以新数据为基准应用；block_size 为 0 时按各自基准的长度自动选择
+/// 两个补丁都用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）（失败时不返回任何补丁）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_bidirectional(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    block_size: u32,
+    forward_data: *mut *mut u8,
+    forward_len: *mut usize,
+    reverse_data: *mut *mut u8,
+    reverse_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<(Vec<u8>, Vec<u8>), XDeltaError> {
+        if forward_data.is_null() || forward_len.is_null() || reverse_data.is_null() || reverse_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch_bidirectional(old_bytes, new_bytes, block_size as usize)
+    })();
+
+    match r {
+        Ok((forward, reverse)) => {
+            let rc = write_output(Ok(forward), forward_data, forward_len);
+            if rc != 0 {
+                return rc;
+            }
+            let rc = write_output(Ok(reverse), reverse_data, reverse_len);
+            if rc != 0 {
+                unsafe {
+                    crate::xdelta_free_data(*forward_data);
+                    *forward_data = std::ptr::null_mut();
+                    *forward_len = 0;
+                }
+            }
+            rc
+        }
+        Err(e) => {
+            for (data, len) in [(forward_data, forward_len), (reverse_data, reverse_len)] {
+                unsafe {
+                    if !data.is_null() {
+                        *data = std::ptr::null_mut();
+                    }
+                    if !len.is_null() {
+                        *len = 0;
+                    }
+                }
+            }
+            ffi_status(Err(e))
+        }
+    }
+}
@@ -0,0 +1,80 @@
+// src/self_test.rs
+//! Built-in self test, so integrators can check the library works in their
+//! environment without shipping test vectors.
+
+use crate::{
+    apply_patch_bytes, apply_streaming, create_patch_with, ffi_status, ApplyOptions, PatchOptions, Rolling,
+    WeakChecksum, XDeltaError,
+};
+use std::os::raw::c_int;
+
+/// Deterministic filler so the self test needs no stored vectors.
+pub(crate) fn filler(len: usize, seed: u32) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (x >> 16) as u8
+        })
+        .collect()
+}
+
+pub(crate) fn check(ok: bool, what: &str) -> Result<(), XDeltaError> {
+    if ok {
+        Ok(())
+    } else {
+        Err(XDeltaError::InvalidArg(format!("self test failed: {}", what)))
+    }
+}
+
+/// Rolling the weak checksum one byte at a time must agree with computing it
+/// from scratch at every position.
+pub(crate) fn check_rolling<W: WeakChecksum>(data: &[u8], window: usize) -> Result<(), XDeltaError> {
+    let mut rolling = W::from_slice(&data[..window]);
+    for start in 1..=data.len() - window {
+        rolling.roll(data[start - 1], data[start + window - 1]);
+        check(rolling.chksum() == W::from_slice(&data[start..start + window]).chksum(), "rolling checksum")?;
+    }
+    Ok(())
+}
+
+/// The fixed old and new of the self test: new is old with an insert, a
+/// modified byte, a deleted range, a moved block and a run.
+pub(crate) fn fixture() -> (Vec<u8>, Vec<u8>) {
+    let old = filler(16 * 1024, 1);
+    let mut new = old[..3000].to_vec();
+    new.extend_from_slice(&filler(333, 2));
+    new.extend_from_slice(&old[3000..6000]);
+    new[4500] ^= 0x5a;
+    new.extend_from_slice(&old[12_000..14_000]);
+    new.extend_from_slice(&old[7000..12_000]);
+    new.extend_from_slice(&[0xabu8; 200]);
+    (old, new)
+}
+
+/// Check the rolling weak checksum against computing it from scratch, and
+/// round-trip the fixed inputs through create and both apply paths.
+///
+/// `tamper` is applied to the patch before it is applied; the self test
+/// proper passes a no-op, and anything that changes the patch's output must
+/// make the run fail.
+pub(crate) fn run_self_test(tamper: impl Fn(&mut Vec<u8>)) -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    for window in [1, 17, 64, 256, 6000] {
+        check_rolling::<Rolling>(&old[..8192], window)?;
+    }
+
+    let mut patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(256))?;
+    tamper(&mut patch);
+    check(apply_patch_bytes(&old, &patch)? == new, "apply")?;
+    let mut streamed = Vec::new();
+    apply_streaming(&mut &old[..], &patch, &mut streamed, &ApplyOptions::new())?;
+    check(streamed == new, "streaming apply")
+}
+
+/// 运行内置自检（补丁创建/应用往返及滚动校验一致性）
+/// 全部通过时返回0，否则返回负的错误码，可通过 xdelta_last_error 获取失败项
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_self_test() -> c_int {
+    ffi_status(run_self_test(|_| {}))
+}
@@ -0,0 +1,644 @@
+// src/signature.rs
+//! Block signatures of an old file, kept around for inspection and reuse.
+//!
+//! Serialized layout (all integers little-endian):
+//!   magic:       b"XDSG" (SHA-256 strong hashes) or b"XDB3" (BLAKE3), with
+//!                rsync-style weak checksums; b"XDSA" or b"XDBA" with Adler-32
+//!   block_size:  u64
+//!   base_len:    u64  // length of the data the signature was computed from
+//!   block_count: u64  // must be ceil(base_len / block_size)
+//!   blocks:      [(weak: u32, strong: [u8; 32])...]  // in block order
+//!
+//! A serialized signature may come from elsewhere (the remote-signature
+//! path), so deserializing checks every count and length before trusting it.
+
+use crate::{
+    add_block_signature, build_signatures, ffi_slice, ffi_status, finish_patch, match_blocks, options_from_ffi,
+    patch_records, read_record, read_u32, read_u64, write_output, write_sized, ApplyIter, HashAlgo, MatchHooks,
+    OldBytes, PatchOptions, Record, SigEntry, TailPolicy, WeakAlgo, XDeltaError, XdeltaOptions,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::os::raw::c_int;
+
+const SIG_MAGIC: &[u8; 4] = b"XDSG";
+const SIG_MAGIC_BLAKE3: &[u8; 4] = b"XDB3";
+const SIG_MAGIC_ADLER32: &[u8; 4] = b"XDSA";
+const SIG_MAGIC_BLAKE3_ADLER32: &[u8; 4] = b"XDBA";
+const SIG_HEADER_LEN: usize = 28;
+const SIG_ENTRY_LEN: usize = 36;
+
+/// The block signatures `create_patch_with` matches new against.
+pub struct Signature {
+    block_size: usize,
+    /// Length of the data the signature was computed from.
+    len: u64,
+    algo: HashAlgo,
+    weak: WeakAlgo,
+    map: HashMap<u32, Vec<SigEntry>>,
+}
+
+/// Shape of a signature's weak-checksum map.
+///
+/// A high `avg_bucket` or `max_bucket` means many blocks share a weak checksum,
+/// either because the data is repetitive or the weak checksum is poor, and
+/// every such window costs a strong hash per candidate.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SignatureStats {
+    pub block_count: u64,
+    pub distinct_weak: u64,
+    pub max_bucket: u64,
+    pub avg_bucket: f64,
+    /// Weak checksum with the largest bucket (the smallest such value on ties).
+    pub most_collided_weak: u32,
+}
+
+impl Signature {
+    /// Compute the signatures of `old` in blocks of `block_size` bytes.
+    pub fn new(old: &[u8], block_size: usize) -> Result<Self, XDeltaError> {
+        Signature::with_hash(old, block_size, HashAlgo::Sha256)
+    }
+
+    /// Like `new`, with `algo` as the strong hash. Patches made against the
+    /// signature use it too, whatever `PatchOptions::strong_hash` says.
+    pub fn with_hash(old: &[u8], block_size: usize, algo: HashAlgo) -> Result<Self, XDeltaError> {
+        Signature::with_checksums(old, block_size, algo, WeakAlgo::Rolling)
+    }
+
+    /// Like `with_hash`, also with `weak` as the weak checksum, which
+    /// likewise replaces `PatchOptions::weak_checksum` in patches made
+    /// against the signature.
+    pub fn with_checksums(old: &[u8], block_size: usize, algo: HashAlgo, weak: WeakAlgo) -> Result<Self, XDeltaError> {
+        if block_size == 0 {
+            return Err(XDeltaError::InvalidArg("block_size must be > 0".into()));
+        }
+        let mut map = HashMap::new();
+        build_signatures(&mut map, old, block_size, TailPolicy::AsIs, algo, weak);
+        Ok(Signature {
+            block_size,
+            len: old.len() as u64,
+            algo,
+            weak,
+            map,
+        })
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.algo
+    }
+
+    pub fn weak_algo(&self) -> WeakAlgo {
+        self.weak
+    }
+
+    /// Like `create_patch_with`, but matching against this signature of
+    /// `old` instead of computing a fresh one. The signature's block size
+    /// and checksums replace the ones in `opts`.
+    pub fn create_patch(&self, old: &[u8], new: &[u8], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+        if old.len() as u64 != self.len {
+            return Err(XDeltaError::InvalidArg(format!(
+                "signature is of {} bytes, old has {}",
+                self.len,
+                old.len()
+            )));
+        }
+        let opts = opts.clone().block_size(self.block_size).strong_hash(self.algo).weak_checksum(self.weak);
+        let mut patch = Vec::with_capacity(new.len() / 4);
+        match_blocks(old, new, &opts, &self.map, &mut patch, &mut Vec::new(), MatchHooks::default())?;
+        finish_patch(old, new, patch, &opts)
+    }
+
+    /// Like `create_patch`, but from the signature alone, for the side of an
+    /// rsync-style exchange that never sees old. Only whole blocks of old are
+    /// matched: `opts.near_miss_diff` and `Quality::Best`, which compare old's
+    /// bytes, are ignored, and `opts.old_hash` and `opts.strict` are refused.
+    pub fn create_patch_without_old(&self, new: &[u8], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+        if opts.old_hash {
+            return Err(XDeltaError::InvalidArg("old_hash needs old, not just its signature".into()));
+        }
+        if opts.strict {
+            return Err(XDeltaError::InvalidArg("strict needs old to apply the patch to, not its signature".into()));
+        }
+        let old = SignedOld(
+            usize::try_from(self.len).map_err(|_| XDeltaError::InvalidArg("signed old too large".into()))?,
+        );
+        let opts = opts.clone().block_size(self.block_size).strong_hash(self.algo).weak_checksum(self.weak);
+        let mut patch = Vec::with_capacity(new.len() / 4);
+        match_blocks(&old, new, &opts, &self.map, &mut patch, &mut Vec::new(), MatchHooks::default())?;
+        // old is only read for old_hash, which was refused above
+        finish_patch(&[], new, patch, &opts)
+    }
+
+    /// Serialize in the layout described at the top of this module.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let blocks = self.blocks();
+        let mut out = Vec::with_capacity(SIG_HEADER_LEN + blocks.len() * SIG_ENTRY_LEN);
+        out.extend_from_slice(match (self.algo, self.weak) {
+            (HashAlgo::Sha256, WeakAlgo::Rolling) => SIG_MAGIC,
+            (HashAlgo::Sha256, WeakAlgo::Adler32) => SIG_MAGIC_ADLER32,
+            #[cfg(feature = "blake3")]
+            (HashAlgo::Blake3, WeakAlgo::Rolling) => SIG_MAGIC_BLAKE3,
+            #[cfg(feature = "blake3")]
+            (HashAlgo::Blake3, WeakAlgo::Adler32) => SIG_MAGIC_BLAKE3_ADLER32,
+        });
+        out.extend_from_slice(&(self.block_size as u64).to_le_bytes());
+        out.extend_from_slice(&self.len.to_le_bytes());
+        out.extend_from_slice(&(blocks.len() as u64).to_le_bytes());
+        for (weak, strong) in blocks {
+            out.extend_from_slice(&weak.to_le_bytes());
+            out.extend_from_slice(strong);
+        }
+        out
+    }
+
+    /// Parse a signature produced by `to_bytes`.
+    ///
+    /// The block count must be exactly the number of blocks of the declared
+    /// base length, and every block must be present, so block offsets derived
+    /// from it later can neither overflow nor point past the base.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, XDeltaError> {
+        if data.len() < SIG_HEADER_LEN {
+            return Err(XDeltaError::InvalidArg("not a signature".into()));
+        }
+        let (algo, weak) = match &data[..4] {
+            magic if magic == SIG_MAGIC => (HashAlgo::Sha256, WeakAlgo::Rolling),
+            magic if magic == SIG_MAGIC_ADLER32 => (HashAlgo::Sha256, WeakAlgo::Adler32),
+            #[cfg(feature = "blake3")]
+            magic if magic == SIG_MAGIC_BLAKE3 => (HashAlgo::Blake3, WeakAlgo::Rolling),
+            #[cfg(feature = "blake3")]
+            magic if magic == SIG_MAGIC_BLAKE3_ADLER32 => (HashAlgo::Blake3, WeakAlgo::Adler32),
+            #[cfg(not(feature = "blake3"))]
+            magic if magic == SIG_MAGIC_BLAKE3 || magic == SIG_MAGIC_BLAKE3_ADLER32 => {
+                return Err(XDeltaError::InvalidArg(
+                    "signature hashes blocks with BLAKE3, which this build lacks (feature blake3)".into(),
+                ));
+            }
+            _ => return Err(XDeltaError::InvalidArg("not a signature".into())),
+        };
+        let block_size = read_u64(data, 4);
+        let len = read_u64(data, 12);
+        let block_count = read_u64(data, 20);
+
+        if block_size == 0 {
+            return Err(XDeltaError::InvalidArg("block_size must be > 0".into()));
+        }
+        let block_size = usize::try_from(block_size)
+            .map_err(|_| XDeltaError::InvalidArg(format!("block_size {} too large", block_size)))?;
+        let covered = block_count
+            .checked_mul(block_size as u64)
+            .ok_or_else(|| XDeltaError::InvalidArg("block_count * block_size overflows".into()))?;
+        // the blocks must cover the base, with only the last one short
+        if covered < len || (block_count > 0 && covered - len >= block_size as u64) {
+            return Err(XDeltaError::InvalidArg(format!(
+                "{} blocks of {} bytes do not match a base of {} bytes",
+                block_count, block_size, len
+            )));
+        }
+        let end = usize::try_from(block_count)
+            .ok()
+            .and_then(|n| n.checked_mul(SIG_ENTRY_LEN))
+            .and_then(|n| n.checked_add(SIG_HEADER_LEN))
+            .ok_or_else(|| XDeltaError::InvalidArg("block count overflows".into()))?;
+        if end != data.len() {
+            return Err(XDeltaError::InvalidArg(format!(
+                "signature of {} blocks needs {} bytes, got {}",
+                block_count,
+                end,
+                data.len()
+            )));
+        }
+
+        let mut map: HashMap<u32, Vec<SigEntry>> = HashMap::new();
+        for (idx, entry) in data[SIG_HEADER_LEN..].chunks_exact(SIG_ENTRY_LEN).enumerate() {
+            let mut strong_hash = [0u8; 32];
+            strong_hash.copy_from_slice(&entry[4..]);
+            // the blocks are as they were, the last one short but unpadded
+            let start = idx as u64 * block_size as u64;
+            map.entry(read_u32(entry, 0)).or_default().push(SigEntry {
+                block_index: idx as u64,
+                strong_hash,
+                len: (len - start).min(block_size as u64) as usize,
+            });
+        }
+        Ok(Signature { block_size, len, algo, weak, map })
+    }
+
+    fn block_count(&self) -> usize {
+        self.map.values().map(Vec::len).sum()
+    }
+
+    /// `(weak, strong)` of every block in block order, whatever the order of
+    /// the map and of the entries within each bucket.
+    fn blocks(&self) -> Vec<(u32, &[u8; 32])> {
+        let mut blocks: Vec<(u32, &[u8; 32])> = vec![(0, &[0; 32]); self.block_count()];
+        for (&weak, bucket) in &self.map {
+            for e in bucket {
+                blocks[e.block_index as usize] = (weak, &e.strong_hash);
+            }
+        }
+        blocks
+    }
+
+    /// Summarize the weak-checksum buckets in one pass over the map.
+    pub fn stats(&self) -> SignatureStats {
+        let mut stats = SignatureStats {
+            distinct_weak: self.map.len() as u64,
+            ..SignatureStats::default()
+        };
+        for (&weak, bucket) in &self.map {
+            let n = bucket.len() as u64;
+            stats.block_count += n;
+            if n > stats.max_bucket || (n == stats.max_bucket && weak < stats.most_collided_weak) {
+                stats.max_bucket = n;
+                stats.most_collided_weak = weak;
+            }
+        }
+        if stats.distinct_weak > 0 {
+            stats.avg_bucket = stats.block_count as f64 / stats.distinct_weak as f64;
+        }
+        stats
+    }
+}
+
+/// Signatures are equal when they have the same block size, base length and
+/// checksums and the same `(weak, strong)` pair for every block, so a
+/// deserialized signature equals the one it was serialized from.
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        self.block_size == other.block_size
+            && self.len == other.len
+            && self.algo == other.algo
+            && self.weak == other.weak
+            && self.block_count() == other.block_count()
+            && self.blocks() == other.blocks()
+    }
+}
+
+impl Eq for Signature {}
+
+/// An old known only through its signature: its length, none of its bytes.
+struct SignedOld(usize);
+
+impl OldBytes for SignedOld {
+    fn len(&self) -> usize {
+        self.0
+    }
+
+    fn bytes(&self, _range: Range<usize>) -> Cow<'_, [u8]> {
+        unreachable!("the matcher checks has_bytes before reading old")
+    }
+
+    fn has_bytes(&self) -> bool {
+        false
+    }
+}
+
+/// Serialized signature of `old` in blocks of `block_size` bytes, as
+/// `Signature::to_bytes` writes it.
+pub fn build_signature_bytes(old: &[u8], block_size: usize) -> Result<Vec<u8>, XDeltaError> {
+    Ok(Signature::new(old, block_size)?.to_bytes())
+}
+
+/// Create a patch turning the old that `sig` (a serialized signature) was
+/// built from into `new`, without old itself; see
+/// `Signature::create_patch_without_old`.
+pub fn create_patch_from_signature(sig: &[u8], new: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    Signature::from_bytes(sig)?.create_patch_without_old(new, &PatchOptions::new())
+}
+
+/// Builds a signature from data that arrives in pieces.
+struct SignatureBuilder {
+    block_size: usize,
+    len: u64,
+    map: HashMap<u32, Vec<SigEntry>>,
+    block: Vec<u8>,
+    index: u64,
+}
+
+impl SignatureBuilder {
+    fn new(block_size: usize) -> Self {
+        SignatureBuilder {
+            block_size,
+            len: 0,
+            map: HashMap::new(),
+            block: Vec::with_capacity(block_size),
+            index: 0,
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = usize::min(self.block_size - self.block.len(), data.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == self.block_size {
+                add_block_signature(&mut self.map, self.index, &self.block, HashAlgo::Sha256, WeakAlgo::Rolling);
+                self.index += 1;
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Signature {
+        if !self.block.is_empty() {
+            add_block_signature(&mut self.map, self.index, &self.block, HashAlgo::Sha256, WeakAlgo::Rolling);
+        }
+        Signature {
+            block_size: self.block_size,
+            len: self.len,
+            algo: HashAlgo::Sha256,
+            weak: WeakAlgo::Rolling,
+            map: self.map,
+        }
+    }
+}
+
+/// Apply `patch` and sign the output as it is produced, at the block size
+/// recorded in the patch (see `PatchOptions::embed_block_size`).
+///
+/// The signature is the one `Signature::new` would compute for the output,
+/// ready for diffing the next version against it without another pass.
+pub fn apply_with_signature(old: &[u8], patch: &[u8]) -> Result<(Vec<u8>, Signature), XDeltaError> {
+    let block_size = embedded_block_size(patch_records(patch)?)?
+        .ok_or_else(|| XDeltaError::InvalidArg("patch has no BLOCK_SIZE record".into()))?;
+    if block_size == 0 {
+        return Err(XDeltaError::InvalidArg("block_size must be > 0".into()));
+    }
+    let mut out = Vec::new();
+    let mut builder = SignatureBuilder::new(block_size as usize);
+    let mut iter = ApplyIter::for_patch(old, patch, false)?;
+    while let Some(chunk) = iter.next_chunk(Some(&out))? {
+        builder.push(&chunk);
+        out.extend_from_slice(&chunk);
+    }
+    Ok((out, builder.finish()))
+}
+
+/// The block size from the BLOCK_SIZE record among the leading metadata records.
+fn embedded_block_size(patch: &[u8]) -> Result<Option<u32>, XDeltaError> {
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        match record {
+            Record::BlockSize(block_size) => return Ok(Some(block_size)),
+            r if r.output_len() == 0 => pos = next,
+            _ => break,
+        }
+    }
+    Ok(None)
+}
+
+/// C layout of `SignatureStats`; the caller sets `size` before the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XdeltaSigStats {
+    pub size: u32,
+    pub most_collided_weak: u32,
+    pub block_count: u64,
+    pub distinct_weak: u64,
+    pub max_bucket: u64,
+    pub avg_bucket: f64,
+}
+
+/// 计算旧数据的块签名，用完后用 xdelta_signature_free 释放；失败时返回 NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_signature_new(old_data: *const u8, old_len: usize, block_size: u32) -> *mut Signature {
+    let r = (|| -> Result<Signature, XDeltaError> {
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        Signature::new(old_bytes, block_size as usize)
+    })();
+
+    match r {
+        Ok(sig) => Box::into_raw(Box::new(sig)),
+        Err(e) => {
+            crate::set_last_error(&e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// 释放块签名
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_signature_free(sig: *mut Signature) {
+    if !sig.is_null() {
+        drop(unsafe { Box::from_raw(sig) });
+    }
+}
+
+/// 统计签名表：块数、不同弱校验值个数、最大/平均桶大小及冲突最多的弱校验值
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_signature_stats(sig: *const Signature, stats: *mut XdeltaSigStats) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let sig = unsafe { sig.as_ref() }.ok_or(XDeltaError::NullPointer)?;
+        if stats.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        let s = sig.stats();
+        let value = XdeltaSigStats {
+            size: 0,
+            most_collided_weak: s.most_collided_weak,
+            block_count: s.block_count,
+            distinct_weak: s.distinct_weak,
+            max_bucket: s.max_bucket,
+            avg_bucket: s.avg_bucket,
+        };
+        write_sized(stats, value, std::mem::size_of::<XdeltaSigStats>())
+    })();
+
+    ffi_status(r)
+}
+
+/// 比较两个块签名：块大小、数据长度及每个块的弱/强校验值（按块序，与内部存储顺序无关）
+/// 相同时返回1，不同时返回0，参数无效时返回负的错误码
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_signatures_equal(sig_a: *const Signature, sig_b: *const Signature) -> c_int {
+    let r = (|| -> Result<bool, XDeltaError> {
+        let (Some(a), Some(b)) = (unsafe { sig_a.as_ref() }, unsafe { sig_b.as_ref() }) else {
+            return Err(XDeltaError::NullPointer);
+        };
+        Ok(a == b)
+    })();
+
+    match r {
+        Ok(equal) => equal as c_int,
+        Err(e) => ffi_status(Err(e)),
+    }
+}
+
+/// 应用补丁并同时计算输出的块签名（块大小取自补丁中的 BLOCK_SIZE 记录，需 XDELTA_OPT_EMBED_BLOCK_SIZE）
+/// 成功时 *sig 为新签名，用 xdelta_signature_free 释放；输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_signature(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+    sig: *mut *mut Signature,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if new_data.is_null() || new_len.is_null() || sig.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        let (out, signature) = apply_with_signature(old_bytes, patch_bytes)?;
+        unsafe { *sig = Box::into_raw(Box::new(signature)) };
+        Ok(out)
+    })();
+
+    let rc = write_output(r, new_data, new_len);
+    if rc != 0 && !sig.is_null() {
+        // don't hand out a signature without its output
+        let made = unsafe { std::mem::replace(&mut *sig, std::ptr::null_mut()) };
+        xdelta_signature_free(made);
+    }
+    rc
+}
+
+/// 使用已有的旧数据签名创建补丁（签名须由同一 old 计算），块大小取自签名；opts 为 NULL 时使用默认选项
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_signature(
+    sig: *const Signature,
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    opts: *const XdeltaOptions,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        let sig = unsafe { sig.as_ref() }.ok_or(XDeltaError::NullPointer)?;
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        sig.create_patch(old_bytes, new_bytes, &options_from_ffi(opts)?)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 只凭签名句柄创建补丁（不需要旧数据本身），同一句柄可对多个新数据重复使用，免去每次重新计算签名
+/// 与 xdelta_create_patch_from_signature 相同只按整块匹配旧数据；补丁照常用旧数据应用，输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_signature_create_patch(
+    sig: *const Signature,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        let sig = unsafe { sig.as_ref() }.ok_or(XDeltaError::NullPointer)?;
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        sig.create_patch_without_old(new_bytes, &PatchOptions::new())
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 序列化块签名，输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_signature_serialize(sig: *const Signature, out_data: *mut *mut u8, out_len: *mut usize) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        let sig = unsafe { sig.as_ref() }.ok_or(XDeltaError::NullPointer)?;
+        if out_data.is_null() || out_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+        Ok(sig.to_bytes())
+    })();
+
+    write_output(r, out_data, out_len)
+}
+
+/// 计算旧数据的块签名并直接序列化（等同 xdelta_signature_new 后 xdelta_signature_serialize），
+/// 输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_build_signature(
+    old_data: *const u8,
+    old_len: usize,
+    block_size: u32,
+    sig_data: *mut *mut u8,
+    sig_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if sig_data.is_null() || sig_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+
+        build_signature_bytes(old_bytes, block_size as usize)
+    })();
+
+    write_output(r, sig_data, sig_len)
+}
+
+/// 只凭序列化的旧数据签名（不需要旧数据本身）创建补丁，用于旧数据不离开接收方的 rsync 式流程
+/// 只按整块匹配旧数据；补丁照常用旧数据应用
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_from_signature(
+    sig_data: *const u8,
+    sig_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let sig_bytes = unsafe { ffi_slice(sig_data, sig_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch_from_signature(sig_bytes, new_bytes)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 解析 xdelta_signature_serialize 的输出（可来自不可信来源，所有计数和长度均经校验）
+/// 用完后用 xdelta_signature_free 释放；失败时返回 NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_signature_deserialize(data: *const u8, len: usize) -> *mut Signature {
+    let r = (|| -> Result<Signature, XDeltaError> {
+        let bytes = unsafe { ffi_slice(data, len)? };
+        Signature::from_bytes(bytes)
+    })();
+
+    match r {
+        Ok(sig) => Box::into_raw(Box::new(sig)),
+        Err(e) => {
+            crate::set_last_error(&e);
+            std::ptr::null_mut()
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// src/simd.rs
+//! The rsync-style weak checksum of a block summed 16 bytes at a time.
+//!
+//! Over a window of `n` bytes the checksum is `a = Σ v[i]` and
+//! `b = Σ (n - i) v[i]`, which is `b = n a - Σ i v[i]`: two sums whose
+//! weights don't depend on `n`, so they split into lanes. Everything wraps
+//! mod 2^32 exactly as the scalar loop in `Rolling::from_slice` does, so the
+//! results are bitwise identical to it.
+//!
+//! x86_64 always has SSE2, so it is used without detection; other targets
+//! get the same lane-wise sums over arrays, for the compiler to vectorize.
+//! SHA-256 needs nothing here: `sha2` already picks the CPU's SHA
+//! extensions at runtime where they exist.
+
+const LANES: usize = 16;
+
+/// The rolling checksum's `(a, b)` of `buf`.
+pub(crate) fn rolling_sums(buf: &[u8]) -> (u32, u32) {
+    let chunks = buf.chunks_exact(LANES);
+    let tail = chunks.remainder();
+    let count = chunks.len() as u32;
+    let Lanes { sum, before, weighted } = lane_sums(chunks);
+    // chunk k contributes Σ (16k + j) v, and Σ k S_k over the chunk sums S
+    // is (count - 1) Σ S minus `before`, the sums of the chunks before each
+    let mut a = sum;
+    let mut positional =
+        count.wrapping_sub(1).wrapping_mul(sum).wrapping_sub(before).wrapping_mul(LANES as u32).wrapping_add(weighted);
+    let base = buf.len() - tail.len();
+    for (i, &v) in tail.iter().enumerate() {
+        a = a.wrapping_add(v as u32);
+        positional = positional.wrapping_add(((base + i) as u32).wrapping_mul(v as u32));
+    }
+    (a, (buf.len() as u32).wrapping_mul(a).wrapping_sub(positional))
+}
+
+/// Lane sums over whole chunks, reduced: of the bytes, of the running
+/// total before each chunk, and of the bytes weighted by their place in
+/// their chunk.
+struct Lanes {
+    sum: u32,
+    before: u32,
+    weighted: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+fn lane_sums(chunks: core::slice::ChunksExact<'_, u8>) -> Lanes {
+    use core::arch::x86_64::*;
+
+    fn reduce(v: __m128i) -> u32 {
+        let mut lanes = [0u32; 4];
+        // SAFETY: SSE2 is part of the x86_64 baseline; the store is unaligned.
+        unsafe { _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, v) };
+        lanes.iter().fold(0u32, |acc, &x| acc.wrapping_add(x))
+    }
+
+    // SAFETY: SSE2 is part of the x86_64 baseline, and every load reads one
+    // whole 16-byte chunk, unaligned.
+    unsafe {
+        let zero = _mm_setzero_si128();
+        let ones = _mm_set1_epi16(1);
+        let low_weights = _mm_setr_epi16(0, 1, 2, 3, 4, 5, 6, 7);
+        let high_weights = _mm_setr_epi16(8, 9, 10, 11, 12, 13, 14, 15);
+        let (mut sum, mut before, mut weighted) = (zero, zero, zero);
+        for chunk in chunks {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let (low, high) = (_mm_unpacklo_epi8(v, zero), _mm_unpackhi_epi8(v, zero));
+            before = _mm_add_epi32(before, sum);
+            sum = _mm_add_epi32(sum, _mm_madd_epi16(_mm_add_epi16(low, high), ones));
+            let place = _mm_add_epi32(_mm_madd_epi16(low, low_weights), _mm_madd_epi16(high, high_weights));
+            weighted = _mm_add_epi32(weighted, place);
+        }
+        Lanes { sum: reduce(sum), before: reduce(before), weighted: reduce(weighted) }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn lane_sums(chunks: core::slice::ChunksExact<'_, u8>) -> Lanes {
+    let (mut sum, mut before, mut weighted) = ([0u32; LANES], [0u32; LANES], [0u32; LANES]);
+    for chunk in chunks {
+        for j in 0..LANES {
+            before[j] = before[j].wrapping_add(sum[j]);
+            sum[j] = sum[j].wrapping_add(chunk[j] as u32);
+            weighted[j] = weighted[j].wrapping_add(j as u32 * chunk[j] as u32);
+        }
+    }
+    let reduce = |lanes: [u32; LANES]| lanes.iter().fold(0u32, |acc, &x| acc.wrapping_add(x));
+    Lanes { sum: reduce(sum), before: reduce(before), weighted: reduce(weighted) }
+}
@@ -0,0 +1,222 @@
+// src/sparse.rs
+//! A sparse old: a region of which only some extents are present, the gaps
+//! between them reading as zeros (memory released with MADV_DONTNEED, say).
+//!
+//! Gaps are never touched: the matcher indexes the present extents plus one
+//! all-zero block standing for every hole, and COPY records into a hole read
+//! zeros when the patch is applied against the same description.
+
+use crate::stream::{apply_streaming, ApplyOptions, OldSource};
+#[cfg(debug_assertions)]
+use crate::with_header;
+use crate::{
+    build_signatures, check_strict, ffi_slice, finish_patch, match_blocks, options_from_ffi, write_output, MatchHooks,
+    OldBytes, PatchOptions, XDeltaError, XdeltaOptions,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::os::raw::c_int;
+
+/// Old data given as the extents of a region that are present.
+#[derive(Clone, Debug)]
+pub struct SparseOld<'a> {
+    len: usize,
+    /// Present extents by offset, sorted and non-overlapping.
+    extents: Vec<(usize, &'a [u8])>,
+}
+
+impl<'a> SparseOld<'a> {
+    /// A region of `len` bytes whose only present bytes are `extents`, each
+    /// given as its offset in the region and its contents.
+    pub fn new(len: u64, extents: &[(u64, &'a [u8])]) -> Result<Self, XDeltaError> {
+        let len = usize::try_from(len).map_err(|_| XDeltaError::InvalidArg("sparse old too large".into()))?;
+        let mut sorted = Vec::with_capacity(extents.len());
+        for &(offset, data) in extents {
+            let range = crate::old_range(len, offset, data.len() as u64, "extent")?;
+            sorted.push((range.start, data));
+        }
+        sorted.sort_unstable_by_key(|&(offset, _)| offset);
+        if sorted.windows(2).any(|w| w[0].0 + w[0].1.len() > w[1].0) {
+            return Err(XDeltaError::InvalidArg("extents overlap".into()));
+        }
+        Ok(SparseOld { len, extents: sorted })
+    }
+
+    /// Extents that share at least one byte with `range`.
+    fn overlapping(&self, range: Range<usize>) -> impl Iterator<Item = (usize, &'a [u8])> + '_ {
+        // the first extent that ends after range.start
+        let first = self.extents.partition_point(|&(offset, data)| offset + data.len() <= range.start);
+        self.extents[first..]
+            .iter()
+            .copied()
+            .take_while(move |&(offset, _)| offset < range.end)
+    }
+}
+
+impl OldBytes for SparseOld<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn bytes(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        let mut extents = self.overlapping(range.clone()).peekable();
+        if let Some(&(offset, data)) = extents.peek() {
+            if offset <= range.start && range.end <= offset + data.len() {
+                return Cow::Borrowed(&data[range.start - offset..range.end - offset]);
+            }
+        }
+        let mut out = vec![0u8; range.len()];
+        for (offset, data) in extents {
+            let from = usize::max(offset, range.start);
+            let to = usize::min(offset + data.len(), range.end);
+            out[from - range.start..to - range.start].copy_from_slice(&data[from - offset..to - offset]);
+        }
+        Cow::Owned(out)
+    }
+
+    fn is_hole(&self, range: Range<usize>) -> bool {
+        self.overlapping(range).next().is_none()
+    }
+}
+
+impl OldSource for &SparseOld<'_> {
+    fn size(&self) -> u64 {
+        self.len as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+        let range = crate::old_range(self.len, offset, buf.len() as u64, "read")?;
+        buf.copy_from_slice(&self.bytes(range));
+        Ok(())
+    }
+}
+
+/// Create a patch turning the sparse `old` into `new`.
+///
+/// Zeros in new may be copied from a hole of old. `old_hash` is not supported,
+/// as it would have to read every hole.
+pub fn create_patch_sparse(old: &SparseOld, new: &[u8], opts: &PatchOptions) -> Result<Vec<u8>, XDeltaError> {
+    if opts.old_hash {
+        return Err(XDeltaError::InvalidArg("old_hash is not supported with a sparse old".into()));
+    }
+    if opts.strict && opts.content_addressed {
+        return Err(XDeltaError::InvalidArg("strict content_addressed is not supported with a sparse old".into()));
+    }
+    let opts = &opts.resolve_block_size(old.len);
+    let mut sigs = HashMap::new();
+    let (tail, weak) = (opts.effective_tail_policy(), opts.weak_checksum);
+    build_signatures(&mut sigs, old, opts.block_size, tail, opts.strong_hash, weak);
+    let mut patch = Vec::with_capacity(new.len() / 4);
+    match_blocks(old, new, opts, &sigs, &mut patch, &mut Vec::new(), MatchHooks::default())?;
+    #[cfg(debug_assertions)]
+    if !opts.content_addressed && opts.dirty_blocks.is_none() {
+        assert!(
+            apply_sparse(old, &with_header(&patch)).is_ok_and(|out| out == new),
+            "patch does not reconstruct new (sparse old of {} bytes, {:?})",
+            old.len,
+            opts
+        );
+    }
+    // old is only read for old_hash, which was refused above, and for
+    // strict, which is checked against the sparse old here instead
+    let patch = finish_patch(&[], new, patch, &opts.as_ref().clone().strict(false))?;
+    if opts.strict {
+        check_strict(apply_sparse(old, &patch), new)?;
+    }
+    Ok(patch)
+}
+
+/// Apply `patch` to the sparse `old`, reading its holes as zeros.
+pub fn apply_sparse(old: &SparseOld, patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    let mut out = Vec::new();
+    apply_streaming(&mut &*old, patch, &mut out, &ApplyOptions::new())?;
+    Ok(out)
+}
+
+/// A present extent of a sparse old, relative to its base address.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XdeltaExtent {
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Build a `SparseOld` from a C base address and extent list, touching only
+/// the extents.
+///
+/// # Safety
+/// Every extent of `base` must be readable for the lifetime of the result.
+unsafe fn sparse_from_ffi<'a>(
+    base: *const u8,
+    len: u64,
+    extents: *const XdeltaExtent,
+    extent_count: usize,
+) -> Result<SparseOld<'a>, XDeltaError> {
+    if extent_count > 0 && (base.is_null() || extents.is_null()) {
+        return Err(XDeltaError::NullPointer);
+    }
+    let mut present = Vec::with_capacity(extent_count);
+    for i in 0..extent_count {
+        let e = unsafe { *extents.add(i) };
+        let range = crate::old_range(usize::try_from(len).unwrap_or(usize::MAX), e.offset, e.len, "extent")?;
+        present.push((e.offset, unsafe { std::slice::from_raw_parts(base.add(range.start), range.len()) }));
+    }
+    SparseOld::new(len, &present)
+}
+
+/// 以稀疏形式的旧数据创建补丁：old_base 起 old_len 字节中只有 extents 所列范围存在，其余视为 0 且不会被读取
+/// 不支持 XDELTA_OPT_OLD_HASH；opts 为 NULL 时使用默认选项
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_sparse(
+    old_base: *const u8,
+    old_len: u64,
+    extents: *const XdeltaExtent,
+    extent_count: usize,
+    new_data: *const u8,
+    new_len: usize,
+    opts: *const XdeltaOptions,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old = unsafe { sparse_from_ffi(old_base, old_len, extents, extent_count)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch_sparse(&old, new_bytes, &options_from_ffi(opts)?)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 对稀疏形式的旧数据应用补丁，未列出的范围视为 0 且不会被读取
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_sparse(
+    old_base: *const u8,
+    old_len: u64,
+    extents: *const XdeltaExtent,
+    extent_count: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old = unsafe { sparse_from_ffi(old_base, old_len, extents, extent_count)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_sparse(&old, patch_bytes)
+    })();
+
+    write_output(r, new_data, new_len)
+}
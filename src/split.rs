@@ -0,0 +1,251 @@
+// src/split.rs
+//! Splitting a patch into sub-patches over contiguous output ranges.
+//!
+//! Each sub-patch is an ordinary patch that starts with an OUTPUT_OFFSET
+//! record telling where its output goes in the full output, so the parts can
+//! be transferred and applied independently (and in parallel) into their
+//! slices of the output buffer.
+//!
+//! OUTPUT_OFFSET layout: opcode 0x83, body length: u32 (8), offset: u64.
+
+use crate::{
+    const_table, ffi_slice, ffi_status, inflate_add, is_identity, patch_records, read_record, read_u32, with_header,
+    write_output, xor_delta, Record, XDeltaError,
+};
+use std::os::raw::c_int;
+
+/// Split `patch` into `parts` sub-patches whose outputs are consecutive,
+/// roughly equal ranges of the full output.
+///
+/// Records straddling a boundary are cut in two, except COPY_HASH blocks,
+/// which cannot be cut and stay whole in the part where they start (so that
+/// part runs a little long). A CONST_TABLE is repeated in every part; INDEX,
+/// OLD_HASH and CHECK records describe the whole patch and are dropped.
+pub fn split_patch(patch: &[u8], parts: usize) -> Result<Vec<Vec<u8>>, XDeltaError> {
+    if parts == 0 {
+        return Err(XDeltaError::InvalidArg("parts must be > 0".into()));
+    }
+    let records = patch_records(patch)?;
+    if is_identity(patch) {
+        // no records to cut; the patch as it is covers the whole output
+        return Ok(vec![patch.to_vec()]);
+    }
+    let patch = records;
+
+    let mut base = 0u64;
+    let mut consts: Option<&[u8]> = None;
+    let mut const_record: &[u8] = &[];
+    let mut total = 0u64;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        match record {
+            Record::ConstTable(body) => {
+                consts = Some(body);
+                const_record = &patch[pos..next];
+            }
+            Record::OutputOffset(offset) => base = offset,
+            Record::Skippable(opcode) => {
+                return Err(XDeltaError::InvalidArg(format!("cannot split unknown opcode {:#x}", opcode)));
+            }
+            _ => {}
+        }
+        total += record.output_len();
+        pos = next;
+    }
+    let target_end = |part: usize| (total as u128 * (part + 1) as u128 / parts as u128) as u64;
+
+    let start_part = |out_start: u64| {
+        let mut part = with_header(&[]);
+        part.push(0x83); // OUTPUT_OFFSET
+        part.extend_from_slice(&8u32.to_le_bytes());
+        part.extend_from_slice(&(base + out_start).to_le_bytes());
+        part.extend_from_slice(const_record);
+        part
+    };
+
+    let mut out = vec![start_part(0)];
+    let mut out_pos = 0u64;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        pos = next;
+        let len = record.output_len();
+        let mut from = 0u64;
+        while from < len {
+            while out.len() < parts && out_pos >= target_end(out.len() - 1) {
+                out.push(start_part(out_pos));
+            }
+            let take = match record {
+                Record::CopyHash { .. } => len,
+                _ if out.len() == parts => len - from,
+                _ => u64::min(len - from, target_end(out.len() - 1) - out_pos),
+            };
+            let part = out.last_mut().expect("at least one part");
+            write_piece(part, &record, consts, from as usize, (from + take) as usize)?;
+            from += take;
+            out_pos += take;
+        }
+    }
+    while out.len() < parts {
+        out.push(start_part(total));
+    }
+    Ok(out)
+}
+
+/// Where the output of a sub-patch from `split_patch` starts; 0 for a whole patch.
+pub fn sub_patch_offset(patch: &[u8]) -> Result<u64, XDeltaError> {
+    let patch = patch_records(patch)?;
+    if patch.is_empty() {
+        return Ok(0);
+    }
+    match read_record(patch, 0)? {
+        (Record::OutputOffset(offset), _) => Ok(offset),
+        _ => Ok(0),
+    }
+}
+
+/// Append the records producing output bytes `from..to` of `record`.
+fn write_piece(
+    part: &mut Vec<u8>,
+    record: &Record,
+    consts: Option<&[u8]>,
+    from: usize,
+    to: usize,
+) -> Result<(), XDeltaError> {
+    match *record {
+        Record::Add(data) => write_add(part, &data[from..to]),
+        Record::AddZstd { len, data } => write_add(part, &inflate_add(len, data)?[from..to]),
+        Record::Copy { offset, .. } => {
+            part.push(0x01); // COPY
+            part.extend_from_slice(&(offset + from as u64).to_le_bytes());
+            part.extend_from_slice(&((to - from) as u32).to_le_bytes());
+        }
+        Record::Diff { offset, deltas, .. } => {
+            let kept: Vec<&[u8]> = deltas
+                .chunks_exact(5)
+                .filter(|e| (from..to).contains(&(read_u32(e, 0) as usize)))
+                .collect();
+            part.push(0x10); // DIFF
+            part.extend_from_slice(&(offset + from as u64).to_le_bytes());
+            part.extend_from_slice(&((to - from) as u32).to_le_bytes());
+            part.extend_from_slice(&(kept.len() as u32).to_le_bytes());
+            for e in kept {
+                part.extend_from_slice(&(read_u32(e, 0) - from as u32).to_le_bytes());
+                part.push(e[4]);
+            }
+        }
+        Record::Xor { offset, body, .. } => {
+            xor_delta::write_record(
+                part,
+                offset + from as u64,
+                (to - from) as u32,
+                &xor_delta::sub_body(body, from, to),
+            );
+        }
+        Record::CopyConst { index, .. } => {
+            // a cut mid-tile would shift the phase, so the partial tile goes out as ADD
+            let tile = const_table::const_entry(consts, index)?;
+            let head = usize::min(to, from.next_multiple_of(tile.len()));
+            write_add(part, &const_table::expand_const(tile, from, head));
+            if head < to {
+                part.push(0x11); // COPY_CONST
+                part.push(index);
+                part.extend_from_slice(&((to - head) as u32).to_le_bytes());
+            }
+        }
+        Record::Run { byte, .. } => {
+            part.push(0x02); // RUN
+            part.push(byte);
+            part.extend_from_slice(&((to - from) as u32).to_le_bytes());
+        }
+        Record::CopyTarget { .. } => {
+            return Err(XDeltaError::InvalidArg(
+                "COPY_TARGET record can't be split: a part applies without the output before it".into(),
+            ));
+        }
+        Record::CopyHash { hash, len } => {
+            part.push(0x12); // COPY_HASH
+            part.extend_from_slice(hash);
+            part.extend_from_slice(&len.to_le_bytes());
+        }
+        Record::Index(_)
+        | Record::OldHash(_)
+        | Record::ConstTable(_)
+        | Record::OutputOffset(_)
+        | Record::Padding
+        | Record::BlockSize(_)
+        | Record::MinVersion
+        | Record::Check(_)
+        | Record::Skippable(_) => {}
+    }
+    Ok(())
+}
+
+fn write_add(part: &mut Vec<u8>, data: &[u8]) {
+    if !data.is_empty() {
+        part.push(0x00); // ADD
+        part.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        part.extend_from_slice(data);
+    }
+}
+
+/// 将补丁按输出位置拆分为 parts 个可独立应用的子补丁，每个子补丁记录其输出偏移
+/// sub_patches/sub_lens 为调用方提供的长度为 parts 的数组，每个子补丁用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）（失败时不返回任何子补丁）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_split_patch(
+    patch_data: *const u8,
+    patch_len: usize,
+    parts: usize,
+    sub_patches: *mut *mut u8,
+    sub_lens: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<Vec<u8>>, XDeltaError> {
+        if sub_patches.is_null() || sub_lens.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        split_patch(patch_bytes, parts)
+    })();
+
+    match r {
+        Ok(subs) => {
+            for (i, sub) in subs.into_iter().enumerate() {
+                let rc = unsafe { write_output(Ok(sub), sub_patches.add(i), sub_lens.add(i)) };
+                if rc != 0 {
+                    for j in 0..i {
+                        unsafe {
+                            crate::xdelta_free_data(*sub_patches.add(j));
+                            *sub_patches.add(j) = std::ptr::null_mut();
+                            *sub_lens.add(j) = 0;
+                        }
+                    }
+                    return rc;
+                }
+            }
+            0
+        }
+        Err(e) => ffi_status(Err(e)),
+    }
+}
+
+/// 读取子补丁的输出偏移（完整补丁为 0）
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_sub_patch_offset(patch_data: *const u8, patch_len: usize, offset: *mut u64) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if offset.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        unsafe { *offset = sub_patch_offset(patch_bytes)? };
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
@@ -0,0 +1,801 @@
+// src/stream.rs
+//! Streaming apply: old is read on demand and output is written as it is produced.
+
+use crate::const_table::{const_entry, expand_const};
+use crate::xor_delta::xor_into;
+use crate::{
+    apply_deltas, check_cancel, ffi_slice, ffi_status, inflate_add, is_identity, patch_records, read_record,
+    without_record_crcs, write_sized, CancelToken, OutputCheck, Record, XDeltaError,
+};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::os::raw::{c_int, c_void};
+
+/// Largest piece of old read (and written out) at once by a COPY.
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// Random-access source for the base ("old") data of a streaming apply.
+pub trait OldSource {
+    /// Total length of old in bytes.
+    fn size(&self) -> u64;
+
+    /// Fill `buf` with the bytes of old starting at `offset`.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError>;
+}
+
+impl OldSource for &[u8] {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+        let range = crate::old_range(self.len(), offset, buf.len() as u64, "read")?;
+        buf.copy_from_slice(&self[range]);
+        Ok(())
+    }
+}
+
+/// How much of old a streaming apply checks against the patch's OLD_HASH record.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VerifyOld {
+    /// No verification.
+    #[default]
+    None,
+    /// Hash only the ranges COPY/DIFF/XOR_DELTA records read. Cheap, but corruption in
+    /// parts of old the patch never references goes unnoticed. Checked once
+    /// all output has been written.
+    Partial,
+    /// Read and hash all of old before any output is written.
+    Full,
+}
+
+/// Options for `apply_streaming`.
+#[derive(Clone, Debug, Default)]
+pub struct ApplyOptions {
+    verify: VerifyOld,
+    cancel: Option<CancelToken>,
+    write_alignment: Option<(usize, Option<u8>)>,
+    read_alignment: Option<usize>,
+    max_old_read: Option<u64>,
+}
+
+impl ApplyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verify old against the patch's OLD_HASH record.
+    pub fn verify(mut self, verify: VerifyOld) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Stop with `XDeltaError::Cancelled` once `token` is cancelled.
+    pub fn cancel_token(mut self, token: &CancelToken) -> Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    /// Hand output to the writer only in chunks of exactly `alignment` bytes,
+    /// e.g. a flash page. The final partial chunk is padded with `pad_final`
+    /// up to `alignment`, or written short when `pad_final` is `None`.
+    pub fn write_alignment(mut self, alignment: usize, pad_final: Option<u8>) -> Self {
+        self.write_alignment = Some((alignment, pad_final));
+        self
+    }
+
+    /// Only read old at offsets and lengths that are multiples of
+    /// `alignment`, e.g. for word-aligned XIP flash. Small neighbouring reads
+    /// are served from one aligned window instead of hitting old each time.
+    /// A read ending at the end of old may be short if old's size isn't a
+    /// multiple of `alignment`.
+    pub fn read_alignment(mut self, alignment: usize) -> Self {
+        self.read_alignment = Some(alignment);
+        self
+    }
+
+    /// Stop with `XDeltaError::OldReadBudgetExceeded` rather than read more
+    /// than `max` bytes from old in total, e.g. from a metered store. Every
+    /// read counts: COPY/DIFF/XOR_DELTA sources, verification and the padding
+    /// of aligned reads. The read that would overrun is refused, not made.
+    pub fn max_old_bytes_read(mut self, max: u64) -> Self {
+        self.max_old_read = Some(max);
+        self
+    }
+}
+
+/// Refuses reads once `left` of the byte budget would go negative.
+struct BudgetSource<'s, S: OldSource + ?Sized> {
+    inner: &'s mut S,
+    budget: u64,
+    left: u64,
+}
+
+impl<S: OldSource + ?Sized> OldSource for BudgetSource<'_, S> {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+        self.left = self
+            .left
+            .checked_sub(buf.len() as u64)
+            .ok_or(XDeltaError::OldReadBudgetExceeded(self.budget))?;
+        self.inner.read_at(offset, buf)
+    }
+}
+
+/// Smallest window an aligned source reads at once.
+const READ_WINDOW: usize = 4096;
+
+/// Serves arbitrary reads from aligned reads of the inner source.
+struct AlignedSource<'s, S: OldSource + ?Sized> {
+    inner: &'s mut S,
+    alignment: u64,
+    /// Bytes read per fill, a multiple of `alignment`.
+    window_size: usize,
+    window: Vec<u8>,
+    /// Offset of `window` in old; its length is the number of valid bytes.
+    window_start: u64,
+}
+
+impl<'s, S: OldSource + ?Sized> AlignedSource<'s, S> {
+    fn new(inner: &'s mut S, alignment: usize) -> Self {
+        AlignedSource {
+            inner,
+            alignment: alignment as u64,
+            window_size: READ_WINDOW.next_multiple_of(alignment),
+            window: Vec::new(),
+            window_start: 0,
+        }
+    }
+
+    /// Load the aligned window containing `offset`.
+    fn fill(&mut self, offset: u64) -> Result<(), XDeltaError> {
+        let start = offset - offset % self.alignment;
+        let len = u64::min(self.window_size as u64, self.inner.size() - start) as usize;
+        self.window.resize(len, 0);
+        self.inner.read_at(start, &mut self.window)?;
+        self.window_start = start;
+        Ok(())
+    }
+}
+
+impl<S: OldSource + ?Sized> OldSource for AlignedSource<'_, S> {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn read_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> Result<(), XDeltaError> {
+        check_range(self.size(), offset, buf.len() as u64, "read")?;
+        while !buf.is_empty() {
+            let window_end = self.window_start + self.window.len() as u64;
+            if offset < self.window_start || offset >= window_end {
+                self.fill(offset)?;
+            }
+            let from = (offset - self.window_start) as usize;
+            let n = usize::min(buf.len(), self.window.len() - from);
+            buf[..n].copy_from_slice(&self.window[from..from + n]);
+            buf = &mut buf[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Buffers output and forwards it in whole `alignment`-sized chunks.
+struct AlignedWriter<'w, W: Write + ?Sized> {
+    inner: &'w mut W,
+    alignment: usize,
+    buf: Vec<u8>,
+}
+
+impl<W: Write + ?Sized> AlignedWriter<'_, W> {
+    fn finish(mut self, pad_final: Option<u8>) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        if let Some(pad) = pad_final {
+            self.buf.resize(self.alignment, pad);
+        }
+        self.inner.write_all(&self.buf)
+    }
+}
+
+impl<W: Write + ?Sized> Write for AlignedWriter<'_, W> {
+    fn write(&mut self, mut data: &[u8]) -> std::io::Result<usize> {
+        let written = data.len();
+        // top up a partially filled chunk first
+        if !self.buf.is_empty() {
+            let n = usize::min(self.alignment - self.buf.len(), data.len());
+            self.buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buf.len() < self.alignment {
+                return Ok(written);
+            }
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        // whole chunks go straight through, one aligned write each
+        let mut chunks = data.chunks_exact(self.alignment);
+        for chunk in &mut chunks {
+            self.inner.write_all(chunk)?;
+        }
+        self.buf.extend_from_slice(chunks.remainder());
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hashes output on its way to `inner` for the patch's CHECK record.
+struct CheckedWriter<'w, W: Write + ?Sized> {
+    inner: &'w mut W,
+    check: OutputCheck,
+}
+
+impl<W: Write + ?Sized> Write for CheckedWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(data)?;
+        self.check.update(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Apply `patch`, reading old from `old` and writing the result to `out`.
+///
+/// Memory use is bounded by the largest ADD/DIFF record plus a fixed copy
+/// buffer. Verification requires a patch created with `PatchOptions::old_hash`.
+pub fn apply_streaming<S, W>(old: &mut S, patch: &[u8], out: &mut W, opts: &ApplyOptions) -> Result<(), XDeltaError>
+where
+    S: OldSource + ?Sized,
+    W: Write + ?Sized,
+{
+    let patch = &*without_record_crcs(patch)?;
+    // the budget sits under the alignment, counting what old is actually asked for
+    match opts.max_old_read {
+        Some(budget) => apply_read_aligned(&mut BudgetSource { inner: old, budget, left: budget }, patch, out, opts),
+        None => apply_read_aligned(old, patch, out, opts),
+    }
+}
+
+fn apply_read_aligned<S, W>(old: &mut S, patch: &[u8], out: &mut W, opts: &ApplyOptions) -> Result<(), XDeltaError>
+where
+    S: OldSource + ?Sized,
+    W: Write + ?Sized,
+{
+    match opts.read_alignment {
+        Some(0) => Err(XDeltaError::InvalidArg("read alignment must be > 0".into())),
+        Some(alignment) => apply_to_writer(&mut AlignedSource::new(old, alignment), patch, out, opts),
+        None => apply_to_writer(old, patch, out, opts),
+    }
+}
+
+fn apply_to_writer<S, W>(old: &mut S, patch: &[u8], out: &mut W, opts: &ApplyOptions) -> Result<(), XDeltaError>
+where
+    S: OldSource + ?Sized,
+    W: Write + ?Sized,
+{
+    match opts.write_alignment {
+        Some((0, _)) => Err(XDeltaError::InvalidArg("write alignment must be > 0".into())),
+        Some((alignment, pad_final)) => {
+            let mut aligned = AlignedWriter {
+                inner: out,
+                alignment,
+                buf: Vec::with_capacity(alignment),
+            };
+            apply_records(old, patch, &mut aligned, opts)?;
+            aligned.finish(pad_final).map_err(io_error)
+        }
+        None => apply_records(old, patch, out, opts),
+    }
+}
+
+fn apply_records<S, W>(old: &mut S, patch: &[u8], out: &mut W, opts: &ApplyOptions) -> Result<(), XDeltaError>
+where
+    S: OldSource + ?Sized,
+    W: Write + ?Sized,
+{
+    let identity = is_identity(patch);
+    let records = patch_records(patch)?;
+    let mut out = CheckedWriter { inner: out, check: OutputCheck::new(patch) };
+    let patch = records;
+    let verify = opts.verify;
+    let expected = if verify == VerifyOld::None {
+        None
+    } else {
+        Some(find_old_hash(patch)?)
+    };
+
+    if let (VerifyOld::Full, Some(expected)) = (verify, expected) {
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; COPY_CHUNK];
+        let mut offset = 0u64;
+        while offset < old.size() {
+            check_cancel(opts.cancel.as_ref())?;
+            let n = u64::min(COPY_CHUNK as u64, old.size() - offset) as usize;
+            old.read_at(offset, &mut buf[..n])?;
+            hasher.update(&buf[..n]);
+            offset += n as u64;
+        }
+        if hasher.finalize()[..] != expected[..32] {
+            return Err(XDeltaError::OldHashMismatch("full hash of old differs".into()));
+        }
+    }
+
+    if identity {
+        // all of old, a chunk at a time
+        let mut buf = vec![0u8; usize::min(COPY_CHUNK, usize::try_from(old.size()).unwrap_or(usize::MAX))];
+        let mut offset = 0u64;
+        while offset < old.size() {
+            check_cancel(opts.cancel.as_ref())?;
+            let n = u64::min(buf.len() as u64, old.size() - offset) as usize;
+            old.read_at(offset, &mut buf[..n])?;
+            out.write_all(&buf[..n]).map_err(io_error)?;
+            offset += n as u64;
+        }
+        return out.check.finish();
+    }
+
+    let mut referenced = Sha256::new();
+    let mut buf = Vec::new();
+    let mut consts = None;
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        check_cancel(opts.cancel.as_ref())?;
+        let (record, next) = read_record(patch, pos)?;
+        pos = next;
+        out.check.record(&record)?;
+        match record {
+            Record::Add(data) => out.write_all(data).map_err(io_error)?,
+            Record::AddZstd { len, data } => out.write_all(&inflate_add(len, data)?).map_err(io_error)?,
+            Record::Copy { offset, len } => {
+                check_range(old.size(), offset, len as u64, "COPY")?;
+                buf.resize(usize::min(COPY_CHUNK, len as usize), 0);
+                let mut done = 0u64;
+                while done < len as u64 {
+                    let n = usize::min(buf.len(), (len as u64 - done) as usize);
+                    old.read_at(offset + done, &mut buf[..n])?;
+                    if verify == VerifyOld::Partial {
+                        referenced.update(&buf[..n]);
+                    }
+                    out.write_all(&buf[..n]).map_err(io_error)?;
+                    done += n as u64;
+                }
+            }
+            Record::Diff { offset, len, deltas } => {
+                check_range(old.size(), offset, len as u64, "DIFF")?;
+                let mut block = vec![0u8; len as usize];
+                old.read_at(offset, &mut block)?;
+                if verify == VerifyOld::Partial {
+                    referenced.update(&block);
+                }
+                apply_deltas(&mut block, deltas)?;
+                out.write_all(&block).map_err(io_error)?;
+            }
+            Record::Xor { offset, len, body } => {
+                check_range(old.size(), offset, len as u64, "XOR_DELTA")?;
+                let mut block = vec![0u8; len as usize];
+                old.read_at(offset, &mut block)?;
+                if verify == VerifyOld::Partial {
+                    referenced.update(&block);
+                }
+                xor_into(&mut block, body, 0);
+                out.write_all(&block).map_err(io_error)?;
+            }
+            Record::CopyConst { index, len } => {
+                let tile = const_entry(consts, index)?;
+                let mut done = 0usize;
+                while done < len as usize {
+                    let n = usize::min(COPY_CHUNK, len as usize - done);
+                    out.write_all(&expand_const(tile, done, done + n)).map_err(io_error)?;
+                    done += n;
+                }
+            }
+            Record::Run { byte, len } => {
+                buf.clear();
+                buf.resize(usize::min(COPY_CHUNK, len as usize), byte);
+                let mut done = 0usize;
+                while done < len as usize {
+                    let n = usize::min(buf.len(), len as usize - done);
+                    out.write_all(&buf[..n]).map_err(io_error)?;
+                    done += n;
+                }
+            }
+            Record::CopyTarget { .. } => return Err(crate::needs_output()),
+            Record::CopyHash { .. } => return Err(crate::needs_resolver()),
+            Record::ConstTable(body) => consts = Some(body),
+            Record::Index(_)
+            | Record::OldHash(_)
+            | Record::OutputOffset(_)
+            | Record::Padding
+            | Record::BlockSize(_)
+            | Record::MinVersion
+            | Record::Check(_) => {}
+            Record::Skippable(opcode) => {
+                return Err(XDeltaError::InvalidArg(format!("unknown skippable opcode {:#x}", opcode)));
+            }
+        }
+    }
+
+    if let (VerifyOld::Partial, Some(expected)) = (verify, expected) {
+        if referenced.finalize()[..] != expected[32..] {
+            return Err(XDeltaError::OldHashMismatch("referenced ranges of old differ".into()));
+        }
+    }
+    out.check.finish()
+}
+
+fn find_old_hash(patch: &[u8]) -> Result<[u8; 64], XDeltaError> {
+    let mut pos = 0usize;
+    while pos < patch.len() {
+        let (record, next) = read_record(patch, pos)?;
+        if let Record::OldHash(body) = record {
+            let mut hash = [0u8; 64];
+            hash.copy_from_slice(body);
+            return Ok(hash);
+        }
+        pos = next;
+    }
+    Err(XDeltaError::InvalidArg("patch carries no old hash to verify against".into()))
+}
+
+fn check_range(old_len: u64, offset: u64, len: u64, what: &str) -> Result<(), XDeltaError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= old_len => Ok(()),
+        _ => Err(XDeltaError::OldOutOfRange(format!("{} out of range", what))),
+    }
+}
+
+fn io_error(e: std::io::Error) -> XDeltaError {
+    XDeltaError::Io(e.to_string())
+}
+
+/// Outcome of `apply_compare`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompareResult {
+    pub matched: bool,
+    /// First offset where output and reference differ (or where the shorter
+    /// one ends); meaningless when `matched`.
+    pub first_diff_offset: u64,
+    /// Reference byte at that offset, `None` past the end of the reference.
+    pub expected_byte: Option<u8>,
+    /// Output byte at that offset, `None` past the end of the output.
+    pub actual_byte: Option<u8>,
+}
+
+/// Compares output against a reference as it is written.
+struct CompareWriter<'e> {
+    expected: &'e [u8],
+    pos: usize,
+    diff: Option<CompareResult>,
+}
+
+impl Write for CompareWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let rest = &self.expected[self.pos..];
+        let same = rest.iter().zip(data).take_while(|(e, a)| e == a).count();
+        if same < data.len() {
+            self.diff = Some(CompareResult {
+                matched: false,
+                first_diff_offset: (self.pos + same) as u64,
+                expected_byte: rest.get(same).copied(),
+                actual_byte: Some(data[same]),
+            });
+            // nothing after the first difference matters
+            return Err(std::io::Error::other("output differs from reference"));
+        }
+        self.pos += data.len();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Apply `patch` and compare the output with `expected` on the fly, reporting
+/// the first differing byte. The output is never buffered, and applying stops
+/// at the first difference.
+pub fn apply_compare<S>(old: &mut S, patch: &[u8], expected: &[u8], opts: &ApplyOptions) -> Result<CompareResult, XDeltaError>
+where
+    S: OldSource + ?Sized,
+{
+    let mut writer = CompareWriter {
+        expected,
+        pos: 0,
+        diff: None,
+    };
+    let r = apply_streaming(old, patch, &mut writer, opts);
+    if let Some(diff) = writer.diff {
+        return Ok(diff);
+    }
+    r?;
+    if writer.pos < expected.len() {
+        return Ok(CompareResult {
+            matched: false,
+            first_diff_offset: writer.pos as u64,
+            expected_byte: Some(expected[writer.pos]),
+            actual_byte: None,
+        });
+    }
+    Ok(CompareResult {
+        matched: true,
+        ..CompareResult::default()
+    })
+}
+
+/// C layout of `CompareResult`; the caller sets `size` before the call.
+/// Bytes past the end of either side are reported as 0.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XdeltaCompareResult {
+    pub size: u32,
+    pub matched: u8,
+    pub expected_byte: u8,
+    pub actual_byte: u8,
+    pub first_diff_offset: u64,
+}
+
+/// C callback reading `len` bytes of old at `offset` into `buf`; nonzero return aborts.
+pub type XdeltaReadFn = extern "C" fn(offset: u64, buf: *mut u8, len: usize, ctx: *mut c_void) -> c_int;
+
+/// C callback receiving the next `len` bytes of output; nonzero return aborts.
+pub type XdeltaWriteFn = extern "C" fn(data: *const u8, len: usize, ctx: *mut c_void) -> c_int;
+
+struct CallbackSource {
+    size: u64,
+    read: XdeltaReadFn,
+    ctx: *mut c_void,
+}
+
+impl OldSource for CallbackSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+        match (self.read)(offset, buf.as_mut_ptr(), buf.len(), self.ctx) {
+            0 => Ok(()),
+            rc => Err(XDeltaError::Io(format!("read callback failed with {}", rc))),
+        }
+    }
+}
+
+pub(crate) struct CallbackWriter {
+    pub(crate) write: XdeltaWriteFn,
+    pub(crate) ctx: *mut c_void,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        match (self.write)(data.as_ptr(), data.len(), self.ctx) {
+            0 => Ok(data.len()),
+            rc => Err(std::io::Error::other(format!("write callback failed with {}", rc))),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `xdelta_apply_patch_callbacks` 的 verify 参数
+pub const XDELTA_VERIFY_NONE: u32 = 0;
+pub const XDELTA_VERIFY_PARTIAL: u32 = 1;
+pub const XDELTA_VERIFY_FULL: u32 = 2;
+
+/// 流式应用补丁：通过 read_old 回调读取旧数据，通过 write_out 回调输出新数据
+/// verify 为 XDELTA_VERIFY_*，需要补丁带有旧数据哈希（XDELTA_OPT_OLD_HASH）
+/// cancel 可为 NULL
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_callbacks(
+    old_len: u64,
+    read_old: Option<XdeltaReadFn>,
+    patch_data: *const u8,
+    patch_len: usize,
+    write_out: Option<XdeltaWriteFn>,
+    ctx: *mut c_void,
+    verify: u32,
+    cancel: *const CancelToken,
+) -> c_int {
+    xdelta_apply_patch_callbacks_budget(
+        old_len, read_old, patch_data, patch_len, write_out, ctx, verify, cancel, u64::MAX,
+    )
+}
+
+/// 同 xdelta_apply_patch_callbacks，但从旧数据读取的总字节数（含校验读取）不超过 max_old_bytes_read，
+/// 将要超出时不再读取并返回失败，错误码为 XDELTA_ERR_OLD_READ_BUDGET；UINT64_MAX 表示不限制
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_callbacks_budget(
+    old_len: u64,
+    read_old: Option<XdeltaReadFn>,
+    patch_data: *const u8,
+    patch_len: usize,
+    write_out: Option<XdeltaWriteFn>,
+    ctx: *mut c_void,
+    verify: u32,
+    cancel: *const CancelToken,
+    max_old_bytes_read: u64,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let (Some(read), Some(write)) = (read_old, write_out) else {
+            return Err(XDeltaError::NullPointer);
+        };
+        let verify = match verify {
+            XDELTA_VERIFY_NONE => VerifyOld::None,
+            XDELTA_VERIFY_PARTIAL => VerifyOld::Partial,
+            XDELTA_VERIFY_FULL => VerifyOld::Full,
+            other => return Err(XDeltaError::InvalidArg(format!("unknown verify mode {}", other))),
+        };
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+        let mut source = CallbackSource {
+            size: old_len,
+            read,
+            ctx,
+        };
+        let mut writer = CallbackWriter { write, ctx };
+        let mut opts = ApplyOptions::new().verify(verify);
+        if let Some(token) = unsafe { cancel.as_ref() } {
+            opts = opts.cancel_token(token);
+        }
+        if max_old_bytes_read != u64::MAX {
+            opts = opts.max_old_bytes_read(max_old_bytes_read);
+        }
+
+        apply_streaming(&mut source, patch_bytes, &mut writer, &opts)
+    })();
+
+    ffi_status(r)
+}
+
+/// 流式应用补丁：旧数据在内存中，输出按记录（ADD 数据、COPY 片段、RUN 展开）分块交给 write_out，
+/// 不分配整个输出；write_out 返回非0时立即中止，返回 XDELTA_ERR_IO，之后不再调用
+/// 含 COPY_TARGET 记录的补丁会被拒绝
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_stream(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    write_out: Option<XdeltaWriteFn>,
+    ctx: *mut c_void,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let Some(write) = write_out else {
+            return Err(XDeltaError::NullPointer);
+        };
+        let mut old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+        let mut writer = CallbackWriter { write, ctx };
+
+        apply_streaming(&mut old_bytes, patch_bytes, &mut writer, &ApplyOptions::new())
+    })();
+
+    ffi_status(r)
+}
+
+/// 流式应用补丁，输出按 alignment 字节对齐分块交给 write_out（适用于 Flash 等按页写入的设备）
+/// pad_final 为 0..=255 时最后不足一块的数据用该字节补齐，为负数时按实际长度输出
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_aligned(
+    old_len: u64,
+    read_old: Option<XdeltaReadFn>,
+    patch_data: *const u8,
+    patch_len: usize,
+    write_out: Option<XdeltaWriteFn>,
+    ctx: *mut c_void,
+    alignment: usize,
+    pad_final: c_int,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let (Some(read), Some(write)) = (read_old, write_out) else {
+            return Err(XDeltaError::NullPointer);
+        };
+        let pad_final = match pad_final {
+            p if p < 0 => None,
+            p => Some(u8::try_from(p).map_err(|_| XDeltaError::InvalidArg("pad byte out of range".into()))?),
+        };
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+        let mut source = CallbackSource {
+            size: old_len,
+            read,
+            ctx,
+        };
+        let mut writer = CallbackWriter { write, ctx };
+        let opts = ApplyOptions::new().write_alignment(alignment, pad_final);
+
+        apply_streaming(&mut source, patch_bytes, &mut writer, &opts)
+    })();
+
+    ffi_status(r)
+}
+
+/// 应用补丁并直接写入调用方的输出缓冲区（RAM）；旧数据通过 read_old 回调读取，
+/// 每次读取的偏移和长度都是 read_alignment 的整数倍（适用于 XIP 闪存等），
+/// 仅当旧数据长度不是其整数倍时，读到末尾的那次长度可能不足
+/// new_cap 不足时失败；成功时 *new_len 为输出长度
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_read_aligned(
+    old_len: u64,
+    read_old: Option<XdeltaReadFn>,
+    ctx: *mut c_void,
+    patch_data: *const u8,
+    patch_len: usize,
+    read_alignment: usize,
+    new_data: *mut u8,
+    new_cap: usize,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let Some(read) = read_old else {
+            return Err(XDeltaError::NullPointer);
+        };
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+        let mut out = unsafe { std::slice::from_raw_parts_mut(new_data, new_cap) };
+        let mut source = CallbackSource {
+            size: old_len,
+            read,
+            ctx,
+        };
+        let opts = ApplyOptions::new().read_alignment(read_alignment);
+
+        apply_streaming(&mut source, patch_bytes, &mut out, &opts)?;
+        unsafe { *new_len = new_cap - out.len() };
+        Ok(())
+    })();
+
+    ffi_status(r)
+}
+
+/// 应用补丁并与参考数据 expected 逐字节比较（不缓存输出），在 result 中报告第一个不同字节
+/// 成功（无论是否一致）时返回0，补丁无法应用时返回负的错误码
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_compare(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    expected: *const u8,
+    expected_len: usize,
+    result: *mut XdeltaCompareResult,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        if result.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let mut old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+        let expected_bytes = unsafe { ffi_slice(expected, expected_len)? };
+
+        let c = apply_compare(&mut old_bytes, patch_bytes, expected_bytes, &ApplyOptions::new())?;
+        let value = XdeltaCompareResult {
+            size: 0,
+            matched: c.matched as u8,
+            expected_byte: c.expected_byte.unwrap_or(0),
+            actual_byte: c.actual_byte.unwrap_or(0),
+            first_diff_offset: c.first_diff_offset,
+        };
+        write_sized(result, value, std::mem::size_of::<XdeltaCompareResult>())
+    })();
+
+    ffi_status(r)
+}
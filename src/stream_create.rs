@@ -0,0 +1,301 @@
+// src/stream_create.rs
+//! Creating a patch from a `new` read in chunks, for a new too large to hold
+//! in memory next to old.
+//!
+//! The matcher only ever looks at a window of new: the block at the current
+//! position and the byte after it, which the weak checksum rolls in, and the
+//! block behind it, where a COPY too short to keep turns back into literal
+//! bytes. Anything older is dropped as reading goes on, and records are
+//! written out once nothing can change them any more, so memory is old, its
+//! signatures and a few blocks of new, however long new is.
+//!
+//! The records are those `create_patch` makes with the same block size. The
+//! header differs: it is written before new's length is known, so it
+//! records the parameters but no output length.
+
+use crate::stream::{CallbackWriter, XdeltaWriteFn};
+use crate::{
+    block_strong_hash, build_signatures, ffi_slice, ffi_status, index_large_buckets, params_header, strong_prefix,
+    write_copy, write_literal, MAX_RECORD_LEN, PatchOptions, Rolling, SigEntry, WeakChecksum, WeakIndex, XDeltaError,
+};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::os::raw::{c_int, c_void};
+
+/// How much is asked of the reader at once, and how many bytes of records
+/// are collected before they go to the writer.
+const STREAM_CHUNK: usize = 64 * 1024;
+
+/// The part of new the matcher can still look at, refilled from the reader.
+struct NewWindow<R> {
+    reader: R,
+    /// new from `base` on, as far as it has been read
+    buf: Vec<u8>,
+    base: usize,
+    eof: bool,
+}
+
+impl<R: Read> NewWindow<R> {
+    /// Read new up to `pos + ahead`, or its end if that comes first, dropping
+    /// what lies more than `behind` bytes before `pos`; returns where the
+    /// bytes read end.
+    fn fill(&mut self, pos: usize, ahead: usize, behind: usize) -> Result<usize, XDeltaError> {
+        // dropped a chunk at a time, so the bytes kept move only now and then
+        let stale = pos.saturating_sub(behind) - self.base;
+        if stale >= STREAM_CHUNK {
+            self.buf.drain(..stale);
+            self.base += stale;
+        }
+        while !self.eof && self.base + self.buf.len() < pos + ahead {
+            let start = self.buf.len();
+            self.buf.resize(start + STREAM_CHUNK, 0);
+            let got = loop {
+                match self.reader.read(&mut self.buf[start..]) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    r => break r,
+                }
+            };
+            let got = got.map_err(|e| {
+                self.buf.truncate(start);
+                io_error(e)
+            })?;
+            self.buf.truncate(start + got);
+            self.eof = got == 0;
+        }
+        Ok(self.base + self.buf.len())
+    }
+
+    /// Bytes `start..end` of new, which must still be held.
+    fn bytes(&self, start: usize, end: usize) -> &[u8] {
+        &self.buf[start - self.base..end - self.base]
+    }
+}
+
+fn io_error(e: std::io::Error) -> XDeltaError {
+    XDeltaError::Io(e.to_string())
+}
+
+/// Create a patch turning `old` into the bytes `new_reader` yields, written
+/// to `out` as it is made, with `block_size` (0 picks one from old's length,
+/// as `create_patch` does).
+///
+/// new is read in chunks and never held whole: memory is old, its block
+/// signatures and a few blocks of new. The records are the ones
+/// `create_patch` would make; the header records the block size and hashes
+/// but not the output length, which is only known at the end. A reader
+/// error stops it with `XDeltaError::Io`, with part of the patch written.
+///
+/// ```
+/// let old = b"the quick brown fox jumps over the lazy dog, again and again".repeat(8);
+/// let new = [&old[..200], b"and then some", &old[200..]].concat();
+/// let mut patch = Vec::new();
+/// xdelta::create_patch_streaming(&old, &new[..], 16, &mut patch).unwrap();
+/// assert_eq!(xdelta::apply_patch(&old, &patch).unwrap(), new);
+/// ```
+pub fn create_patch_streaming<R: Read>(
+    old: &[u8],
+    new_reader: R,
+    block_size: usize,
+    out: &mut impl Write,
+) -> Result<(), XDeltaError> {
+    let opts = PatchOptions::new().block_size(block_size);
+    let opts = opts.resolve_block_size(old.len());
+    let block_size = opts.block_size;
+    if block_size > MAX_RECORD_LEN {
+        return Err(XDeltaError::InvalidArg("block_size must fit a record length (u32)".into()));
+    }
+    let min_match = opts.effective_min_match();
+    let max_add = opts.effective_max_add_len();
+    let mut records = params_header(&opts.header_params()?);
+    if opts.embed_block_size {
+        records.push(0x85); // BLOCK_SIZE
+        records.extend_from_slice(&4u32.to_le_bytes());
+        records.extend_from_slice(&(block_size as u32).to_le_bytes());
+    }
+
+    let mut sigs: HashMap<u32, Vec<SigEntry>> = HashMap::new();
+    build_signatures(&mut sigs, old, block_size, opts.effective_tail_policy(), opts.strong_hash, opts.weak_checksum);
+    let weak_index = WeakIndex::new(&sigs);
+    let large_buckets = index_large_buckets(&sigs);
+
+    let mut new = NewWindow { reader: new_reader, buf: Vec::new(), base: 0, eof: false };
+    let mut pos: usize = 0;
+    let mut pending_add: Vec<u8> = Vec::new();
+    // (offset in old, length) of a COPY that may still be extended
+    let mut pending_copy: Option<(u64, usize)> = None;
+    let mut rolling: Option<(usize, Rolling)> = None;
+    // where in `records` the length of the last RUN record is, see `write_literal`
+    let mut last_run: Option<usize> = None;
+
+    // a pending COPY ending at `end` in new; one shorter than `min_match`
+    // joins the literal bytes before it, which `end` is at most a block past
+    let flush_copy = |records: &mut Vec<u8>,
+                      pending: &mut Option<(u64, usize)>,
+                      adds: &mut Vec<u8>,
+                      last_run: &mut Option<usize>,
+                      new: &NewWindow<R>,
+                      end: usize| {
+        if let Some((offset, len)) = pending.take() {
+            if len < min_match {
+                adds.extend_from_slice(new.bytes(end - len, end));
+                return;
+            }
+            write_literal(records, adds, last_run, max_add);
+            adds.clear();
+            write_copy(records, offset, len, |_, _| {});
+        }
+    };
+
+    loop {
+        // a block and the byte after it ahead, for the rolled checksum; a
+        // block behind, for a short COPY to join the literal bytes
+        let end = new.fill(pos, block_size + 1, block_size)?;
+        if pos >= end {
+            break;
+        }
+        let try_len = usize::min(block_size, end - pos);
+        let window = new.bytes(pos, pos + try_len);
+
+        // the previous COPY continuing in old, as in `match_blocks`
+        if let Some((offset, len)) = pending_copy {
+            let cont = offset as usize + len;
+            if cont + try_len <= old.len() && old[cont..cont + try_len] == *window {
+                pending_copy = Some((offset, len + try_len));
+                pos += try_len;
+                continue;
+            }
+        }
+
+        let weak = match rolling {
+            Some((at, r)) if at == pos && try_len == block_size => r.chksum(),
+            _ => {
+                let r = Rolling::from_slice(window);
+                rolling = Some((pos, r));
+                r.chksum()
+            }
+        };
+        // a bucket with no block of the window's length is not strong-hashed for
+        let candidates = weak_index
+            .get(weak)
+            .filter(|bucket| large_buckets.contains_key(&weak) || bucket.iter().any(|e| e.len == window.len()));
+        let hit = candidates.and_then(|bucket| {
+            let strong = block_strong_hash(window, opts.strong_hash);
+            let (scanned, narrowed): (&[SigEntry], &[&SigEntry]) = match large_buckets.get(&weak) {
+                Some(by_prefix) => (&[], by_prefix.get(&strong_prefix(&strong)).map_or(&[], Vec::as_slice)),
+                None => (bucket, &[]),
+            };
+            scanned.iter().chain(narrowed.iter().copied()).find(|e| e.strong_hash[..] == strong[..])
+        });
+
+        if let Some(e) = hit {
+            let offset_in_old = e.block_index * block_size as u64;
+            let len = usize::min(try_len, old.len() - offset_in_old as usize);
+            match pending_copy {
+                Some((offset, pending_len)) if offset + pending_len as u64 == offset_in_old => {
+                    pending_copy = Some((offset, pending_len + len));
+                }
+                _ => {
+                    flush_copy(&mut records, &mut pending_copy, &mut pending_add, &mut last_run, &new, pos);
+                    pending_copy = Some((offset_in_old, len));
+                }
+            }
+            if len < try_len {
+                flush_copy(&mut records, &mut pending_copy, &mut pending_add, &mut last_run, &new, pos + len);
+                pending_add.extend_from_slice(new.bytes(pos + len, pos + try_len));
+            }
+            pos += try_len;
+        } else {
+            flush_copy(&mut records, &mut pending_copy, &mut pending_add, &mut last_run, &new, pos);
+            let byte = window[0];
+            pending_add.push(byte);
+            if let Some((at, r)) = rolling.as_mut() {
+                // `fill` read a byte past the block unless new ends there
+                if *at == pos && pos + block_size < end {
+                    r.roll(byte, new.bytes(pos + block_size, pos + block_size + 1)[0]);
+                    *at = pos + 1;
+                }
+            }
+            pos += 1;
+            if pending_add.len() >= max_add {
+                write_literal(&mut records, &pending_add, &mut last_run, max_add);
+                pending_add.clear();
+            }
+        }
+
+        if records.len() >= STREAM_CHUNK {
+            drain_records(&mut records, &mut last_run, out)?;
+        }
+    }
+
+    flush_copy(&mut records, &mut pending_copy, &mut pending_add, &mut last_run, &new, pos);
+    write_literal(&mut records, &pending_add, &mut last_run, max_add);
+    out.write_all(&records).map_err(io_error)?;
+    out.flush().map_err(io_error)
+}
+
+/// Write `records` out but for a RUN record at their end, which the next
+/// literal bytes may still lengthen, see `write_literal`.
+fn drain_records(records: &mut Vec<u8>, last_run: &mut Option<usize>, out: &mut impl Write) -> Result<(), XDeltaError> {
+    // a RUN record is opcode, byte, then the length `last_run` points at
+    let keep = match *last_run {
+        Some(at) if at + 4 == records.len() => at - 2,
+        _ => {
+            *last_run = None;
+            records.len()
+        }
+    };
+    out.write_all(&records[..keep]).map_err(io_error)?;
+    records.drain(..keep);
+    if let Some(at) = last_run.as_mut() {
+        *at -= keep;
+    }
+    Ok(())
+}
+
+/// C callback handing over the next bytes of new: it fills `buf` with up to
+/// `len` of them and stores how many in `*got`, 0 at the end of new.
+/// Returns 0 on success; anything else aborts.
+pub type XdeltaReadNextFn = extern "C" fn(buf: *mut u8, len: usize, got: *mut usize, ctx: *mut c_void) -> c_int;
+
+struct CallbackReader {
+    read: XdeltaReadNextFn,
+    ctx: *mut c_void,
+}
+
+impl Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut got = 0usize;
+        match (self.read)(buf.as_mut_ptr(), buf.len(), &mut got, self.ctx) {
+            0 if got <= buf.len() => Ok(got),
+            0 => Err(std::io::Error::other(format!("read callback reported {} bytes of {}", got, buf.len()))),
+            rc => Err(std::io::Error::other(format!("read callback failed with {}", rc))),
+        }
+    }
+}
+
+/// 流式创建补丁：旧数据在内存中，新数据通过 read_new 回调分块读取（*got 为 0 表示结束），
+/// 补丁边生成边交给 write_patch，新数据不会整体驻留内存；两个回调共用 ctx
+/// 补丁头不含输出长度，记录与 xdelta_create_patch 相同；block_size 为 0 时按旧数据长度自动选择
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*），回调返回非0时为 XDELTA_ERR_IO
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_streaming(
+    old_data: *const u8,
+    old_len: usize,
+    read_new: Option<XdeltaReadNextFn>,
+    write_patch: Option<XdeltaWriteFn>,
+    ctx: *mut c_void,
+    block_size: u32,
+) -> c_int {
+    let r = (|| -> Result<(), XDeltaError> {
+        let (Some(read), Some(write)) = (read_new, write_patch) else {
+            return Err(XDeltaError::NullPointer);
+        };
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let reader = CallbackReader { read, ctx };
+        let mut writer = CallbackWriter { write, ctx };
+
+        create_patch_streaming(old_bytes, reader, block_size as usize, &mut writer)
+    })();
+
+    ffi_status(r)
+}
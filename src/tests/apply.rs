@@ -0,0 +1,1056 @@
+// src/tests/apply.rs
+//! Applying patches: every apply path, budgets, verification and hostile
+//! input.
+
+use super::*;
+
+/// A CHECK trailer passes on every apply path when old is the right base,
+/// and fails each of them once a byte of old or of the trailer differs. A
+/// patch cut before its trailer, or with output after it, is malformed.
+#[test]
+fn output_check() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256).output_check(true))?;
+    let required = crate::compat::required_version(patch_records(&patch)?)?;
+    check(required >= 5 && patch_info(&patch)?.output_len == new.len() as u64, "CHECK trailer")?;
+    let applies = |old: &[u8], patch: &[u8]| {
+        let mut streamed = Vec::new();
+        let mut feed = ApplyFeed::new(old, Vec::new(), VerifyOld::None);
+        let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+        let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), patch.as_ptr(), patch.len(), &mut data, &mut len);
+        xdelta_free_data(data);
+        [
+            apply_patch_bytes(old, patch).map(|out| out == new),
+            apply_streaming(&mut &old[..], patch, &mut streamed, &ApplyOptions::new()).map(|_| streamed == new),
+            feed.feed(patch).and_then(|_| feed.finish()).map(|out| out == new),
+            apply_to(old, patch, ApplyOutput::Grow(&mut Vec::new())).map(|len| len == new.len() as u64),
+            apply_then_diff(old, patch, new, &PatchOptions::new()).map(|_| true),
+            if rc == XDELTA_OK { Ok(true) } else { Err(XDeltaError::ChecksumMismatch(rc.to_string())) },
+        ]
+    };
+    check(applies(old, &patch).iter().all(|r| matches!(r, Ok(true))), "CHECK trailer apply")?;
+    let mut bad_old = old.to_vec();
+    bad_old[100] ^= 1;
+    let mut bad_check = patch.clone();
+    *bad_check.last_mut().unwrap() ^= 1;
+    for (old, patch) in [(&bad_old[..], &patch[..]), (old, &bad_check[..])] {
+        let mismatched = applies(old, patch).iter().all(|r| matches!(r, Err(XDeltaError::ChecksumMismatch(_))));
+        check(mismatched, "CHECK trailer mismatch")?;
+    }
+    let cut = &patch[..patch.len() - 33];
+    let after = [&patch[..], &[0x00][..], &1u32.to_le_bytes(), b"x"].concat();
+    for patch in [cut, &after[..]] {
+        check(matches!(validate_patch(patch), Err(XDeltaError::MalformedPatch(_))), "misplaced CHECK trailer")?;
+        check(applies(old, patch).iter().all(|r| r.is_err()), "misplaced CHECK trailer")?;
+    }
+    let mut data = std::ptr::null_mut();
+    let rc = xdelta_apply_patch_data(bad_old.as_ptr(), old.len(), patch.as_ptr(), patch.len(), &mut data, &mut 0);
+    check(rc == XDELTA_ERR_CHECKSUM_MISMATCH && data.is_null(), "CHECK trailer error code")
+}
+
+/// `PatchReader` yields the output-producing records of a mixed patch in
+/// order, metadata consumed, and stops after the error of a truncated one.
+#[test]
+fn patch_reader() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let patch = with_header(
+        &[
+            &[0x00][..],
+            &2u32.to_le_bytes(),
+            b"ab",
+            &[0x06], // NOP
+            &[0x01],
+            &2u64.to_le_bytes(),
+            &3u32.to_le_bytes(),
+            &[0x02, b'z'],
+            &4u32.to_le_bytes(),
+            &[0x82], // CONST_TABLE of one tile
+            &4u32.to_le_bytes(),
+            &[1, 2, b'x', b'y'],
+            &[0x11, 0],
+            &5u32.to_le_bytes(),
+            &[0x04],
+            &0u64.to_le_bytes(),
+            &2u32.to_le_bytes(),
+        ]
+        .concat(),
+    );
+    let expected = [
+        PatchOp::Add(b"ab"),
+        PatchOp::Copy { offset: 2, len: 3 },
+        PatchOp::Run { byte: b'z', len: 4 },
+        PatchOp::CopyConst { tile: b"xy", len: 5 },
+        PatchOp::CopyTarget { offset: 0, len: 2 },
+    ];
+    let ops = PatchReader::new(&patch)?.collect::<Result<Vec<_>, _>>()?;
+    let out = [&b"ab"[..], &old[2..5], b"zzzz", b"xyxyx", b"ab"].concat();
+    check(ops == expected && apply_patch_bytes(old, &patch)? == out, "patch reader ops")?;
+
+    let mut cut = PatchReader::new(&patch[..patch.len() - 1])?;
+    let before: Vec<_> = cut.by_ref().take(4).collect::<Result<_, _>>()?;
+    let failed = matches!(cut.next(), Some(Err(XDeltaError::MalformedPatch(_)))) && cut.next().is_none();
+    check(before == expected[..4] && failed, "patch reader truncated record")?;
+    let identity = crate::identity_patch();
+    let mut reader = PatchReader::new(&identity)?;
+    check(reader.is_identity() && reader.next().is_none(), "patch reader identity")?;
+    check(matches!(PatchReader::new(&patch[..4]), Err(XDeltaError::MalformedPatch(_))), "patch reader header")
+}
+
+/// Random blobs, random records behind a valid header, and single-byte
+/// corruptions of a real patch all come back from apply as a result rather
+/// than a panic. Zero-length ADD and COPY records are malformed, and
+/// `apply_patch_limited` refuses output over its limit before making any.
+#[test]
+fn apply_fuzz() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    const OPCODES: [u8; 19] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x10, 0x11, 0x12, 0x13, 0x7f, 0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86,
+    ];
+    let survives = |patch: &[u8], limit: u64| {
+        std::panic::catch_unwind(|| {
+            let _ = validate_patch(patch);
+            let _ = apply_patch_limited(old, patch, limit);
+        })
+        .is_ok()
+    };
+    for seed in 0..300u32 {
+        let blob = filler(seed as usize % 64, seed);
+        let headed = [&with_header(&[])[..], &blob].concat();
+        check(survives(&blob, 1 << 20) && survives(&headed, 1 << 20), "random patch")?;
+        // records with known opcodes and random fields, so parsing gets past the first byte
+        let noise = filler(400, seed + 1000);
+        let mut records = Vec::new();
+        for chunk in noise.chunks(20) {
+            records.push(OPCODES[chunk[0] as usize % OPCODES.len()]);
+            records.extend_from_slice(&chunk[1..1 + chunk[1] as usize % 19]);
+        }
+        check(survives(&with_header(&records), 1 << 20), "random records")?;
+    }
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256).output_check(true))?;
+    for (i, &flip) in filler(400, 77).iter().enumerate() {
+        let mut bad = patch.clone();
+        bad[i * 7919 % patch.len()] ^= flip | 1;
+        check(survives(&bad, 2 * new.len() as u64), "corrupted patch")?;
+    }
+
+    let zero_add = with_header(&[0x00, 0, 0, 0, 0]);
+    let zero_copy = with_header(&[&[0x01][..], &0u64.to_le_bytes(), &0u32.to_le_bytes()].concat());
+    for patch in [&zero_add, &zero_copy] {
+        let malformed = matches!(validate_patch(patch), Err(XDeltaError::MalformedPatch(_)))
+            && matches!(apply_patch_bytes(old, patch), Err(XDeltaError::MalformedPatch(_)));
+        check(malformed, "zero-length record")?;
+    }
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let limited = |limit: u64| apply_patch_limited(old, &patch, limit);
+    let bomb = with_header(&[&[0x02, b'x'][..], &u32::MAX.to_le_bytes()].concat());
+    check(
+        limited(new.len() as u64)? == new
+            && matches!(limited(new.len() as u64 - 1), Err(XDeltaError::OutputLimitExceeded(_)))
+            && matches!(apply_patch_limited(old, &bomb, 1 << 20), Err(XDeltaError::OutputLimitExceeded(_))),
+        "output limit",
+    )?;
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let (old_ptr, bomb_ptr) = (old.as_ptr(), bomb.as_ptr());
+    let rc = xdelta_apply_patch_data_limited(old_ptr, old.len(), bomb_ptr, bomb.len(), 1 << 20, &mut data, &mut len);
+    check(rc == XDELTA_ERR_OUTPUT_LIMIT && data.is_null(), "output limit error code")
+}
+
+/// Old as a slice, tallying the bytes read from it.
+struct CountingOld<'a> {
+    data: &'a [u8],
+    read: u64,
+}
+
+impl OldSource for CountingOld<'_> {
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+        self.read += buf.len() as u64;
+        (&mut self.data).read_at(offset, buf)
+    }
+}
+
+/// A read budget of exactly what `patch` reads is enough; one byte less
+/// fails with the budget error without reading past it.
+#[test]
+fn read_budget() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = &create_patch_with(old, new, &plain())?;
+    let mut counted = CountingOld { data: old, read: 0 };
+    apply_streaming(&mut counted, patch, &mut Vec::new(), &ApplyOptions::new())?;
+    let needed = counted.read;
+    check(needed > 0, "patch reads old")?;
+
+    let mut counted = CountingOld { data: old, read: 0 };
+    let mut out = Vec::new();
+    apply_streaming(&mut counted, patch, &mut out, &ApplyOptions::new().max_old_bytes_read(needed))?;
+    check(out == new, "apply within read budget")?;
+
+    let mut counted = CountingOld { data: old, read: 0 };
+    let r = apply_streaming(&mut counted, patch, &mut Vec::new(), &ApplyOptions::new().max_old_bytes_read(needed - 1));
+    check(
+        matches!(r, Err(XDeltaError::OldReadBudgetExceeded(b)) if b == needed - 1) && counted.read < needed,
+        "read budget exceeded",
+    )
+}
+
+/// Created patches declare the length of new in their header, and a
+/// declared length the records don't produce is refused by every apply
+/// path and by `validate_patch`; patches without one still apply.
+#[test]
+fn declared_output_len() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let malformed = |r: Result<(), XDeltaError>| matches!(r, Err(XDeltaError::MalformedPatch(_)));
+    for (opts, new) in [
+        (PatchOptions::new().block_size(256), new),
+        (PatchOptions::new().block_size(64).output_check(true).index_granularity(1000).pad_to(8192), new),
+        (PatchOptions::new().block_size(256).content_addressed(true), new),
+        (PatchOptions::new(), &[][..]),
+    ] {
+        let patch = create_patch_with(old, new, &opts)?;
+        check(crate::declared_output_len(&patch) == Some(new.len() as u64), "declared output length")?;
+    }
+    let identity = create_patch_with(old, old, &PatchOptions::new())?;
+    check(crate::declared_output_len(&identity).is_none(), "identity patch declares no length")?;
+
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    for lie in [new.len() as u64 - 1, new.len() as u64 + 1, u64::MAX] {
+        let mut tampered = patch.clone();
+        tampered[PATCH_HEADER_LEN..][..8].copy_from_slice(&lie.to_le_bytes());
+        let streamed = apply_streaming(&mut &old[..], &tampered, &mut Vec::new(), &ApplyOptions::new());
+        let mut feed = ApplyFeed::new(old, Vec::new(), VerifyOld::None);
+        let fed = feed.feed(&tampered).and_then(|()| feed.finish().map(drop));
+        check(
+            malformed(apply_patch_bytes(old, &tampered).map(drop))
+                && malformed(validate_patch(&tampered))
+                && malformed(streamed)
+                && malformed(fed),
+            "tampered output length",
+        )?;
+    }
+    check(malformed(apply_patch_bytes(old, &patch[..12]).map(drop)), "truncated output length")?;
+    let bare = with_header(patch_records(&patch)?);
+    check(crate::declared_output_len(&bare).is_none() && apply_patch_bytes(old, &bare)? == new, "no output length")
+}
+
+/// `validate_patch` accepts what create makes, even with COPY ranges old
+/// doesn't have, and refuses broken framing with the apply error.
+#[test]
+fn validate_patch_framing() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    check(validate_patch(&patch).is_ok() && xdelta_validate_patch(patch.as_ptr(), patch.len()) == XDELTA_OK, "valid")?;
+    let mut far = vec![0x01]; // COPY past any old
+    far.extend_from_slice(&u64::MAX.to_le_bytes());
+    far.extend_from_slice(&16u32.to_le_bytes());
+    check(validate_patch(&with_header(&far)).is_ok(), "validate leaves COPY ranges to apply")?;
+
+    let truncated_add = with_header(&[0x00, 5, 0]);
+    let truncated_copy = with_header(&far[..9]);
+    let mut trailing = patch.clone();
+    trailing.extend_from_slice(&[0xde, 0xad]);
+    for (broken, what) in [
+        (truncated_add, "truncated ADD length"),
+        (truncated_copy, "truncated COPY entry"),
+        (trailing, "trailing garbage"),
+    ] {
+        let r = validate_patch(&broken);
+        let same = matches!((&r, apply_patch_bytes(old, &broken)), (Err(a), Err(b)) if a.to_string() == b.to_string());
+        let rc = xdelta_validate_patch(broken.as_ptr(), broken.len());
+        check(matches!(r, Err(XDeltaError::MalformedPatch(_))) && same && rc == XDELTA_ERR_MALFORMED_PATCH, what)?;
+    }
+    check(xdelta_validate_patch(std::ptr::null(), 4) == XDELTA_ERR_NULL_POINTER, "validate null patch")
+}
+
+/// Each record of a patch made with `record_crc` carries its CRC-32: such
+/// a patch applies as any other, and a corrupt byte is reported with the
+/// index of its record, here the second of two COPY records, before any
+/// output is made; readers that can't check the CRCs refuse the patch.
+#[test]
+fn record_crc() -> Result<(), XDeltaError> {
+    use crate::crc32::crc32;
+    use crate::PATCH_HEADER_MAX_LEN;
+
+    let (old, _) = fixture();
+    check(crc32(b"123456789") == 0xcbf4_3926 && crc32(b"") == 0, "CRC-32 check value")?;
+    let new = [&old[4096..8192], &old[..4096]].concat();
+    let opts = PatchOptions::new().block_size(256).record_crc(true);
+    let patch = create_patch_with(old, &new, &opts)?;
+    let plain = crate::without_record_crcs(&patch)?;
+    let records = patch_records(&plain)?;
+    check(records.len() == 26 && records[0] == 0x01 && records[13] == 0x01, "two COPY records")?;
+    check(patch.len() == PATCH_HEADER_MAX_LEN + 2 * (13 + 4), "a CRC after each record")?;
+    let mut streamed = Vec::new();
+    apply_streaming(&mut &old[..], &patch, &mut streamed, &ApplyOptions::new())?;
+    let mut exact = Vec::new();
+    apply_to(old, &patch, ApplyOutput::ExactReserve(&mut exact))?;
+    check(
+        apply_patch_bytes(old, &patch)? == new && streamed == new && exact == new && validate_patch(&patch).is_ok(),
+        "patch with record CRCs",
+    )?;
+
+    let mut corrupt = patch.clone();
+    corrupt[PATCH_HEADER_MAX_LEN + 13 + 4 + 3] ^= 0x10; // offset of the second COPY
+    let names_second = |r: Result<(), XDeltaError>| {
+        matches!(r, Err(XDeltaError::ChecksumMismatch(ref m)) if m.starts_with("record 1 at byte"))
+    };
+    let mut out = Vec::new();
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), corrupt.as_ptr(), corrupt.len(), &mut data, &mut len);
+    let detail = unsafe { std::ffi::CStr::from_ptr(xdelta_last_error_detail(std::ptr::null_mut())) };
+    check(
+        names_second(apply_patch_bytes(old, &corrupt).map(drop))
+            && names_second(validate_patch(&corrupt))
+            && names_second(apply_streaming(&mut &old[..], &corrupt, &mut out, &ApplyOptions::new()))
+            && out.is_empty()
+            && rc == XDELTA_ERR_CHECKSUM_MISMATCH
+            && detail.to_string_lossy().contains("record 1 at byte"),
+        "corrupt record located",
+    )?;
+    let cut = validate_patch(&patch[..patch.len() - 2]);
+    check(matches!(cut, Err(XDeltaError::MalformedPatch(ref m)) if m.ends_with("has no CRC")), "missing CRC")?;
+    check(matches!(PatchReader::new(&patch), Err(XDeltaError::InvalidArg(_))), "reader without CRC support")?;
+
+    let padded = create_patch_with(old, &new, &opts.clone().pad_to(4096))?;
+    check(padded.len() == 4096 && apply_patch_bytes(old, &padded)? == new, "padded patch with record CRCs")?;
+    let tight = create_patch_with(old, &new, &opts.clone().pad_to(patch.len() + 3));
+    check(matches!(tight, Err(XDeltaError::InvalidArg(_))), "no room for a CRC-framed PADDING record")?;
+
+    let opts = XdeltaOptions { block_size: 256, flags: XDELTA_OPT_RECORD_CRC, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &opts, &mut data, &mut len);
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == patch.as_slice();
+    xdelta_free_data(data);
+    check(same, "record CRCs through XdeltaOptions")
+}
+
+#[test]
+fn apply_prefix() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let opts = PatchOptions::new().block_size(64);
+    let second = create_patch_with(new, old, &opts)?;
+    let patches = [
+        create_patch_with(old, new, &opts)?,
+        create_patch_with(old, new, &opts.clone().output_check(true))?,
+        create_patch_with(old, new, &opts.clone().record_crc(true))?,
+        create_patch_with(old, &[], &PatchOptions::new())?,
+        create_patch_with(old, old, &opts)?,
+    ];
+    for patch in &patches {
+        let expected = apply_patch_bytes(old, patch)?;
+        for trailer in [&b""[..], b"\x00\x00\x00\x00\x07trailing", &second] {
+            let mut stream = patch.clone();
+            stream.extend_from_slice(trailer);
+            let (out, consumed) = apply_patch_prefix(old, &stream)?;
+            check(out == expected && consumed == patch.len(), "apply_patch_prefix stops where the patch ends")?;
+            if trailer == second.as_slice() {
+                let (next, _) = apply_patch_prefix(new, &stream[consumed..])?;
+                check(next == old, "patch after the prefix applies from where it stopped")?;
+            }
+        }
+    }
+
+    // padding can't be told from what follows it and is left over
+    let padded = create_patch_with(old, new, &opts.clone().pad_to(4096))?;
+    let (out, consumed) = apply_patch_prefix(old, &padded)?;
+    let unpadded = create_patch_with(old, new, &opts)?.len();
+    check(out == new && consumed == unpadded && padded.len() > consumed, "padding is not consumed")?;
+
+    let unbounded = with_header(&[0x06]);
+    check(
+        matches!(apply_patch_prefix(old, &unbounded), Err(XDeltaError::InvalidArg(_))),
+        "patch with no end to find",
+    )?;
+    let patch = &patches[0];
+    check(
+        matches!(apply_patch_prefix(old, &patch[..patch.len() - 1]), Err(XDeltaError::MalformedPatch(_))),
+        "truncated prefix patch",
+    )?;
+
+    let mut stream = patch.clone();
+    stream.extend_from_slice(b"after");
+    let (mut data, mut len, mut consumed) = (std::ptr::null_mut(), 0, 0);
+    let rc = xdelta_apply_patch_prefix(
+        old.as_ptr(),
+        old.len(),
+        stream.as_ptr(),
+        stream.len(),
+        &mut data,
+        &mut len,
+        &mut consumed,
+    );
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == new && consumed == patch.len();
+    xdelta_free_data(data);
+    check(same, "xdelta_apply_patch_prefix")?;
+    consumed = 7;
+    let rc = xdelta_apply_patch_prefix(
+        old.as_ptr(),
+        old.len(),
+        unbounded.as_ptr(),
+        unbounded.len(),
+        &mut data,
+        &mut len,
+        &mut consumed,
+    );
+    check(rc == XDELTA_ERR_INVALID_ARG && data.is_null() && consumed == 7, "failed prefix leaves consumed")
+}
+
+/// The seeds of the `roundtrip` fuzz target, in fuzz/corpus/roundtrip.
+#[test]
+fn roundtrip_seeds() -> Result<(), XDeltaError> {
+    let text = b"the quick brown fox jumps over the lazy dog. ".repeat(6);
+    let mut moved = text[64..].to_vec();
+    moved.extend_from_slice(&text[..64]);
+    let cases: [(&[u8], Vec<u8>, usize); 8] = [
+        (&text[..100], [&text[..100], b"tail!"].concat(), 16),
+        (&[0; 256], vec![0; 300], 16),
+        (&[0; 128], [&[0; 64][..], &[1; 64]].concat(), 8),
+        (b"a", b"b".to_vec(), 1),
+        (b"", text[..64].to_vec(), 16),
+        (&text[..64], Vec::new(), 16),
+        (&text, moved, 8),
+        (&text, text.to_vec(), 0),
+    ];
+    for (old, new, block_size) in &cases {
+        check(crate::roundtrip_check(old, new, *block_size)?, "fuzz seed round-trips")?;
+    }
+    Ok(())
+}
+
+#[test]
+fn apply_verify() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(64))?;
+    let expected: [u8; 32] = Sha256::digest(new).into();
+    let mut wrong = expected;
+    wrong[31] ^= 1;
+    check(
+        apply_patch_verify(old, &patch, expected)? == new
+            && matches!(apply_patch_verify(old, &patch, wrong), Err(XDeltaError::NewHashMismatch)),
+        "apply_patch_verify",
+    )?;
+    // a CHECK record agreeing with a tampered output doesn't get it past
+    let tampered = create_patch_with(old, old, &PatchOptions::new().block_size(64).output_check(true))?;
+    check(
+        matches!(apply_patch_verify(old, &tampered, expected), Err(XDeltaError::NewHashMismatch)),
+        "apply_patch_verify ignores the patch's own CHECK record",
+    )?;
+
+    let verify = |expected: &[u8; 32]| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc = xdelta_apply_patch_verify(
+            old.as_ptr(),
+            old.len(),
+            patch.as_ptr(),
+            patch.len(),
+            expected.as_ptr(),
+            &mut data,
+            &mut len,
+        );
+        let out = (!data.is_null()).then(|| unsafe { std::slice::from_raw_parts(data, len) }.to_vec());
+        xdelta_free_data(data);
+        (rc, out)
+    };
+    let (rc, out) = verify(&expected);
+    check(rc == XDELTA_OK && out.as_deref() == Some(new), "xdelta_apply_patch_verify")?;
+    let (rc, out) = verify(&wrong);
+    check(rc == XDELTA_ERR_NEW_HASH_MISMATCH && out.is_none(), "mismatch returns no output")
+}
+
+/// `apply_iter` yields one chunk per record producing output, COPY chunks
+/// borrowed from old and ADD chunks from the patch, which together are what
+/// `apply_patch` gives; it stops after the first error, header errors
+/// included.
+#[test]
+fn apply_iter_chunks() -> Result<(), XDeltaError> {
+    use crate::apply_iter;
+    use std::borrow::Cow;
+
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let chunks = apply_iter(old, &patch).collect::<Result<Vec<_>, _>>()?;
+    let records = patch_records(&patch)?;
+    let (mut pos, mut outputs) = (0, Vec::new());
+    while pos < records.len() {
+        let (record, next) = crate::read_record(records, pos)?;
+        if record.output_len() > 0 {
+            outputs.push(record);
+        }
+        pos = next;
+    }
+    let within = |chunk: &[u8], of: &[u8]| of.as_ptr_range().contains(&chunk.as_ptr()) && chunk.len() <= of.len();
+    let borrowed = chunks.iter().zip(&outputs).all(|(chunk, record)| match (chunk, record) {
+        (Cow::Borrowed(data), crate::Record::Copy { .. }) => within(data, old),
+        (Cow::Borrowed(data), crate::Record::Add(_)) => within(data, &patch),
+        (Cow::Owned(_), crate::Record::Copy { .. } | crate::Record::Add(_)) => false,
+        _ => true,
+    });
+    check(
+        chunks.concat() == apply_patch_bytes(old, &patch)? && chunks.concat() == new,
+        "lazy chunks make the applied output",
+    )?;
+    check(
+        borrowed && chunks.len() == outputs.len() && outputs.iter().any(|r| matches!(r, crate::Record::Add(_))),
+        "lazy chunks borrowed, one per record",
+    )?;
+
+    let past_end = [&[0x01][..], &u64::MAX.to_le_bytes(), &1u32.to_le_bytes()].concat();
+    let bad = with_header(&[&[0x00, 3, 0, 0, 0][..], b"abc", &past_end, &[0x00, 1, 0, 0, 0, b'd']].concat());
+    let mut iter = apply_iter(old, &bad);
+    let first = matches!(iter.next(), Some(Ok(chunk)) if *chunk == *b"abc");
+    let failed = matches!(iter.next(), Some(Err(XDeltaError::OldOutOfRange(_))));
+    let mut header = apply_iter(old, b"nope");
+    check(
+        first
+            && failed
+            && iter.next().is_none()
+            && matches!(header.next(), Some(Err(_)))
+            && header.next().is_none(),
+        "lazy apply stops at the first error",
+    )
+}
+
+#[test]
+fn apply_lenient() -> Result<(), XDeltaError> {
+    // COPY, ADD, COPY: cut off in the middle of the second COPY, which
+    // starts 13 bytes before the end, the first two records' output is kept
+    let old = filler(4096, 0x1e41);
+    let mut new = old[1024..2048].to_vec();
+    new.extend_from_slice(b"inserted");
+    let expected = new.clone();
+    new.extend_from_slice(&old[..512]);
+    let patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(64))?;
+    let (out, err) = crate::apply_patch_lenient(&old, &patch);
+    check(out == new && err.is_none(), "lenient apply of a whole patch")?;
+    let cut = &patch[..patch.len() - 5];
+    let (out, err) = crate::apply_patch_lenient(&old, cut);
+    check(out == expected && matches!(err, Some(XDeltaError::MalformedPatch(_))), "lenient apply of a cut COPY")?;
+    check(matches!(apply_patch_bytes(&old, cut), Err(XDeltaError::MalformedPatch(_))), "strict apply of a cut COPY")?;
+
+    // with record CRCs, a record failing its CRC ends the output just the same
+    let mut framed = create_patch_with(&old, &new, &PatchOptions::new().block_size(64).record_crc(true))?;
+    let last_record = framed.len() - 17;
+    framed[last_record + 1] ^= 1;
+    let (out, err) = crate::apply_patch_lenient(&old, &framed);
+    check(out == expected && matches!(err, Some(XDeltaError::ChecksumMismatch(_))), "lenient apply of a bad CRC")?;
+
+    let lenient = |patch: &[u8]| {
+        let (mut data, mut len, mut failed_at) = (std::ptr::null_mut(), 0, usize::MAX);
+        let rc = crate::xdelta_apply_patch_lenient(
+            old.as_ptr(),
+            old.len(),
+            patch.as_ptr(),
+            patch.len(),
+            &mut data,
+            &mut len,
+            &mut failed_at,
+        );
+        let out = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+        xdelta_free_data(data);
+        (rc, out, failed_at)
+    };
+    check(lenient(&patch) == (XDELTA_OK, new, usize::MAX), "xdelta_apply_patch_lenient")?;
+    check(
+        lenient(cut) == (XDELTA_ERR_MALFORMED_PATCH, expected.clone(), patch.len() - 13),
+        "xdelta_apply_patch_lenient of a cut COPY",
+    )?;
+    check(
+        lenient(&framed) == (XDELTA_ERR_CHECKSUM_MISMATCH, expected, last_record),
+        "xdelta_apply_patch_lenient of a bad CRC",
+    )?;
+    check(lenient(b"XDR").0 == XDELTA_ERR_MALFORMED_PATCH && lenient(b"XDR").2 == 0, "lenient apply of a bad header")
+}
+
+/// Output collected by `collect_chunk`, which fails once `fail_after` chunks
+/// have been taken.
+struct Collected {
+    out: Vec<u8>,
+    calls: usize,
+    fail_after: usize,
+}
+
+extern "C" fn collect_chunk(data: *const u8, len: usize, ctx: *mut std::ffi::c_void) -> c_int {
+    let collected = unsafe { &mut *(ctx as *mut Collected) };
+    collected.calls += 1;
+    if collected.calls > collected.fail_after {
+        return -1;
+    }
+    collected.out.extend_from_slice(unsafe { std::slice::from_raw_parts(data, len) });
+    0
+}
+
+/// `xdelta_apply_patch_stream` hands over the same output as an in-memory
+/// apply, and stops at the first chunk the callback refuses.
+#[test]
+fn apply_stream() -> Result<(), XDeltaError> {
+    use crate::stream::xdelta_apply_patch_stream;
+
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let stream = |fail_after: usize| {
+        let mut collected = Collected { out: Vec::new(), calls: 0, fail_after };
+        let ctx = &mut collected as *mut Collected as *mut std::ffi::c_void;
+        let rc =
+            xdelta_apply_patch_stream(old.as_ptr(), old.len(), patch.as_ptr(), patch.len(), Some(collect_chunk), ctx);
+        (rc, collected)
+    };
+    let (rc, whole) = stream(usize::MAX);
+    check(rc == XDELTA_OK && whole.out == apply_patch_bytes(old, &patch)? && whole.calls > 3, "stream apply")?;
+    let (rc, cut) = stream(2);
+    check(
+        rc == XDELTA_ERR_IO && cut.calls == 3 && cut.out.len() < new.len() && new.starts_with(&cut.out),
+        "stream apply abort",
+    )?;
+    let null_ctx = std::ptr::null_mut();
+    let rc = xdelta_apply_patch_stream(old.as_ptr(), old.len(), patch.as_ptr(), patch.len(), None, null_ctx);
+    check(rc == XDELTA_ERR_NULL_POINTER, "stream apply without callback")
+}
+
+/// Strict creation returns the same patches on honest inputs and refuses
+/// one that doesn't reconstruct new, here from a dirty bitmap claiming a
+/// changed block is clean.
+#[test]
+fn strict() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    for opts in [PatchOptions::new().block_size(64), PatchOptions::new().block_size(64).content_addressed(true)] {
+        let strict = create_patch_with(old, new, &opts.clone().strict(true))?;
+        check(strict == create_patch_with(old, new, &opts)?, "strict on honest inputs")?;
+    }
+
+    let mut edited = old.to_vec();
+    edited[10] ^= 0xff;
+    let lying = PatchOptions::new().block_size(64).dirty_blocks(&vec![0; old.len() / 64 / 8 + 1]);
+    let unchecked = create_patch_with(old, &edited, &lying)?;
+    check(apply_patch_bytes(old, &unchecked)? != edited, "clean bitmap hides the edit")?;
+    let r = create_patch_with(old, &edited, &lying.strict(true));
+    check(matches!(r, Err(XDeltaError::InvalidArg(ref m)) if m.starts_with("strict check failed")), "strict mismatch")?;
+
+    let opts = XdeltaOptions { block_size: 64, flags: XDELTA_OPT_STRICT, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &opts, &mut data, &mut len);
+    let ok = rc == XDELTA_OK && apply_patch_bytes(old, unsafe { std::slice::from_raw_parts(data, len) })? == new;
+    xdelta_free_data(data);
+    check(ok, "strict through XdeltaOptions")?;
+
+    let signed = Signature::new(old, 64)?.create_patch_without_old(new, &PatchOptions::new().strict(true));
+    check(matches!(signed, Err(XDeltaError::InvalidArg(_))), "strict without old")
+}
+
+/// Every patch flavour round-trips through every apply path: whole, streamed,
+/// ranged, fed in uneven pieces, each output strategy, and split into
+/// sub-patches; with the block size embedded, the output signature apply
+/// hands back diffs a further version.
+#[test]
+fn every_apply_path() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let plain = PatchOptions::new().block_size(256);
+    let full = plain
+        .clone()
+        .near_miss_diff(true)
+        .old_hash(true)
+        .const_table(true)
+        .index_granularity(1024)
+        .embed_block_size(true)
+        .quality(Quality::Best);
+    let skim = plain.clone().quality(Quality::Skim(4));
+    for opts in [&plain, &full, &skim] {
+        let patch = create_patch_with(old, new, opts)?;
+        check(apply_patch_bytes(old, &patch)? == new, "apply")?;
+
+        let mut streamed = Vec::new();
+        let verify = if opts.old_hash { VerifyOld::Full } else { VerifyOld::None };
+        apply_streaming(&mut &old[..], &patch, &mut streamed, &ApplyOptions::new().verify(verify))?;
+        check(streamed == new, "streaming apply")?;
+
+        check(apply_range_bytes(old, &patch, 2900, 1500)? == new[2900..4400], "range apply")?;
+
+        // fed in uneven pieces, as if arriving over the network
+        let mut feed = ApplyFeed::new(old, Vec::new(), verify);
+        let mut rest = &patch[..];
+        for piece in (1..).map(|i| i * 37 % 1000 + 1) {
+            let (head, tail) = rest.split_at(usize::min(piece, rest.len()));
+            feed.feed(head)?;
+            rest = tail;
+            if rest.is_empty() {
+                break;
+            }
+        }
+        check(feed.finish()? == new, "fed apply")?;
+
+        // every output strategy gives the same output
+        let (mut exact, mut grown, mut streamed, mut buf) = (Vec::new(), Vec::new(), Vec::new(), vec![0u8; new.len()]);
+        apply_to(old, &patch, ApplyOutput::ExactReserve(&mut exact))?;
+        apply_to(old, &patch, ApplyOutput::Grow(&mut grown))?;
+        apply_to(old, &patch, ApplyOutput::Streaming(&mut streamed))?;
+        let written = apply_to(old, &patch, ApplyOutput::IntoBuffer(&mut buf))?;
+        check(exact == new && grown == new && streamed == new, "output strategies")?;
+        check(written == new.len() as u64 && buf == new, "apply into buffer")?;
+
+        let mut joined = vec![0u8; new.len()];
+        for part in split_patch(&patch, 3)? {
+            let offset = sub_patch_offset(&part)? as usize;
+            let out = apply_patch_bytes(old, &part)?;
+            check(offset + out.len() <= joined.len(), "split patch offsets")?;
+            joined[offset..offset + out.len()].copy_from_slice(&out);
+        }
+        check(joined == new, "split patch")?;
+
+        if opts.embed_block_size {
+            // diff a further version against the signature apply handed back
+            let (out, sig) = apply_with_signature(old, &patch)?;
+            check(out == new, "apply with signature")?;
+            let mut newer = new.to_vec();
+            newer[9000..9100].fill(0x11);
+            let next = sig.create_patch(new, &newer, &plain)?;
+            check(apply_patch_bytes(new, &next)? == newer, "patch from output signature")?;
+        }
+    }
+    Ok(())
+}
+
+/// An apply context reuses its buffer across patches of different sizes.
+#[test]
+fn apply_context_reuse() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let mut ctx = ApplyContext::new();
+    let patch = create_patch_with(old, new, &plain())?;
+    check(ctx.apply_patch(old, &patch)? == new, "apply context")?;
+    let short = create_patch_with(old, &old[..3000], &plain())?;
+    check(ctx.apply_patch(old, &short)? == &old[..3000], "apply context reuse")
+}
+
+/// Offsets and lengths picked to wrap `offset + len` or `pos + len` are
+/// refused cleanly on every apply path, never a panic.
+#[test]
+fn wrapping_offsets() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let mut wrapping = Vec::new();
+    let copy = |offset: u64, len: u32| [&[0x01][..], &offset.to_le_bytes(), &len.to_le_bytes()].concat();
+    wrapping.push(copy(usize::MAX as u64 - 1, u32::MAX));
+    wrapping.push(copy(u64::MAX - 1, 16));
+    wrapping.push([&[0x00][..], &u32::MAX.to_le_bytes(), b"short"].concat()); // ADD
+    wrapping.push([&[0x10][..], &(u64::MAX - 1).to_le_bytes(), &16u32.to_le_bytes(), &0u32.to_le_bytes()].concat());
+    wrapping.push([&[0x10][..], &0u64.to_le_bytes(), &16u32.to_le_bytes(), &u32::MAX.to_le_bytes()].concat());
+    wrapping.push([&[0x84][..], &u32::MAX.to_le_bytes()].concat()); // PADDING
+    for bare in &wrapping {
+        let patch = with_header(bare);
+        let mut feed = ApplyFeed::new(old, Vec::new(), VerifyOld::None);
+        let fed = feed.feed(&patch).and_then(|()| feed.finish());
+        check(
+            rejected(&apply_patch_bytes(old, &patch))
+                && rejected(&apply_streaming(&mut &old[..], &patch, &mut Vec::new(), &ApplyOptions::new()))
+                && rejected(&apply_to(old, &patch, ApplyOutput::Grow(&mut Vec::new())))
+                && rejected(&apply_range_bytes(old, &patch, 0, 8))
+                && rejected(&fed),
+            "wrapping offset or length",
+        )?;
+    }
+    Ok(())
+}
+
+/// An offset one back repeats like a run; one at the output position has
+/// nothing to copy yet.
+#[test]
+fn overlapping_copy_target() -> Result<(), XDeltaError> {
+    let copy_target_at = |offset: u64| {
+        let mut bare = vec![0x00, 2, 0, 0, 0, b'a', b'b', 0x04]; // ADD "ab", COPY_TARGET
+        bare.extend_from_slice(&offset.to_le_bytes());
+        bare.extend_from_slice(&7u32.to_le_bytes());
+        with_header(&bare)
+    };
+    check(
+        apply_patch_bytes(&[], &copy_target_at(0))? == b"ababababa"
+            && apply_patch_bytes(&[], &copy_target_at(1))? == b"abbbbbbbb"
+            && rejected(&apply_patch_bytes(&[], &copy_target_at(2))),
+        "overlapping copy target",
+    )
+}
+
+/// An unknown opcode with the skippable bit fails an apply unless
+/// `XDELTA_APPLY_SKIP_UNKNOWN` asks to skip it; an unknown critical opcode
+/// fails either way.
+#[test]
+fn skip_unknown() -> Result<(), XDeltaError> {
+    use crate::{xdelta_apply_patch_data_ex, XDELTA_APPLY_SKIP_UNKNOWN};
+
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let records = patch_records(&patch)?;
+    let (_, first) = crate::read_record(records, 0)?;
+    let future = |opcode: u8| {
+        let record = [opcode, 3, 0, 0, 0, 1, 2, 3];
+        with_header(&[&records[..first], &record, &records[first..], &record].concat())
+    };
+    let apply = |patch: &[u8], flags: u32| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc = xdelta_apply_patch_data_ex(
+            old.as_ptr(),
+            old.len(),
+            patch.as_ptr(),
+            patch.len(),
+            flags,
+            &mut data,
+            &mut len,
+        );
+        taken(rc, data, len)
+    };
+    let skippable = future(0x9f);
+    check(
+        apply(&skippable, XDELTA_APPLY_SKIP_UNKNOWN) == (XDELTA_OK, new.to_vec())
+            && apply(&patch, 0) == (XDELTA_OK, new.to_vec())
+            && apply(&skippable, 0).0 != XDELTA_OK
+            && apply_patch_bytes(old, &skippable).is_err()
+            && apply(&future(0x60), XDELTA_APPLY_SKIP_UNKNOWN).0 != XDELTA_OK,
+        "skippable opcodes skipped only when asked",
+    )
+}
+
+/// With an OLD_HASH record, corruption of old the patch never reads passes
+/// a partial check and fails a full one before any output; corruption it
+/// reads fails both. The callback FFI verifies the same.
+#[test]
+fn verify_old() -> Result<(), XDeltaError> {
+    use crate::stream::{xdelta_apply_patch_callbacks, XDELTA_VERIFY_FULL, XDELTA_VERIFY_PARTIAL};
+    use crate::XDELTA_ERR_OLD_HASH_MISMATCH;
+
+    let old = filler(32 * 1024, 0x01d);
+    let new = [&old[..8192], &filler(2000, 0x01e), &old[8192..16_384]].concat();
+    let patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(256).old_hash(true))?;
+    let apply = |old: &[u8], verify: VerifyOld| {
+        let mut out = Vec::new();
+        let r = apply_streaming(&mut &old[..], &patch, &mut out, &ApplyOptions::new().verify(verify));
+        (r, out)
+    };
+    let mut unread = old.clone();
+    unread[30_000] ^= 1;
+    let mut read = old.clone();
+    read[100] ^= 1;
+    let (partial, out) = apply(&unread, VerifyOld::Partial);
+    check(partial.is_ok() && out == new, "partial check passes unread corruption")?;
+    let (full, out) = apply(&unread, VerifyOld::Full);
+    check(matches!(full, Err(XDeltaError::OldHashMismatch(_))) && out.is_empty(), "full check before output")?;
+    let (partial, _) = apply(&read, VerifyOld::Partial);
+    check(matches!(partial, Err(XDeltaError::OldHashMismatch(_))), "partial check of read corruption")?;
+    check(apply(&old, VerifyOld::Full).1 == new, "full check of intact old")?;
+
+    let callbacks = |old: &[u8], verify: u32| {
+        let mut io = CallbackIo { old, out: Vec::new(), writes: Vec::new() };
+        let ctx = &mut io as *mut CallbackIo as *mut std::ffi::c_void;
+        let rc = xdelta_apply_patch_callbacks(
+            old.len() as u64,
+            Some(read_io),
+            patch.as_ptr(),
+            patch.len(),
+            Some(write_io),
+            ctx,
+            verify,
+            std::ptr::null(),
+        );
+        (rc, io.out)
+    };
+    check(
+        callbacks(&unread, XDELTA_VERIFY_PARTIAL) == (XDELTA_OK, new.clone())
+            && callbacks(&unread, XDELTA_VERIFY_FULL) == (XDELTA_ERR_OLD_HASH_MISMATCH, Vec::new())
+            && callbacks(&read, XDELTA_VERIFY_PARTIAL).0 == XDELTA_ERR_OLD_HASH_MISMATCH,
+        "xdelta_apply_patch_callbacks verify",
+    )
+}
+
+/// With a write alignment every write is exactly that long, the last
+/// padded up to it or left short, in Rust and through
+/// `xdelta_apply_patch_aligned` alike.
+#[test]
+fn write_alignment() -> Result<(), XDeltaError> {
+    use crate::stream::xdelta_apply_patch_aligned;
+
+    let (old, new) = fixture();
+    struct Writes(Vec<Vec<u8>>);
+    impl std::io::Write for Writes {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.push(data.to_vec());
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let tail = new.len() % 512;
+    check(tail != 0, "output with a partial last chunk")?;
+    let padded = [new, &vec![0xee; 512 - tail]].concat();
+    for (pad, expected, last) in [(None, new, tail), (Some(0xee), &padded[..], 512)] {
+        let mut writes = Writes(Vec::new());
+        apply_streaming(&mut &old[..], &patch, &mut writes, &ApplyOptions::new().write_alignment(512, pad))?;
+        let (body, end) = writes.0.split_at(writes.0.len() - 1);
+        check(
+            writes.0.concat() == expected && body.iter().all(|w| w.len() == 512) && end[0].len() == last,
+            "aligned writes",
+        )?;
+
+        let mut io = CallbackIo { old, out: Vec::new(), writes: Vec::new() };
+        let ctx = &mut io as *mut CallbackIo as *mut std::ffi::c_void;
+        let pad_final = pad.map_or(-1, c_int::from);
+        let rc = xdelta_apply_patch_aligned(
+            old.len() as u64,
+            Some(read_io),
+            patch.as_ptr(),
+            patch.len(),
+            Some(write_io),
+            ctx,
+            512,
+            pad_final,
+        );
+        let (body, end) = io.writes.split_at(io.writes.len() - 1);
+        check(
+            rc == XDELTA_OK && io.out == expected && body.iter().all(|&n| n == 512) && end == [last],
+            "xdelta_apply_patch_aligned",
+        )?;
+    }
+    Ok(())
+}
+
+/// COPY offsets past 4 GiB read old exactly there, and ranges that run or
+/// wrap past old's end are refused rather than truncated to 32 bits. Old
+/// only reports its size and what is read, so nothing near 4 GiB is
+/// allocated.
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn offsets_past_4gib() -> Result<(), XDeltaError> {
+    struct Phantom {
+        size: u64,
+        reads: Vec<(u64, usize)>,
+    }
+    impl OldSource for Phantom {
+        fn size(&self) -> u64 {
+            self.size
+        }
+
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+            self.reads.push((offset, buf.len()));
+            buf.fill(0x5);
+            Ok(())
+        }
+    }
+    let copy = |offset: u64, len: u32| [&[0x01][..], &offset.to_le_bytes(), &len.to_le_bytes()].concat();
+    let apply = |records: &[u8]| {
+        let mut old = Phantom { size: 6 << 30, reads: Vec::new() };
+        let mut out = Vec::new();
+        let r = apply_streaming(&mut old, &with_header(records), &mut out, &ApplyOptions::new());
+        (r, old.reads, out.len())
+    };
+    let (r, reads, len) = apply(&[copy(5 << 30, 100), copy((6 << 30) - 10, 10)].concat());
+    check(r.is_ok() && reads == [(5 << 30, 100), ((6 << 30) - 10, 10)] && len == 110, "COPY past 4 GiB")?;
+    for bad in [copy((6 << 30) - 5, 10), copy(u64::MAX - 2, 5)] {
+        let (r, reads, _) = apply(&bad);
+        check(matches!(r, Err(XDeltaError::OldOutOfRange(_))) && reads.is_empty(), "COPY past old's end")?;
+    }
+    // offset 2^32 + 3 would be 3 cut to 32 bits, well within old
+    let old = filler(64, 0x4ff);
+    check(
+        matches!(apply_patch_bytes(&old, &with_header(&copy((1 << 32) + 3, 4))), Err(XDeltaError::OldOutOfRange(_)))
+            && crate::old_range(64, 1 << 32, 4, "read").is_err()
+            && crate::old_range(1 << 33, (1 << 32) + 3, 4, "read").is_ok_and(|r| r == ((1 << 32) + 3..(1 << 32) + 7)),
+        "offsets kept in 64 bits",
+    )
+}
+
+/// A store of old's blocks keyed by their SHA-256 for `resolve_block`.
+type BlockStore<'a> = HashMap<[u8; 32], &'a [u8]>;
+
+extern "C" fn resolve_block(
+    hash: *const u8,
+    data: *mut *const u8,
+    len: *mut usize,
+    ctx: *mut std::ffi::c_void,
+) -> c_int {
+    let store = unsafe { &*(ctx as *const BlockStore) };
+    let mut key = [0u8; 32];
+    key.copy_from_slice(unsafe { std::slice::from_raw_parts(hash, 32) });
+    match store.get(&key) {
+        Some(block) => {
+            unsafe {
+                *data = block.as_ptr();
+                *len = block.len();
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// A content-addressed patch names old's blocks only by hash: it applies
+/// from a store of blocks keyed by hash, fails when a block is missing or
+/// doesn't match its hash, and can't be applied to old by offset; the FFI
+/// resolves through a callback.
+#[test]
+fn cas() -> Result<(), XDeltaError> {
+    use crate::cas::xdelta_apply_patch_cas;
+
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256).content_addressed(true))?;
+    let records = parsed_records(&patch)?;
+    let store: BlockStore = old.chunks(256).map(|block| (block_strong_hash(block, HashAlgo::Sha256), block)).collect();
+    let mut swapped = store.clone();
+    let hashes: Vec<[u8; 32]> = store.keys().copied().collect();
+    swapped.insert(hashes[0], store[&hashes[1]]);
+    check(
+        records.iter().any(|record| matches!(record, crate::Record::CopyHash { .. }))
+            && !records.iter().any(|record| matches!(record, crate::Record::Copy { .. }))
+            && crate::apply_cas(&patch, |hash| store.get(hash).copied())? == new
+            && crate::apply_cas(&patch, |_| None).is_err()
+            && apply_patch_bytes(old, &patch).is_err(),
+        "content-addressed round trip",
+    )?;
+    let used = crate::apply_cas(&patch, |hash| swapped.get(hash).copied());
+    let needs_first =
+        records.iter().any(|record| matches!(record, crate::Record::CopyHash { hash, .. } if **hash == hashes[0]));
+    check(!needs_first || used.is_err(), "block not matching its hash")?;
+
+    let resolve = |store: &BlockStore| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let ctx = store as *const BlockStore as *mut std::ffi::c_void;
+        let rc = xdelta_apply_patch_cas(patch.as_ptr(), patch.len(), Some(resolve_block), ctx, &mut data, &mut len);
+        taken(rc, data, len)
+    };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let no_callback =
+        xdelta_apply_patch_cas(patch.as_ptr(), patch.len(), None, std::ptr::null_mut(), &mut data, &mut len);
+    check(
+        resolve(&store) == (XDELTA_OK, new.to_vec())
+            && resolve(&BlockStore::new()).0 != XDELTA_OK
+            && no_callback == XDELTA_ERR_INVALID_ARG,
+        "xdelta_apply_patch_cas",
+    )
+}
+
+/// Overlapping COPY ranges are counted with the bytes they read again,
+/// in Rust and through the FFI, and apply as usual.
+#[test]
+fn overlap_stats() -> Result<(), XDeltaError> {
+    use crate::copy_overlap;
+    use crate::overlap::{xdelta_copy_overlap_stats, XdeltaOverlapStats};
+
+    let copy = |offset: u64, len: u32| [&[0x01][..], &offset.to_le_bytes(), &len.to_le_bytes()].concat();
+    let old = filler(400, 0x0e1);
+    let disjoint = with_header(&[copy(0, 100), copy(200, 100)].concat());
+    let patch = with_header(&[copy(0, 100), copy(50, 100), copy(200, 100), copy(0, 100)].concat());
+    let stats = copy_overlap(&patch)?;
+    check(
+        copy_overlap(&disjoint)? == crate::OverlapStats { ranges: 2, overlapping_ranges: 0, reread_bytes: 0 }
+            && stats == crate::OverlapStats { ranges: 4, overlapping_ranges: 3, reread_bytes: 150 }
+            && apply_patch_bytes(&old, &patch)? == [&old[..100], &old[50..150], &old[200..300], &old[..100]].concat(),
+        "overlapping COPY ranges",
+    )?;
+    let size = std::mem::size_of::<XdeltaOverlapStats>() as u32;
+    let mut c_stats = XdeltaOverlapStats { size, ranges: 0, overlapping_ranges: 0, reread_bytes: 0 };
+    let rc = xdelta_copy_overlap_stats(patch.as_ptr(), patch.len(), &mut c_stats);
+    check(
+        rc == XDELTA_OK && c_stats.ranges == 4 && c_stats.overlapping_ranges == 3 && c_stats.reread_bytes == 150,
+        "xdelta_copy_overlap_stats",
+    )
+}
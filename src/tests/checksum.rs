@@ -0,0 +1,319 @@
+// src/tests/checksum.rs
+//! Weak checksums and strong hashes.
+
+use super::*;
+
+/// Rolling the weak checksums one byte at a time agrees with computing them
+/// from scratch at every position, for windows from one byte to most of the
+/// data.
+#[test]
+fn rolling_matches_from_slice() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    for window in [1, 17, 64, 256, 6000] {
+        check_rolling::<Rolling>(&old[..8192], window)?;
+        check_rolling::<Rolling>(&filler(8192, 11), window)?;
+        check_rolling::<Adler32>(&old[..8192], window)?;
+        check_rolling::<Adler32>(&[0xff; 8192], window)?;
+    }
+    Ok(())
+}
+
+/// Past `u32::MAX / 255` bytes a window's `len * prev` wraps, and rolling
+/// must wrap with it rather than overflow.
+#[test]
+fn rolling_past_u32_weight() -> Result<(), XDeltaError> {
+    check_rolling::<Rolling>(&[0xff; (17 << 20) + 4], 17 << 20)
+}
+
+/// The weak checksum is `b mod 65536` over `a mod 65536`, with sums past 16
+/// bits folded to their low halves; signatures already on disk depend on
+/// these exact values.
+#[test]
+fn rolling_chksum_values() {
+    assert_eq!(Rolling::from_slice(b"").chksum(), 0);
+    assert_eq!(Rolling::from_slice(b"abc").chksum(), 0x024a_0126);
+    assert_eq!(Rolling::from_slice(&[0xff; 1024]).chksum(), 0xfe00_fc00);
+    let ramp: Vec<u8> = (0..=255).cycle().take(1024).collect();
+    assert_eq!(Rolling::from_slice(&ramp).chksum(), 0xaa00_fe00);
+}
+
+/// Distinct windows rarely share a weak checksum: under 1% of 4096
+/// pseudo-random 16-byte windows may collide with an earlier one.
+#[test]
+fn weak_collisions() -> Result<(), XDeltaError> {
+    let count = 4096;
+    let data = filler(count * 16, 7);
+    let mut seen = HashSet::new();
+    let collisions = data.chunks_exact(16).filter(|w| !seen.insert(Rolling::from_slice(w).chksum())).count();
+    check(collisions * 100 < count, "weak checksum collisions")
+}
+
+/// The two-level weak lookup finds exactly the bucket the signature map
+/// holds for every window of new, hits and misses alike, so the matcher
+/// makes the same choices through it. Old's blocks are near duplicates,
+/// whose weak checksums crowd together.
+#[test]
+fn weak_index() -> Result<(), XDeltaError> {
+    let pattern = filler(64, 13);
+    let mut old = Vec::new();
+    for i in 0..200u8 {
+        let mut block = pattern.clone();
+        block[usize::from(i) % 64] ^= i | 1;
+        old.extend_from_slice(&block);
+    }
+    let mut new = old[7..].to_vec();
+    new.extend_from_slice(&old[..300]);
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, &old[..], 64, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let index = WeakIndex::new(&sigs);
+    let mut hits = 0;
+    for window in new.windows(64) {
+        let weak = Rolling::from_slice(window).chksum();
+        let (found, want) = (index.get(weak), sigs.get(&weak));
+        check(found.map(|b| b as *const _) == want.map(|b| b as *const _), "weak index lookup")?;
+        hits += usize::from(want.is_some());
+    }
+    check(hits >= 200 && sigs.keys().all(|&weak| index.get(weak).is_some()), "weak index hits")
+}
+
+/// Adler-32 gives zlib's value, and patches made with either weak checksum
+/// apply, directly or through a signature that records it.
+#[test]
+fn weak_algos() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    check(
+        Adler32::from_slice(b"Wikipedia").chksum() == 0x11e6_0398 && Adler32::from_slice(b"").chksum() == 1,
+        "Adler-32 value",
+    )?;
+    for weak in [WeakAlgo::Rolling, WeakAlgo::Adler32] {
+        let plain = PatchOptions::new().block_size(256).weak_checksum(weak);
+        for opts in [plain.clone(), plain.clone().near_miss_diff(true).copy_target(true).quality(Quality::Best)] {
+            let patch = create_patch_with(old, new, &opts)?;
+            check(apply_patch_bytes(old, &patch)? == new, "patch with each weak checksum")?;
+        }
+
+        let sig = Signature::with_checksums(old, 256, HashAlgo::Sha256, weak)?;
+        let bytes = sig.to_bytes();
+        let magic: &[u8] = if weak == WeakAlgo::Adler32 { b"XDSA" } else { b"XDSG" };
+        let remote = create_patch_from_signature(&bytes, new)?;
+        check(
+            bytes[..4] == *magic
+                && Signature::from_bytes(&bytes)? == sig
+                && Signature::from_bytes(&bytes)?.weak_algo() == weak
+                && apply_patch_bytes(old, &remote)? == new,
+            "signature with each weak checksum",
+        )?;
+    }
+
+    let opts = XdeltaOptions { block_size: 256, flags: XDELTA_OPT_ADLER32, ..XdeltaOptions::default() };
+    let adler = create_patch_with(old, new, &PatchOptions::new().block_size(256).weak_checksum(WeakAlgo::Adler32))?;
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &opts, &mut data, &mut len);
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == adler.as_slice();
+    xdelta_free_data(data);
+    check(same, "Adler-32 through XdeltaOptions")
+}
+
+/// The vectorized rolling sum is the scalar one bit for bit: random
+/// buffers of every length around the lane width, from every alignment,
+/// and long runs of 0xff whose sums wrap.
+#[cfg(feature = "simd")]
+#[test]
+fn simd_rolling() -> Result<(), XDeltaError> {
+    use crate::{rolling_sums, simd};
+
+    let data = filler(4096, 0x51d);
+    for seed in 0..64u32 {
+        let len = filler(1, seed)[0] as usize * 8 + seed as usize;
+        let start = seed as usize % 16;
+        let buf = &data[start..start + len];
+        check(simd::rolling_sums(buf) == rolling_sums(buf), "vectorized rolling sum")?;
+    }
+    for len in 0..=80 {
+        check(simd::rolling_sums(&data[..len]) == rolling_sums(&data[..len]), "vectorized rolling sum lengths")?;
+    }
+    let saturated = vec![0xff; (17 << 20) + 5];
+    check(simd::rolling_sums(&saturated) == rolling_sums(&saturated), "vectorized rolling sum wrapping")
+}
+
+/// BLAKE3 gives the published test vectors (input bytes `i % 251`), and
+/// confirming matches with it changes nothing in a patch but the strong hash
+/// its header records and the hashes a content-addressed one carries, which
+/// its header then flags.
+#[cfg(feature = "blake3")]
+#[test]
+fn blake3() -> Result<(), XDeltaError> {
+    use crate::{apply_cas, XDELTA_CREATE_BLAKE3};
+
+    let (old, new) = fixture();
+    let hex = |h: [u8; 32]| h.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    for (len, expected) in [
+        (0usize, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+        (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+        (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+        (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+        (3072, "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2"),
+    ] {
+        let input: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        check(hex(block_strong_hash(&input, HashAlgo::Blake3)) == expected, "BLAKE3 test vector")?;
+    }
+    let mut via_ffi = [0u8; 32];
+    let rc = xdelta_block_strong_hash(old.as_ptr(), 256, XDELTA_HASH_BLAKE3, via_ffi.as_mut_ptr());
+    check(rc == XDELTA_OK && via_ffi == block_strong_hash(&old[..256], HashAlgo::Blake3), "BLAKE3 FFI hash")?;
+
+    let plain = PatchOptions::new().block_size(256);
+    for opts in [plain.clone(), plain.clone().near_miss_diff(true).quality(Quality::Best)] {
+        let sha = create_patch_with(old, new, &opts)?;
+        let blake = create_patch_with(old, new, &opts.strong_hash(HashAlgo::Blake3))?;
+        let recorded = patch_info(&blake)?.strong_hash == XDELTA_HASH_BLAKE3;
+        check(
+            patch_records(&blake)? == patch_records(&sha)? && recorded && apply_patch_bytes(old, &blake)? == new,
+            "BLAKE3 patch",
+        )?;
+    }
+    let mut ffi_patch: *mut u8 = std::ptr::null_mut();
+    let mut ffi_len = 0usize;
+    let rc = xdelta_create_patch_data_ex(
+        old.as_ptr(),
+        old.len(),
+        new.as_ptr(),
+        new.len(),
+        &mut ffi_patch,
+        &mut ffi_len,
+        256,
+        XDELTA_CREATE_BLAKE3,
+    );
+    let same = rc == XDELTA_OK
+        && unsafe { std::slice::from_raw_parts(ffi_patch, ffi_len) }
+            == &create_patch_with(old, new, &plain.clone().strong_hash(HashAlgo::Blake3))?[..];
+    xdelta_free_data(ffi_patch);
+    check(same, "BLAKE3 create flag")?;
+
+    // content-addressed: the store is keyed by BLAKE3 and the header says so
+    let cas = plain.clone().content_addressed(true).strong_hash(HashAlgo::Blake3);
+    let patch = create_patch_with(old, new, &cas)?;
+    let store: HashMap<[u8; 32], &[u8]> =
+        old.chunks(256).map(|block| (block_strong_hash(block, HashAlgo::Blake3), block)).collect();
+    let sha_store: HashMap<[u8; 32], &[u8]> =
+        old.chunks(256).map(|block| (block_strong_hash(block, HashAlgo::Sha256), block)).collect();
+    check(
+        patch[5] & crate::PATCH_FLAG_BLAKE3 != 0
+            && create_patch_with(old, new, &plain.clone().content_addressed(true))?[5] & crate::PATCH_FLAG_BLAKE3 == 0
+            && apply_cas(&patch, |hash| store.get(hash).copied())? == new
+            && apply_cas(&patch, |hash| sha_store.get(hash).copied()).is_err(),
+        "BLAKE3 content-addressed patch",
+    )?;
+
+    let sig = Signature::with_hash(old, 256, HashAlgo::Blake3)?;
+    let bytes = sig.to_bytes();
+    let back = Signature::from_bytes(&bytes)?;
+    check(
+        &bytes[..4] == b"XDB3"
+            && back == sig
+            && back.hash_algo() == HashAlgo::Blake3
+            && back != Signature::new(old, 256)?
+            && apply_patch_bytes(old, &create_patch_from_signature(&bytes, new)?)? == new,
+        "BLAKE3 signature",
+    )
+}
+
+/// Of windows whose weak checksum a few blocks of old share but whose bytes
+/// none has, the CRC precheck lets through only those it can't rule out:
+/// against blocks that each collide with a handful of others, the matcher
+/// strong-hashes a fraction of the windows it did, and makes the same patch.
+#[test]
+fn crc_precheck() -> Result<(), XDeltaError> {
+    // adding k, -2k, k keeps the weak checksum of a block whose bytes don't wrap
+    let base = |seed: u32| filler(64, seed).iter().map(|b| b % 200 + 28).collect::<Vec<u8>>();
+    let perturbed = |seed: u32, at: usize, k: u8| {
+        let mut block = base(seed);
+        block[at] += k;
+        block[at + 1] -= 2 * k;
+        block[at + 2] += k;
+        block
+    };
+    let (mut old, mut new) = (Vec::new(), Vec::new());
+    for seed in 0..8 {
+        for at in 0..8 {
+            old.extend_from_slice(&perturbed(seed, at * 7, 1 + at as u8));
+            new.extend_from_slice(&perturbed(seed, at * 7 + 3, 1 + at as u8));
+        }
+        new.extend_from_slice(&old[old.len() - 128..]);
+    }
+    let tail = base(9)[..40].to_vec();
+    old.extend_from_slice(&tail);
+    new.extend_from_slice(&tail);
+
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, &old[..], 64, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let hashed = |precheck: bool| -> Result<(usize, Vec<u8>), XDeltaError> {
+        let (mut count, mut patch) = (0usize, Vec::new());
+        let opts = PatchOptions::new().block_size(64).crc_precheck(precheck);
+        let mut on_strong_hash = || count += 1;
+        let hooks = MatchHooks { on_strong_hash: Some(&mut on_strong_hash), ..MatchHooks::default() };
+        match_blocks(&old[..], &new, &opts, &sigs, &mut patch, &mut Vec::new(), hooks)?;
+        Ok((count, patch))
+    };
+    let ((without, plain), (with, prechecked)) = (hashed(false)?, hashed(true)?);
+    check(
+        sigs.values().all(|bucket| bucket.len() <= 8) && without >= 64 && with * 4 <= without && plain == prechecked,
+        "CRC precheck skips strong hashes of weak collisions",
+    )?;
+    check(apply_patch_bytes(&old, &with_header(&prechecked))? == new, "CRC-prechecked patch")?;
+
+    let opts = PatchOptions::new().block_size(64);
+    let c_opts = XdeltaOptions { block_size: 64, flags: XDELTA_OPT_CRC_PRECHECK, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &c_opts, &mut data, &mut len);
+    let same =
+        rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == create_patch_with(&old, &new, &opts)?;
+    xdelta_free_data(data);
+    check(same, "CRC precheck through XdeltaOptions")
+}
+
+/// The public strong hash is what the signatures store, short tail included.
+#[test]
+fn strong_hash_matches_signatures() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, &old[..1000], 256, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let stored: Vec<(u64, [u8; 32])> = sigs.values().flatten().map(|e| (e.block_index, e.strong_hash)).collect();
+    check(
+        stored.len() == 4
+            && stored.iter().all(|&(idx, hash)| {
+                let start = idx as usize * 256;
+                hash == block_strong_hash(&old[start..usize::min(start + 256, 1000)], HashAlgo::Sha256)
+            }),
+        "block strong hash",
+    )
+}
+
+/// One weak checksum bucket of 100 distinct blocks, each still matched to its own.
+#[test]
+fn large_weak_bucket() -> Result<(), XDeltaError> {
+    let blocks = colliding_blocks(100);
+    let weak: HashSet<u32> = blocks.iter().map(|b| Rolling::from_slice(b).chksum()).collect();
+    let bucket_old = blocks.concat();
+    let bucket_new: Vec<u8> = blocks.iter().rev().flatten().copied().collect();
+    let patch = create_patch_with(&bucket_old, &bucket_new, &PatchOptions::new().block_size(64))?;
+    check(
+        weak.len() == 1
+            && apply_patch_bytes(&bucket_old, &patch)? == bucket_new
+            && opcode_histogram(&patch)?.iter().all(|s| s.name != "ADD"),
+        "large weak checksum bucket",
+    )
+}
+
+/// BLAKE3 is refused as a strong hash without the feature.
+#[cfg(not(feature = "blake3"))]
+#[test]
+fn blake3_refused_without_feature() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    check(
+        xdelta_block_strong_hash(old.as_ptr(), 16, XDELTA_HASH_BLAKE3, [0u8; 32].as_mut_ptr())
+            == XDELTA_ERR_INVALID_ARG,
+        "BLAKE3 refused without the feature",
+    )
+}
@@ -0,0 +1,1152 @@
+// src/tests/create.rs
+//! Creating patches: the matcher and the options that steer it.
+
+use super::*;
+
+extern "C" fn record_progress(processed: usize, total: usize, ctx: *mut std::ffi::c_void) {
+    unsafe { &mut *(ctx as *mut Vec<(usize, usize)>) }.push((processed, total));
+}
+
+/// Progress reports come at most once per cancellation interval, with
+/// `processed` increasing up to new's length, through the Rust API and
+/// `xdelta_create_patch_data_progress` alike, and don't change the patch.
+#[test]
+fn progress() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let mut new = old.repeat(40);
+    new.extend_from_slice(&filler(300_000, 15));
+    let opts = PatchOptions::new().block_size(256);
+    let mut seen = Vec::new();
+    let patch = create_patch_with_progress(old, &new, &opts, |processed, total| seen.push((processed, total)))?;
+    check(patch == create_patch_with(old, &new, &opts)?, "progress patch")?;
+    let reported = |seen: &[(usize, usize)], total: usize| {
+        seen.windows(2).all(|w| w[0].0 < w[1].0)
+            && seen.iter().all(|&(_, t)| t == total)
+            && seen.last() == Some(&(total, total))
+            && seen.len() <= total / crate::CANCEL_CHECK_INTERVAL + 2
+    };
+    check(seen.len() > 2 && reported(&seen, new.len()), "progress reports")?;
+    for new in [&new[..], old, &[]] {
+        let mut seen: Vec<(usize, usize)> = Vec::new();
+        let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+        let ctx = &mut seen as *mut Vec<(usize, usize)> as *mut std::ffi::c_void;
+        let rc = xdelta_create_patch_data_progress(
+            old.as_ptr(),
+            old.len(),
+            new.as_ptr(),
+            new.len(),
+            &mut data,
+            &mut len,
+            256,
+            Some(record_progress),
+            ctx,
+        );
+        check(rc == XDELTA_OK && reported(&seen, new.len()), "xdelta_create_patch_data_progress")?;
+        let patch = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+        xdelta_free_data(data);
+        check(apply_patch_bytes(old, &patch)? == new, "progress patch apply")?;
+    }
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let rc = xdelta_create_patch_data_progress(
+        old.as_ptr(),
+        old.len(),
+        new.as_ptr(),
+        new.len(),
+        &mut data,
+        &mut len,
+        256,
+        None,
+        std::ptr::null_mut(),
+    );
+    xdelta_free_data(data);
+    check(rc == XDELTA_OK, "progress without callback")
+}
+
+/// A copy longer than a record can say is split at the u32 boundary into
+/// consecutive COPY records, each reported as a match of its own, which
+/// apply as the one copy. Old only reports its size and which ranges are
+/// read, so nothing near 4 GiB is allocated.
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn long_copy() -> Result<(), XDeltaError> {
+    struct Phantom {
+        size: u64,
+        next: u64,
+        in_order: bool,
+    }
+    impl OldSource for Phantom {
+        fn size(&self) -> u64 {
+            self.size
+        }
+
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), XDeltaError> {
+            self.in_order &= offset == self.next;
+            self.next = offset + buf.len() as u64;
+            Ok(())
+        }
+    }
+    struct Counter(u64);
+    impl std::io::Write for Counter {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0 += data.len() as u64;
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let max = crate::MAX_RECORD_LEN;
+    for (len, lens) in [(max, vec![max]), (max + 1, vec![max, 1]), (2 * max + 5, vec![max, max, 5])] {
+        let (mut records, mut reported) = (Vec::new(), Vec::new());
+        crate::write_copy(&mut records, 7, len, |done, n| reported.push((done, n)));
+        let mut parsed = Vec::new();
+        let mut pos = 0usize;
+        while pos < records.len() {
+            let (record, next) = crate::read_record(&records, pos)?;
+            if let crate::Record::Copy { offset, len } = record {
+                parsed.push((offset - 7, len as usize));
+            }
+            pos = next;
+        }
+        let starts = lens.iter().scan(0, |at, &n| Some(std::mem::replace(at, *at + n) as u64));
+        let want: Vec<(u64, usize)> = starts.zip(lens.iter().copied()).collect();
+        let done: Vec<(u64, usize)> = reported.iter().map(|&(done, n)| (done as u64, n)).collect();
+        check(parsed == want && done == want, "long copy split into records")?;
+        let mut old = Phantom { size: 7 + len as u64, next: 7, in_order: true };
+        let mut out = Counter(0);
+        apply_streaming(&mut old, &with_header(&records), &mut out, &ApplyOptions::new())?;
+        check(out.0 == len as u64 && old.in_order && old.next == old.size, "long copy apply")?;
+    }
+    Ok(())
+}
+
+/// `create_patch_with_stats` finds identical inputs all matched, unrelated
+/// ones all literal and a half-changed one about half matched, through the C
+/// struct too.
+#[test]
+fn patch_stats() -> Result<(), XDeltaError> {
+    use crate::histogram::{xdelta_create_patch_stats, XdeltaStats};
+
+    let (old, _) = fixture();
+    let (patch, same) = create_patch_with_stats(old, old, 64)?;
+    check(
+        same.matched_bytes == old.len() as u64 && same.similarity == 100.0 && same.patch_size == patch.len() as u64,
+        "stats of identical inputs",
+    )?;
+
+    let other = filler(old.len(), 0x5eed);
+    let (_, apart) = create_patch_with_stats(old, &other, 64)?;
+    check(apart.similarity < 5.0 && apart.literal_bytes > other.len() as u64 * 95 / 100, "stats of unrelated inputs")?;
+
+    let mut half = old.to_vec();
+    let mid = half.len() / 2;
+    half[mid..].copy_from_slice(&other[mid..]);
+    let (_, part) = create_patch_with_stats(old, &half, 64)?;
+    check((40.0..60.0).contains(&part.similarity), "stats of a half-changed input")?;
+
+    let mut c = XdeltaStats {
+        size: std::mem::size_of::<XdeltaStats>() as u32,
+        matched_bytes: 0,
+        literal_bytes: 0,
+        patch_size: 0,
+        similarity: 0.0,
+    };
+    let mut patch_data = std::ptr::null_mut();
+    let mut patch_len = 0;
+    let rc = xdelta_create_patch_stats(
+        old.as_ptr(),
+        old.len(),
+        half.as_ptr(),
+        half.len(),
+        64,
+        &mut patch_data,
+        &mut patch_len,
+        &mut c,
+    );
+    let ok = rc == XDELTA_OK
+        && (c.matched_bytes, c.literal_bytes) == (part.matched_bytes, part.literal_bytes)
+        && (c.patch_size, c.similarity) == (patch_len as u64, part.similarity);
+    xdelta_free_data(patch_data);
+    check(ok, "patch stats FFI")
+}
+
+/// `create_patch_bidirectional` gives a forward patch and a reverse patch
+/// that, applied in turn, give back old exactly, through the C call too.
+#[test]
+fn bidirectional() -> Result<(), XDeltaError> {
+    use crate::reverse::xdelta_create_patch_bidirectional;
+
+    let (old, _) = fixture();
+    let mut new = old[old.len() / 3..].to_vec();
+    new.extend_from_slice(&filler(5000, 0xb1d1));
+    new.extend_from_slice(&old[..old.len() / 4]);
+    let (forward, reverse) = create_patch_bidirectional(old, &new, 64)?;
+    let rebuilt = apply_patch_bytes(old, &forward)?;
+    check(rebuilt == new && apply_patch_bytes(&rebuilt, &reverse)? == old, "forward then reverse gives old")?;
+
+    let (same, back) = create_patch_bidirectional(old, old, 64)?;
+    let identity = crate::identity_patch();
+    check(same == identity && back == identity, "bidirectional patches of identical inputs")?;
+
+    let (mut fwd, mut fwd_len, mut rev, mut rev_len) = (std::ptr::null_mut(), 0, std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_bidirectional(
+        old.as_ptr(),
+        old.len(),
+        new.as_ptr(),
+        new.len(),
+        64,
+        &mut fwd,
+        &mut fwd_len,
+        &mut rev,
+        &mut rev_len,
+    );
+    let ok = rc == XDELTA_OK
+        && unsafe { std::slice::from_raw_parts(fwd, fwd_len) } == forward.as_slice()
+        && unsafe { std::slice::from_raw_parts(rev, rev_len) } == reverse.as_slice();
+    xdelta_free_data(fwd);
+    xdelta_free_data(rev);
+    check(ok, "bidirectional FFI")
+}
+
+/// With a small block size, scattered block-long matches cost more as COPY
+/// records than as literal bytes; `min_match` sends them as ADD, giving a
+/// smaller patch, also through `XdeltaOptions`.
+#[test]
+fn min_match() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let mut new = Vec::new();
+    for (i, novel) in filler(400, 0x3a7c).chunks(4).enumerate() {
+        let at = i * 1237 % (old.len() - 8) / 8 * 8;
+        new.extend_from_slice(&old[at..at + 8]);
+        new.extend_from_slice(novel);
+    }
+    let tiny = PatchOptions::new().block_size(8);
+    let every = create_patch_with(old, &new, &tiny.clone().min_match(0))?;
+    let long = create_patch_with(old, &new, &tiny.clone().min_match(32))?;
+    check(
+        long.len() < every.len()
+            && patch_info(&long)?.copied_bytes == 0
+            && apply_patch_bytes(old, &long)? == new
+            && apply_patch_bytes(old, &every)? == new,
+        "min match drops short copies",
+    )?;
+
+    let opts = XdeltaOptions { block_size: 8, min_match: 32, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &opts, &mut data, &mut len);
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == long.as_slice();
+    xdelta_free_data(data);
+    check(same, "min match through XdeltaOptions")
+}
+
+/// A file diffed against itself is one COPY of all of it, made without
+/// walking new (progress is reported once, at the end), also when the
+/// options rule out an identity patch.
+#[test]
+fn whole_copy() -> Result<(), XDeltaError> {
+    let big = filler(5 << 20, 0x1d);
+    let mut whole = vec![0x01]; // COPY
+    whole.extend_from_slice(&0u64.to_le_bytes());
+    whole.extend_from_slice(&(big.len() as u32).to_le_bytes());
+    check(crate::create_patch_bytes(&big, &big, &PatchOptions::new())? == whole, "whole-file copy")?;
+
+    let mut reports = Vec::new();
+    let opts = PatchOptions::new().embed_block_size(true);
+    let patch = create_patch_with_progress(&big, &big, &opts, |done, total| reports.push((done, total)))?;
+    let info = patch_info(&patch)?;
+    check(
+        reports == [(big.len(), big.len())]
+            && (info.num_add, info.num_copy, info.copied_bytes) == (0, 1, big.len() as u64)
+            && apply_patch_bytes(&big, &patch)? == big,
+        "whole-file copy skips matching",
+    )?;
+
+    // shorter than min_match, even identical inputs go out as literal data
+    let short = create_patch_with(&big[..40], &big[..40], &opts.min_match(64))?;
+    check(patch_info(&short)?.num_copy == 0, "whole-file copy below min match")
+}
+
+/// Against an unrelated old, the records outgrow `max_ratio` and the patch
+/// becomes one ADD of new, which applies; a low ratio stops matching early.
+/// A similar new stays within the ratio and gets the usual patch. A new
+/// ending in a run falls back as well, though the run alone would be small.
+#[test]
+fn max_ratio() -> Result<(), XDeltaError> {
+    use crate::create_patch_stats_with;
+
+    let (old, new) = fixture();
+    let noise = filler(20_000, 0x7a11);
+    let opts = PatchOptions::new().block_size(64);
+    let (patch, stats) = create_patch_stats_with(old, &noise, &opts.clone().max_ratio(1.0))?;
+    let records = patch_records(&patch)?;
+    check(
+        stats.fell_back
+            && stats.literal_bytes == noise.len() as u64
+            && records.len() == 5 + noise.len()
+            && records[0] == 0x00
+            && apply_patch_bytes(old, &patch)? == noise,
+        "patch outgrowing max_ratio falls back to one ADD",
+    )?;
+    let (_, unbounded) = create_patch_stats_with(old, &noise, &opts)?;
+    check(!unbounded.fell_back && unbounded.patch_size >= stats.patch_size, "literal fallback is no larger")?;
+
+    // progress is reported every 64 KiB of new matched, and once at the end
+    let long_noise = filler(512 * 1024, 0x7a12);
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, old, 64, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let reports = |opts: &PatchOptions| -> Result<(usize, bool), XDeltaError> {
+        let (mut count, mut fell_back, mut patch) = (0usize, false, Vec::new());
+        let mut on_progress = |_, _| count += 1;
+        let mut on_fallback = || fell_back = true;
+        let hooks = MatchHooks {
+            on_progress: Some(&mut on_progress),
+            on_fallback: Some(&mut on_fallback),
+            ..MatchHooks::default()
+        };
+        match_blocks(old, &long_noise, opts, &sigs, &mut patch, &mut Vec::new(), hooks)?;
+        Ok((count, fell_back && apply_patch_bytes(old, &with_header(&patch))? == long_noise))
+    };
+    let ((all, _), (early, fell_back)) = (reports(&opts)?, reports(&opts.clone().max_ratio(0.1))?);
+    check(all == 9 && early == 2 && fell_back, "max_ratio stops matching early")?;
+
+    // given up on with literals pending that flush as one small RUN, which
+    // brings the records back under the ratio: still all of new
+    let run = [0u8; 10_000];
+    let opts_run = PatchOptions::new().block_size(1024).max_ratio(0.01);
+    let (patch, stats) = create_patch_stats_with(&old[..4096], &run, &opts_run)?;
+    check(stats.fell_back && apply_patch_bytes(&old[..4096], &patch)? == run, "max_ratio with a run pending")?;
+
+    let (patch, stats) = create_patch_stats_with(old, new, &opts.clone().max_ratio(1.0))?;
+    check(!stats.fell_back && patch == create_patch_with(old, new, &opts)?, "patch within max_ratio")?;
+    for ratio in [0.0, -1.0, f64::NAN] {
+        let refused = create_patch_with(old, &noise, &opts.clone().max_ratio(ratio));
+        check(matches!(refused, Err(XDeltaError::InvalidArg(_))), "max_ratio not above 0")?;
+    }
+
+    let c_opts = XdeltaOptions { block_size: 64, max_ratio: 1.0, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_data_opts(
+        old.as_ptr(),
+        old.len(),
+        noise.as_ptr(),
+        noise.len(),
+        &c_opts,
+        &mut data,
+        &mut len,
+    );
+    let same = rc == XDELTA_OK
+        && unsafe { std::slice::from_raw_parts(data, len) } == create_patch_with(old, &noise, &opts.max_ratio(1.0))?;
+    xdelta_free_data(data);
+    check(same, "max_ratio through XdeltaOptions")
+}
+
+#[test]
+fn periodic_copy_target() -> Result<(), XDeltaError> {
+    // a 4-byte tile 1000 times is one ADD of the tile and one COPY_TARGET
+    // from 4 bytes back, expanded from its own output
+    let tiled = [0x12u8, 0x34, 0x56, 0x78].repeat(1000);
+    let opts = PatchOptions::new().copy_target(true);
+    let patch = create_patch_with(&[], &tiled, &opts)?;
+    check(
+        patch_records(&patch)?.len() < 24
+            && opcode_histogram(&patch)?.iter().any(|s| s.name == "COPY_TARGET" && s.count == 1),
+        "periodic copy target size",
+    )?;
+    check(apply_patch_bytes(&[], &patch)? == tiled, "periodic copy target")?;
+
+    // a tile between data old has, which is COPYs around the tile
+    let old = filler(4096, 0x7e11);
+    let mut new = old[..1024].to_vec();
+    new.extend(b"tile!".repeat(300));
+    new.extend_from_slice(&old[2048..]);
+    let patch = create_patch_with(&old, &new, &opts.clone().block_size(64))?;
+    let histogram = opcode_histogram(&patch)?;
+    check(
+        histogram.iter().any(|s| s.name == "COPY_TARGET" && s.count == 1)
+            && histogram.iter().any(|s| s.name == "COPY" && s.count == 2),
+        "periodic copy target between copies",
+    )?;
+    check(apply_patch_bytes(&old, &patch)? == new, "periodic copy target between copies")
+}
+
+/// Unmatched new longer than `max_add_len` goes out as several ADD records
+/// none of them longer, with the matcher and with the streaming creator,
+/// and applies as the one region. A cap past a record's u32 length is held
+/// to it, checked without allocating anything near 4 GiB.
+#[test]
+fn max_add_len() -> Result<(), XDeltaError> {
+    let adds = |patch: &[u8]| -> Result<Vec<usize>, XDeltaError> {
+        let records = patch_records(patch)?;
+        let (mut lens, mut pos) = (Vec::new(), 0);
+        while pos < records.len() {
+            let (record, next) = crate::read_record(records, pos)?;
+            if let crate::Record::Add(data) = record {
+                lens.push(data.len());
+            }
+            pos = next;
+        }
+        Ok(lens)
+    };
+    // matched block by block, literals still pending go out at the cap, as
+    // they do when given up on (max_ratio) and all of new is sent as literals
+    let new = filler(5 << 20, 0xadd5);
+    let blocks = create_patch_with(&[], &new, &PatchOptions::new())?;
+    let given_up = PatchOptions::new().max_ratio(0.5);
+    let default = create_patch_with(&[], &new, &given_up)?;
+    let mib = create_patch_with(&[], &new, &given_up.clone().max_add_len(1 << 20))?;
+    check(
+        adds(&blocks)? == [crate::DEFAULT_MAX_ADD_LEN, (5 << 20) - crate::DEFAULT_MAX_ADD_LEN]
+            && adds(&default)? == [crate::DEFAULT_MAX_ADD_LEN, (5 << 20) - crate::DEFAULT_MAX_ADD_LEN]
+            && adds(&mib)? == [1 << 20; 5]
+            && apply_patch_bytes(&[], &blocks)? == new
+            && apply_patch_bytes(&[], &default)? == new
+            && apply_patch_bytes(&[], &mib)? == new,
+        "literals split at max_add_len",
+    )?;
+    // novel data one byte past the cap, slid over against an old that has
+    // blocks to look for, is exactly two ADDs, from the streaming creator too
+    let over = &new[..crate::DEFAULT_MAX_ADD_LEN + 1];
+    let old = &new[over.len()..over.len() + 16 * 1024];
+    let mut streamed = Vec::new();
+    crate::create_patch_streaming(old, over, 256, &mut streamed)?;
+    let capped = create_patch_with(old, &new[..1001], &plain().max_add_len(1000))?;
+    check(
+        adds(&create_patch_with(old, over, &plain())?)? == [crate::DEFAULT_MAX_ADD_LEN, 1]
+            && adds(&streamed)? == [crate::DEFAULT_MAX_ADD_LEN, 1]
+            && adds(&capped)? == [1000, 1]
+            && apply_patch_bytes(old, &capped)? == new[..1001],
+        "literals just over max_add_len",
+    )?;
+    // a cap under the block size splits each block's literals, and ADD_SMALL
+    // records count against it too
+    let small = create_patch_with(b"", &new[..1000], &PatchOptions::new().block_size(512).max_add_len(100))?;
+    check(
+        adds(&small)? == [100; 10] && apply_patch_bytes(b"", &small)? == new[..1000],
+        "short literals split at max_add_len",
+    )?;
+    check(
+        matches!(create_patch_with(b"", &new[..10], &PatchOptions::new().max_add_len(0)), Err(XDeltaError::InvalidArg(_))),
+        "max_add_len 0 refused",
+    )?;
+
+    #[cfg(target_pointer_width = "64")]
+    check(
+        PatchOptions::new().max_add_len(usize::MAX).effective_max_add_len() == crate::MAX_RECORD_LEN
+            && PatchOptions::new().max_add_len(crate::MAX_RECORD_LEN + 1).effective_max_add_len()
+                == crate::MAX_RECORD_LEN
+            && PatchOptions::new().max_add_len(crate::MAX_RECORD_LEN).effective_max_add_len() == crate::MAX_RECORD_LEN,
+        "max_add_len held to a record length",
+    )?;
+    let whole = create_patch_with(&[], &new, &given_up.clone().max_add_len(usize::MAX))?;
+    check(adds(&whole)? == [5 << 20], "max_add_len past the literals")?;
+
+    let c_opts = XdeltaOptions { max_ratio: 0.5, max_add_len: 1 << 20, ..XdeltaOptions::default() };
+    let mib = create_patch_with(&[], &new, &given_up.max_add_len(1 << 20).block_size(0))?;
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let empty: &[u8] = &[];
+    let rc = xdelta_create_patch_data_opts(empty.as_ptr(), 0, new.as_ptr(), new.len(), &c_opts, &mut data, &mut len);
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == mib.as_slice();
+    xdelta_free_data(data);
+    check(same, "max_add_len through XdeltaOptions")
+}
+
+#[test]
+fn aligned() -> Result<(), XDeltaError> {
+    // a disk image of 256 sectors, with 8 new sectors inserted after the
+    // 100th and the 200th rewritten: matching only at sector boundaries
+    // looks up one window a sector rather than one a byte of the new ones
+    let old = filler(256 * 512, 0x5ec7);
+    let mut new = old[..100 * 512].to_vec();
+    new.extend(filler(8 * 512, 0x0b1e));
+    new.extend_from_slice(&old[100 * 512..]);
+    new[208 * 512 + 7] ^= 0xff;
+
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, &old[..], 512, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let lookups = |opts: &PatchOptions| -> Result<(usize, Vec<u8>), XDeltaError> {
+        let (mut lookups, mut patch) = (0usize, Vec::new());
+        let mut on_weak_lookup = || lookups += 1;
+        let hooks = MatchHooks { on_weak_lookup: Some(&mut on_weak_lookup), ..MatchHooks::default() };
+        match_blocks(&old[..], &new, opts, &sigs, &mut patch, &mut Vec::new(), hooks)?;
+        Ok((lookups, with_header(&patch)))
+    };
+    let plain = PatchOptions::new().block_size(512);
+    let (sliding, slid) = lookups(&plain)?;
+    let (aligned, patch) = lookups(&plain.clone().aligned(true))?;
+    check(aligned <= new.len() / 512 + 1 && aligned * 8 < sliding, "aligned matching looks up a window a sector")?;
+    check(
+        patch.len() <= slid.len() && apply_patch_bytes(&old, &patch)? == new,
+        "aligned patch of a sector-aligned edit",
+    )?;
+
+    // an insertion of a few bytes shifts old off the sectors: still correct,
+    // but past it nothing matches
+    let mut shifted = old[..1000].to_vec();
+    shifted.extend_from_slice(b"xyz");
+    shifted.extend_from_slice(&old[1000..]);
+    let opts = plain.clone().aligned(true).alignment(256);
+    let patch = create_patch_with(&old, &shifted, &opts)?;
+    check(
+        apply_patch_bytes(&old, &patch)? == shifted && patch.len() > create_patch_with(&old, &shifted, &plain)?.len() * 100,
+        "aligned patch misses unaligned matches",
+    )?;
+    let refused = create_patch_with(&old, &new, &plain.clone().aligned(true).alignment(0));
+    check(matches!(refused, Err(XDeltaError::InvalidArg(_))), "alignment(0)")?;
+
+    let c_opts =
+        XdeltaOptions { block_size: 512, flags: XDELTA_OPT_ALIGNED, alignment: 256, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_data_opts(
+        old.as_ptr(),
+        old.len(),
+        shifted.as_ptr(),
+        shifted.len(),
+        &c_opts,
+        &mut data,
+        &mut len,
+    );
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == patch;
+    xdelta_free_data(data);
+    check(same, "aligned through XdeltaOptions")
+}
+
+#[test]
+fn cancel_flag() -> Result<(), XDeltaError> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+
+    // 1 MiB of new none of which old has, so the matcher slides through
+    // all of it; another thread raises the flag once it is past the first
+    // cancellation check, and the next one stops it
+    let old = filler(64 * 1024, 0xca11);
+    let new = filler(1 << 20, 0x0ff5);
+    let opts = PatchOptions::new().block_size(64);
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, &old[..], 64, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let flag = AtomicBool::new(false);
+    let mut reports = Vec::new();
+    let r = std::thread::scope(|scope| {
+        let (request, requested) = mpsc::channel::<()>();
+        let (done, wait) = mpsc::channel::<()>();
+        let flag = &flag;
+        scope.spawn(move || {
+            if requested.recv().is_ok() {
+                flag.store(true, Ordering::Relaxed);
+                let _ = done.send(());
+            }
+        });
+        let mut on_progress = |pos: usize, _: usize| {
+            reports.push(pos);
+            if pos > 0 && request.send(()).is_ok() {
+                let _ = wait.recv();
+            }
+        };
+        let is_cancelled = || flag.load(Ordering::Relaxed);
+        let hooks = MatchHooks {
+            on_progress: Some(&mut on_progress),
+            is_cancelled: Some(&is_cancelled),
+            ..MatchHooks::default()
+        };
+        match_blocks(&old[..], &new, &opts, &sigs, &mut Vec::new(), &mut Vec::new(), hooks)
+    });
+    check(
+        matches!(r, Err(XDeltaError::Cancelled)) && reports.len() == 2 && reports[1] < new.len() / 8,
+        "flag raised by another thread cancels at the next check",
+    )?;
+
+    flag.store(true, Ordering::Relaxed);
+    let cancelled = crate::create_patch_cancelable(&old, &new, &opts, &flag);
+    flag.store(false, Ordering::Relaxed);
+    let patch = crate::create_patch_cancelable(&old, &new, &opts, &flag)?;
+    check(
+        matches!(cancelled, Err(XDeltaError::Cancelled)) && patch == create_patch_with(&old, &new, &opts)?,
+        "create_patch_cancelable",
+    )?;
+
+    let create = |cancel_flag: *const c_int| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc = crate::xdelta_create_patch_data_cancelable(
+            old.as_ptr(),
+            old.len(),
+            new.as_ptr(),
+            new.len(),
+            &mut data,
+            &mut len,
+            64,
+            cancel_flag,
+        );
+        let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == patch;
+        xdelta_free_data(data);
+        (rc, same, data.is_null())
+    };
+    check(
+        create(&1) == (crate::XDELTA_ERR_CANCELLED, false, true)
+            && create(&0).1
+            && create(std::ptr::null()).1,
+        "xdelta_create_patch_data_cancelable",
+    )
+}
+
+/// A reader handing over at most `step` bytes of `data` per call, and an
+/// error once `fail_at` bytes have been read.
+struct Trickle<'a> {
+    data: &'a [u8],
+    step: usize,
+    fail_at: usize,
+}
+
+impl std::io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.fail_at == 0 {
+            return Err(std::io::Error::other("read failed"));
+        }
+        let n = self.data.len().min(self.step).min(buf.len()).min(self.fail_at);
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        self.fail_at -= n;
+        Ok(n)
+    }
+}
+
+/// `Trickle` behind the read callback of `xdelta_create_patch_streaming`,
+/// with the patch written collected next to it.
+struct StreamCtx<'a> {
+    new: Trickle<'a>,
+    patch: Vec<u8>,
+}
+
+extern "C" fn read_next(buf: *mut u8, len: usize, got: *mut usize, ctx: *mut std::ffi::c_void) -> c_int {
+    use std::io::Read;
+
+    let ctx = unsafe { &mut *(ctx as *mut StreamCtx) };
+    match ctx.new.read(unsafe { std::slice::from_raw_parts_mut(buf, len) }) {
+        Ok(n) => {
+            unsafe { *got = n };
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+extern "C" fn write_next(data: *const u8, len: usize, ctx: *mut std::ffi::c_void) -> c_int {
+    let ctx = unsafe { &mut *(ctx as *mut StreamCtx) };
+    ctx.patch.extend_from_slice(unsafe { std::slice::from_raw_parts(data, len) });
+    0
+}
+
+/// `create_patch_streaming` over a new read 7 bytes at a time writes the
+/// same patch as over one read whole, with the records of `create_patch`,
+/// also when new outgrows the chunk of records collected before a write and
+/// a RUN spans it; the FFI writes it too, and a failing read stops both.
+#[test]
+fn create_streaming() -> Result<(), XDeltaError> {
+    use crate::create_patch_streaming;
+    use crate::stream_create::xdelta_create_patch_streaming;
+
+    let (old, new) = fixture();
+    let big_old = filler(300_000, 31);
+    // runs of zeros between bits of noise, so flushed literals often end in a RUN
+    let runs: Vec<u8> = (0..1500).flat_map(|i| [filler(40, 100 + i), vec![0; 88]].concat()).collect();
+    let big_new = [&big_old[1000..150_000], &runs, &big_old[150_000..]].concat();
+    let cases: [(&[u8], &[u8], usize); 6] = [
+        (old, new, 256),
+        (old, new, 0),
+        (old, &new[..new.len() - 7], 7),
+        (&big_old, &big_new, 64),
+        (b"", new, 16),
+        (old, b"", 16),
+    ];
+    for (i, &(old, new, block_size)) in cases.iter().enumerate() {
+        let streamed = |step: usize| {
+            let mut patch = Vec::new();
+            let reader = Trickle { data: new, step, fail_at: usize::MAX };
+            create_patch_streaming(old, reader, block_size, &mut patch).map(|()| patch)
+        };
+        let trickled = streamed(7)?;
+        let whole = streamed(usize::MAX)?;
+        let in_memory = create_patch_with(old, new, &PatchOptions::new().block_size(block_size))?;
+        let resolved = if block_size == 0 { auto_block_size(old.len()) } else { block_size };
+        check(
+            trickled == whole
+                && patch_records(&trickled)? == patch_records(&in_memory)?
+                && apply_patch_bytes(old, &trickled)? == new
+                && patch_info(&trickled)?.block_size == resolved as u32,
+            &format!("streaming create, case {}", i),
+        )?;
+
+        let mut ctx = StreamCtx { new: Trickle { data: new, step: 7, fail_at: usize::MAX }, patch: Vec::new() };
+        let ctx_ptr = &mut ctx as *mut StreamCtx as *mut std::ffi::c_void;
+        let rc = xdelta_create_patch_streaming(
+            old.as_ptr(),
+            old.len(),
+            Some(read_next),
+            Some(write_next),
+            ctx_ptr,
+            block_size as u32,
+        );
+        check(rc == XDELTA_OK && ctx.patch == trickled, &format!("streaming create through FFI, case {}", i))?;
+    }
+
+    let failing = Trickle { data: &big_new, step: 7, fail_at: 100_000 };
+    let failed = create_patch_streaming(&big_old, failing, 64, &mut Vec::new());
+    let mut ctx = StreamCtx { new: Trickle { data: new, step: 7, fail_at: 100 }, patch: Vec::new() };
+    let ctx_ptr = &mut ctx as *mut StreamCtx as *mut std::ffi::c_void;
+    let rc = xdelta_create_patch_streaming(old.as_ptr(), old.len(), Some(read_next), Some(write_next), ctx_ptr, 64);
+    let null = xdelta_create_patch_streaming(old.as_ptr(), old.len(), None, Some(write_next), ctx_ptr, 64);
+    check(
+        matches!(failed, Err(XDeltaError::Io(_))) && rc == XDELTA_ERR_IO && null == XDELTA_ERR_NULL_POINTER,
+        "streaming create read errors",
+    )
+}
+
+/// Old == new is a bare header that every apply path answers with old.
+#[test]
+fn identical_inputs() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, old, &plain())?;
+    check(patch.len() == PATCH_HEADER_LEN, "identity patch size")?;
+    let mut streamed = Vec::new();
+    apply_streaming(&mut &old[..], &patch, &mut streamed, &ApplyOptions::new())?;
+    let mut feed = ApplyFeed::new(old, Vec::new(), VerifyOld::None);
+    feed.feed(&patch)?;
+    let mut exact = Vec::new();
+    apply_to(old, &patch, ApplyOutput::ExactReserve(&mut exact))?;
+    check(
+        apply_patch_bytes(old, &patch)? == old
+            && streamed == old
+            && feed.finish()? == old
+            && exact == old
+            && apply_range_bytes(old, &patch, 100, 50)? == old[100..150]
+            && apply_patch_bytes(old, &apply_then_diff(old, &patch, new, &plain())?)? == new,
+        "identity patch",
+    )?;
+    check(create_patch_with(old, old, &full())?.len() > PATCH_HEADER_LEN, "identity patch with old hash")
+}
+
+/// An unchanged 10 MiB prefix is one COPY spanning every block, not one per block.
+#[test]
+fn copy_extended_across_blocks() -> Result<(), XDeltaError> {
+    let big = filler(10 << 20, 13);
+    let mut grown = big.clone();
+    grown.extend_from_slice(&filler(100, 14));
+    let patch = create_patch_with(&big, &grown, &plain())?;
+    check(
+        apply_patch_bytes(&big, &patch)? == grown
+            && opcode_histogram(&patch)?.iter().any(|s| s.name == "COPY" && s.count == 1 && s.output_bytes == 10 << 20),
+        "copy extended across blocks",
+    )
+}
+
+/// Sliding a 17 MiB block past ten leading bytes rolls a window whose
+/// `len * prev` wraps a u32, and still finds old one slide in.
+#[test]
+fn slide_past_u32_weight() -> Result<(), XDeltaError> {
+    let old = filler(17 << 20, 15);
+    let new = [&[0xff; 10][..], &old].concat();
+    let patch = crate::create_patch(&old, &new, 17 << 20)?;
+    let records = parsed_records(&patch)?;
+    check(
+        matches!(records[..], [crate::Record::Add(literal), crate::Record::Copy { offset: 0, len }]
+            if literal == [0xff; 10] && len == 17 << 20)
+            && apply_patch_bytes(&old, &patch)? == new,
+        "slide to a 17 MiB block",
+    )
+}
+
+/// A long run of one byte is a single RUN record, not a megabyte of ADD.
+#[test]
+fn run_record() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let zeros = vec![0u8; 1 << 20];
+    let patch = create_patch_with(old, &zeros, &plain())?;
+    check(patch_records(&patch)?.len() == 6, "run record size")?;
+    check(apply_patch_bytes(old, &patch)? == zeros, "run record")
+}
+
+/// A tail repeating a record old lacks is one COPY_TARGET reaching into
+/// what it writes itself, rather than ADDs; applying it needs the output
+/// kept, so the streaming, ranged and split paths refuse it.
+#[test]
+fn copy_target() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let record = filler(300, 15);
+    let mut repeated = old.to_vec();
+    for _ in 0..200 {
+        repeated.extend_from_slice(&record);
+    }
+    let without = create_patch_with(old, &repeated, &plain())?;
+    let patch = create_patch_with(old, &repeated, &plain().copy_target(true))?;
+    check(
+        patch.len() * 10 < without.len()
+            && opcode_histogram(&patch)?.iter().any(|s| s.name == "COPY_TARGET" && s.count == 1),
+        "copy target size",
+    )?;
+    let (mut exact, mut buf) = (Vec::new(), vec![0u8; repeated.len()]);
+    apply_to(old, &patch, ApplyOutput::ExactReserve(&mut exact))?;
+    apply_to(old, &patch, ApplyOutput::IntoBuffer(&mut buf))?;
+    check(apply_patch_bytes(old, &patch)? == repeated && exact == repeated && buf == repeated, "copy target")?;
+    check(
+        matches!(apply_to(old, &patch, ApplyOutput::Streaming(&mut Vec::new())), Err(XDeltaError::InvalidArg(_)))
+            && matches!(apply_range_bytes(old, &patch, old.len() as u64 + 600, 8), Err(XDeltaError::InvalidArg(_)))
+            && split_patch(&patch, 3).is_err(),
+        "copy target needs the output",
+    )
+}
+
+/// Sampling tells a worthwhile patch from unrelated data.
+#[test]
+fn should_patch_samples() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    check(
+        should_patch(old, new, 256, 0.5)? && !should_patch(old, &filler(new.len(), 3), 256, 0.5)?,
+        "should patch",
+    )
+}
+
+/// Scattered single-byte changes in a same-length file make one small XOR_DELTA.
+#[test]
+fn xor_delta() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let mut patched = old.to_vec();
+    for i in 0..10 {
+        patched[i * 1601 + 7] ^= 0x5a;
+    }
+    let patch = create_patch_with(old, &patched, &plain().xor_delta(true))?;
+    check(apply_patch_bytes(old, &patch)? == patched && patch_records(&patch)?.len() < 64, "xor delta")
+}
+
+/// A further version diffs against a patch's output without building it.
+#[test]
+fn diff_against_patch_output() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let mut newer = new.to_vec();
+    newer[100..200].fill(0x22);
+    let patch = create_patch_with(old, new, &full())?;
+    let next = apply_then_diff(old, &patch, &newer, &plain())?;
+    check(apply_patch_bytes(new, &next)? == newer, "apply then diff")
+}
+
+/// A sparse old with a described hole, which must read as zeros.
+#[test]
+fn sparse_old() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let sparse = SparseOld::new(64 * 1024, &[(0, &old[..4096]), (40 * 1024, &old[8192..])])?;
+    let mut filled = old[..4096].to_vec();
+    filled.resize(20 * 1024, 0);
+    filled.extend_from_slice(&old[8192..10_000]);
+    let patch = create_patch_sparse(&sparse, &filled, &plain())?;
+    check(apply_sparse(&sparse, &patch)? == filled, "sparse old")
+}
+
+/// A block size longer than a record can hold is refused.
+#[test]
+fn block_size_over_record_len() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    check(
+        matches!(
+            create_patch_with(old, new, &PatchOptions::new().block_size(crate::MAX_RECORD_LEN + 1)),
+            Err(XDeltaError::InvalidArg(_))
+        ),
+        "block size over a record length",
+    )
+}
+
+/// Two halves of old swapped in new are one COPY each, the second reaching
+/// back to the start of old, however many blocks either spans.
+#[test]
+fn transposition() -> Result<(), XDeltaError> {
+    let old = filler(64 * 1024, 0x5a9);
+    let new = [&old[32 * 1024..], &old[..32 * 1024]].concat();
+    let patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(256))?;
+    let copies: Vec<(u64, u32)> = parsed_records(&patch)?
+        .iter()
+        .map(|record| match *record {
+            crate::Record::Copy { offset, len } => (offset, len),
+            _ => (u64::MAX, 0),
+        })
+        .collect();
+    check(
+        copies == [(32 * 1024, 32 * 1024), (0, 32 * 1024)] && apply_patch_bytes(&old, &patch)? == new,
+        "swapped regions as two COPYs",
+    )
+}
+
+/// A block of old with three bytes changed goes out as one DIFF record of
+/// three deltas instead of 256 literal bytes, through
+/// `xdelta_create_patch_data_diff` too; a block changed throughout stays literal.
+#[test]
+fn near_miss_diff() -> Result<(), XDeltaError> {
+    use crate::xdelta_create_patch_data_diff;
+
+    let old = filler(64 * 256, 0xd1ff);
+    let mut new = old.clone();
+    for at in [10 * 256 + 3, 10 * 256 + 40, 10 * 256 + 200] {
+        new[at] ^= 0x40;
+    }
+    let plain = PatchOptions::new().block_size(256);
+    let without = create_patch_with(&old, &new, &plain)?;
+    let with = create_patch_with(&old, &new, &plain.clone().near_miss_diff(true))?;
+    let records = parsed_records(&with)?;
+    let diffs: Vec<usize> = records
+        .iter()
+        .filter_map(|record| match record {
+            crate::Record::Diff { deltas, .. } => Some(deltas.len() / 5),
+            _ => None,
+        })
+        .collect();
+    check(
+        diffs == [3]
+            && !records.iter().any(|record| matches!(record, crate::Record::Add(_)))
+            && with.len() + 200 < without.len()
+            && apply_patch_bytes(&old, &with)? == new,
+        "near-miss block as a DIFF record",
+    )?;
+
+    let mut rewritten = old.clone();
+    rewritten[10 * 256..11 * 256].copy_from_slice(&filler(256, 0x0dd));
+    let rewrite = create_patch_with(&old, &rewritten, &plain.clone().near_miss_diff(true))?;
+    check(
+        !parsed_records(&rewrite)?.iter().any(|record| matches!(record, crate::Record::Diff { .. }))
+            && apply_patch_bytes(&old, &rewrite)? == rewritten,
+        "rewritten block stays literal",
+    )?;
+
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_data_diff(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &mut data, &mut len, 256);
+    check(taken(rc, data, len) == (XDELTA_OK, with), "xdelta_create_patch_data_diff")
+}
+
+/// `PatchOptions` defaults make `create_patch`'s patch, setting an option
+/// on a clone leaves the original as it was, and combinations of options
+/// all round trip.
+#[test]
+fn patch_options() -> Result<(), XDeltaError> {
+    use crate::create_patch;
+
+    let (old, new) = fixture();
+    let base = PatchOptions::new().block_size(128);
+    let tuned = base.clone().near_miss_diff(true).old_hash(true).index_granularity(512);
+    check(
+        create_patch_with(old, new, &base)? == create_patch(old, new, 128)?
+            && create_patch_with(old, new, &PatchOptions::default())?
+                == create_patch_with(old, new, &PatchOptions::new())?
+            && create_patch_with(old, new, &tuned)? != create_patch_with(old, new, &base)?,
+        "builder defaults",
+    )?;
+    for opts in [
+        tuned.clone(),
+        tuned.clone().const_table(true).record_crc(true),
+        base.clone().quality(Quality::Best).copy_target(true),
+        base.clone().content_addressed(true).output_check(true),
+        base.clone().weak_checksum(WeakAlgo::Adler32).tail_policy(TailPolicy::Pad),
+    ] {
+        let patch = create_patch_with(old, new, &opts)?;
+        let out = if opts.content_addressed {
+            let store: HashMap<[u8; 32], &[u8]> =
+                old.chunks(128).map(|block| (block_strong_hash(block, HashAlgo::Sha256), block)).collect();
+            crate::apply_cas(&patch, |hash| store.get(hash).copied())?
+        } else {
+            apply_patch_bytes(old, &patch)?
+        };
+        check(out == new, "builder combinations round trip")?;
+    }
+    Ok(())
+}
+
+/// A token cancelled from another thread stops a running create at its next
+/// check, every clone sees it, and an apply given a cancelled token writes
+/// nothing; the FFI token does the same through `XdeltaOptions::cancel`.
+#[test]
+fn cancel_token() -> Result<(), XDeltaError> {
+    use crate::cancel::{xdelta_cancel_token_cancel, xdelta_cancel_token_free, xdelta_cancel_token_new};
+    use crate::stream::{xdelta_apply_patch_callbacks, XDELTA_VERIFY_NONE};
+    use crate::{CancelToken, XDELTA_ERR_CANCELLED};
+    use std::sync::mpsc;
+
+    let (old, new) = fixture();
+    // as with the flag: 1 MiB none of which old has, cancelled once past
+    // the first check
+    let token = CancelToken::new();
+    let unmatched = filler(1 << 20, 0x70c);
+    let opts = PatchOptions::new().block_size(64).cancel_token(&token);
+    let mut reports = Vec::new();
+    let r = std::thread::scope(|scope| {
+        let (request, requested) = mpsc::channel::<()>();
+        let (done, wait) = mpsc::channel::<()>();
+        let clone = token.clone();
+        scope.spawn(move || {
+            if requested.recv().is_ok() {
+                clone.cancel();
+                let _ = done.send(());
+            }
+        });
+        create_patch_with_progress(old, &unmatched, &opts, |pos, _| {
+            reports.push(pos);
+            if pos > 0 && request.send(()).is_ok() {
+                let _ = wait.recv();
+            }
+        })
+    });
+    check(
+        matches!(r, Err(XDeltaError::Cancelled)) && reports.len() == 2 && token.is_cancelled(),
+        "token cancelled by another thread stops create",
+    )?;
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(64))?;
+    let mut out = Vec::new();
+    let applied = apply_streaming(&mut &old[..], &patch, &mut out, &ApplyOptions::new().cancel_token(&token));
+    check(matches!(applied, Err(XDeltaError::Cancelled)) && out.is_empty(), "cancelled token stops apply")?;
+
+    let c_token = xdelta_cancel_token_new();
+    let opts = XdeltaOptions { block_size: 64, cancel: c_token, ..XdeltaOptions::default() };
+    let create = || {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc =
+            xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &opts, &mut data, &mut len);
+        taken(rc, data, len)
+    };
+    let before = create();
+    xdelta_cancel_token_cancel(c_token);
+    let after = create();
+    let mut io = CallbackIo { old, out: Vec::new(), writes: Vec::new() };
+    let ctx = &mut io as *mut CallbackIo as *mut std::ffi::c_void;
+    let rc = xdelta_apply_patch_callbacks(
+        old.len() as u64,
+        Some(read_io),
+        patch.as_ptr(),
+        patch.len(),
+        Some(write_io),
+        ctx,
+        XDELTA_VERIFY_NONE,
+        c_token,
+    );
+    xdelta_cancel_token_free(c_token);
+    xdelta_cancel_token_cancel(std::ptr::null());
+    xdelta_cancel_token_free(std::ptr::null_mut());
+    check(
+        before == (XDELTA_OK, patch)
+            && after.0 == XDELTA_ERR_CANCELLED
+            && rc == XDELTA_ERR_CANCELLED
+            && io.out.is_empty(),
+        "FFI cancel token",
+    )
+}
+
+/// The estimate covers what creation demonstrably holds, the signature map
+/// of old and the patch, within a small factor, and grows with smaller
+/// blocks and more new; the FFI gives the same figure.
+#[test]
+fn memory_estimate() -> Result<(), XDeltaError> {
+    use crate::{estimate_memory, xdelta_estimate_memory};
+
+    let (old, _) = fixture();
+    let new = filler(64 * 1024, 0xe57);
+    let opts = PatchOptions::new().block_size(256);
+    let mut sigs: HashMap<u32, Vec<SigEntry>> = HashMap::new();
+    build_signatures(&mut sigs, old, 256, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let map = sigs.capacity() * (4 + std::mem::size_of::<Vec<SigEntry>>());
+    let entries: usize = sigs.values().map(|bucket| bucket.capacity() * std::mem::size_of::<SigEntry>()).sum();
+    let held = (map + entries + create_patch_with(old, &new, &opts)?.len()) as u64;
+    let estimate = estimate_memory(old.len() as u64, new.len() as u64, &opts);
+    let (old_len, new_len) = (old.len() as u64, new.len() as u64);
+    check(held <= estimate && estimate <= 8 * held, "estimate bounds what creation holds")?;
+    check(
+        estimate_memory(old_len, new_len, &PatchOptions::new().block_size(64)) > estimate
+            && estimate_memory(old_len, 2 * new_len, &opts) > estimate
+            && estimate_memory(old_len, new_len, &opts.clone().old_hash(true)) > estimate,
+        "estimate grows with the work",
+    )?;
+    let c_opts = XdeltaOptions { size: 2, ..XdeltaOptions::default() };
+    check(
+        xdelta_estimate_memory(old_len, new_len, 256, std::ptr::null()) == estimate
+            && xdelta_estimate_memory(
+                old_len,
+                new_len,
+                0,
+                &XdeltaOptions { block_size: 256, ..XdeltaOptions::default() },
+            ) == estimate
+            && xdelta_estimate_memory(old_len, new_len, 256, &c_opts) == 0,
+        "xdelta_estimate_memory",
+    )
+}
+
+/// A context reused across many diffs, small, identical and empty, makes
+/// the patches fresh calls make, through `xdelta_create_patch_ctx` too.
+#[test]
+fn diff_context() -> Result<(), XDeltaError> {
+    use crate::context::{xdelta_context_free, xdelta_context_new, xdelta_create_patch_ctx};
+    use crate::DiffContext;
+
+    let mut ctx = DiffContext::new();
+    let c_ctx = xdelta_context_new();
+    for i in 0..24u32 {
+        let old = filler(2000 + i as usize * 37, i);
+        let mut new = old.clone();
+        if i % 4 != 0 {
+            new.truncate(old.len() - i as usize * 11);
+            new.extend_from_slice(&filler(i as usize * 5, i + 100));
+        }
+        if i % 8 == 7 {
+            new.clear();
+        }
+        let opts = PatchOptions::new().block_size(64);
+        let fresh = create_patch_with(&old, &new, &opts)?;
+        check(ctx.create_patch(&old, &new, &opts)? == fresh, "context patch")?;
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc =
+            xdelta_create_patch_ctx(c_ctx, old.as_ptr(), old.len(), new.as_ptr(), new.len(), 64, &mut data, &mut len);
+        check(taken(rc, data, len) == (XDELTA_OK, fresh), "xdelta_create_patch_ctx")?;
+    }
+    xdelta_context_free(c_ctx);
+    xdelta_context_free(std::ptr::null_mut());
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_ctx(std::ptr::null_mut(), b"a".as_ptr(), 1, b"a".as_ptr(), 1, 64, &mut data, &mut len);
+    check(rc == XDELTA_ERR_NULL_POINTER, "null context")
+}
+
+/// Only blocks the bitmap marks dirty are looked at: a clean block is
+/// copied unread even where new differs, a dirty one is matched, and blocks
+/// past the bitmap count as dirty. `XdeltaOptions::dirty_bitmap` does the same.
+#[test]
+fn dirty_blocks() -> Result<(), XDeltaError> {
+    let old = filler(32 * 256, 0xd127);
+    let mut new = old.clone();
+    new[5 * 256..6 * 256].copy_from_slice(&filler(256, 0xd128));
+    new[20 * 256 + 9] ^= 1;
+    // block 5 dirty, block 20 (wrongly) clean
+    let mut bitmap = [0u8; 4];
+    bitmap[0] = 1 << 5;
+    let opts = PatchOptions::new().block_size(256).dirty_blocks(&bitmap);
+    let patch = create_patch_with(&old, &new, &opts)?;
+    let mut unread = new.clone();
+    unread[20 * 256 + 9] ^= 1;
+    check(apply_patch_bytes(&old, &patch)? == unread, "clean blocks copied, dirty ones matched")?;
+
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, &old[..], 256, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let mut lookups = 0usize;
+    let mut on_weak_lookup = || lookups += 1;
+    let hooks = MatchHooks { on_weak_lookup: Some(&mut on_weak_lookup), ..MatchHooks::default() };
+    match_blocks(&old[..], &new, &opts, &sigs, &mut Vec::new(), &mut Vec::new(), hooks)?;
+    check(lookups > 0 && lookups <= 256 + 1, "lookups only in the dirty block")?;
+
+    // a bitmap covering blocks 0 to 7 only: block 20 is dirty and matched
+    let short = PatchOptions::new().block_size(256).dirty_blocks(&bitmap[..1]);
+    let patch = create_patch_with(&old, &new, &short)?;
+    check(apply_patch_bytes(&old, &patch)? == new, "blocks past the bitmap are dirty")?;
+
+    let c_opts = XdeltaOptions {
+        block_size: 256,
+        dirty_bitmap: bitmap.as_ptr(),
+        dirty_bitmap_len: bitmap.len(),
+        ..XdeltaOptions::default()
+    };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &c_opts, &mut data, &mut len);
+    check(
+        taken(rc, data, len) == (XDELTA_OK, create_patch_with(&old, &new, &opts)?),
+        "dirty bitmap through XdeltaOptions",
+    )
+}
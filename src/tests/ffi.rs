@@ -0,0 +1,502 @@
+// src/tests/ffi.rs
+//! The C entry points: status codes, caller buffers, batches and the
+//! allocator.
+
+use super::*;
+
+/// A failing call leaves its message and length for this thread; clearing
+/// takes both away, and the next failure sets them again.
+#[test]
+fn last_error() -> Result<(), XDeltaError> {
+    let fail = || {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+        xdelta_apply_patch_data(b"old".as_ptr(), 3, b"XDR1".as_ptr(), 4, &mut data, &mut len)
+    };
+    let set = fail() == XDELTA_ERR_MALFORMED_PATCH && !xdelta_last_error().is_null();
+    let message = unsafe { std::ffi::CStr::from_ptr(xdelta_last_error()) }.to_bytes().to_vec();
+    let copied = unsafe { std::slice::from_raw_parts(xdelta_last_error().cast::<u8>(), xdelta_last_error_len()) };
+    check(set && !message.is_empty() && copied == message, "last error and its length")?;
+
+    xdelta_clear_last_error();
+    let mut code = XDELTA_ERR_IO;
+    let cleared = xdelta_last_error().is_null()
+        && xdelta_last_error_len() == 0
+        && xdelta_last_error_detail(&mut code).is_null()
+        && code == XDELTA_OK;
+    xdelta_clear_last_error();
+    check(cleared && xdelta_last_error().is_null(), "cleared last error")?;
+    check(fail() != XDELTA_OK && xdelta_last_error_len() == message.len(), "last error set again after clearing")
+}
+
+/// Every pairing of an empty and a non-empty old and new round-trips, with
+/// an automatic and a fixed block size and through the C functions, whose
+/// empty output is still a buffer to free; an empty new gives a patch with
+/// no output records. An empty old has no signatures, and an empty patch
+/// applies to nothing. The C functions are handed an empty input as NULL.
+#[test]
+fn empty_inputs() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let ptr = |data: &[u8]| if data.is_empty() { std::ptr::null() } else { data.as_ptr() };
+    let cases: [(&[u8], &[u8]); 4] = [(&[], &[]), (&[], new), (old, &[]), (old, new)];
+    for (old, new) in cases {
+        for opts in [
+            PatchOptions::new(),
+            PatchOptions::new().block_size(16).near_miss_diff(true),
+            PatchOptions::new().block_size(256).record_crc(true).output_check(true),
+        ] {
+            let patch = create_patch_with(old, new, &opts)?;
+            let records = crate::without_record_crcs(&patch)?;
+            let empty_records = crate::is_identity(&patch) || crate::output_len(patch_records(&records)?)? == 0;
+            check(
+                apply_patch_bytes(old, &patch)? == new
+                    && validate_patch(&patch).is_ok()
+                    && (!new.is_empty() || empty_records),
+                "patch between empty and non-empty inputs",
+            )?;
+        }
+
+        let (mut patch, mut patch_len) = (std::ptr::null_mut(), 0usize);
+        let rc = xdelta_create_patch_data(ptr(old), old.len(), ptr(new), new.len(), &mut patch, &mut patch_len, 0);
+        let (mut out, mut out_len) = (std::ptr::null_mut(), usize::MAX);
+        let applied = rc == XDELTA_OK
+            && xdelta_apply_patch_data(ptr(old), old.len(), patch, patch_len, &mut out, &mut out_len) == XDELTA_OK;
+        let same = applied && !out.is_null() && unsafe { std::slice::from_raw_parts(out, out_len) } == new;
+        xdelta_free_data(patch);
+        xdelta_free_data(out);
+        check(same, "C patch between empty and non-empty inputs")?;
+    }
+
+    let mut sigs = HashMap::new();
+    for tail in [TailPolicy::AsIs, TailPolicy::Pad, TailPolicy::Skip] {
+        build_signatures(&mut sigs, &[][..], 16, tail, HashAlgo::Sha256, WeakAlgo::Rolling);
+        check(sigs.is_empty(), "signatures of an empty old")?;
+    }
+    build_signatures(&mut sigs, old, 0, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    check(sigs.is_empty(), "signatures in blocks of 0 bytes")?;
+
+    let (mut out, mut out_len) = (std::ptr::null_mut(), usize::MAX);
+    let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), std::ptr::null(), 0, &mut out, &mut out_len);
+    let empty = rc == XDELTA_OK && !out.is_null() && out_len == 0;
+    xdelta_free_data(out);
+    check(apply_patch_bytes(old, &[])?.is_empty() && empty, "an empty patch")
+}
+
+/// An empty input may be NULL on every entry point, as it already could
+/// for `xdelta_block_strong_hash`; NULL with a length is still refused.
+#[test]
+fn null_buffers() -> Result<(), XDeltaError> {
+    let null = std::ptr::null();
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let mut with_output = |call: &mut dyn FnMut(*mut *mut u8, *mut usize) -> c_int| {
+        let rc = call(&mut data, &mut len);
+        let out = taken(rc, data, len);
+        (data, len) = (std::ptr::null_mut(), 0);
+        out
+    };
+    let abc = b"abc".as_ptr();
+    let patch = with_output(&mut |d, l| xdelta_create_patch_data(null, 0, abc, 3, d, l, 0));
+    check(patch == (XDELTA_OK, crate::create_patch(&[], b"abc", 0)?), "create from a NULL empty old")?;
+    let applied = with_output(&mut |d, l| xdelta_apply_patch_data(null, 0, patch.1.as_ptr(), patch.1.len(), d, l));
+    check(applied == (XDELTA_OK, b"abc".to_vec()), "apply to a NULL empty old")?;
+    let empty = with_output(&mut |d, l| xdelta_create_patch_data(abc, 3, null, 0, d, l, 0));
+    check(empty.0 == XDELTA_OK && apply_patch_bytes(b"abc", &empty.1)?.is_empty(), "create to a NULL empty new")?;
+    let sig = with_output(&mut |d, l| xdelta_build_signature(null, 0, 16, d, l));
+    check(sig.0 == XDELTA_OK, "signature of a NULL empty old")?;
+    let dict = with_output(&mut |d, l| xdelta_create_patch_with_dictionary(null, 0, abc, 3, d, l, 16));
+    check(dict.0 == XDELTA_OK, "NULL empty dictionary")?;
+
+    let refused = [
+        with_output(&mut |d, l| xdelta_create_patch_data(null, 3, abc, 3, d, l, 0)).0,
+        with_output(&mut |d, l| xdelta_apply_patch_data(abc, 3, null, 1, d, l)).0,
+        with_output(&mut |d, l| xdelta_build_signature(null, 3, 16, d, l)).0,
+        xdelta_block_strong_hash(null, 3, 0, [0u8; 32].as_mut_ptr()),
+    ];
+    check(refused.iter().all(|&rc| rc == XDELTA_ERR_NULL_POINTER), "NULL with a length")
+}
+
+/// A batch written into plain entries reports each pair on its own: the
+/// pairs with a null pointer fail where they are, the rest get the patch
+/// `create_patch` makes, and one free releases them all.
+#[test]
+fn patch_batch_entries() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let pair = |old: &[u8], new: &[u8]| XdeltaPatchPair {
+        old_data: old.as_ptr(),
+        old_len: old.len(),
+        new_data: new.as_ptr(),
+        new_len: new.len(),
+    };
+    let pairs = [
+        pair(old, new),
+        XdeltaPatchPair { new_data: std::ptr::null(), ..pair(old, new) },
+        pair(new, old),
+        pair(&[], new),
+        XdeltaPatchPair { old_data: std::ptr::null(), ..pair(new, old) },
+        pair(old, &[]),
+    ];
+    let expected: Vec<Option<(&[u8], &[u8])>> =
+        vec![Some((old, new)), None, Some((new, old)), Some((&[], new)), None, Some((old, &[]))];
+    let entry = || XdeltaBatchEntry { status: 1, patch_data: std::ptr::null_mut(), patch_len: usize::MAX };
+    let mut entries: Vec<XdeltaBatchEntry> = (0..pairs.len()).map(|_| entry()).collect();
+    let rc = xdelta_create_patch_batch(pairs.as_ptr(), pairs.len(), entries.as_mut_ptr(), 256);
+    let mut each = rc == XDELTA_OK;
+    for (entry, expected) in entries.iter().zip(&expected) {
+        each &= match expected {
+            Some((old, new)) => {
+                let patch = unsafe { std::slice::from_raw_parts(entry.patch_data, entry.patch_len) };
+                entry.status == XDELTA_OK
+                    && patch == crate::create_patch(old, new, 256)?
+                    && apply_patch_bytes(old, patch)? == *new
+            }
+            None => entry.status == XDELTA_ERR_NULL_POINTER && entry.patch_data.is_null() && entry.patch_len == 0,
+        };
+    }
+    check(each, "patch batch entries, failing pairs among them")?;
+
+    xdelta_free_batch(entries.as_mut_ptr(), entries.len());
+    let freed = entries.iter().all(|e| e.patch_data.is_null() && e.patch_len == 0);
+    xdelta_free_batch(entries.as_mut_ptr(), entries.len());
+    xdelta_free_batch(std::ptr::null_mut(), 3);
+    check(freed, "freed patch batch")?;
+
+    let mut untouched = [entry()];
+    let refused = xdelta_create_patch_batch(pairs.as_ptr(), 1, std::ptr::null_mut(), 256) == XDELTA_ERR_NULL_POINTER
+        && xdelta_create_patch_batch(std::ptr::null(), 1, untouched.as_mut_ptr(), 256) == XDELTA_ERR_NULL_POINTER
+        && xdelta_create_patch_batch(std::ptr::null(), 0, untouched.as_mut_ptr(), 256) == XDELTA_OK;
+    check(refused && untouched[0].status == 1, "patch batch without pairs or entries")
+}
+
+#[test]
+fn allocator() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let _swap = ALLOCATOR_SWAP.lock().unwrap_or_else(PoisonError::into_inner);
+    check(xdelta_set_allocator(Some(counting_malloc), None) == XDELTA_ERR_INVALID_ARG, "half an allocator")?;
+    check(swap_allocator(true), "counting allocator installed")?;
+    let counted = COUNTED.with(Cell::get);
+
+    // patch, output, dump and range array all come from the installed
+    // allocator and go back to it, failures allocating nothing
+    let (mut patch, mut patch_len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_data(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &mut patch, &mut patch_len, 64);
+    check(rc == XDELTA_OK, "create with a counting allocator")?;
+    let (mut out, mut out_len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), patch, patch_len, &mut out, &mut out_len);
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(out, out_len) } == new;
+    let (mut dump, mut dump_len) = (std::ptr::null_mut(), 0);
+    let dumped = crate::dump::xdelta_dump_patch(patch, patch_len, &mut dump, &mut dump_len) == XDELTA_OK;
+    let (mut ranges, mut range_count) = (std::ptr::null_mut(), 0);
+    let ranged =
+        crate::overlap::xdelta_patch_old_ranges_merged(patch, patch_len, 0, &mut ranges, &mut range_count) == XDELTA_OK;
+    // the swap is refused while buffers are out
+    let refused = xdelta_set_allocator(None, None) == XDELTA_ERR_INVALID_ARG;
+    for data in [out, dump, ranges as *mut u8] {
+        xdelta_free_data(data);
+    }
+    let failed = xdelta_apply_patch_data(old.as_ptr(), old.len(), patch, 3, &mut out, &mut out_len);
+    xdelta_free_data(patch);
+    let same = same && out.is_null();
+    let restored = swap_allocator(false);
+    check(same && dumped && ranged && failed == XDELTA_ERR_MALFORMED_PATCH, "FFI through a counting allocator")?;
+    check(refused && restored, "allocator swapped only with no buffer out")?;
+
+    let (allocs, frees) = COUNTED.with(Cell::get);
+    let (allocs, frees) = (allocs - counted.0, frees - counted.1);
+    check(allocs >= 3 && allocs == frees, "every allocation matched by a free")
+}
+
+/// A batch of pairs, one with a null pointer, gets one independent result each.
+#[test]
+fn patch_batch_handles() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let pair = |old: &[u8], new: &[u8]| XdeltaPatchPair {
+        old_data: old.as_ptr(),
+        old_len: old.len(),
+        new_data: new.as_ptr(),
+        new_len: new.len(),
+    };
+    let pairs =
+        [pair(old, new), XdeltaPatchPair { old_data: std::ptr::null(), ..pair(old, new) }, pair(new, old)];
+    let mut results = [std::ptr::null_mut(); 3];
+    check(xdelta_create_patch_batch_handles(pairs.as_ptr(), 3, 256, results.as_mut_ptr()) == 0, "patch batch")?;
+    let outcomes: Vec<Result<Vec<u8>, c_int>> = results
+        .iter()
+        .map(|&r| {
+            let outcome = match xdelta_result_status(r) {
+                XDELTA_OK => {
+                    Ok(unsafe { std::slice::from_raw_parts(xdelta_result_data(r), xdelta_result_len(r)) }.to_vec())
+                }
+                status => Err(status),
+            };
+            xdelta_result_free(r);
+            outcome
+        })
+        .collect();
+    check(
+        matches!(&outcomes[0], Ok(p) if apply_patch_bytes(old, p)? == new)
+            && outcomes[1] == Err(XDELTA_ERR_NULL_POINTER)
+            && matches!(&outcomes[2], Ok(p) if apply_patch_bytes(new, p)? == old),
+        "patch batch results",
+    )
+}
+
+/// Create flags: none gives the plain patch, COMPRESS and unknown bits are refused.
+#[test]
+fn create_flags() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let create_ex = |flags: u32| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+        let status = xdelta_create_patch_data_ex(
+            old.as_ptr(),
+            old.len(),
+            new.as_ptr(),
+            new.len(),
+            &mut data,
+            &mut len,
+            256,
+            flags,
+        );
+        let patch = (status == 0).then(|| unsafe { std::slice::from_raw_parts(data, len) }.to_vec());
+        xdelta_free_data(data);
+        patch
+    };
+    check(
+        create_ex(0) == Some(create_patch_with(old, new, &plain())?)
+            && create_ex(XDELTA_CREATE_COMPRESS).is_some() == cfg!(feature = "zstd")
+            && create_ex(1 << 7).is_none(),
+        "create flags",
+    )
+}
+
+/// Caller buffers: one byte short fails with the size needed, an exact fit succeeds.
+#[test]
+fn output_into_caller_buffer() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &plain())?;
+    let into = |cap: usize, create: bool| {
+        let mut buf = vec![0u8; cap];
+        let mut written = 0usize;
+        let status = if create {
+            xdelta_create_patch_data_into(
+                old.as_ptr(),
+                old.len(),
+                new.as_ptr(),
+                new.len(),
+                buf.as_mut_ptr(),
+                cap,
+                &mut written,
+                256,
+            )
+        } else {
+            let (old_data, patch_data) = (old.as_ptr(), patch.as_ptr());
+            xdelta_apply_patch_into(old_data, old.len(), patch_data, patch.len(), buf.as_mut_ptr(), cap, &mut written)
+        };
+        let mut code = XDELTA_OK;
+        if status != 0 {
+            xdelta_last_error_detail(&mut code);
+        }
+        (code, written, buf)
+    };
+    for (create, expected) in [(true, &patch[..]), (false, new)] {
+        let (short, needed, _) = into(expected.len() - 1, create);
+        let (fit, written, buf) = into(expected.len(), create);
+        check(
+            short == XDELTA_ERR_BUFFER_TOO_SMALL
+                && needed == expected.len()
+                && fit == XDELTA_OK
+                && written == expected.len()
+                && buf == *expected,
+            "output into caller buffer",
+        )?;
+    }
+    Ok(())
+}
+
+/// Each kind of failure returns its own code, the same one
+/// `xdelta_last_error_detail` reports.
+#[test]
+fn error_codes() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let past_end = with_header(&[&[0x01][..], &(old.len() as u64).to_le_bytes(), &1u32.to_le_bytes()].concat());
+    let apply_rc = |old: *const u8, patch: &[u8]| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+        let rc = xdelta_apply_patch_data(old, 1, patch.as_ptr(), patch.len(), &mut data, &mut len);
+        xdelta_free_data(data);
+        let mut code = XDELTA_OK;
+        xdelta_last_error_detail(&mut code);
+        (rc, code)
+    };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let (old_ptr, new_ptr) = (old.as_ptr(), new.as_ptr());
+    let bad_flags =
+        xdelta_create_patch_data_ex(old_ptr, old.len(), new_ptr, new.len(), &mut data, &mut len, 256, 1 << 7);
+    for ((rc, code), expected) in [
+        (apply_rc(std::ptr::null(), &past_end), XDELTA_ERR_NULL_POINTER),
+        (apply_rc(old.as_ptr(), b"XDR1"), XDELTA_ERR_MALFORMED_PATCH),
+        (apply_rc(old.as_ptr(), &past_end), XDELTA_ERR_OLD_OUT_OF_RANGE),
+        ((bad_flags, bad_flags), XDELTA_ERR_INVALID_ARG),
+    ] {
+        check(rc == expected && code == expected, "error codes")?;
+    }
+    Ok(())
+}
+
+/// Null options are the defaults, a zero block size picks one from old's
+/// length as the flat API's does, and each `XdeltaOptions` field set makes
+/// the patch of the builder option it mirrors.
+#[test]
+fn ffi_options() -> Result<(), XDeltaError> {
+    use crate::{XDELTA_OPT_CONST_TABLE, XDELTA_OPT_NEAR_MISS_DIFF, XDELTA_OPT_OLD_HASH};
+
+    let (old, new) = fixture();
+    let create = |opts: *const XdeltaOptions| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc =
+            xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), opts, &mut data, &mut len);
+        taken(rc, data, len)
+    };
+    let all = XdeltaOptions {
+        block_size: 256,
+        flags: XDELTA_OPT_NEAR_MISS_DIFF | XDELTA_OPT_OLD_HASH | XDELTA_OPT_CONST_TABLE,
+        index_granularity: 1024,
+        ..XdeltaOptions::default()
+    };
+    let built = PatchOptions::new().block_size(256).near_miss_diff(true).old_hash(true).const_table(true);
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let flat = xdelta_create_patch_data(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &mut data, &mut len, 0);
+    check(
+        create(std::ptr::null()) == (XDELTA_OK, create_patch_with(old, new, &PatchOptions::default().block_size(0))?)
+            && create(&XdeltaOptions::default()) == create(std::ptr::null())
+            && create(&XdeltaOptions::default()) == taken(flat, data, len)
+            && create(&all) == (XDELTA_OK, create_patch_with(old, new, &built.index_granularity(1024))?),
+        "XdeltaOptions mirrors PatchOptions",
+    )?;
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_data_opts(std::ptr::null(), 1, new.as_ptr(), new.len(), &all, &mut data, &mut len);
+    check(rc == XDELTA_ERR_NULL_POINTER && data.is_null(), "XdeltaOptions with null old")
+}
+
+/// An older caller's shorter `XdeltaOptions` has the fields past its size
+/// read as defaults, whatever they hold, and an implausible size is
+/// refused; a newer caller's larger output struct is written only as far as
+/// this build's fields go.
+#[test]
+fn struct_size() -> Result<(), XDeltaError> {
+    use crate::signature::XdeltaSigStats;
+    use crate::XDELTA_OPT_OLD_HASH;
+
+    let (old, new) = fixture();
+    let create = |opts: &XdeltaOptions| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc =
+            xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), opts, &mut data, &mut len);
+        taken(rc, data, len)
+    };
+    // size, block_size and flags, then index_granularity at offset 16
+    let set = XdeltaOptions {
+        block_size: 256,
+        flags: XDELTA_OPT_OLD_HASH,
+        index_granularity: 1024,
+        ..XdeltaOptions::default()
+    };
+    let plain = PatchOptions::new().block_size(256);
+    check(
+        create(&XdeltaOptions { size: 8, ..set }) == (XDELTA_OK, create_patch_with(old, new, &plain)?)
+            && create(&XdeltaOptions { size: 12, ..set })
+                == (XDELTA_OK, create_patch_with(old, new, &plain.old_hash(true))?)
+            && create(&XdeltaOptions { size: 4, ..set }).0 == XDELTA_ERR_INVALID_ARG
+            && create(&XdeltaOptions { size: 1 << 20, ..set }).0 == XDELTA_ERR_INVALID_ARG,
+        "XdeltaOptions read up to its size",
+    )?;
+
+    // a newer caller's larger struct keeps the fields this build doesn't
+    // know; one smaller than the fields written is refused untouched
+    #[repr(C)]
+    struct Later {
+        stats: XdeltaSigStats,
+        extra: u64,
+    }
+    let sig = Signature::new(old, 256)?;
+    let unset = XdeltaSigStats {
+        size: 0,
+        most_collided_weak: u32::MAX,
+        block_count: u64::MAX,
+        distinct_weak: u64::MAX,
+        max_bucket: u64::MAX,
+        avg_bucket: -1.0,
+    };
+    let size = std::mem::size_of::<Later>() as u32;
+    let mut later = Later { stats: XdeltaSigStats { size, ..unset }, extra: u64::MAX };
+    let rc = crate::signature::xdelta_signature_stats(&sig, &mut later.stats);
+    let mut short = XdeltaSigStats { size: 16, ..unset };
+    let refused = crate::signature::xdelta_signature_stats(&sig, &mut short);
+    check(
+        rc == XDELTA_OK
+            && later.stats.size == size
+            && later.stats.block_count == sig.stats().block_count
+            && later.stats.avg_bucket == sig.stats().avg_bucket
+            && later.extra == u64::MAX
+            && refused == XDELTA_ERR_INVALID_ARG
+            && short.block_count == u64::MAX,
+        "output struct written up to its fields",
+    )
+}
+
+/// `xdelta_free_string` gives a string back to the allocator it came from,
+/// and ignores NULL.
+#[test]
+fn free_string() -> Result<(), XDeltaError> {
+    use crate::allocator::ffi_malloc;
+    use crate::xdelta_free_string;
+
+    let _swap = ALLOCATOR_SWAP.lock().unwrap_or_else(PoisonError::into_inner);
+    xdelta_free_string(std::ptr::null_mut());
+    check(swap_allocator(true), "counting allocator installed")?;
+    let frees = COUNTED.with(Cell::get).1;
+    let s = ffi_malloc(6);
+    let allocated = !s.is_null();
+    if allocated {
+        unsafe { std::ptr::copy_nonoverlapping(c"hello".as_ptr() as *const u8, s, 6) };
+        xdelta_free_string(s as *mut std::os::raw::c_char);
+    }
+    let freed = COUNTED.with(Cell::get).1 - frees;
+    let restored = swap_allocator(false);
+    check(allocated && freed == 1 && restored, "string freed through the installed allocator")
+}
+
+/// Result handles carry the patch or output of a call, or its error code
+/// and message, until freed.
+#[test]
+fn result_handle() -> Result<(), XDeltaError> {
+    use crate::result::{xdelta_apply_patch_result, xdelta_create_patch_result, xdelta_result_error};
+    use std::ffi::CStr;
+
+    let (old, new) = fixture();
+    let held = |result: *mut crate::result::XdeltaResult| {
+        let status = xdelta_result_status(result);
+        let data = xdelta_result_data(result);
+        let bytes = if data.is_null() {
+            Vec::new()
+        } else {
+            unsafe { std::slice::from_raw_parts(data, xdelta_result_len(result)) }.to_vec()
+        };
+        let error = xdelta_result_error(result);
+        let message = (!error.is_null()).then(|| unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned());
+        xdelta_result_free(result);
+        (status, bytes, message)
+    };
+    let patch = create_patch_with(old, new, &PatchOptions::default().block_size(0))?;
+    let created = held(xdelta_create_patch_result(old.as_ptr(), old.len(), new.as_ptr(), new.len(), std::ptr::null()));
+    let applied = held(xdelta_apply_patch_result(old.as_ptr(), old.len(), patch.as_ptr(), patch.len()));
+    check(created == (XDELTA_OK, patch, None) && applied == (XDELTA_OK, new.to_vec(), None), "result handles")?;
+
+    let (status, bytes, message) = held(xdelta_apply_patch_result(old.as_ptr(), old.len(), b"XDR1".as_ptr(), 4));
+    let (null, _, _) = held(xdelta_create_patch_result(std::ptr::null(), 1, new.as_ptr(), new.len(), std::ptr::null()));
+    xdelta_result_free(std::ptr::null_mut());
+    check(
+        status == XDELTA_ERR_MALFORMED_PATCH
+            && bytes.is_empty()
+            && message.is_some_and(|m| !m.is_empty())
+            && null == XDELTA_ERR_NULL_POINTER,
+        "failed result handles",
+    )
+}
@@ -0,0 +1,755 @@
+// src/tests/format.rs
+//! The patch format: header, records, versions and inspection.
+
+use super::*;
+
+/// `patch_info` counts the records of a hand-built patch, through the C
+/// struct too, and refuses a truncated one.
+#[test]
+fn patch_info_counts() -> Result<(), XDeltaError> {
+    use crate::histogram::{xdelta_patch_info, XdeltaPatchInfo};
+
+    let (old, _) = fixture();
+    let mut bare = vec![0x00]; // ADD "abc"
+    bare.extend_from_slice(&3u32.to_le_bytes());
+    bare.extend_from_slice(b"abc");
+    for (offset, len) in [(0u64, 10u32), (20, 5)] {
+        bare.push(0x01); // COPY
+        bare.extend_from_slice(&offset.to_le_bytes());
+        bare.extend_from_slice(&len.to_le_bytes());
+    }
+    bare.extend_from_slice(&[0x02, 0xff]); // RUN of 4
+    bare.extend_from_slice(&4u32.to_le_bytes());
+    bare.push(0x10); // DIFF of 8 without deltas
+    bare.extend_from_slice(&30u64.to_le_bytes());
+    bare.extend_from_slice(&8u32.to_le_bytes());
+    bare.extend_from_slice(&0u32.to_le_bytes());
+    let patch = with_header(&bare);
+
+    let info = patch_info(&patch)?;
+    let expected = PatchInfo {
+        version: PATCH_HEADER_VERSION,
+        identity: false,
+        num_add: 1,
+        literal_bytes: 3,
+        num_copy: 3,
+        copied_bytes: 23,
+        output_len: 30,
+        block_size: 0,
+        weak_checksum: 0,
+        strong_hash: 0,
+        sampled: false,
+    };
+    check(info == expected && apply_patch_bytes(old, &patch)?.len() == 30, "patch info")?;
+
+    let mut c = XdeltaPatchInfo {
+        size: std::mem::size_of::<XdeltaPatchInfo>() as u32,
+        version: 0,
+        identity: 0,
+        num_add: 0,
+        num_copy: 0,
+        literal_bytes: 0,
+        copied_bytes: 0,
+        output_len: 0,
+        block_size: 0,
+        weak_checksum: 0,
+        strong_hash: 0,
+        sampled: 0,
+    };
+    let rc = xdelta_patch_info(patch.as_ptr(), patch.len(), &mut c);
+    check(
+        rc == XDELTA_OK
+            && (c.version, c.identity, c.num_add, c.num_copy) == (PATCH_HEADER_VERSION, 0, 1, 3)
+            && (c.literal_bytes, c.copied_bytes, c.output_len) == (3, 23, 30),
+        "patch info FFI",
+    )?;
+    let rc = xdelta_patch_info(patch.as_ptr(), patch.len() - 3, &mut c);
+    check(rc == XDELTA_ERR_MALFORMED_PATCH, "patch info of a truncated patch")?;
+
+    let identity = create_patch_with(old, old, &PatchOptions::new())?;
+    let info = patch_info(&identity)?;
+    check(info.identity && info.output_len == 0 && info.num_copy == 0, "patch info of an identity patch")
+}
+
+/// `xdelta_version` is the crate version as semver, and `xdelta_format_version`
+/// covers what create writes; neither touches the last error.
+#[test]
+fn versions() -> Result<(), XDeltaError> {
+    use crate::compat::{required_version, xdelta_format_version, xdelta_version};
+
+    let (old, new) = fixture();
+    xdelta_validate_patch(std::ptr::null(), 0);
+    let before = xdelta_last_error_detail(std::ptr::null_mut());
+    let version = unsafe { std::ffi::CStr::from_ptr(xdelta_version()) }.to_str().unwrap_or_default();
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let semver = core.split('.').count() == 3 && core.split('.').all(|n| n.parse::<u64>().is_ok());
+    check(semver && version == env!("CARGO_PKG_VERSION"), "library version is semver")?;
+
+    // CHECK needs format 5, the output length in the header 6, CRCs after
+    // records 7, short literals, as ADD_SMALL, 8, reading old front to
+    // back only 10 and sampled signatures 11
+    let opts = PatchOptions::new().output_check(true).min_version(true).record_crc(true).forward_only(true);
+    let opts = opts.sample_blocks(2).max_add_len(200);
+    let patch = crate::without_record_crcs(&create_patch_with(old, new, &opts)?)?.into_owned();
+    let records = patch_records(&patch)?;
+    check(
+        xdelta_format_version() == XDELTA_FORMAT_VERSION
+            && required_version(&records[9..])? == 8
+            && records[..5] == [0x86, 4, 0, 0, 0]
+            && records[5..9] == 11u32.to_le_bytes()
+            && patch[4] == PATCH_HEADER_VERSION
+            && xdelta_last_error_detail(std::ptr::null_mut()) == before,
+        "format version",
+    )
+}
+
+/// The buffer out-params are only filled in once the copy has succeeded: a
+/// failed allocation or a failed call leaves NULL and 0 behind, whatever
+/// they held before, and an empty output is still a buffer to free.
+#[test]
+fn output_params() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let stale = |data: &mut *mut u8, len: &mut usize| {
+        *data = std::ptr::NonNull::dangling().as_ptr();
+        *len = 77;
+    };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+
+    stale(&mut data, &mut len);
+    let rc = crate::write_output_with(Ok(new), &mut data, &mut len, |_| std::ptr::null_mut());
+    check(rc == XDELTA_ERR_NO_MEMORY && data.is_null() && len == 0, "out-params after a failed allocation")?;
+
+    stale(&mut data, &mut len);
+    let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), b"XDR1".as_ptr(), 4, &mut data, &mut len);
+    check(rc == XDELTA_ERR_MALFORMED_PATCH && data.is_null() && len == 0, "out-params after a failed apply")?;
+
+    stale(&mut data, &mut len);
+    let rc = xdelta_create_patch_data(std::ptr::null(), 1, new.as_ptr(), new.len(), &mut data, &mut len, 256);
+    check(rc == XDELTA_ERR_NULL_POINTER && data.is_null() && len == 0, "out-params after a failed create")?;
+
+    let empty = with_header(&[]);
+    let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), empty.as_ptr(), empty.len(), &mut data, &mut len);
+    let ok = rc == XDELTA_OK && !data.is_null() && len == 0;
+    xdelta_free_data(data);
+    check(ok, "empty output buffer")
+}
+
+/// The header of a created patch records its block size, weak checksum and
+/// strong hash, which `patch_info` reports, also to callers of the smaller C
+/// struct from before. Apply doesn't need them and lets unknown ids through,
+/// but refuses a header at odds with the patch: reserved bytes set, a block
+/// size of 0 or other than a BLOCK_SIZE record's, BLAKE3 COPY_HASH records
+/// with another strong hash, or a CHECK trailer flagged and missing.
+#[test]
+fn header_params() -> Result<(), XDeltaError> {
+    use crate::histogram::{xdelta_patch_info, XdeltaPatchInfo};
+    use crate::{
+        PATCH_FLAG_BLAKE3, PATCH_FLAG_CHECK, PATCH_HEADER_MAX_LEN, XDELTA_HASH_SHA256, XDELTA_WEAK_ADLER32,
+        XDELTA_WEAK_ROLLING,
+    };
+
+    let (old, new) = fixture();
+    let at = PATCH_HEADER_MAX_LEN - 8;
+    let plain = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let adler = create_patch_with(old, new, &PatchOptions::new().block_size(512).weak_checksum(WeakAlgo::Adler32))?;
+    let auto = create_patch_with(old, new, &PatchOptions::new().block_size(0))?;
+    let params = |patch: &[u8]| patch_info(patch).map(|i| (i.block_size, i.weak_checksum, i.strong_hash));
+    check(
+        params(&plain)? == (256, XDELTA_WEAK_ROLLING, XDELTA_HASH_SHA256)
+            && params(&adler)? == (512, XDELTA_WEAK_ADLER32, XDELTA_HASH_SHA256)
+            && params(&auto)?.0 == auto_block_size(old.len()) as u32
+            && params(&create_patch_with(old, old, &PatchOptions::new())?)? == (0, 0, 0),
+        "creation parameters in the header",
+    )?;
+    #[cfg(feature = "blake3")]
+    {
+        let opts = PatchOptions::new().block_size(256).strong_hash(HashAlgo::Blake3);
+        let plain = create_patch_with(old, new, &opts)?;
+        let cas = create_patch_with(old, new, &opts.content_addressed(true))?;
+        let blake3 = (256, XDELTA_WEAK_ROLLING, XDELTA_HASH_BLAKE3);
+        check(params(&plain)? == blake3 && params(&cas)? == blake3, "BLAKE3 in the header")?;
+    }
+
+    let size = std::mem::offset_of!(XdeltaPatchInfo, block_size) as u32;
+    let mut c = XdeltaPatchInfo {
+        size,
+        version: 0,
+        identity: 0,
+        num_add: 0,
+        num_copy: 0,
+        literal_bytes: 0,
+        copied_bytes: 0,
+        output_len: 0,
+        block_size: 7,
+        weak_checksum: 7,
+        strong_hash: 7,
+        sampled: 7,
+    };
+    let before = xdelta_patch_info(adler.as_ptr(), adler.len(), &mut c);
+    let untouched = (c.block_size, c.weak_checksum, c.output_len) == (7, 7, new.len() as u64);
+    c.size = std::mem::size_of::<XdeltaPatchInfo>() as u32;
+    let rc = xdelta_patch_info(adler.as_ptr(), adler.len(), &mut c);
+    check(
+        before == XDELTA_OK
+            && untouched
+            && rc == XDELTA_OK
+            && (c.block_size, c.weak_checksum, c.strong_hash) == (512, XDELTA_WEAK_ADLER32, XDELTA_HASH_SHA256),
+        "creation parameters through FFI",
+    )?;
+
+    let mut unknown = plain.clone();
+    unknown[at + 4..at + 6].copy_from_slice(&[9, 9]);
+    check(apply_patch_bytes(old, &unknown)? == new && params(&unknown)? == (256, 9, 9), "unknown ids let through")?;
+
+    let malformed = |patch: &[u8], what: &str| {
+        let mut out = Vec::new();
+        let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+        let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), patch.as_ptr(), patch.len(), &mut data, &mut len);
+        let mut feed = ApplyFeed::new(old, Vec::new(), VerifyOld::None);
+        let fed = feed.feed(patch).and_then(|()| feed.finish().map(drop));
+        let streamed = apply_streaming(&mut &old[..], patch, &mut out, &ApplyOptions::new());
+        let is = |r: Result<(), XDeltaError>| matches!(r, Err(XDeltaError::MalformedPatch(ref m)) if m.contains(what));
+        is(validate_patch(patch))
+            && is(apply_patch_bytes(old, patch).map(drop))
+            && is(patch_info(patch).map(drop))
+            && is(fed)
+            && is(streamed)
+            && rc == XDELTA_ERR_MALFORMED_PATCH
+    };
+    let mut trailerless = plain.clone();
+    trailerless[5] |= PATCH_FLAG_CHECK;
+    check(malformed(&trailerless, "ends before its CHECK record"), "CHECK flag without a trailer")?;
+    let mut reserved = plain.clone();
+    reserved[at + 7] = 1;
+    check(malformed(&reserved, "reserved patch header bytes"), "reserved parameter bytes")?;
+    let mut zero = plain.clone();
+    zero[at..at + 4].copy_from_slice(&0u32.to_le_bytes());
+    check(malformed(&zero, "block size of 0"), "block size 0 in the header")?;
+    let mut blake3 = plain.clone();
+    blake3[5] |= PATCH_FLAG_BLAKE3;
+    check(malformed(&blake3, "BLAKE3 COPY_HASH records"), "BLAKE3 flag against the strong hash")?;
+    let mut resized = auto.clone();
+    resized[at..at + 4].copy_from_slice(&(auto_block_size(old.len()) as u32 * 2).to_le_bytes());
+    check(
+        patch_records(&auto)?[0] == 0x85 && malformed(&resized, "BLOCK_SIZE record says"),
+        "BLOCK_SIZE record against the header",
+    )
+}
+
+/// Hundreds of scattered 4-byte edits leave as many short literals between
+/// copies; each is one ADD_SMALL, three bytes under the same bytes as ADD,
+/// and every reader of records takes it as an ADD. 255 bytes is the longest
+/// ADD_SMALL, and one with no bytes, or fewer than its length, is malformed.
+#[test]
+fn add_small() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let mut new = old.repeat(4);
+    for (i, edit) in filler(4 * 320, 0x5a11).chunks(4).enumerate() {
+        new[i * 200 + 100..i * 200 + 104].copy_from_slice(edit);
+    }
+    let patch = create_patch_with(old, &new, &PatchOptions::new().block_size(32))?;
+    let records = patch_records(&patch)?;
+    let (mut wide, mut small) = (Vec::new(), 0);
+    let mut pos = 0usize;
+    while pos < records.len() {
+        let next = crate::read_record(records, pos)?.1;
+        if records[pos] == 0x05 {
+            small += 1;
+            wide.push(0x00); // ADD
+            wide.extend_from_slice(&((next - pos - 2) as u32).to_le_bytes());
+            wide.extend_from_slice(&records[pos + 2..next]);
+        } else {
+            wide.extend_from_slice(&records[pos..next]);
+        }
+        pos = next;
+    }
+    let wide = [&patch[..patch.len() - records.len()], &wide].concat();
+    let stats = opcode_histogram(&patch)?;
+    check(
+        small >= 320
+            && patch.len() + 3 * small == wide.len()
+            && stats.iter().all(|s| s.opcode != 0x00)
+            && apply_patch_bytes(old, &patch)? == new
+            && apply_patch_bytes(old, &wide)? == new,
+        "short literals as ADD_SMALL",
+    )?;
+
+    let adds = PatchReader::new(&patch)?.filter(|op| matches!(op, Ok(PatchOp::Add(data)) if data.len() < 256)).count();
+    let mut streamed = Vec::new();
+    apply_streaming(&mut &old[..], &patch, &mut streamed, &ApplyOptions::new())?;
+    let mut feed = ApplyFeed::new(old, Vec::new(), VerifyOld::None);
+    for byte in patch.chunks(1) {
+        feed.feed(byte)?;
+    }
+    check(adds == small && streamed == new && feed.finish()? == new, "ADD_SMALL through every reader")?;
+
+    for (len, opcode) in [(255, 0x05), (256, 0x00)] {
+        let literal = filler(len, 0x1ea7);
+        let patch = create_patch_with(&[], &literal, &PatchOptions::new())?;
+        let records = patch_records(&patch)?;
+        check(records[0] == opcode && apply_patch_bytes(&[], &patch)? == literal, "longest ADD_SMALL")?;
+    }
+    for bad in [&[0x05, 0][..], &[0x05], &[0x05, 3, b'a', b'b']] {
+        check(matches!(validate_patch(&with_header(bad)), Err(XDeltaError::MalformedPatch(_))), "malformed ADD_SMALL")?;
+    }
+    Ok(())
+}
+
+/// The patch format is little-endian whatever the host: a COPY record is
+/// its opcode, the u64 offset and the u32 length, lowest byte first.
+#[test]
+fn little_endian_layout() -> Result<(), XDeltaError> {
+    let old = filler(70_000, 0x1e0d);
+    let new = old[0x1_0200..0x1_0200 + 0x410].to_vec();
+    let patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(16))?;
+    let copy = [0x01, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x04, 0x00, 0x00];
+    let header_len = patch.len() - copy.len();
+    check(
+        patch[header_len..] == copy
+            && patch[PATCH_HEADER_LEN..PATCH_HEADER_LEN + 8] == [0x10, 0x04, 0, 0, 0, 0, 0, 0]
+            && patch[PATCH_HEADER_LEN + 8..PATCH_HEADER_LEN + 12] == [0x10, 0, 0, 0],
+        "COPY record and header fields are little-endian",
+    )?;
+    check(
+        apply_patch_bytes(&old, &patch)? == new && apply_patch_bytes(&old, &with_header(&copy))? == new,
+        "little-endian COPY applies",
+    )
+}
+
+#[test]
+fn dump_lists_records() -> Result<(), XDeltaError> {
+    // a COPY of old from 0x10200, an ADD, and a COPY from the start
+    let old = filler(70_000, 0x0d0e);
+    let mut new = old[0x1_0200..0x1_0600].to_vec();
+    new.extend_from_slice(b"inserted");
+    new.extend_from_slice(&old[..512]);
+    let patch = create_patch_with(&old, &new, &PatchOptions::new().block_size(16).record_crc(true))?;
+    let dump = crate::dump_patch(&patch)?;
+    let lines: Vec<&str> = dump.lines().collect();
+    check(
+        lines.len() == 5
+            && lines[0].starts_with("XDR1 version=1 flags=0x38 output_len=1544 block_size=16")
+            && lines[1] == "0 COPY old=66048 len=1024"
+            && lines[2] == "1024 ADD len=8 data=696e736572746564"
+            && lines[3] == "1032 COPY old=0 len=512"
+            && lines[4] == "records=3",
+        "dump_patch lists the records",
+    )?;
+
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = crate::dump::xdelta_dump_patch(patch.as_ptr(), patch.len(), &mut data, &mut len);
+    let same = rc == XDELTA_OK
+        && unsafe { std::slice::from_raw_parts(data, len + 1) }.split_last() == Some((&0, dump.as_bytes()));
+    xdelta_free_data(data);
+    check(same, "xdelta_dump_patch")?;
+    let rc = crate::dump::xdelta_dump_patch(patch.as_ptr(), 5, &mut data, &mut len);
+    check(rc == XDELTA_ERR_MALFORMED_PATCH && data.is_null(), "dump of a truncated patch")
+}
+
+/// Compressed literals: text compresses into ADD_ZSTD records, consecutive
+/// ADD records together, that every apply path inflates back, and a frame
+/// that is corrupt, truncated or of the wrong length is a malformed patch.
+#[cfg(feature = "zstd")]
+#[test]
+fn add_zstd() -> Result<(), XDeltaError> {
+    use crate::{apply_iter, compat::required_version, inflate_add};
+
+    let text = |lines: std::ops::Range<u32>, tag: &str| -> Vec<u8> {
+        lines.flat_map(|i| format!("    let {}_{} = lookup(\"{}\", {});\n", tag, i, tag, i * 7).into_bytes()).collect()
+    };
+    let old = text(0..2000, "value");
+    let mut new = old[..20_000].to_vec();
+    new.extend_from_slice(&text(0..1500, "inserted"));
+    new.extend_from_slice(&old[20_000..]);
+    let plain = PatchOptions::new().block_size(512);
+    let raw = create_patch_with(&old, &new, &plain)?;
+    let patch = create_patch_with(&old, &new, &plain.clone().compress(true))?;
+    let records = patch_records(&patch)?;
+    let (mut pos, mut zstd_records) = (0, 0);
+    while pos < records.len() {
+        let (record, next) = crate::read_record(records, pos)?;
+        zstd_records += matches!(record, crate::Record::AddZstd { .. }) as usize;
+        pos = next;
+    }
+    let mut streamed = Vec::new();
+    apply_streaming(&mut &old[..], &patch, &mut streamed, &ApplyOptions::new())?;
+    let lazy = apply_iter(&old, &patch).collect::<Result<Vec<_>, _>>()?.concat();
+    check(
+        zstd_records > 0
+            && patch.len() * 4 < raw.len()
+            && required_version(records)? == 12
+            && apply_patch_bytes(&old, &patch)? == new
+            && streamed == new
+            && lazy == new
+            && apply_range_bytes(&old, &patch, 19_000, 5_000)? == new[19_000..24_000],
+        "ADD_ZSTD round trip",
+    )?;
+
+    // literals zstd can't shrink stay ADD records
+    let noise = filler(500, 0x25d);
+    let incompressible = create_patch_with(b"", &noise, &plain.clone().compress(true))?;
+    check(incompressible == create_patch_with(b"", &noise, &plain)?, "incompressible literals kept")?;
+
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let rc = xdelta_create_patch_data_ex(
+        old.as_ptr(),
+        old.len(),
+        new.as_ptr(),
+        new.len(),
+        &mut data,
+        &mut len,
+        512,
+        XDELTA_CREATE_COMPRESS,
+    );
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == &patch[..];
+    xdelta_free_data(data);
+    check(same, "compress create flag")?;
+
+    let frame = zstd::bulk::compress(b"hello, hello, hello", 3).map_err(|e| XDeltaError::InvalidArg(e.to_string()))?;
+    let record = |len: u32, data: &[u8]| {
+        with_header(&[&[0x03][..], &len.to_le_bytes(), &(data.len() as u32).to_le_bytes(), data].concat())
+    };
+    let malformed = |patch: &[u8]| matches!(apply_patch_bytes(b"", patch), Err(XDeltaError::MalformedPatch(_)));
+    let mut corrupt = frame.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0xff;
+    check(
+        apply_patch_bytes(b"", &record(19, &frame))? == b"hello, hello, hello"
+            && inflate_add(19, &frame)? == b"hello, hello, hello"
+            && malformed(&record(18, &frame))
+            && malformed(&record(20, &frame))
+            && malformed(&record(19, &frame[..frame.len() - 1]))
+            && malformed(&record(19, &[&frame[..], &[0]].concat()))
+            && malformed(&record(19, &corrupt))
+            && malformed(&record(19, b"not zstd"))
+            && malformed(&record(19, &[])),
+        "bad ADD_ZSTD frames refused",
+    )
+}
+
+/// Two runs of the matcher trace identically, and picking the other of two
+/// equal candidates, as a changed bucket order might, changes the trace
+/// without changing the output.
+#[cfg(feature = "match-trace")]
+#[test]
+fn match_trace_shows_pick() -> Result<(), XDeltaError> {
+    use crate::{match_trace, patch_trace};
+
+    let (old, new) = fixture();
+    let opts = PatchOptions::new().block_size(64);
+    let first = match_trace(old, new, &opts)?;
+    check(!first.is_empty() && first == match_trace(old, new, &opts)?, "match trace repeats")?;
+
+    // one block twice in old: a COPY from either copy gives the same new
+    let block = &old[..64];
+    let twice = [block, block].concat();
+    let copy_from = |offset: u64| {
+        let mut bare = vec![0x01]; // COPY
+        bare.extend_from_slice(&offset.to_le_bytes());
+        bare.extend_from_slice(&64u32.to_le_bytes());
+        with_header(&bare)
+    };
+    let (a, b) = (copy_from(0), copy_from(64));
+    let picked = match_trace(&twice, block, &opts)?;
+    check(
+        apply_patch_bytes(&twice, &a)? == apply_patch_bytes(&twice, &b)?
+            && patch_trace(&a)? != patch_trace(&b)?
+            && (picked == patch_trace(&a)? || picked == patch_trace(&b)?),
+        "match trace shows the candidate picked",
+    )
+}
+
+/// A forward-only patch of a new that reorders and repeats old reads old
+/// front to back, COPY, DIFF and the best-quality choice alike, and still
+/// gives new; a patch whose header claims it but seeks back is refused by
+/// every applier.
+#[test]
+fn forward_only() -> Result<(), XDeltaError> {
+    use crate::validate_patch;
+
+    let (old, _) = fixture();
+    let block = |i: usize| &old[i * 256..(i + 1) * 256];
+    let mut near = block(7).to_vec();
+    near[100] ^= 1;
+    let new = [block(5), block(1), block(6), &near, block(3), block(7), block(4), block(8)].concat();
+    let reads = |patch: &[u8]| -> Result<Vec<(u64, u32)>, XDeltaError> {
+        let mut reads = Vec::new();
+        for op in crate::PatchReader::new(patch)? {
+            match op? {
+                PatchOp::Copy { offset, len } | PatchOp::Diff { offset, len, .. } => reads.push((offset, len)),
+                _ => {}
+            }
+        }
+        Ok(reads)
+    };
+    let forward = |reads: &[(u64, u32)]| reads.windows(2).all(|w| w[1].0 >= w[0].0 + w[0].1 as u64);
+    let opts = PatchOptions::new().block_size(256);
+    let backward = create_patch_with(old, &new, &opts)?;
+    check(!forward(&reads(&backward)?), "a reordering new seeks back in old")?;
+    for opts in [
+        opts.clone().forward_only(true),
+        opts.clone().forward_only(true).near_miss_diff(true),
+        opts.clone().forward_only(true).quality(Quality::Best),
+        opts.clone().forward_only(true).min_version(true).record_crc(true),
+    ] {
+        let patch = create_patch_with(old, &new, &opts)?;
+        let plain = crate::without_record_crcs(&patch)?.into_owned();
+        check(
+            forward(&reads(&plain)?)
+                && reads(&plain)?.len() >= 3
+                && patch[5] & 1 << 6 != 0
+                && apply_patch_bytes(old, &patch)? == new
+                && validate_patch(&patch).is_ok(),
+            "forward-only patch reads old front to back",
+        )?;
+    }
+    let with_diff = create_patch_with(old, &new, &opts.clone().forward_only(true).near_miss_diff(true))?;
+    check(
+        PatchReader::new(&with_diff)?.any(|op| matches!(op, Ok(PatchOp::Diff { .. }))),
+        "forward-only DIFF ahead of the last read",
+    )?;
+
+    let mut claimed = backward.clone();
+    claimed[5] |= 1 << 6;
+    let refused =
+        |r: Result<(), XDeltaError>| matches!(r, Err(XDeltaError::MalformedPatch(ref m)) if m.contains("forward-only"));
+    let streamed = apply_streaming(&mut &old[..], &claimed, &mut Vec::new(), &ApplyOptions::new());
+    check(
+        refused(apply_patch_bytes(old, &claimed).map(drop))
+            && refused(validate_patch(&claimed))
+            && refused(streamed)
+            && refused(apply_to(old, &claimed, ApplyOutput::ExactReserve(&mut Vec::new())).map(drop)),
+        "a patch claiming forward-only but seeking back",
+    )?;
+    let cas = create_patch_with(old, &new, &opts.clone().forward_only(true).content_addressed(true));
+    check(matches!(cas, Err(XDeltaError::InvalidArg(_))), "forward-only content-addressed patch")?;
+
+    let c_opts = XdeltaOptions { block_size: 256, flags: XDELTA_OPT_FORWARD_ONLY, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &c_opts, &mut data, &mut len);
+    let same = rc == XDELTA_OK
+        && unsafe { std::slice::from_raw_parts(data, len) } == create_patch_with(old, &new, &opts.forward_only(true))?;
+    xdelta_free_data(data);
+    check(same, "forward-only through XdeltaOptions")
+}
+
+/// The full flavour uses newer opcodes, which a baseline applier lacks; the
+/// plain one does too once new ends in a run, so that is left off here,
+/// and writes its short literals as ADD_SMALL.
+#[test]
+fn opcode_compatibility() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let unrun = &new[..new.len() - 200];
+    let small_adds = XDELTA_OPCODES_BASELINE | XDELTA_OPCODE_ADD_SMALL;
+    check(
+        patch_uses_only(&create_patch_with(old, unrun, &plain())?, small_adds).is_ok()
+            && patch_uses_only(&create_patch_with(old, new, &plain())?, XDELTA_OPCODES_BASELINE).is_err()
+            && patch_uses_only(&create_patch_with(old, new, &full())?, XDELTA_OPCODES_BASELINE).is_err(),
+        "opcode compatibility check",
+    )
+}
+
+/// Per-opcode tally of a hand-made mix: one ADD, two COPYs and a NOP.
+#[test]
+fn opcode_tally() -> Result<(), XDeltaError> {
+    let mut mix = vec![0x00, 3, 0, 0, 0, b'a', b'b', b'c']; // ADD
+    for _ in 0..2 {
+        mix.push(0x01); // COPY
+        mix.extend_from_slice(&0u64.to_le_bytes());
+        mix.extend_from_slice(&10u32.to_le_bytes());
+    }
+    mix.push(0x06); // NOP
+    let stat =
+        |opcode, name, count, patch_bytes, output_bytes| OpcodeStat { opcode, name, count, patch_bytes, output_bytes };
+    let expected = [stat(0x00, "ADD", 1, 8, 3), stat(0x01, "COPY", 2, 26, 20), stat(0x06, "NOP", 1, 1, 0)];
+    check(opcode_histogram(&with_header(&mix))? == expected, "opcode histogram")
+}
+
+/// A declared minimum version above ours is refused by version, not by opcode.
+#[test]
+fn min_version() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &full().min_version(true))?;
+    check(apply_patch_bytes(old, &patch)? == new, "apply with min version")?;
+    let records = patch_records(&patch)?;
+    let mut newer = patch[..patch.len() - records.len()].to_vec();
+    newer.extend_from_slice(&[0x86, 4, 0, 0, 0]); // MIN_VERSION
+    newer.extend_from_slice(&(XDELTA_FORMAT_VERSION + 1).to_le_bytes());
+    newer.extend_from_slice(records);
+    let refused = format!("patch requires applier >= {}, this is {}", XDELTA_FORMAT_VERSION + 1, XDELTA_FORMAT_VERSION);
+    check(
+        matches!(apply_patch_bytes(old, &newer), Err(XDeltaError::InvalidArg(msg)) if msg == refused),
+        "min version refusal",
+    )
+}
+
+/// Close COPY sources merge for prefetch, distant ones don't.
+#[test]
+fn merged_old_ranges() -> Result<(), XDeltaError> {
+    let mut copies = with_header(&[]);
+    for (offset, len) in [(110u64, 90u32), (0, 100), (1000, 100)] {
+        copies.push(0x01); // COPY
+        copies.extend_from_slice(&offset.to_le_bytes());
+        copies.extend_from_slice(&len.to_le_bytes());
+    }
+    check(
+        old_ranges_merged(&copies, 10)? == [0..200, 1000..1100] && old_ranges_merged(&copies, 9)?.len() == 3,
+        "merged old ranges",
+    )
+}
+
+/// Every patch starts with the header; a short or foreign one is refused up front.
+#[test]
+fn patch_header() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &plain())?;
+    check(patch.starts_with(b"XDR1") && patch[4] == PATCH_HEADER_VERSION, "patch header")?;
+    for (bad, code) in [
+        (&patch[..5], XDELTA_ERR_MALFORMED_PATCH),
+        (b"XDR2\x01\0\0\0", XDELTA_ERR_MALFORMED_PATCH),
+        (b"XDR1\x02\0\0\0", XDELTA_ERR_INVALID_ARG),
+    ] {
+        check(apply_patch_bytes(old, bad).err().map(|e| e.code()) == Some(code), "bad patch header")?;
+    }
+    Ok(())
+}
+
+/// An ADD_ZSTD record is refused without the feature.
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn add_zstd_refused_without_feature() -> Result<(), XDeltaError> {
+    check(
+        matches!(
+            apply_patch_bytes(b"", &with_header(&[0x03, 1, 0, 0, 0, 1, 0, 0, 0, 0])),
+            Err(XDeltaError::InvalidArg(_))
+        ),
+        "ADD_ZSTD refused without the feature",
+    )
+}
+
+/// An indexed patch leads with its INDEX record and gives any range of the
+/// output the unindexed one does, at and between index points, and so do
+/// `xdelta_create_patch_data_indexed` and `xdelta_apply_patch_range`.
+#[test]
+fn output_index() -> Result<(), XDeltaError> {
+    use crate::{xdelta_apply_patch_range, xdelta_create_patch_data_indexed};
+
+    let (old, new) = fixture();
+    let plain = PatchOptions::new().block_size(256);
+    let unindexed = create_patch_with(old, new, &plain)?;
+    let indexed = create_patch_with(old, new, &plain.clone().index_granularity(1024))?;
+    check(matches!(parsed_records(&indexed)?.first(), Some(crate::Record::Index(_))), "INDEX record first")?;
+    for start in [0, 1, 1023, 1024, 3333, 9000, new.len() - 100] {
+        let len = usize::min(700, new.len() - start);
+        let range = apply_range_bytes(old, &indexed, start as u64, len)?;
+        check(
+            range == new[start..start + len] && range == apply_range_bytes(old, &unindexed, start as u64, len)?,
+            "range apply of an indexed patch",
+        )?;
+    }
+
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_data_indexed(
+        old.as_ptr(),
+        old.len(),
+        new.as_ptr(),
+        new.len(),
+        &mut data,
+        &mut len,
+        256,
+        1024,
+    );
+    check(taken(rc, data, len) == (XDELTA_OK, indexed.clone()), "xdelta_create_patch_data_indexed")?;
+    let rc = xdelta_apply_patch_range(
+        old.as_ptr(),
+        old.len(),
+        indexed.as_ptr(),
+        indexed.len(),
+        5000,
+        500,
+        &mut data,
+        &mut len,
+    );
+    check(taken(rc, data, len) == (XDELTA_OK, new[5000..5500].to_vec()), "xdelta_apply_patch_range")
+}
+
+/// A short tile repeated into runs between literals goes into a
+/// CONST_TABLE once and is referenced by COPY_CONST records after, which
+/// makes the patch smaller and applies on every path.
+#[test]
+fn const_table() -> Result<(), XDeltaError> {
+    let new: Vec<u8> = (0..200).flat_map(|i| [filler(40, i), b"\xde\xad\xbe".repeat(8)].concat()).collect();
+    let plain = PatchOptions::new().block_size(64);
+    let without = create_patch_with(b"", &new, &plain)?;
+    let with = create_patch_with(b"", &new, &plain.clone().const_table(true))?;
+    let records = parsed_records(&with)?;
+    let tiled =
+        records.iter().filter(|record| matches!(record, crate::Record::CopyConst { index: 0, len: 24 })).count();
+    let mut streamed = Vec::new();
+    apply_streaming(&mut &b""[..], &with, &mut streamed, &ApplyOptions::new())?;
+    check(
+        matches!(records.first(), Some(crate::Record::ConstTable(_)))
+            && tiled == 200
+            && with.len() + 200 * 10 < without.len()
+            && apply_patch_bytes(b"", &with)? == new
+            && streamed == new
+            && apply_range_bytes(b"", &with, 50, 100)? == new[50..150],
+        "recurring tile through the constant table",
+    )
+}
+
+/// The constant table covers periodic runs only: a 3-byte sequence
+/// recurring alone between random literals leaves the patch as it was,
+/// while the same sequence repeated into 48-byte runs saves most of each
+/// run.
+#[test]
+fn const_table_periodic_runs_only() -> Result<(), XDeltaError> {
+    let opts = PatchOptions::new().block_size(64);
+    let gain = |tile_repeats: usize| -> Result<(usize, usize), XDeltaError> {
+        let new: Vec<u8> =
+            (0..500).flat_map(|i| [filler(30, i), b"\x01\x02\x03".repeat(tile_repeats)].concat()).collect();
+        let with = create_patch_with(b"", &new, &opts.clone().const_table(true))?;
+        check(apply_patch_bytes(b"", &with)? == new, "constant table round trip")?;
+        Ok((create_patch_with(b"", &new, &opts)?.len(), with.len()))
+    };
+    let (lone_without, lone_with) = gain(1)?;
+    let (runs_without, runs_with) = gain(16)?;
+    check(lone_with == lone_without, "isolated short sequences left as literals")?;
+    check(runs_with + 500 * 30 < runs_without, "periodic runs through the constant table")
+}
+
+/// Four sub-patches, each applied alone and written at its own offset,
+/// rebuild new; the FFI cuts the same four and reads the same offsets.
+#[test]
+fn split_into_sub_patches() -> Result<(), XDeltaError> {
+    use crate::split::{xdelta_split_patch, xdelta_sub_patch_offset};
+
+    let (old, new) = fixture();
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let parts = split_patch(&patch, 4)?;
+    let mut joined = vec![0u8; new.len()];
+    let mut offsets = Vec::new();
+    for part in &parts {
+        let offset = sub_patch_offset(part)? as usize;
+        let out = apply_patch_bytes(old, part)?;
+        check(offset + out.len() <= joined.len(), "sub-patch within new")?;
+        joined[offset..offset + out.len()].copy_from_slice(&out);
+        offsets.push(offset as u64);
+    }
+    check(
+        parts.len() == 4 && joined == new && offsets[0] == 0 && offsets.windows(2).all(|w| w[0] < w[1]),
+        "four sub-patches rebuild new",
+    )?;
+    check(split_patch(&patch, 0).is_err(), "zero parts refused")?;
+
+    let (mut subs, mut lens) = ([std::ptr::null_mut(); 4], [0usize; 4]);
+    let rc = xdelta_split_patch(patch.as_ptr(), patch.len(), 4, subs.as_mut_ptr(), lens.as_mut_ptr());
+    let mut same = rc == XDELTA_OK;
+    for ((&sub, &len), (part, &offset)) in subs.iter().zip(&lens).zip(parts.iter().zip(&offsets)) {
+        let mut at = u64::MAX;
+        same &= xdelta_sub_patch_offset(sub, len, &mut at) == XDELTA_OK && at == offset;
+        same &= taken(rc, sub, len).1 == *part;
+    }
+    check(same, "xdelta_split_patch")
+}
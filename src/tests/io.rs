@@ -0,0 +1,399 @@
+// src/tests/io.rs
+//! Files on disk, containers and foreign formats.
+
+use super::*;
+
+/// A VCDIFF delta assembled by hand in the layout xdelta3 writes: an
+/// application header, then a window with an Adler-32 that copies from old
+/// using paired instructions and every kind of address mode, a RUN, and
+/// COPYs from its own output, one overlapping what it writes; then a window
+/// copying from the output of the first.
+#[cfg(feature = "vcdiff")]
+const VCDIFF_FIXTURE: [u8; 58] = [
+    0xd6, 0xc3, 0xc4, 0x00, 0x04, 0x08, 0x6e, 0x65, 0x77, 0x2f, 0x6f, 0x6c, 0x64, 0x2f, 0x05, 0x24, 0x00, 0x19, 0x1e,
+    0x00, 0x04, 0x07, 0x05, 0x96, 0x97, 0x09, 0x1c, 0x58, 0x59, 0x21, 0x5a, 0xa8, 0x24, 0x45, 0x00, 0x03, 0xfd, 0x15,
+    0x00, 0x22, 0x14, 0x1e, 0x3c, 0x02, 0x08, 0x02, 0x0b, 0x0b, 0x00, 0x03, 0x02, 0x01, 0x65, 0x6e, 0x64, 0x18, 0x04,
+    0x00,
+];
+/// The old `VCDIFF_FIXTURE` applies to, and what it gives.
+#[cfg(feature = "vcdiff")]
+const VCDIFF_FIXTURE_OLD: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+#[cfg(feature = "vcdiff")]
+const VCDIFF_FIXTURE_NEW: &[u8] = b"XYabcdefklmn45678!!!4567ZZZZZZabcdefklend";
+
+/// VCDIFF deltas round trip through `apply_patch_vcdiff`, the fixture
+/// applies, and what can't be applied is refused with the right error.
+#[cfg(feature = "vcdiff")]
+#[test]
+fn vcdiff() -> Result<(), XDeltaError> {
+    use crate::vcdiff::xdelta_apply_patch_vcdiff;
+    use crate::{apply_patch_vcdiff, create_patch_vcdiff};
+
+    let (old, new) = fixture();
+    // several windows: 9 MiB of output in 4 MiB windows
+    let big_old = filler(9 << 20, 21);
+    let mut big_new = big_old.clone();
+    big_new[5 << 20..(5 << 20) + 4096].fill(0x5a);
+    let cases: [(&[u8], &[u8]); 4] = [(old, new), (&[], new), (old, &[]), (&big_old, &big_new)];
+    for (old, new) in cases {
+        check(apply_patch_vcdiff(old, &create_patch_vcdiff(old, new, 64)?)? == new, "vcdiff round trip")?;
+    }
+
+    let fixture = &VCDIFF_FIXTURE[..];
+    check(apply_patch_vcdiff(VCDIFF_FIXTURE_OLD, fixture)? == VCDIFF_FIXTURE_NEW, "vcdiff fixture")?;
+    let (mut out, mut out_len) = (std::ptr::null_mut(), 0);
+    let status = xdelta_apply_patch_vcdiff(
+        VCDIFF_FIXTURE_OLD.as_ptr(),
+        VCDIFF_FIXTURE_OLD.len(),
+        fixture.as_ptr(),
+        fixture.len(),
+        &mut out,
+        &mut out_len,
+    );
+    let same = status == XDELTA_OK && unsafe { std::slice::from_raw_parts(out, out_len) } == VCDIFF_FIXTURE_NEW;
+    xdelta_free_data(out);
+    check(same, "ffi vcdiff apply")?;
+
+    // a changed output byte fails the window checksum; a source segment
+    // past old, a stream cut inside a window (VCDIFF has no end marker, so
+    // one cut between windows is not noticed) and secondary compression are
+    // refused
+    let mut tampered = fixture.to_vec();
+    tampered[27] ^= 1; // 'X', first byte of the data section
+    // the header ends at 14, the first window at 43
+    let mut compressed = fixture[..5].to_vec();
+    compressed[4] = 0x01; // VCD_DECOMPRESS
+    compressed.push(2); // LZMA
+    let mut compressed_window = fixture.to_vec();
+    compressed_window[19] = 0x01; // Delta_Indicator: VCD_DATACOMP
+    check(
+        matches!(apply_patch_vcdiff(VCDIFF_FIXTURE_OLD, &tampered), Err(XDeltaError::MalformedPatch(_)))
+            && matches!(
+                apply_patch_vcdiff(&VCDIFF_FIXTURE_OLD[..35], fixture),
+                Err(XDeltaError::OldOutOfRange(_))
+            )
+            && (1..fixture.len())
+                .filter(|&n| n != 14 && n != 43)
+                .all(|n| rejected(&apply_patch_vcdiff(VCDIFF_FIXTURE_OLD, &fixture[..n])))
+            && matches!(apply_patch_vcdiff(VCDIFF_FIXTURE_OLD, &compressed), Err(XDeltaError::InvalidArg(_)))
+            && matches!(apply_patch_vcdiff(VCDIFF_FIXTURE_OLD, &compressed_window), Err(XDeltaError::InvalidArg(_))),
+        "vcdiff refusals",
+    )
+}
+
+/// A VCDIFF delta of ours decodes with the stock `xdelta3 -d` and one
+/// `xdelta3 -e` made applies here, across several windows too. Skipped when
+/// there is no `xdelta3` to run.
+#[cfg(feature = "vcdiff")]
+#[test]
+fn vcdiff_xdelta3() -> Result<(), XDeltaError> {
+    use crate::{apply_patch_vcdiff, create_patch_vcdiff};
+    use std::process::Command;
+
+    let (old, new) = fixture();
+    if Command::new("xdelta3").arg("-V").output().is_err() {
+        return Ok(());
+    }
+    let io = |e: std::io::Error| XDeltaError::Io(e.to_string());
+    let tmp = tempfile::tempdir().map_err(io)?;
+    let dir = tmp.path();
+    let big_old = filler(9 << 20, 21);
+    let mut big_new = big_old.clone();
+    big_new[5 << 20..(5 << 20) + 4096].fill(0x5a);
+    let cases: [(&[u8], &[u8]); 4] = [(old, new), (&[], new), (old, &[]), (&big_old, &big_new)];
+    let (mut decoded, mut applied) = (true, true);
+    for (old, new) in cases {
+        let (old_path, new_path) = (dir.join("old"), dir.join("new"));
+        let (patch_path, out_path) = (dir.join("patch"), dir.join("out"));
+        std::fs::write(&old_path, old).map_err(io)?;
+        std::fs::write(&new_path, new).map_err(io)?;
+        std::fs::write(&patch_path, create_patch_vcdiff(old, new, 64)?).map_err(io)?;
+        let status = Command::new("xdelta3")
+            .args(["-d", "-f", "-s"])
+            .args([&old_path, &patch_path, &out_path])
+            .status()
+            .map_err(io)?;
+        decoded &= status.success() && std::fs::read(&out_path).map_err(io)? == new;
+
+        // -S none: no secondary compression, which apply_patch_vcdiff refuses
+        let status = Command::new("xdelta3")
+            .args(["-e", "-f", "-S", "none", "-s"])
+            .args([&old_path, &new_path, &patch_path])
+            .status()
+            .map_err(io)?;
+        applied &= status.success() && apply_patch_vcdiff(old, &std::fs::read(&patch_path).map_err(io)?)? == new;
+    }
+    check(decoded, "vcdiff decodes with xdelta3")?;
+    check(applied, "xdelta3 delta applies")
+}
+
+/// The file-path FFI pair round trips through files on disk, empty ones
+/// too, and a missing file is an I/O error naming it.
+#[test]
+fn files() -> Result<(), XDeltaError> {
+    use crate::file::{xdelta_apply_patch_file, xdelta_create_patch_file};
+    use std::ffi::{CStr, CString};
+
+    let (old, new) = fixture();
+    let io = |e: std::io::Error| XDeltaError::Io(e.to_string());
+    let tmp = tempfile::tempdir().map_err(io)?;
+    let dir = tmp.path();
+    let path = |name: &str| CString::new(dir.join(name).to_string_lossy().into_owned()).unwrap();
+    let (old_path, new_path, patch_path, out_path) = (path("old"), path("new"), path("patch"), path("out"));
+    let mut round_trips = true;
+    for (old, new) in [(old, new), (&[][..], new), (old, &[][..])] {
+        std::fs::write(dir.join("old"), old).map_err(io)?;
+        std::fs::write(dir.join("new"), new).map_err(io)?;
+        round_trips &= xdelta_create_patch_file(old_path.as_ptr(), new_path.as_ptr(), patch_path.as_ptr(), 256)
+            == XDELTA_OK
+            && xdelta_apply_patch_file(old_path.as_ptr(), patch_path.as_ptr(), out_path.as_ptr()) == XDELTA_OK
+            && std::fs::read(dir.join("out")).map_err(io)? == new
+            && apply_patch_bytes(old, &std::fs::read(dir.join("patch")).map_err(io)?)? == new;
+    }
+    let missing = path("missing");
+    let status = xdelta_apply_patch_file(missing.as_ptr(), patch_path.as_ptr(), out_path.as_ptr());
+    let mut code = XDELTA_OK;
+    let message = unsafe { CStr::from_ptr(xdelta_last_error_detail(&mut code)) }.to_string_lossy().into_owned();
+    check(round_trips, "file round trip")?;
+    check(
+        status == XDELTA_ERR_IO && code == XDELTA_ERR_IO && message.contains(&*missing.to_string_lossy()),
+        "missing file",
+    )
+}
+
+/// Applying to a mapped base file writes what the in-memory apply returns,
+/// for a base of several MiB and an empty one; a patch with a broken header
+/// fails before the output file is created.
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap() -> Result<(), XDeltaError> {
+    use crate::file::xdelta_apply_patch_mmap;
+    use std::ffi::CString;
+
+    let io = |e: std::io::Error| XDeltaError::Io(e.to_string());
+    let tmp = tempfile::tempdir().map_err(io)?;
+    let dir = tmp.path();
+    let path = |name: &str| CString::new(dir.join(name).to_string_lossy().into_owned()).unwrap();
+    let (old_path, out_path) = (path("old"), path("out"));
+    let big = filler(8 << 20, 0x3e);
+    let mut edited = big.clone();
+    for at in (0..edited.len()).step_by(300_007) {
+        edited[at..at + 5].copy_from_slice(b"edit!");
+    }
+    let mut same = true;
+    for (old, new) in [(&big[..], &edited[..]), (&[][..], &b"from nothing"[..])] {
+        std::fs::write(dir.join("old"), old).map_err(io)?;
+        let patch = create_patch_with(old, new, &PatchOptions::new())?;
+        let rc = xdelta_apply_patch_mmap(old_path.as_ptr(), patch.as_ptr(), patch.len(), out_path.as_ptr());
+        same &= rc == XDELTA_OK && std::fs::read(dir.join("out")).map_err(io)? == apply_patch_bytes(old, &patch)?;
+    }
+    std::fs::remove_file(dir.join("out")).map_err(io)?;
+    let broken = xdelta_apply_patch_mmap(old_path.as_ptr(), b"XDR".as_ptr(), 3, out_path.as_ptr());
+    let created = dir.join("out").exists();
+    let null = xdelta_apply_patch_mmap(old_path.as_ptr(), std::ptr::null(), 1, out_path.as_ptr());
+    check(same, "mapped apply")?;
+    check(broken == XDELTA_ERR_MALFORMED_PATCH && !created && null == XDELTA_ERR_NULL_POINTER, "mapped apply errors")
+}
+
+/// `gzip -9` of the lines `GZIP_FIXTURE_WORDS` makes, with a dynamic Huffman block.
+#[cfg(feature = "gzip")]
+const GZIP_FIXTURE: [u8; 135] = [
+    0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x55, 0xcf, 0x5b, 0x0a, 0xc2, 0x40, 0x0c, 0x85, 0xe1,
+    0xf7, 0xac, 0x62, 0x96, 0xd0, 0x73, 0x5a, 0x6f, 0x84, 0x2e, 0x66, 0xc4, 0x41, 0x85, 0xa9, 0x16, 0xec, 0xfe, 0x69,
+    0x2b, 0x62, 0xce, 0xbc, 0x25, 0x81, 0x9f, 0x8f, 0xe4, 0x3a, 0x3f, 0x72, 0x1a, 0x53, 0xe7, 0x76, 0x2d, 0xcb, 0x3e,
+    0xc1, 0xed, 0x9e, 0xa7, 0x69, 0x1f, 0xe9, 0x76, 0x2b, 0xf5, 0x7b, 0xed, 0xdd, 0xca, 0xfc, 0x79, 0xd6, 0xf7, 0x6b,
+    0x5b, 0x06, 0xb7, 0xfc, 0xeb, 0x0e, 0xff, 0xee, 0x18, 0xdd, 0x29, 0xba, 0xb3, 0x76, 0x97, 0xe8, 0x20, 0xa0, 0x88,
+    0x10, 0x12, 0x8d, 0x09, 0x41, 0x11, 0x2a, 0x84, 0x85, 0xb8, 0x68, 0x60, 0x88, 0xcc, 0x90, 0xa9, 0xbf, 0x8a, 0xcc,
+    0x46, 0xa6, 0xc8, 0x0c, 0x99, 0x22, 0x53, 0x64, 0x36, 0x32, 0x37, 0x79, 0x05, 0x10, 0x34, 0x2f, 0x29, 0x64, 0x01,
+    0x00, 0x00,
+];
+
+/// Words of the lines `GZIP_FIXTURE` holds: `word = index;` for each in turn, six times over.
+#[cfg(feature = "gzip")]
+const GZIP_FIXTURE_WORDS: [&str; 5] = ["alpha", "beta", "gamma", "delta", "epsilon"];
+
+/// A gzip-wrapped patch inflates back to the patch and applies, through
+/// `apply_patch` and `xdelta_apply_patch_data`, as the bare one does; the
+/// fixture from zlib and concatenated members inflate, and a corrupt or
+/// truncated container is a malformed patch.
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip() -> Result<(), XDeltaError> {
+    use crate::gzip::{xdelta_compress_patch, xdelta_decompress_patch};
+    use crate::{apply_patch, compress_patch, decompress_patch};
+
+    let (old, new) = fixture();
+    let ffi_apply = |patch: &[u8]| {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+        let rc = xdelta_apply_patch_data(old.as_ptr(), old.len(), patch.as_ptr(), patch.len(), &mut data, &mut len);
+        let out = (rc == XDELTA_OK).then(|| unsafe { std::slice::from_raw_parts(data, len) }.to_vec());
+        xdelta_free_data(data);
+        (rc, out)
+    };
+    let patch = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    let blob = compress_patch(&patch);
+    check(
+        blob.starts_with(&[0x1f, 0x8b])
+            && decompress_patch(&blob)? == patch
+            && apply_patch(old, &blob)? == new
+            && ffi_apply(&blob) == ffi_apply(&patch)
+            && ffi_apply(&blob).1.as_deref() == Some(new),
+        "gzip-wrapped patch applies as the bare one",
+    )?;
+
+    let text: Vec<u8> = (0..3000).flat_map(|i| format!("key_{} = value_{};\n", i % 40, i % 7).into_bytes()).collect();
+    let text_patch = create_patch_with(b"", &text, &PatchOptions::new().block_size(64))?;
+    let text_blob = compress_patch(&text_patch);
+    check(
+        text_blob.len() * 4 < text_patch.len() && decompress_patch(&text_blob)? == text_patch,
+        "gzip shrinks a patch of text",
+    )?;
+
+    let lines: String = (0..30).map(|i| format!("{} = {};\n", GZIP_FIXTURE_WORDS[i % 5], i)).collect();
+    let members = [compress_patch(b"first "), compress_patch(b""), compress_patch(b"second")].concat();
+    check(
+        decompress_patch(&GZIP_FIXTURE)? == lines.as_bytes() && decompress_patch(&members)? == b"first second",
+        "gzip fixture and concatenated members",
+    )?;
+
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let rc = xdelta_compress_patch(patch.as_ptr(), patch.len(), &mut data, &mut len);
+    let ffi_blob = (rc == XDELTA_OK).then(|| unsafe { std::slice::from_raw_parts(data, len) }.to_vec());
+    xdelta_free_data(data);
+    let (mut data, mut len) = (std::ptr::null_mut(), 0usize);
+    let rc = xdelta_decompress_patch(blob.as_ptr(), blob.len(), &mut data, &mut len);
+    let ffi_patch = (rc == XDELTA_OK).then(|| unsafe { std::slice::from_raw_parts(data, len) }.to_vec());
+    xdelta_free_data(data);
+    check(ffi_blob.as_ref() == Some(&blob) && ffi_patch.as_ref() == Some(&patch), "gzip through FFI")?;
+
+    let mut bad_crc = blob.clone();
+    let at = bad_crc.len() - 8;
+    bad_crc[at] ^= 1;
+    let mut bad_data = text_blob.clone();
+    bad_data[20] ^= 0x40;
+    let malformed = |r: Result<Vec<u8>, XDeltaError>| matches!(r, Err(XDeltaError::MalformedPatch(_)));
+    let streamed = apply_streaming(&mut &old[..], &blob, &mut Vec::new(), &ApplyOptions::new());
+    check(
+        malformed(decompress_patch(&bad_crc))
+            && malformed(decompress_patch(&bad_data))
+            && malformed(decompress_patch(&blob[..blob.len() - 1]))
+            && malformed(decompress_patch(&[&blob[..], &[0]].concat()))
+            && malformed(apply_patch(old, &bad_crc))
+            && ffi_apply(&bad_crc).0 == XDELTA_ERR_MALFORMED_PATCH
+            && matches!(streamed, Err(XDeltaError::MalformedPatch(ref m)) if m.contains("gzip container")),
+        "corrupt gzip containers refused",
+    )
+}
+
+/// Similar JSON messages delta to a fraction of their size against a
+/// representative dictionary, far smaller than against an empty base, and
+/// one dictionary serves every call, threads included, without changing.
+#[test]
+fn dictionary() -> Result<(), XDeltaError> {
+    let message = |i: u32| {
+        format!(
+            r#"{{"event":"order.created","version":3,"order":{{"id":{},"customer":"cust-{:05}","currency":"EUR","#,
+            100_000 + i * 37,
+            i * 7919 % 100_000
+        ) + &format!(
+            r#""items":[{{"sku":"SKU-{:04}","quantity":{},"unit_price":{}.99}}],"shipping":{{"method":"standard","#,
+            i * 13 % 10_000,
+            1 + i % 5,
+            5 + i % 90
+        ) + &format!(
+            r#""country":"{}"}},"status":"pending","source":"web-checkout"}}}}"#,
+            ["DE", "FR", "NL"][i as usize % 3]
+        )
+    };
+    let dict = message(0).into_bytes();
+    let pristine = dict.clone();
+    let messages: Vec<Vec<u8>> = (1..=40).map(|i| message(i).into_bytes()).collect();
+
+    let (mut with_dict, mut without, mut raw) = (0, 0, 0);
+    for msg in &messages {
+        let patch = create_patch_with_dictionary(&dict, msg, 8)?;
+        check(apply_patch_with_dictionary(&dict, &patch)? == *msg, "message rebuilt from the dictionary")?;
+        with_dict += patch.len();
+        without += create_patch_with(&[], msg, &PatchOptions::new().block_size(8))?.len();
+        raw += msg.len();
+    }
+    check(with_dict * 3 < without * 2 && with_dict < raw, "dictionary patches smaller than empty-base ones")?;
+
+    let shared = &dict;
+    let rebuilt = std::thread::scope(|scope| {
+        let workers: Vec<_> = messages
+            .chunks(10)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk.iter().all(|msg| {
+                        create_patch_with_dictionary(shared, msg, 8)
+                            .and_then(|patch| apply_patch_with_dictionary(shared, &patch))
+                            .is_ok_and(|out| out == *msg)
+                    })
+                })
+            })
+            .collect();
+        workers.into_iter().all(|w| w.join().unwrap_or(false))
+    });
+    check(rebuilt && dict == pristine, "one dictionary shared across threads, unchanged")?;
+
+    let msg = &messages[0];
+    let (mut patch, mut patch_len) = (std::ptr::null_mut(), 0usize);
+    let (dict_ptr, msg_ptr, len) = (dict.as_ptr(), msg.as_ptr(), msg.len());
+    let rc = xdelta_create_patch_with_dictionary(dict_ptr, dict.len(), msg_ptr, len, &mut patch, &mut patch_len, 8);
+    check(rc == XDELTA_OK, "dictionary patch through FFI")?;
+    let (mut out, mut out_len) = (std::ptr::null_mut(), 0usize);
+    let rc = xdelta_apply_patch_with_dictionary(dict_ptr, dict.len(), patch, patch_len, &mut out, &mut out_len);
+    let same = rc == XDELTA_OK
+        && unsafe { std::slice::from_raw_parts(patch, patch_len) } == create_patch_with_dictionary(&dict, msg, 8)?
+        && unsafe { std::slice::from_raw_parts(out, out_len) } == msg.as_slice();
+    xdelta_free_data(patch);
+    xdelta_free_data(out);
+    check(same, "dictionary round trip through FFI")?;
+    let rc = xdelta_create_patch_with_dictionary(std::ptr::null(), 1, msg_ptr, len, &mut patch, &mut patch_len, 8);
+    check(rc == XDELTA_ERR_NULL_POINTER, "null dictionary")
+}
+
+/// A pack hands back each revision whole, diffs any two of them, adjacent
+/// or not, and refuses duplicate or missing revisions; the FFI builds and
+/// diffs the same.
+#[test]
+fn pack() -> Result<(), XDeltaError> {
+    use crate::pack::{xdelta_pack_build, xdelta_pack_diff};
+    use crate::{build_pack, pack_diff, pack_revision};
+
+    let first = filler(20_000, 0x9ac);
+    let mut second = first.clone();
+    second[5000..5100].fill(0x22);
+    let mut third = second[..12_000].to_vec();
+    third.extend_from_slice(&filler(700, 0x9ad));
+    third.extend_from_slice(&second[12_000..]);
+    let revs: [(u64, &[u8]); 3] = [(10, &first), (20, &second), (30, &third)];
+    let pack = build_pack(&revs)?;
+    let opts = PatchOptions::new().block_size(256);
+    let skip = pack_diff(&pack, 10, 30, &opts)?;
+    let back = pack_diff(&pack, 30, 20, &opts)?;
+    check(
+        revs.iter().all(|&(id, data)| pack_revision(&pack, id).is_ok_and(|rev| rev == data))
+            && skip == create_patch_with(&first, &third, &opts)?
+            && apply_patch_bytes(&first, &skip)? == third
+            && apply_patch_bytes(&third, &back)? == second,
+        "pack revisions and diffs",
+    )?;
+    check(
+        build_pack(&[(1, &first), (1, &second)]).is_err()
+            && pack_revision(&pack, 40).is_err()
+            && pack_diff(&pack, 10, 40, &opts).is_err()
+            && pack_revision(&first, 10).is_err(),
+        "bad revisions refused",
+    )?;
+
+    let ids = revs.map(|(id, _)| id);
+    let datas = revs.map(|(_, data)| data.as_ptr());
+    let lens = revs.map(|(_, data)| data.len());
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_pack_build(ids.as_ptr(), datas.as_ptr(), lens.as_ptr(), 3, &mut data, &mut len);
+    check(taken(rc, data, len) == (XDELTA_OK, pack.clone()), "xdelta_pack_build")?;
+    let rc = xdelta_pack_diff(pack.as_ptr(), pack.len(), 10, 30, std::ptr::null(), &mut data, &mut len);
+    check(taken(rc, data, len) == (XDELTA_OK, pack_diff(&pack, 10, 30, &PatchOptions::default().block_size(0))?), "xdelta_pack_diff")
+}
@@ -0,0 +1,177 @@
+// src/tests/mod.rs
+//! Unit tests, one module per area of the crate. `xdelta_self_test` keeps
+//! only the round trip integrators run in the field; everything else is
+//! checked here.
+
+mod apply;
+mod checksum;
+mod create;
+mod ffi;
+mod format;
+mod io;
+mod self_test;
+mod signature;
+
+use crate::dictionary::{xdelta_apply_patch_with_dictionary, xdelta_create_patch_with_dictionary};
+use crate::output::xdelta_apply_patch_into;
+use crate::result::{
+    xdelta_create_patch_batch, xdelta_create_patch_batch_handles, xdelta_free_batch, xdelta_result_data,
+    xdelta_result_free, xdelta_result_len, xdelta_result_status, XdeltaBatchEntry, XdeltaPatchPair,
+};
+use crate::signature::{
+    xdelta_build_signature, xdelta_create_patch_from_signature, xdelta_signature_create_patch, xdelta_signature_free,
+    xdelta_signature_new, xdelta_signatures_equal,
+};
+use crate::{
+    apply_patch_bytes, apply_patch_limited, apply_patch_prefix, apply_patch_verify, apply_patch_with_dictionary,
+    apply_range_bytes, apply_sparse, apply_streaming, apply_then_diff, apply_to, apply_with_signature, auto_block_size,
+    block_strong_hash, build_signature_bytes, build_signatures, build_signatures_on, create_patch_bidirectional,
+    create_patch_from_signature, create_patch_sparse, create_patch_with, create_patch_with_dictionary,
+    create_patch_with_matches, create_patch_with_progress, create_patch_with_stats, ffi_status, match_blocks,
+    old_ranges_merged, opcode_histogram, patch_info, patch_records, patch_uses_only, should_patch, split_patch,
+    sub_patch_offset, validate_patch, with_header, xdelta_apply_patch_data, xdelta_apply_patch_data_limited,
+    xdelta_apply_patch_prefix, xdelta_apply_patch_verify, xdelta_block_strong_hash, xdelta_create_patch_auto,
+    xdelta_create_patch_data, xdelta_create_patch_data_ex, xdelta_clear_last_error, xdelta_create_patch_data_into,
+    xdelta_create_patch_data_opts, xdelta_create_patch_data_progress, xdelta_free_data, xdelta_last_error,
+    xdelta_last_error_detail, xdelta_last_error_len, xdelta_validate_patch, Adler32, ApplyContext, ApplyFeed,
+    ApplyOptions, ApplyOutput, CopyMatch, HashAlgo, MatchHooks, OldSource, OpcodeStat, PatchInfo, PatchOp, PatchOptions,
+    PatchReader, Quality, Rolling, SigEntry, Signature, SparseOld, TailPolicy, VerifyOld, WeakAlgo, WeakChecksum,
+    WeakIndex, XDeltaError, XdeltaOptions, PATCH_HEADER_LEN, PATCH_HEADER_VERSION, XDELTA_CREATE_COMPRESS,
+    XDELTA_ERR_BUFFER_TOO_SMALL, XDELTA_ERR_CHECKSUM_MISMATCH, XDELTA_ERR_INVALID_ARG, XDELTA_ERR_IO,
+    XDELTA_ERR_MALFORMED_PATCH, XDELTA_ERR_NEW_HASH_MISMATCH, XDELTA_ERR_NO_MEMORY, XDELTA_ERR_NULL_POINTER,
+    XDELTA_ERR_OLD_OUT_OF_RANGE, XDELTA_ERR_OUTPUT_LIMIT, XDELTA_FORMAT_VERSION, XDELTA_HASH_BLAKE3, XDELTA_OK,
+    XDELTA_OPCODES_BASELINE, XDELTA_OPCODE_ADD_SMALL, XDELTA_OPT_ADLER32, XDELTA_OPT_CRC_PRECHECK,
+    XDELTA_OPT_ALIGNED, XDELTA_OPT_FORWARD_ONLY, XDELTA_OPT_RECORD_CRC, XDELTA_OPT_STRICT,
+};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::os::raw::c_int;
+
+use crate::self_test::{check, check_rolling, filler};
+use crate::allocator::{xdelta_set_allocator, XdeltaFreeFn, XdeltaMallocFn};
+use std::cell::Cell;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// The old and new `xdelta_self_test` round-trips, built once.
+fn fixture() -> (&'static [u8], &'static [u8]) {
+    static FIXTURE: OnceLock<(Vec<u8>, Vec<u8>)> = OnceLock::new();
+    let (old, new) = FIXTURE.get_or_init(crate::self_test::fixture);
+    (old, new)
+}
+
+/// The options most tests create with.
+fn plain() -> PatchOptions {
+    PatchOptions::new().block_size(256)
+}
+
+/// `plain` with the optional records and the best quality: newer opcodes,
+/// and a patch that isn't a bare header even for old == new.
+fn full() -> PatchOptions {
+    plain()
+        .near_miss_diff(true)
+        .old_hash(true)
+        .const_table(true)
+        .index_granularity(1024)
+        .embed_block_size(true)
+        .quality(Quality::Best)
+}
+
+/// `count` distinct 64-byte blocks sharing one weak checksum: a base block
+/// with `+s, -2s, +s` added at different places, which leaves both of the
+/// checksum's sums unchanged.
+fn colliding_blocks(count: usize) -> Vec<Vec<u8>> {
+    let base: Vec<u8> = filler(64, 5).iter().map(|&b| b % 250 + 3).collect();
+    let mut blocks = vec![base.clone()];
+    for (at, s) in (0..62).flat_map(|at| [(at, 1i16), (at, -1)]).take(count.saturating_sub(1)) {
+        let mut block = base.clone();
+        for (i, d) in [s, -2 * s, s].into_iter().enumerate() {
+            block[at + i] = (block[at + i] as i16 + d) as u8;
+        }
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// Whether an apply of a hostile patch was refused as malformed or as
+/// reading outside old, rather than succeeding or failing some other way.
+fn rejected<T>(r: &Result<T, XDeltaError>) -> bool {
+    matches!(r, Err(XDeltaError::MalformedPatch(_) | XDeltaError::OldOutOfRange(_)))
+}
+
+/// The records of `patch`, parsed.
+fn parsed_records(patch: &[u8]) -> Result<Vec<crate::Record<'_>>, XDeltaError> {
+    let records = patch_records(patch)?;
+    let (mut parsed, mut pos) = (Vec::new(), 0);
+    while pos < records.len() {
+        let (record, next) = crate::read_record(records, pos)?;
+        parsed.push(record);
+        pos = next;
+    }
+    Ok(parsed)
+}
+
+/// The bytes an FFI call handed back through `data` and `len`, which are
+/// freed; empty unless `rc` is `XDELTA_OK`.
+fn taken(rc: c_int, data: *mut u8, len: usize) -> (c_int, Vec<u8>) {
+    let bytes = if rc == XDELTA_OK { unsafe { std::slice::from_raw_parts(data, len) }.to_vec() } else { Vec::new() };
+    xdelta_free_data(data);
+    (rc, bytes)
+}
+
+thread_local! {
+    /// Allocations and frees through `counting_malloc` and `counting_free`
+    /// on this thread; tests on other threads allocate through them too
+    /// while they are installed.
+    static COUNTED: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// Held by the tests that swap the FFI allocator.
+static ALLOCATOR_SWAP: Mutex<()> = Mutex::new(());
+
+unsafe extern "C" fn counting_malloc(size: usize) -> *mut std::ffi::c_void {
+    COUNTED.with(|c| c.set((c.get().0 + 1, c.get().1)));
+    unsafe { libc::malloc(size) }
+}
+
+unsafe extern "C" fn counting_free(ptr: *mut std::ffi::c_void) {
+    COUNTED.with(|c| c.set((c.get().0, c.get().1 + 1)));
+    unsafe { libc::free(ptr) }
+}
+
+/// Install the counting allocator (or, with `counting` false, the default
+/// one), waiting for other tests to give back the buffers they have out.
+fn swap_allocator(counting: bool) -> bool {
+    let pair: (Option<XdeltaMallocFn>, Option<XdeltaFreeFn>) =
+        if counting { (Some(counting_malloc), Some(counting_free)) } else { (None, None) };
+    (0..5000).any(|_| {
+        let swapped = xdelta_set_allocator(pair.0, pair.1) == XDELTA_OK;
+        if !swapped {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        swapped
+    })
+}
+
+/// Old and the output of a callback apply, for `read_io` and `write_io`,
+/// with the length of every write.
+struct CallbackIo<'a> {
+    old: &'a [u8],
+    out: Vec<u8>,
+    writes: Vec<usize>,
+}
+
+extern "C" fn read_io(offset: u64, buf: *mut u8, len: usize, ctx: *mut std::ffi::c_void) -> c_int {
+    let io = unsafe { &mut *(ctx as *mut CallbackIo) };
+    let Some(data) = usize::try_from(offset).ok().and_then(|at| io.old.get(at..at.checked_add(len)?)) else {
+        return -1;
+    };
+    unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buf, len) };
+    0
+}
+
+extern "C" fn write_io(data: *const u8, len: usize, ctx: *mut std::ffi::c_void) -> c_int {
+    let io = unsafe { &mut *(ctx as *mut CallbackIo) };
+    io.out.extend_from_slice(unsafe { std::slice::from_raw_parts(data, len) });
+    io.writes.push(len);
+    0
+}
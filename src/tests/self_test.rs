@@ -0,0 +1,20 @@
+// src/tests/self_test.rs
+//! The exported self test itself.
+
+use super::*;
+use crate::self_test::{run_self_test, xdelta_self_test};
+
+#[test]
+fn self_test_passes() {
+    assert_eq!(xdelta_self_test(), XDELTA_OK);
+}
+
+/// A patch corrupted between create and apply fails the self test: a
+/// flipped last byte changes the output, a cut one doesn't parse.
+#[test]
+fn self_test_catches_a_corrupt_patch() {
+    let flipped = |patch: &mut Vec<u8>| *patch.last_mut().unwrap() ^= 0x5a;
+    let cut = |patch: &mut Vec<u8>| patch.truncate(patch.len() - 1);
+    assert_ne!(ffi_status(run_self_test(flipped)), XDELTA_OK);
+    assert_ne!(ffi_status(run_self_test(cut)), XDELTA_OK);
+}
@@ -0,0 +1,469 @@
+// src/tests/signature.rs
+//! Signatures of old: building, serializing and matching against them.
+
+use super::*;
+
+/// Hashing the blocks of old on several threads gives the serial map, each
+/// weak bucket in block order, and so the serial patch. Old repeats itself so
+/// buckets hold several blocks, and ends in a short block for the tail policy.
+#[test]
+fn parallel_signatures() -> Result<(), XDeltaError> {
+    let unit = filler(3000, 11);
+    let old: Vec<u8> = unit.iter().cycle().take(8 * unit.len() + 100).copied().collect();
+    let mut new = old.clone();
+    new[5000] ^= 1;
+    new.extend_from_slice(&filler(700, 12));
+    // bucket by bucket, each in its stored order
+    let buckets = |sigs: &HashMap<u32, Vec<crate::SigEntry>>| {
+        let mut all: Vec<(u32, u64, [u8; 32])> =
+            sigs.iter().flat_map(|(&weak, es)| es.iter().map(move |e| (weak, e.block_index, e.strong_hash))).collect();
+        all.sort_by_key(|&(weak, _, _)| weak);
+        all
+    };
+    for tail in [TailPolicy::AsIs, TailPolicy::Pad, TailPolicy::Skip] {
+        let opts = PatchOptions::new().block_size(500).tail_policy(tail);
+        let mut serial = (HashMap::new(), Vec::new());
+        build_signatures_on(&mut serial.0, &old[..], 500, tail, HashAlgo::Sha256, WeakAlgo::Rolling, 1, 1);
+        match_blocks(&old[..], &new, &opts, &serial.0, &mut serial.1, &mut Vec::new(), MatchHooks::default())?;
+        check(serial.0.values().any(|es| es.len() == 8), "repeated blocks share a bucket")?;
+        check(apply_patch_bytes(&old, &with_header(&serial.1))? == new, "serial signature patch")?;
+        for threads in [2, 3, 8] {
+            let mut sigs = HashMap::new();
+            build_signatures_on(&mut sigs, &old[..], 500, tail, HashAlgo::Sha256, WeakAlgo::Rolling, 1, threads);
+            let mut patch = Vec::new();
+            match_blocks(&old[..], &new, &opts, &sigs, &mut patch, &mut Vec::new(), MatchHooks::default())?;
+            check(buckets(&sigs) == buckets(&serial.0) && patch == serial.1, "parallel signatures")?;
+        }
+    }
+    Ok(())
+}
+
+/// Of blocks of old with the same contents, the one a COPY points at is the
+/// lowest-numbered: every weak bucket is in ascending block order however
+/// many threads hashed it, and the matcher takes the first that confirms.
+#[test]
+fn signature_order() -> Result<(), XDeltaError> {
+    let dup = filler(256, 15);
+    let mut old = filler(10 * 256, 16);
+    for idx in [9, 2, 5] {
+        old[idx * 256..(idx + 1) * 256].copy_from_slice(&dup);
+    }
+    let mut new = filler(300, 17);
+    new.extend_from_slice(&dup);
+    new.extend_from_slice(&filler(300, 18));
+    let opts = PatchOptions::new().block_size(256);
+    for threads in [1, 2, 3, 8] {
+        let mut sigs = HashMap::new();
+        let (tail, algo, weak) = (TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+        build_signatures_on(&mut sigs, &old[..], 256, tail, algo, weak, 1, threads);
+        let ascending = sigs.values().all(|es| es.windows(2).all(|w| w[0].block_index < w[1].block_index));
+        let dup_hash = block_strong_hash(&dup, HashAlgo::Sha256);
+        let dups: Vec<u64> =
+            sigs.values().flatten().filter(|e| e.strong_hash == dup_hash).map(|e| e.block_index).collect();
+        check(ascending && dups == [2, 5, 9], "signature buckets in ascending block order")?;
+    }
+    let mut matches = Vec::new();
+    let patch = create_patch_with_matches(&old, &new, &opts, |m| matches.push(m))?;
+    check(apply_patch_bytes(&old, &patch)? == new, "duplicate blocks apply")?;
+    check(
+        matches == [CopyMatch { new_offset: 300, old_offset: 2 * 256, len: 256 }],
+        "duplicate block copied from the lowest-numbered one",
+    )
+}
+
+/// The short last window of new is matched against the short last block of
+/// old: new equal to old, whose length is not a multiple of the block size,
+/// is one COPY (the matcher is run without the identity shortcut), and a
+/// tail of old behind novel data is copied too. A skipped tail never is.
+#[test]
+fn tail_copy() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let old = &old[..1000];
+    let mut moved = filler(500, 14);
+    moved.extend_from_slice(&old[900..]);
+    let whole = CopyMatch { new_offset: 0, old_offset: 0, len: 1000 };
+    let tail = CopyMatch { new_offset: 500, old_offset: 900, len: 100 };
+    for policy in [TailPolicy::AsIs, TailPolicy::Pad, TailPolicy::Skip] {
+        let opts = PatchOptions::new().block_size(300).tail_policy(policy);
+        let mut same = Vec::new();
+        let patch = create_patch_with_matches(old, old, &opts, |m| same.push(m))?;
+        check(apply_patch_bytes(old, &patch)? == old, "tail copy apply")?;
+        let mut behind = Vec::new();
+        let patch = create_patch_with_matches(old, &moved, &opts, |m| behind.push(m))?;
+        check(apply_patch_bytes(old, &patch)? == moved, "tail copy apply")?;
+        let copied = match policy {
+            TailPolicy::Skip => same == [CopyMatch { len: 900, ..whole }] && behind.is_empty(),
+            _ => same == [whole] && behind == [tail],
+        };
+        check(copied, "tail copied")?;
+    }
+    Ok(())
+}
+
+/// One signature handle of old serves patches to several news, each applying
+/// to old and matching what the serialized signature gives.
+#[test]
+fn signature_handle() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let sig = xdelta_signature_new(old.as_ptr(), old.len(), 256);
+    check(!sig.is_null(), "signature handle")?;
+    let mut grown = old.to_vec();
+    grown.extend_from_slice(&filler(3000, 15));
+    let mut shuffled = old[8192..].to_vec();
+    shuffled.extend_from_slice(&old[..8192]);
+    let sig_bytes = build_signature_bytes(old, 256)?;
+    let mut ok = true;
+    for target in [new, &grown, &shuffled, &old[..5000], &[]] {
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        let rc = xdelta_signature_create_patch(sig, target.as_ptr(), target.len(), &mut data, &mut len);
+        if rc != XDELTA_OK {
+            ok = false;
+            continue;
+        }
+        let patch = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+        xdelta_free_data(data);
+        ok &= apply_patch_bytes(old, &patch)? == target && patch == create_patch_from_signature(&sig_bytes, target)?;
+    }
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let null_sig = xdelta_signature_create_patch(std::ptr::null(), new.as_ptr(), new.len(), &mut data, &mut len);
+    xdelta_signature_free(sig);
+    check(ok && null_sig == XDELTA_ERR_NULL_POINTER, "patches from one signature handle")
+}
+
+/// A block size of 0 picks one from old's length, records it in a
+/// BLOCK_SIZE record, and `xdelta_create_patch_auto` reports it; the patches
+/// apply like any other.
+#[test]
+fn auto_block_size_picked() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let sizes = [(0, 512), (1000, 512), (1 << 20, 1024), ((1 << 22) - 1, 2048), (3 << 20, 2048), (1 << 30, 32768)];
+    let picked = sizes.iter().all(|&(len, size)| auto_block_size(len) == size);
+    check(picked && auto_block_size(usize::MAX) == 65536, "auto block size")?;
+    let big_old = old.repeat(80);
+    let mut big_new = big_old.clone();
+    big_new[500_000..500_000 + new.len()].copy_from_slice(new);
+    for (old, new) in [(old, new), (&old[..300], &new[..200]), (&big_old[..], &big_new[..]), (old, &[][..])] {
+        let patch = create_patch_with(old, new, &PatchOptions::new().block_size(0))?;
+        let expected = auto_block_size(old.len()) as u32;
+        let records = patch_records(&patch)?;
+        let recorded = records[0] == 0x85 && records[5..][..4] == expected.to_le_bytes();
+        check(recorded && apply_patch_bytes(old, &patch)? == new, "auto block size patch")?;
+        let (mut data, mut len, mut used) = (std::ptr::null_mut(), 0usize, 0u32);
+        let rc = xdelta_create_patch_auto(
+            old.as_ptr(),
+            old.len(),
+            new.as_ptr(),
+            new.len(),
+            std::ptr::null(),
+            &mut data,
+            &mut len,
+            &mut used,
+        );
+        let ffi_patch = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+        xdelta_free_data(data);
+        check(rc == XDELTA_OK && used == expected && ffi_patch == patch, "xdelta_create_patch_auto")?;
+    }
+    // an explicit block size is kept, and not recorded unless asked for
+    let opts = XdeltaOptions { block_size: 256, ..XdeltaOptions::default() };
+    let (mut data, mut len, mut used) = (std::ptr::null_mut(), 0usize, 0u32);
+    let (old_ptr, new_ptr) = (old.as_ptr(), new.as_ptr());
+    let rc = xdelta_create_patch_auto(old_ptr, old.len(), new_ptr, new.len(), &opts, &mut data, &mut len, &mut used);
+    let ffi_patch = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+    xdelta_free_data(data);
+    let plain = create_patch_with(old, new, &PatchOptions::new().block_size(256))?;
+    check(rc == XDELTA_OK && used == 256 && ffi_patch == plain, "xdelta_create_patch_auto with a block size")
+}
+
+#[test]
+fn sample_blocks() -> Result<(), XDeltaError> {
+    // old with a few bytes changed, a stretch inserted and a stretch moved
+    let old = filler(256 * 1024, 0x5a3e);
+    let mut new = old[..40_000].to_vec();
+    new.extend_from_slice(b"inserted");
+    new.extend_from_slice(&old[40_000..100_000]);
+    new.extend_from_slice(&old[200_000..]);
+    new.extend_from_slice(&old[100_000..200_000]);
+    for at in [5_000, 70_001, 150_123, 230_000] {
+        new[at] ^= 0x55;
+    }
+
+    let memory = |every: usize| {
+        let mut sigs = HashMap::new();
+        let tail = TailPolicy::AsIs;
+        build_signatures_on(&mut sigs, &old[..], 256, tail, HashAlgo::Sha256, WeakAlgo::Rolling, every, 1);
+        sigs.values().map(Vec::len).sum::<usize>() * std::mem::size_of::<SigEntry>()
+    };
+    let (full_memory, sampled_memory) = (memory(1), memory(8));
+    check(sampled_memory * 8 <= full_memory + 8 * std::mem::size_of::<SigEntry>(), "sampled signature memory")?;
+
+    let opts = PatchOptions::new().block_size(256);
+    let full = create_patch_with(&old, &new, &opts)?;
+    let sampled = create_patch_with(&old, &new, &opts.clone().sample_blocks(8))?;
+    let (full_info, sampled_info) = (patch_info(&full)?, patch_info(&sampled)?);
+    // each of the 4 edits costs at most a sampling stride of literals more
+    let stride = 8 * 256;
+    check(
+        apply_patch_bytes(&old, &sampled)? == new
+            && sampled_info.sampled
+            && !full_info.sampled
+            && sampled.len() <= full.len() + 4 * stride
+            && sampled_info.copied_bytes + 4 * stride as u64 >= full_info.copied_bytes,
+        "sampled signatures round-trip, extended around the blocks they index",
+    )?;
+    check(create_patch_with(&old, &new, &opts.clone().sample_blocks(1))? == full, "sample_blocks(1) indexes all")?;
+    let refused = create_patch_with(&old, &new, &opts.clone().sample_blocks(0));
+    check(matches!(refused, Err(XDeltaError::InvalidArg(_))), "sample_blocks(0)")?;
+
+    let c_opts = XdeltaOptions { block_size: 256, sample_blocks: 8, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc = xdelta_create_patch_data_opts(
+        old.as_ptr(),
+        old.len(),
+        new.as_ptr(),
+        new.len(),
+        &c_opts,
+        &mut data,
+        &mut len,
+    );
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == sampled;
+    xdelta_free_data(data);
+    check(same, "sample_blocks through XdeltaOptions")
+}
+
+#[test]
+fn max_candidates() -> Result<(), XDeltaError> {
+    // 1024 identical blocks, and new breaking the COPY after every block,
+    // so each block of new is looked up again among all of them
+    let block = filler(64, 0xca9d);
+    let old = block.repeat(1024);
+    let new: Vec<u8> = (0..512u32).flat_map(|i| block.iter().copied().chain([i as u8])).collect();
+
+    let mut sigs = HashMap::new();
+    build_signatures(&mut sigs, &old[..], 64, TailPolicy::AsIs, HashAlgo::Sha256, WeakAlgo::Rolling);
+    let compared = |max: usize| -> Result<(usize, usize, Vec<u8>), XDeltaError> {
+        let (mut lookups, mut candidates, mut patch) = (0usize, 0usize, Vec::new());
+        let opts = PatchOptions::new().block_size(64).quality(Quality::Best).max_candidates(max);
+        let mut on_strong_hash = || lookups += 1;
+        let mut on_candidate = || candidates += 1;
+        let hooks = MatchHooks {
+            on_strong_hash: Some(&mut on_strong_hash),
+            on_candidate: Some(&mut on_candidate),
+            ..MatchHooks::default()
+        };
+        match_blocks(&old[..], &new, &opts, &sigs, &mut patch, &mut Vec::new(), hooks)?;
+        Ok((lookups, candidates, patch))
+    };
+    let (lookups, capped, patch) = compared(crate::DEFAULT_MAX_CANDIDATES)?;
+    let (_, uncapped, _) = compared(usize::MAX)?;
+    check(
+        lookups >= 256 && capped <= lookups * crate::DEFAULT_MAX_CANDIDATES && uncapped >= lookups * 1000,
+        "max_candidates bounds the blocks compared per lookup",
+    )?;
+    check(apply_patch_bytes(&old, &with_header(&patch))? == new, "capped patch of identical blocks")?;
+
+    let opts = PatchOptions::new().block_size(64);
+    let patch = create_patch_with(&old, &new, &opts.clone().max_candidates(1))?;
+    check(apply_patch_bytes(&old, &patch)? == new, "patch comparing one candidate")?;
+    let refused = create_patch_with(&old, &new, &opts.clone().max_candidates(0));
+    check(matches!(refused, Err(XDeltaError::InvalidArg(_))), "max_candidates(0)")?;
+
+    let c_opts = XdeltaOptions { block_size: 64, max_candidates: 1, ..XdeltaOptions::default() };
+    let (mut data, mut len) = (std::ptr::null_mut(), 0);
+    let rc =
+        xdelta_create_patch_data_opts(old.as_ptr(), old.len(), new.as_ptr(), new.len(), &c_opts, &mut data, &mut len);
+    let same = rc == XDELTA_OK && unsafe { std::slice::from_raw_parts(data, len) } == patch;
+    xdelta_free_data(data);
+    check(same, "max_candidates through XdeltaOptions")
+}
+
+/// Of the blocks of old a window matches, the one continuing the pending
+/// COPY is taken, so a new running through a block old has twice is one COPY
+/// even from a signature, without old's bytes; with nothing to continue the
+/// lowest block is.
+#[test]
+fn duplicate_blocks() -> Result<(), XDeltaError> {
+    let block = |seed: u32| filler(64, seed);
+    let old = [block(41), block(42), block(41), block(43)].concat();
+    let new = &old[64..];
+    let sig = build_signature_bytes(&old, 64)?;
+    let from_sig = create_patch_from_signature(&sig, new)?;
+    let with_old = create_patch_with(&old, new, &PatchOptions::new().block_size(64))?;
+    let best = create_patch_with(&old, new, &PatchOptions::new().block_size(64).quality(Quality::Best))?;
+    let one_copy = |patch: &[u8]| patch_records(patch).is_ok_and(|r| r.len() == 13 && r[0] == 0x01);
+    check(
+        one_copy(&from_sig) && one_copy(&with_old) && one_copy(&best) && apply_patch_bytes(&old, &from_sig)? == new,
+        "a duplicated block continues the pending COPY",
+    )?;
+    let first = create_patch_from_signature(&sig, &block(41))?;
+    let records = patch_records(&first)?;
+    check(one_copy(&first) && records[1..9] == 0u64.to_le_bytes(), "the lowest duplicate with nothing to continue")
+}
+
+#[test]
+fn changed_blocks_listed() -> Result<(), XDeltaError> {
+    // 16 blocks of 64 and a 40-byte tail; in new the first two blocks swap
+    // places, which changes nothing, one byte of block 3 flips and block 9
+    // is replaced outright
+    let old = filler(16 * 64 + 40, 0xb10c);
+    let mut new = old.clone();
+    new[..128].rotate_left(64);
+    new[3 * 64 + 5] ^= 1;
+    new[9 * 64..10 * 64].copy_from_slice(&filler(64, 0x0909));
+    check(crate::changed_blocks(&old, &new, 64)? == [3, 9], "changed blocks are exactly the modified ones")?;
+    check(crate::changed_blocks(&old, &old, 64)?.is_empty(), "no changed blocks against itself")?;
+    let mut tail = old.clone();
+    tail.truncate(old.len() - 1);
+    check(
+        crate::changed_blocks(&old, &tail, 64)? == [16]
+            && crate::changed_blocks(&[], &new, 64)? == (0..17).collect::<Vec<_>>(),
+        "short or unmatched last block counts as changed",
+    )?;
+    check(
+        matches!(crate::changed_blocks(&old, &new, 0), Err(XDeltaError::InvalidArg(_))),
+        "changed_blocks rejects block size 0",
+    )?;
+
+    let changed = |new: &[u8]| {
+        let (mut blocks, mut count) = (std::ptr::null_mut(), usize::MAX);
+        let rc = crate::changed::xdelta_changed_blocks(
+            old.as_ptr(),
+            old.len(),
+            new.as_ptr(),
+            new.len(),
+            64,
+            &mut blocks,
+            &mut count,
+        );
+        let found = if rc == XDELTA_OK && count > 0 {
+            unsafe { std::slice::from_raw_parts(blocks, count) }.to_vec()
+        } else {
+            Vec::new()
+        };
+        xdelta_free_data(blocks as *mut u8);
+        (rc, found, count)
+    };
+    let (mut blocks, mut count) = (std::ptr::null_mut(), 0);
+    let null =
+        crate::changed::xdelta_changed_blocks(old.as_ptr(), old.len(), std::ptr::null(), 1, 64, &mut blocks, &mut count);
+    check(
+        changed(&new) == (XDELTA_OK, vec![3, 9], 2)
+            && changed(&old) == (XDELTA_OK, Vec::new(), 0)
+            && null == XDELTA_ERR_NULL_POINTER,
+        "xdelta_changed_blocks",
+    )
+}
+
+/// A signature survives a serialize round trip; a tweaked strong hash or
+/// another block size does not compare equal.
+#[test]
+fn signature_equality() -> Result<(), XDeltaError> {
+    let (old, _new) = fixture();
+    let sig = Signature::new(old, 256)?;
+    let mut bytes = sig.to_bytes();
+    let same = Signature::from_bytes(&bytes)?;
+    bytes[28 + 4] ^= 1; // first strong hash
+    let tweaked = Signature::from_bytes(&bytes)?;
+    check(
+        xdelta_signatures_equal(&sig, &same) == 1
+            && xdelta_signatures_equal(&sig, &tweaked) == 0
+            && sig != Signature::new(old, 512)?
+            && xdelta_signatures_equal(&sig, std::ptr::null()) == XDELTA_ERR_NULL_POINTER,
+        "signature equality",
+    )
+}
+
+/// The remote side diffs against the serialized signature alone, and the
+/// patch still applies to old; the FFI pair agrees with the Rust one.
+#[test]
+fn patch_from_signature_alone() -> Result<(), XDeltaError> {
+    let (old, new) = fixture();
+    let sig_bytes = build_signature_bytes(old, 256)?;
+    let remote = create_patch_from_signature(&sig_bytes, new)?;
+    check(apply_patch_bytes(old, &remote)? == new, "patch from signature alone")?;
+    let (mut ffi_sig, mut ffi_sig_len) = (std::ptr::null_mut(), 0);
+    let (mut ffi_patch, mut ffi_patch_len) = (std::ptr::null_mut(), 0);
+    let built = xdelta_build_signature(old.as_ptr(), old.len(), 256, &mut ffi_sig, &mut ffi_sig_len);
+    let made = xdelta_create_patch_from_signature(
+        ffi_sig,
+        ffi_sig_len,
+        new.as_ptr(),
+        new.len(),
+        &mut ffi_patch,
+        &mut ffi_patch_len,
+    );
+    let same_patch = built == XDELTA_OK
+        && made == XDELTA_OK
+        && unsafe { std::slice::from_raw_parts(ffi_patch, ffi_patch_len) } == &remote[..];
+    xdelta_free_data(ffi_sig);
+    xdelta_free_data(ffi_patch);
+    check(same_patch, "ffi patch from signature")
+}
+
+/// Matched blocks contiguous in old merge into one COPY even where the
+/// matcher can't read old to extend the COPY, as when diffing against a
+/// signature alone; here a file against itself in 64-byte blocks.
+#[test]
+fn contiguous_copies_merged() -> Result<(), XDeltaError> {
+    let (old, _) = fixture();
+    let sig_bytes = build_signature_bytes(old, 64)?;
+    let patch = create_patch_from_signature(&sig_bytes, old)?;
+    check(
+        apply_patch_bytes(old, &patch)? == old
+            && opcode_histogram(&patch)?.len() == 1
+            && opcode_histogram(&patch)?.iter().all(|s| s.name == "COPY" && s.count == 1),
+        "contiguous copies merged",
+    )
+}
+
+/// A file of mostly zero blocks puts them all in one weak bucket, which the
+/// statistics name; distinct blocks each get their own. The FFI reports the
+/// same numbers.
+#[test]
+fn signature_stats() -> Result<(), XDeltaError> {
+    use crate::signature::{xdelta_signature_stats, XdeltaSigStats};
+
+    let zeros = [vec![0u8; 64 * 256], filler(8 * 256, 0x5a7)].concat();
+    let stats = Signature::new(&zeros, 256)?.stats();
+    let zero_weak = Rolling::from_slice(&[0u8; 256]).chksum();
+    check(
+        stats.block_count == 72
+            && stats.distinct_weak == 9
+            && stats.max_bucket == 64
+            && stats.avg_bucket == 8.0
+            && stats.most_collided_weak == zero_weak,
+        "zero blocks collide",
+    )?;
+    let distinct = Signature::new(&filler(64 * 256, 0x5a8), 256)?.stats();
+    check(
+        distinct.block_count == 64
+            && distinct.distinct_weak == 64
+            && distinct.max_bucket == 1
+            && distinct.avg_bucket == 1.0,
+        "distinct blocks spread",
+    )?;
+
+    let sig = xdelta_signature_new(zeros.as_ptr(), zeros.len(), 256);
+    let size = std::mem::size_of::<XdeltaSigStats>() as u32;
+    let mut c_stats = XdeltaSigStats {
+        size,
+        most_collided_weak: 0,
+        block_count: 0,
+        distinct_weak: 0,
+        max_bucket: 0,
+        avg_bucket: 0.0,
+    };
+    let rc = xdelta_signature_stats(sig, &mut c_stats);
+    let null = xdelta_signature_stats(std::ptr::null(), &mut c_stats);
+    xdelta_signature_free(sig);
+    check(
+        rc == XDELTA_OK
+            && null == XDELTA_ERR_NULL_POINTER
+            && c_stats.size == size
+            && c_stats.most_collided_weak == zero_weak
+            && c_stats.block_count == 72
+            && c_stats.distinct_weak == 9
+            && c_stats.max_bucket == 64
+            && c_stats.avg_bucket == 8.0,
+        "xdelta_signature_stats",
+    )
+}
@@ -0,0 +1,69 @@
+// src/trace.rs
+//! A canonical text dump of the matcher's decisions, for checking that two
+//! runs over the same inputs decide the same way (only with the `match-trace`
+//! feature).
+//!
+//! Each line is one record the matcher emitted, keyed by the position in new
+//! where its output starts, e.g. `4096 COPY old=8192 len=1024`. Unlike
+//! `create_patch_with_matches` every decision is listed, not just COPYs, and
+//! the text diffs cleanly: a change in which of several equal candidates is
+//! picked (say, after a bucket iteration order changes) shows up as a changed
+//! line even when the patch still reconstructs new.
+
+use crate::{create_patch_bytes, patch_records, read_record, PatchOptions, Record, XDeltaError};
+use std::fmt::Write;
+
+/// Trace of the decisions the matcher makes turning `old` into `new`.
+///
+/// The identity shortcut and the optional records of `opts` are left out:
+/// only the matcher's own output is traced.
+pub fn match_trace(old: &[u8], new: &[u8], opts: &PatchOptions) -> Result<String, XDeltaError> {
+    trace_records(&create_patch_bytes(old, new, opts)?)
+}
+
+/// Trace of the output-producing records of `patch`, in the format of
+/// `match_trace`. Metadata records don't produce output and are not listed.
+pub fn patch_trace(patch: &[u8]) -> Result<String, XDeltaError> {
+    trace_records(patch_records(patch)?)
+}
+
+fn trace_records(records: &[u8]) -> Result<String, XDeltaError> {
+    let mut trace = String::new();
+    let mut out_pos = 0u64;
+    let mut pos = 0usize;
+    while pos < records.len() {
+        let (record, next) = read_record(records, pos)?;
+        let len = record.output_len();
+        // writing to a String cannot fail
+        let _ = match record {
+            Record::Add(_) => writeln!(trace, "{} ADD len={}", out_pos, len),
+            Record::AddZstd { data, .. } => {
+                writeln!(trace, "{} ADD_ZSTD len={} compressed={}", out_pos, len, data.len())
+            }
+            Record::Copy { offset, .. } => writeln!(trace, "{} COPY old={} len={}", out_pos, offset, len),
+            Record::Run { byte, .. } => writeln!(trace, "{} RUN byte={:#04x} len={}", out_pos, byte, len),
+            Record::Diff { offset, deltas, .. } => {
+                writeln!(trace, "{} DIFF old={} len={} deltas={}", out_pos, offset, len, deltas.len() / 5)
+            }
+            Record::CopyTarget { offset, .. } => writeln!(trace, "{} COPY_TARGET at={} len={}", out_pos, offset, len),
+            Record::Xor { offset, .. } => writeln!(trace, "{} XOR_DELTA old={} len={}", out_pos, offset, len),
+            Record::CopyConst { index, .. } => writeln!(trace, "{} COPY_CONST index={} len={}", out_pos, index, len),
+            Record::CopyHash { hash, .. } => {
+                let prefix: String = hash[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(trace, "{} COPY_HASH hash={} len={}", out_pos, prefix, len)
+            }
+            Record::Index(_)
+            | Record::OldHash(_)
+            | Record::ConstTable(_)
+            | Record::OutputOffset(_)
+            | Record::Padding
+            | Record::BlockSize(_)
+            | Record::MinVersion
+            | Record::Check(_)
+            | Record::Skippable(_) => Ok(()),
+        };
+        out_pos += len;
+        pos = next;
+    }
+    Ok(trace)
+}
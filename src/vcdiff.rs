@@ -0,0 +1,544 @@
+// src/vcdiff.rs
+//! VCDIFF (RFC 3284) output and input, for pipelines that already ship
+//! `xdelta3` on one side (only with the `vcdiff` feature).
+//!
+//! The matcher runs as usual and its ADD, COPY and RUN records are written
+//! as VCDIFF instructions: one instruction per code table entry from the
+//! default code table, COPY addresses in VCD_SELF mode, no secondary
+//! compression and no application header. Each window's source segment is
+//! just the span of old its COPYs read, so `xdelta3 -d -s old` can decode the
+//! output like one of its own.
+//!
+//! Reading covers what `xdelta3 -e -S none` writes: the default code table
+//! with its paired instructions, every address mode, source and target
+//! segments, the application header (skipped) and the Adler-32 window
+//! checksum of the xdelta3 extension (checked). Secondary compression and
+//! custom code tables are refused.
+
+use crate::{create_patch_bytes, ffi_slice, read_record, write_output, PatchOptions, Record, XDeltaError};
+use std::os::raw::c_int;
+
+const VCDIFF_MAGIC: [u8; 4] = [0xd6, 0xc3, 0xc4, 0x00];
+/// Win_Indicator bit: the window copies from a segment of the source (old).
+const VCD_SOURCE: u8 = 0x01;
+/// Win_Indicator bit: the window copies from a segment of the output so far.
+const VCD_TARGET: u8 = 0x02;
+/// Win_Indicator bit (xdelta3): an Adler-32 of the window's output follows the section lengths.
+const VCD_ADLER32: u8 = 0x04;
+/// Hdr_Indicator bits: secondary compressor, custom code table, application header (xdelta3).
+const VCD_DECOMPRESS: u8 = 0x01;
+const VCD_CODETABLE: u8 = 0x02;
+const VCD_APPHEADER: u8 = 0x04;
+/// Slots of the near and same address caches of the default code table.
+const NEAR_SLOTS: usize = 4;
+const SAME_SLOTS: usize = 3 * 256;
+/// Most output bytes per window, half the default window size of xdelta3.
+const WINDOW_SIZE: usize = 1 << 22;
+
+/// One instruction of a window, already cut to fit it.
+#[derive(Clone, Copy)]
+enum Op<'a> {
+    Add(&'a [u8]),
+    Copy { offset: u64, len: usize },
+    Run { byte: u8, len: usize },
+}
+
+impl<'a> Op<'a> {
+    fn len(&self) -> usize {
+        match *self {
+            Op::Add(data) => data.len(),
+            Op::Copy { len, .. } | Op::Run { len, .. } => len,
+        }
+    }
+
+    /// The first `at` bytes of output, and the rest if there is any.
+    fn split(self, at: usize) -> (Op<'a>, Option<Op<'a>>) {
+        if self.len() <= at {
+            return (self, None);
+        }
+        match self {
+            Op::Add(data) => (Op::Add(&data[..at]), Some(Op::Add(&data[at..]))),
+            Op::Copy { offset, len } => {
+                (Op::Copy { offset, len: at }, Some(Op::Copy { offset: offset + at as u64, len: len - at }))
+            }
+            Op::Run { byte, len } => (Op::Run { byte, len: at }, Some(Op::Run { byte, len: len - at })),
+        }
+    }
+}
+
+/// Create a VCDIFF delta turning `old` into `new`, matching in blocks of
+/// `block_size` bytes.
+///
+/// Unlike `create_patch` there is no identity shortcut or any of the
+/// optional records of `PatchOptions`: the output is plain VCDIFF that
+/// `xdelta3 -d` decodes.
+pub fn create_patch_vcdiff(old: &[u8], new: &[u8], block_size: usize) -> Result<Vec<u8>, XDeltaError> {
+    let records = create_patch_bytes(old, new, &PatchOptions::new().block_size(block_size))?;
+    let mut out = VCDIFF_MAGIC.to_vec();
+    out.push(0); // Hdr_Indicator: no secondary compressor, default code table
+    let mut window: Vec<Op> = Vec::new();
+    let mut room = WINDOW_SIZE;
+    let mut pos = 0;
+    while pos < records.len() {
+        let (record, next) = read_record(&records, pos)?;
+        let mut op = match record {
+            Record::Add(data) => Op::Add(data),
+            Record::Copy { offset, len } => Op::Copy { offset, len: len as usize },
+            Record::Run { byte, len } => Op::Run { byte, len: len as usize },
+            _ => unreachable!("the default options write only ADD, COPY and RUN"),
+        };
+        loop {
+            if room == 0 {
+                write_window(&mut out, &window);
+                window.clear();
+                room = WINDOW_SIZE;
+            }
+            let (head, rest) = op.split(room);
+            room -= head.len();
+            window.push(head);
+            match rest {
+                Some(rest) => op = rest,
+                None => break,
+            }
+        }
+        pos = next;
+    }
+    // an empty new is still one (empty) window
+    if !window.is_empty() || out.len() == VCDIFF_MAGIC.len() + 1 {
+        write_window(&mut out, &window);
+    }
+    Ok(out)
+}
+
+fn write_window(out: &mut Vec<u8>, ops: &[Op]) {
+    // the span of old the window's COPYs read, which becomes its source segment
+    let source = ops
+        .iter()
+        .filter_map(|op| match *op {
+            Op::Copy { offset, len } => Some((offset, offset + len as u64)),
+            _ => None,
+        })
+        .reduce(|(start, end), (lo, hi)| (start.min(lo), end.max(hi)));
+    let (mut data, mut inst, mut addrs) = (Vec::new(), Vec::new(), Vec::new());
+    let mut target_len = 0;
+    for op in ops {
+        target_len += op.len();
+        match *op {
+            Op::Add(bytes) => {
+                write_instruction(&mut inst, false, bytes.len());
+                data.extend_from_slice(bytes);
+            }
+            Op::Copy { offset, len } => {
+                write_instruction(&mut inst, true, len);
+                // VCD_SELF: the address in the source segment as is
+                let start = source.map_or(0, |(start, _)| start);
+                write_varint(&mut addrs, offset - start);
+            }
+            Op::Run { byte, len } => {
+                inst.push(0); // RUN, size 0: the size follows
+                write_varint(&mut inst, len as u64);
+                data.push(byte);
+            }
+        }
+    }
+
+    let mut delta = Vec::with_capacity(data.len() + inst.len() + addrs.len() + 32);
+    write_varint(&mut delta, target_len as u64);
+    delta.push(0); // Delta_Indicator: no section is compressed
+    write_varint(&mut delta, data.len() as u64);
+    write_varint(&mut delta, inst.len() as u64);
+    write_varint(&mut delta, addrs.len() as u64);
+    delta.extend_from_slice(&data);
+    delta.extend_from_slice(&inst);
+    delta.extend_from_slice(&addrs);
+
+    match source {
+        Some((start, end)) => {
+            out.push(VCD_SOURCE);
+            write_varint(out, end - start);
+            write_varint(out, start);
+        }
+        None => out.push(0),
+    }
+    write_varint(out, delta.len() as u64);
+    out.extend_from_slice(&delta);
+}
+
+/// An ADD or (mode 0) COPY of `size` bytes: its default code table index,
+/// followed by the size unless the index implies it.
+fn write_instruction(inst: &mut Vec<u8>, copy: bool, size: usize) {
+    // index of the size 0 entry, and the sizes with an entry of their own after it
+    let (base, sized) = if copy { (19, 4..=18) } else { (1, 1..=17) };
+    if sized.contains(&size) {
+        inst.push((base + 1 + size - sized.start()) as u8);
+    } else {
+        inst.push(base as u8);
+        write_varint(inst, size as u64);
+    }
+}
+
+/// RFC 3284 integer: base 128, most significant digit first, the high bit
+/// set on all digits but the last.
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    let mut digits = [0u8; 10];
+    let mut i = digits.len() - 1;
+    digits[i] = (n & 0x7f) as u8;
+    n >>= 7;
+    while n > 0 {
+        i -= 1;
+        digits[i] = (n & 0x7f) as u8 | 0x80;
+        n >>= 7;
+    }
+    out.extend_from_slice(&digits[i..]);
+}
+
+/// Apply a VCDIFF delta, such as one `xdelta3 -e -s old` wrote, to `old`.
+///
+/// Secondary compression (`xdelta3 -S`) and custom code tables are refused
+/// with `InvalidArg`; anything else the stream gets wrong is
+/// `MalformedPatch`, or `OldOutOfRange` for a source segment past the end of
+/// old.
+pub fn apply_patch_vcdiff(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, XDeltaError> {
+    let mut r = Reader::new(patch, "header");
+    if r.take(4)? != VCDIFF_MAGIC {
+        return Err(XDeltaError::MalformedPatch("not a VCDIFF stream".into()));
+    }
+    let indicator = r.byte()?;
+    if indicator & VCD_DECOMPRESS != 0 {
+        return Err(XDeltaError::InvalidArg(format!("VCDIFF secondary compressor {} is not supported", r.byte()?)));
+    }
+    if indicator & VCD_CODETABLE != 0 {
+        return Err(XDeltaError::InvalidArg("VCDIFF custom code tables are not supported".into()));
+    }
+    if indicator & !VCD_APPHEADER != 0 {
+        return Err(XDeltaError::MalformedPatch(format!("unknown VCDIFF header indicator {:#04x}", indicator)));
+    }
+    if indicator & VCD_APPHEADER != 0 {
+        let len = r.varint()?;
+        r.take(len)?;
+    }
+
+    let table = default_code_table();
+    let mut out = Vec::new();
+    while !r.at_end() {
+        r.what = "window header";
+        apply_window(&mut r, old, &table, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// Decode the window at `r` onto the end of `out`.
+fn apply_window(r: &mut Reader, old: &[u8], table: &[[Inst; 2]], out: &mut Vec<u8>) -> Result<(), XDeltaError> {
+    let indicator = r.byte()?;
+    if indicator & !(VCD_SOURCE | VCD_TARGET | VCD_ADLER32) != 0
+        || indicator & (VCD_SOURCE | VCD_TARGET) == VCD_SOURCE | VCD_TARGET
+    {
+        return Err(XDeltaError::MalformedPatch(format!("bad VCDIFF window indicator {:#04x}", indicator)));
+    }
+    let segment: &[u8] = if indicator & (VCD_SOURCE | VCD_TARGET) != 0 {
+        let len = r.varint()?;
+        let pos = r.varint()?;
+        let from: &[u8] = if indicator & VCD_SOURCE != 0 { old } else { out };
+        let range = pos.checked_add(len).filter(|&end| end <= from.len() as u64).map(|end| pos as usize..end as usize);
+        match range {
+            Some(range) => &from[range],
+            None if indicator & VCD_SOURCE != 0 => {
+                return Err(XDeltaError::OldOutOfRange(format!(
+                    "source segment of {} bytes at {} past the end of old ({} bytes)",
+                    len,
+                    pos,
+                    old.len()
+                )));
+            }
+            None => {
+                return Err(XDeltaError::MalformedPatch(format!(
+                    "target segment of {} bytes at {} past the output so far ({} bytes)",
+                    len,
+                    pos,
+                    out.len()
+                )));
+            }
+        }
+    } else {
+        &[]
+    };
+
+    let len = r.varint()?;
+    let mut delta = Reader::new(r.take(len)?, "window");
+    let target_len =
+        usize::try_from(delta.varint()?).map_err(|_| XDeltaError::MalformedPatch("VCDIFF window too large".into()))?;
+    if delta.byte()? != 0 {
+        return Err(XDeltaError::InvalidArg("VCDIFF sections with secondary compression are not supported".into()));
+    }
+    let data_len = delta.varint()?;
+    let inst_len = delta.varint()?;
+    let addr_len = delta.varint()?;
+    let checksum = match indicator & VCD_ADLER32 {
+        0 => None,
+        _ => Some(delta.take(4)?.iter().fold(0u32, |sum, &b| (sum << 8) | b as u32)),
+    };
+    let mut data = Reader::new(delta.take(data_len)?, "data section");
+    let mut inst = Reader::new(delta.take(inst_len)?, "instruction section");
+    let mut addrs = Reader::new(delta.take(addr_len)?, "address section");
+    if !delta.at_end() {
+        return Err(XDeltaError::MalformedPatch("VCDIFF window longer than its sections".into()));
+    }
+
+    let mut target: Vec<u8> = Vec::new();
+    let mut cache = AddressCache::new();
+    while !inst.at_end() {
+        let entry = table[inst.byte()? as usize];
+        for op in entry {
+            if op.kind == Kind::Noop {
+                continue;
+            }
+            let size = match op.size {
+                0 => inst.varint()?,
+                size => size as u64,
+            };
+            if size > (target_len - target.len()) as u64 {
+                return Err(XDeltaError::MalformedPatch(format!(
+                    "VCDIFF instructions overrun the {}-byte target window",
+                    target_len
+                )));
+            }
+            let size = size as usize;
+            match op.kind {
+                Kind::Add => target.extend_from_slice(data.take(size as u64)?),
+                Kind::Run => {
+                    let byte = data.byte()?;
+                    target.resize(target.len() + size, byte);
+                }
+                Kind::Copy => {
+                    let here = (segment.len() + target.len()) as u64;
+                    let start = cache.decode(&mut addrs, op.mode, here)? as usize;
+                    if start + size <= segment.len() {
+                        target.extend_from_slice(&segment[start..start + size]);
+                    } else {
+                        // reaches into the target window, possibly into the bytes
+                        // it is producing, so one byte at a time
+                        for at in start..start + size {
+                            let byte = match at.checked_sub(segment.len()) {
+                                Some(in_target) => target[in_target],
+                                None => segment[at],
+                            };
+                            target.push(byte);
+                        }
+                    }
+                }
+                Kind::Noop => {}
+            }
+        }
+    }
+    if target.len() != target_len {
+        return Err(XDeltaError::MalformedPatch(format!(
+            "VCDIFF window decodes to {} bytes, not {}",
+            target.len(),
+            target_len
+        )));
+    }
+    if !data.at_end() || !addrs.at_end() {
+        return Err(XDeltaError::MalformedPatch("VCDIFF window has unused data or addresses".into()));
+    }
+    if checksum.is_some_and(|sum| sum != adler32(&target)) {
+        return Err(XDeltaError::MalformedPatch("VCDIFF window checksum mismatch".into()));
+    }
+    out.extend_from_slice(&target);
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Noop,
+    Add,
+    Run,
+    Copy,
+}
+
+/// Half of a code table entry; size 0 means the size follows in the
+/// instruction section.
+#[derive(Clone, Copy)]
+struct Inst {
+    kind: Kind,
+    size: u8,
+    mode: u8,
+}
+
+/// The default code table of RFC 3284 section 5.6.
+fn default_code_table() -> Vec<[Inst; 2]> {
+    let noop = Inst { kind: Kind::Noop, size: 0, mode: 0 };
+    let add = |size| Inst { kind: Kind::Add, size, mode: 0 };
+    let copy = |size, mode| Inst { kind: Kind::Copy, size, mode };
+    let mut table = vec![[Inst { kind: Kind::Run, size: 0, mode: 0 }, noop]];
+    table.extend((0..=17).map(|size| [add(size), noop]));
+    for mode in 0..9 {
+        table.extend([0].into_iter().chain(4..=18).map(|size| [copy(size, mode), noop]));
+    }
+    for mode in 0..6 {
+        for add_size in 1..=4 {
+            table.extend((4..=6).map(|size| [add(add_size), copy(size, mode)]));
+        }
+    }
+    for mode in 6..9 {
+        table.extend((1..=4).map(|add_size| [add(add_size), copy(4, mode)]));
+    }
+    table.extend((0..9).map(|mode| [copy(4, mode), add(1)]));
+    debug_assert_eq!(table.len(), 256);
+    table
+}
+
+/// The near and same caches COPY addresses are encoded against, reset at
+/// each window.
+struct AddressCache {
+    near: [u64; NEAR_SLOTS],
+    next_near: usize,
+    same: [u64; SAME_SLOTS],
+}
+
+impl AddressCache {
+    fn new() -> Self {
+        AddressCache { near: [0; NEAR_SLOTS], next_near: 0, same: [0; SAME_SLOTS] }
+    }
+
+    /// Decode the next address in `mode` from `addrs`, where `here` is the
+    /// current position in the source segment followed by the target window.
+    fn decode(&mut self, addrs: &mut Reader, mode: u8, here: u64) -> Result<u64, XDeltaError> {
+        let mode = mode as usize;
+        let addr = match mode {
+            0 => Some(addrs.varint()?),             // VCD_SELF
+            1 => here.checked_sub(addrs.varint()?), // VCD_HERE
+            m if m < 2 + NEAR_SLOTS => self.near[m - 2].checked_add(addrs.varint()?),
+            m => Some(self.same[(m - 2 - NEAR_SLOTS) * 256 + addrs.byte()? as usize]),
+        };
+        let addr = addr.filter(|&addr| addr < here).ok_or_else(|| {
+            XDeltaError::MalformedPatch(format!("VCDIFF COPY address (mode {}) not before position {}", mode, here))
+        })?;
+        self.near[self.next_near] = addr;
+        self.next_near = (self.next_near + 1) % NEAR_SLOTS;
+        self.same[(addr % SAME_SLOTS as u64) as usize] = addr;
+        Ok(addr)
+    }
+}
+
+/// Cursor over a VCDIFF stream or one of a window's sections; `what` names
+/// it in errors.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], what: &'static str) -> Self {
+        Reader { bytes, pos: 0, what }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    fn ends_early(&self) -> XDeltaError {
+        XDeltaError::MalformedPatch(format!("VCDIFF {} ends early", self.what))
+    }
+
+    fn byte(&mut self) -> Result<u8, XDeltaError> {
+        let byte = *self.bytes.get(self.pos).ok_or_else(|| self.ends_early())?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, n: u64) -> Result<&'a [u8], XDeltaError> {
+        let left = (self.bytes.len() - self.pos) as u64;
+        if n > left {
+            return Err(self.ends_early());
+        }
+        let taken = &self.bytes[self.pos..self.pos + n as usize];
+        self.pos += n as usize;
+        Ok(taken)
+    }
+
+    /// An integer in the form `write_varint` writes.
+    fn varint(&mut self) -> Result<u64, XDeltaError> {
+        let mut n: u64 = 0;
+        loop {
+            let digit = self.byte()?;
+            if n > u64::MAX >> 7 {
+                return Err(XDeltaError::MalformedPatch(format!("VCDIFF integer in {} too large", self.what)));
+            }
+            n = (n << 7) | (digit & 0x7f) as u64;
+            if digit & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+/// Adler-32, as the xdelta3 window checksum.
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // the most bytes before b can overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// 创建 VCDIFF（RFC 3284）格式的补丁，可用 xdelta3 -d 解码（需启用 vcdiff 特性）
+/// 输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_create_patch_vcdiff(
+    old_data: *const u8,
+    old_len: usize,
+    new_data: *const u8,
+    new_len: usize,
+    block_size: u32,
+    patch_data: *mut *mut u8,
+    patch_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if patch_data.is_null() || patch_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let new_bytes = unsafe { ffi_slice(new_data, new_len)? };
+
+        create_patch_vcdiff(old_bytes, new_bytes, block_size as usize)
+    })();
+
+    write_output(r, patch_data, patch_len)
+}
+
+/// 应用 VCDIFF（RFC 3284）格式的补丁，如 xdelta3 -e -s old 生成的补丁（需启用 vcdiff 特性）
+/// 不支持二级压缩（xdelta3 -S）和自定义指令表，此时返回 XDELTA_ERR_INVALID_ARG
+/// 输出用 xdelta_free_data 释放
+/// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+#[unsafe(no_mangle)]
+pub extern "C" fn xdelta_apply_patch_vcdiff(
+    old_data: *const u8,
+    old_len: usize,
+    patch_data: *const u8,
+    patch_len: usize,
+    new_data: *mut *mut u8,
+    new_len: *mut usize,
+) -> c_int {
+    let r = (|| -> Result<Vec<u8>, XDeltaError> {
+        if new_data.is_null() || new_len.is_null() {
+            return Err(XDeltaError::NullPointer);
+        }
+
+        let old_bytes = unsafe { ffi_slice(old_data, old_len)? };
+        let patch_bytes = unsafe { ffi_slice(patch_data, patch_len)? };
+
+        apply_patch_vcdiff(old_bytes, patch_bytes)
+    })();
+
+    write_output(r, new_data, new_len)
+}
@@ -0,0 +1,124 @@
+// src/xor_delta.rs
+//! Whole-range XOR deltas, for equal-length inputs differing in scattered
+//! bytes (a binary with a few patched bytes, say).
+//!
+//! XOR_DELTA layout: opcode 0x13, then
+//!   offset: u64, length: u32   // range of old, as for COPY
+//!   body_len: u32
+//!   body: [(skip: varint, count: varint, xor: [u8; count])...]
+//! The body run-length encodes old XOR new over the range: `skip` unchanged
+//! bytes, then `count` bytes to XOR in. Varints are LEB128, so a lone changed
+//! byte costs about three bytes of body.
+
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
+/// Append `v` as an LEB128 varint.
+#[cfg(feature = "std")]
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+/// Read an LEB128 varint at `*pos`, advancing past it.
+fn get_varint(body: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *body.get(*pos)?;
+        *pos += 1;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Encode `old` XOR `new` (of equal length) as an XOR_DELTA body, or `None`
+/// when more than `max_diffs` bytes differ.
+#[cfg(feature = "std")]
+pub(crate) fn encode(old: &[u8], new: &[u8], max_diffs: usize) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut diffs = 0usize;
+    let mut run_end = 0usize; // end of the last run written
+    let mut i = 0usize;
+    while i < new.len() {
+        if old[i] == new[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        // a changed run ends at two unchanged bytes in a row, which cost
+        // more to skip than to XOR through
+        while i < new.len() && (old[i] != new[i] || (i + 1 < new.len() && old[i + 1] != new[i + 1])) {
+            i += 1;
+        }
+        diffs += (start..i).filter(|&j| old[j] != new[j]).count();
+        if diffs > max_diffs {
+            return None;
+        }
+        put_varint(&mut body, (start - run_end) as u64);
+        put_varint(&mut body, (i - start) as u64);
+        body.extend((start..i).map(|j| old[j] ^ new[j]));
+        run_end = i;
+    }
+    Some(body)
+}
+
+/// Whether `body` is a well-formed XOR_DELTA body for a range of `len` bytes.
+pub(crate) fn validate(body: &[u8], len: u32) -> bool {
+    let mut pos = 0usize;
+    let mut at = 0u64;
+    while pos < body.len() {
+        let (Some(skip), Some(count)) = (get_varint(body, &mut pos), get_varint(body, &mut pos)) else {
+            return false;
+        };
+        at = match at.checked_add(skip).and_then(|a| a.checked_add(count)) {
+            Some(end) if end <= len as u64 && count <= (body.len() - pos) as u64 => end,
+            _ => return false,
+        };
+        pos += count as usize;
+    }
+    true
+}
+
+/// XOR the delta into `out`, which holds bytes `from..from + out.len()` of
+/// the range. `body` must have passed `validate`.
+pub(crate) fn xor_into(out: &mut [u8], body: &[u8], from: usize) {
+    let to = from + out.len();
+    let mut pos = 0usize;
+    let mut at = 0usize;
+    while pos < body.len() && at < to {
+        let skip = get_varint(body, &mut pos).unwrap_or(0) as usize;
+        let count = get_varint(body, &mut pos).unwrap_or(0) as usize;
+        at += skip;
+        for (i, &x) in body[pos..pos + count].iter().enumerate() {
+            if (from..to).contains(&(at + i)) {
+                out[at + i - from] ^= x;
+            }
+        }
+        pos += count;
+        at += count;
+    }
+}
+
+/// Append an XOR_DELTA record for `len` bytes of old at `offset`.
+#[cfg(feature = "std")]
+pub(crate) fn write_record(out: &mut Vec<u8>, offset: u64, len: u32, body: &[u8]) {
+    out.push(0x13); // XOR_DELTA
+    out.extend_from_slice(&offset.to_le_bytes());
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+}
+
+/// The XOR_DELTA body covering bytes `from..to` of the range of `body`.
+#[cfg(feature = "std")]
+pub(crate) fn sub_body(body: &[u8], from: usize, to: usize) -> Vec<u8> {
+    let mut xor = vec![0u8; to - from];
+    xor_into(&mut xor, body, from);
+    encode(&vec![0u8; xor.len()], &xor, usize::MAX).unwrap_or_default()
+}
@@ -9,16 +9,747 @@
 extern "C" {
 #endif
 
-// 返回 0 表示成功，负数表示失败。失败后可通过 xdelta_last_error() 获取错误字符串（只读指针，线程局部）。
+#define XDELTA_OPT_NEAR_MISS_DIFF (1u << 0)
+#define XDELTA_OPT_OLD_HASH       (1u << 1)  // 记录旧数据哈希，供流式应用校验
+#define XDELTA_OPT_CONST_TABLE    (1u << 2)  // 用常量表编码字面数据中由不超过 8 字节的图块连续重复、至少 16 字节的片段（零散出现的短序列不编码）
+#define XDELTA_OPT_CONTENT_ADDRESSED (1u << 3)  // 按 SHA-256 引用旧数据块（COPY_HASH），用 xdelta_apply_patch_cas 应用
+#define XDELTA_OPT_EMBED_BLOCK_SIZE (1u << 4)   // 在补丁中记录块大小，供 xdelta_apply_patch_signature 使用
+#define XDELTA_OPT_FUZZY_INDEX      (1u << 5)   // 配合 NEAR_MISS_DIFF：用 SimHash 索引在整个旧数据中查找相近块
+#define XDELTA_OPT_XOR_DELTA        (1u << 6)   // 新旧数据等长且至多 1/16 字节不同时，输出整段异或差值（XOR_DELTA）
+#define XDELTA_OPT_MIN_VERSION      (1u << 7)   // 在补丁中声明能应用它的最低格式版本（MIN_VERSION），旧版应用方据此明确拒绝
+// 也从已编码的新数据中查找重复块（COPY_TARGET），适合新数据内部有重复而旧数据中没有的情况；
+// 周期不超过 32 字节的重复图案（如重复的 4 字节图块）用一条与自身输出重叠的 COPY_TARGET 表示；
+// 这类补丁需在内存中保留输出的应用方式（流式、按范围应用和拆分会拒绝）
+#define XDELTA_OPT_COPY_TARGET      (1u << 8)
+// 在补丁末尾附加整个输出的 SHA-256（CHECK），应用时逐字节校验输出，不符返回 XDELTA_ERR_CHECKSUM_MISMATCH；
+// 补丁需格式版本 5 的应用方
+#define XDELTA_OPT_OUTPUT_CHECK     (1u << 9)
+// 用标准 Adler-32（与 zlib、xdelta3 相同）代替 rsync 式弱校验和查找候选块；补丁格式不变
+#define XDELTA_OPT_ADLER32          (1u << 10)
+// 返回前把补丁应用到旧数据并与新数据比较，不一致时返回 XDELTA_ERR_INVALID_ARG；每个补丁多一次应用的开销
+#define XDELTA_OPT_STRICT           (1u << 11)
+// 每条记录后附加该记录的 CRC-32（头标志位 4），应用时先逐条校验，损坏时返回 XDELTA_ERR_CHECKSUM_MISMATCH 并在错误信息中
+// 给出记录序号和字节偏移，不产生任何输出；补丁每条记录多 4 字节，需格式版本 7 的应用方
+#define XDELTA_OPT_RECORD_CRC       (1u << 12)
+// 只向前读取旧数据：每条 COPY/DIFF 的偏移不小于上一条读取结束处（头标志位 6），适合磁带等只能顺序读取的旧数据；
+// 应用时逐条检查，违反时返回 XDELTA_ERR_MALFORMED_PATCH；不能与 CONTENT_ADDRESSED 同用，需格式版本 10 的应用方
+#define XDELTA_OPT_FORWARD_ONLY     (1u << 13)
+// 计算强哈希前先比较窗口与候选块的 CRC-32，都不符时跳过强哈希；弱校验和冲突多时更快，
+// 代价是预先计算每个旧数据块的 CRC-32；补丁不变
+#define XDELTA_OPT_CRC_PRECHECK     (1u << 14)
+// 只在新数据中 alignment 的整数倍位置查找匹配，未匹配时直接跳到下一个对齐位置（中间字节作为 ADD）；
+// 适合按扇区、页对齐的磁盘镜像和内存转储，大幅减少弱校验和计算，代价是错过未对齐的匹配；应用方无需感知
+#define XDELTA_OPT_ALIGNED          (1u << 15)
+
+// XdeltaOptions::tail_policy
+#define XDELTA_TAIL_AS_IS 0  // 按实际长度索引，可匹配新数据末尾相同的短块（默认）
+#define XDELTA_TAIL_PAD   1  // 用 0 字节补齐到 block_size 后索引，可匹配“短块+补零”的完整窗口
+#define XDELTA_TAIL_SKIP  2  // 不索引，末尾短块总是作为 ADD 发送
+
+// XdeltaOptions::quality
+#define XDELTA_QUALITY_FAST 0  // 取第一个匹配的旧数据块（默认）
+#define XDELTA_QUALITY_BEST 1  // 在所有匹配的块中取向后延续最长的一个，记录更少，但需逐个比较候选块
+// 在未匹配数据中每 probe_stride 个位置才查一次签名（查的位置逐块轮换，所有对齐方式都会被尝试）：更快，
+// 但匹配数据最多晚 probe_stride 块才被发现，每次从新数据回到匹配数据最多多出 probe_stride*block_size 字节 ADD；
+// 用于延迟比补丁大小更重要的场景
+#define XDELTA_QUALITY_SKIM 2
+
+// xdelta_apply_patch_data_ex 的 flags：跳过未知的可跳过操作码（最高位为 1）
+#define XDELTA_APPLY_SKIP_UNKNOWN (1u << 0)
+
+// xdelta_create_patch_data_ex 的 flags：用 zstd 压缩 ADD 数据（ADD_ZSTD，操作码 0x03）。
+// 需以 zstd 特性编译，否则设置此位时创建失败；应用 ADD_ZSTD 记录同样需要 zstd 特性
+#define XDELTA_CREATE_COMPRESS (1u << 0)
+// xdelta_create_patch_data_ex 的 flags：用 BLAKE3 代替 SHA-256 确认匹配块，补丁内容不变；在没有 SHA 指令的 CPU 上更快，
+// 有 SHA 指令时 SHA-256 更快。需以 blake3 特性编译，否则设置此位时创建失败
+#define XDELTA_CREATE_BLAKE3   (1u << 1)
+
+// 取消令牌（不透明句柄），可在其他线程调用 xdelta_cancel_token_cancel
+typedef struct XdeltaCancelToken XdeltaCancelToken;
+
+// 所有传给库的结构体首字段均为 size，调用方填 sizeof(结构体)。
+// 库只读取 size 范围内的字段，未覆盖的新字段按 0（默认值）处理；size 不合理时返回错误。
+
+// 补丁创建选项；新字段只追加在末尾
+typedef struct XdeltaOptions {
+    uint32_t size;
+    uint32_t block_size;         // 0 表示按旧数据长度自动选择（同 xdelta_create_patch_data 的 block_size 参数）
+    uint32_t flags;              // XDELTA_OPT_* 位
+    uint64_t index_granularity;  // 0 表示不生成索引
+    const XdeltaCancelToken* cancel;  // 可为 NULL
+    // 可选的脏块位图（低位在前），第 i 位对应第 i 个 block_size 大小的块；
+    // 位为 0 表示该块在新旧数据的同一位置未变化，直接输出 COPY 而不做匹配
+    const uint8_t* dirty_bitmap;      // 可为 NULL
+    size_t dirty_bitmap_len;
+    uint64_t pad_to;                  // 非 0 时用填充记录把补丁补齐到恰好该长度，补丁已超出时失败
+    // 非 0 时，连续这么多字节未匹配后跳过同样多字节直接输出 ADD（不做哈希），
+    // 之后再探测一个块长度的位置，避免在全新内容上浪费哈希计算
+    uint64_t novel_skip;
+    uint32_t tail_policy;             // XDELTA_TAIL_*：旧数据末尾不足一块的短块如何建立索引
+    uint32_t quality;                 // XDELTA_QUALITY_*：每个位置如何在多个匹配块中选择
+    uint32_t probe_stride;            // XDELTA_QUALITY_SKIM 的查找间隔，0 或 1 表示每个位置都查
+    // 短于该长度的匹配作为 ADD 发送而不输出 COPY/COPY_TARGET（COPY 记录本身 13 字节）；
+    // 0 表示默认值（XDELTA_DEFAULT_MIN_MATCH 与 block_size 中较小者，整块匹配总是保留），1 表示保留所有匹配
+    uint32_t min_match;
+    // 非 0 时，记录超过新数据长度的这么多倍就停止匹配，改为把整个新数据作为一条 ADD 发送，
+    // 补丁不会比新数据大出许多（近乎随机或完全改变的数据）；须大于 0，0 表示不限制
+    double max_ratio;
+    // 大于 1 时只为每 sample_blocks 个旧数据块中的第一个建立索引，签名内存降为 1/sample_blocks；
+    // 命中的块向前后扩展覆盖未索引的块，压缩率可能略差，补丁头标志位 7 记录此事（需格式版本 11）；0 或 1 表示索引每个块
+    uint32_t sample_blocks;
+    // 每个窗口最多与这么多个弱校验和相同（大桶中强哈希前缀也相同）的旧数据块比较，都不符时按未匹配处理，
+    // 限制大量相同或碰撞块时的最坏匹配时间，可能错过个别匹配；0 表示默认值（XDELTA_DEFAULT_MAX_CANDIDATES）
+    uint32_t max_candidates;
+    // XDELTA_OPT_ALIGNED 的对齐字节数，0 表示块大小
+    uint32_t alignment;
+    // 单个 ADD 记录最多包含的字节数，更长的未匹配数据拆成连续多个 ADD，同时限制创建时暂存的未匹配数据量；
+    // 0 表示默认值（XDELTA_DEFAULT_MAX_ADD_LEN）
+    uint32_t max_add_len;
+} XdeltaOptions;
+
+// XdeltaOptions::min_match 为 0 时的默认值（不超过 block_size）
+#define XDELTA_DEFAULT_MIN_MATCH 16
+// XdeltaOptions::max_candidates 为 0 时的默认值
+#define XDELTA_DEFAULT_MAX_CANDIDATES 32
+// XdeltaOptions::max_add_len 为 0 时的默认值（4 MiB）
+#define XDELTA_DEFAULT_MAX_ADD_LEN 4194304
+
+// 内存归属（每种分配只有一种释放方式）：
+//   - 返回的字节缓冲区（uint8_t**）及数组（如 XdeltaExtent**）由调用方用 xdelta_free_data 释放，
+//     批量写入 XdeltaBatchEntry 的补丁也可用 xdelta_free_batch 一次释放；
+//     字节缓冲区只在成功时写入（输出为空时也是一个需释放的缓冲区），失败时指针置 NULL、长度置 0，不会泄漏；
+//   - 返回的字符串（char**）由调用方用 xdelta_free_string 释放；
+//   - 以上缓冲区和字符串默认用 libc malloc 分配，可用 xdelta_set_allocator 换成调用方的分配/释放函数；
+//   - xdelta_last_error 返回的指针归库所有，不要释放；
+//   - 结果句柄（XdeltaResult*）及其数据、错误信息只用 xdelta_result_free 释放；
+//   - xdelta_apply_patch_ctx 返回的输出归应用上下文所有，不要释放；
+//   - 其余不透明句柄用各自的 *_free 释放（context/apply_context/signature/cancel_token/apply_feed）。
+// 输入缓冲区：长度为 0 的输入（const uint8_t* 与其长度）可传 NULL；长度非 0 而指针为 NULL 时返回 XDELTA_ERR_NULL_POINTER。
+// 线程：所有函数可在多个线程中并发调用；同一个 XdeltaContext 不可并发使用；
+//   xdelta_last_error 为线程局部，只反映本线程最近一次失败，可用 xdelta_clear_last_error 清除。
+
+// 错误码：返回 int 的函数失败时直接返回对应的错误码（均为负数），
+// 结果句柄 API 的 xdelta_result_status、xdelta_last_error_detail 也报告同一错误码；
+// 可读的详细信息仍由 xdelta_last_error 给出。已发布的错误码含义不变，新错误码只追加
+#define XDELTA_OK                     0
+#define XDELTA_ERR_INVALID_ARG       (-1)  // 参数无效，或补丁需要更新版本的应用方（版本、未知操作码）
+#define XDELTA_ERR_IO                (-2)
+#define XDELTA_ERR_OLD_HASH_MISMATCH (-3)
+#define XDELTA_ERR_CANCELLED         (-4)
+#define XDELTA_ERR_NO_MEMORY         (-5)
+#define XDELTA_ERR_OLD_READ_BUDGET   (-6)  // 从旧数据读取的字节数将超出预算
+#define XDELTA_ERR_BUFFER_TOO_SMALL  (-7)  // 调用方提供的输出缓冲区不足，所需长度由输出长度参数返回
+#define XDELTA_ERR_NULL_POINTER      (-8)  // 必需的指针参数为 NULL
+#define XDELTA_ERR_MALFORMED_PATCH   (-9)  // 补丁无法解析：头部错误、记录被截断或字段自相矛盾
+#define XDELTA_ERR_OLD_OUT_OF_RANGE  (-10) // 记录读取的范围超出旧数据末尾
+#define XDELTA_ERR_CHECKSUM_MISMATCH (-11) // 输出与补丁 CHECK 记录中的哈希不符（旧数据或补丁已损坏）
+#define XDELTA_ERR_OUTPUT_LIMIT      (-12) // 补丁的输出将超过调用方给出的上限
+#define XDELTA_ERR_NEW_HASH_MISMATCH (-13) // 输出与调用方给出的新数据 SHA-256 不符（xdelta_apply_patch_verify）
+
+// 结果句柄 API：便于 ctypes 等绑定使用，无需输出参数。
+// create/apply 总是返回非 NULL 句柄，先查 status，再读 data/len 或 error；
+// data 与 error 指针归句柄所有，在 xdelta_result_free 之前有效。
+typedef struct XdeltaResult XdeltaResult;
+
+XdeltaResult* xdelta_create_patch_result(const uint8_t* old_data, size_t old_len,
+                                         const uint8_t* new_data, size_t new_len,
+                                         const XdeltaOptions* opts);
+XdeltaResult* xdelta_apply_patch_result(const uint8_t* old_data, size_t old_len,
+                                        const uint8_t* patch_data, size_t patch_len);
+int xdelta_result_status(const XdeltaResult* result);
+const uint8_t* xdelta_result_data(const XdeltaResult* result);
+size_t xdelta_result_len(const XdeltaResult* result);
+const char* xdelta_result_error(const XdeltaResult* result);
+void xdelta_result_free(XdeltaResult* result);
+
+// 批量创建补丁：一次调用处理 count 对 (旧, 新)，默认选项加上 block_size
+typedef struct XdeltaPatchPair {
+    const uint8_t* old_data;
+    size_t old_len;
+    const uint8_t* new_data;
+    size_t new_len;
+} XdeltaPatchPair;
+// results 为调用方提供的 count 个 XdeltaResult* 的数组，第 i 对的补丁或错误写入 results[i]，各对互不影响，
+// 每个句柄用 xdelta_result_free 释放；以 parallel 特性编译时在内部多线程并行。
+// 返回 0 表示已写入全部 results；参数本身无效（pairs/results 为 NULL）时返回负的错误码且不写入
+// 不需要句柄时见 xdelta_create_patch_batch
+int xdelta_create_patch_batch_handles(const XdeltaPatchPair* pairs, size_t count, size_t block_size,
+                                      XdeltaResult** results);
+// 同 xdelta_create_patch_batch_handles，但不使用句柄：第 i 对的状态和补丁直接写入调用方提供的 out[i]
+typedef struct XdeltaBatchEntry {
+    int status;             // XDELTA_OK 或该对的 XDELTA_ERR_* 错误码
+    uint8_t* patch_data;    // 成功时为补丁，失败时为 NULL
+    size_t patch_len;       // 失败时为 0
+} XdeltaBatchEntry;
+// 各对互不影响，xdelta_last_error 给出最后一个失败的对的错误信息；补丁全部用 xdelta_free_batch 释放。
+// 返回 0 表示已写入全部 out；参数本身无效（pairs/out 为 NULL）时返回负的错误码且不写入
+int xdelta_create_patch_batch(const XdeltaPatchPair* pairs, size_t count, XdeltaBatchEntry* out, size_t block_size);
+// 释放 entries 中全部补丁并把各项置为 NULL/0，entries 数组本身归调用方；entries 可为 NULL，重复调用无害
+void xdelta_free_batch(XdeltaBatchEntry* entries, size_t count);
+
+// 返回 0 表示成功，负数（XDELTA_ERR_* 错误码）表示失败。失败后可通过 xdelta_last_error() 获取错误字符串（只读指针，线程局部）。
+// block_size 为 0 时按旧数据长度自动选择（见 xdelta_create_patch_auto），其他接收 block_size 参数的创建函数相同
 int xdelta_create_patch_data(const uint8_t* old_data, size_t old_len,
                              const uint8_t* new_data, size_t new_len,
                              uint8_t** patch_data, size_t* patch_len,
                              uint32_t block_size);
+// 以 gzip 特性构建时，gzip 容器中的补丁（xdelta_compress_patch）先自动解压再应用；0 字节的补丁输出为空
 int xdelta_apply_patch_data(const uint8_t* old_data, size_t old_len,
                             const uint8_t* patch_data, size_t patch_len,
                             uint8_t** new_data, size_t* new_len);
 void xdelta_free_data(uint8_t* data);
+void xdelta_free_string(char* s);
+
+// 设置库返回的所有缓冲区和字符串使用的分配/释放函数，xdelta_free_data/xdelta_free_string 随之改用 free_fn；
+// 两者都为 NULL 时恢复默认的 libc malloc/free，只有一个为 NULL 返回 XDELTA_ERR_INVALID_ARG；
+// 应在初始化时调用：仍有已分配的缓冲区未释放时拒绝切换，返回 XDELTA_ERR_INVALID_ARG；线程安全
+typedef void* (*xdelta_malloc_fn)(size_t size);
+typedef void (*xdelta_free_fn)(void* ptr);
+int xdelta_set_allocator(xdelta_malloc_fn malloc_fn, xdelta_free_fn free_fn);
+
+// flags 为 XDELTA_CREATE_* 位，未知的位会被拒绝
+int xdelta_create_patch_data_ex(const uint8_t* old_data, size_t old_len,
+                                const uint8_t* new_data, size_t new_len,
+                                uint8_t** patch_data, size_t* patch_len,
+                                uint32_t block_size, uint32_t flags);
+
+int xdelta_apply_patch_data_ex(const uint8_t* old_data, size_t old_len,
+                               const uint8_t* patch_data, size_t patch_len,
+                               uint32_t flags,
+                               uint8_t** new_data, size_t* new_len);
+
+// 输出超过 max_output 字节时在生成任何输出之前返回 XDELTA_ERR_OUTPUT_LIMIT，防止很小的补丁耗尽内存
+int xdelta_apply_patch_data_limited(const uint8_t* old_data, size_t old_len,
+                                    const uint8_t* patch_data, size_t patch_len,
+                                    uint64_t max_output,
+                                    uint8_t** new_data, size_t* new_len);
+
+// 应用补丁并将输出的 SHA-256 与调用方另行获得（如签名清单）的 expected_sha256（32 字节）比较，
+// 不符时返回 XDELTA_ERR_NEW_HASH_MISMATCH 且不返回输出；不依赖补丁自带的 CHECK 记录
+int xdelta_apply_patch_verify(const uint8_t* old_data, size_t old_len,
+                              const uint8_t* patch_data, size_t patch_len,
+                              const uint8_t* expected_sha256,
+                              uint8_t** new_data, size_t* new_len);
+
+// 应用 patch_data 开头的补丁（其后可以跟着其它数据），*consumed 为补丁占用的字节数，失败时不修改；
+// 结尾由补丁头确定：CHECK 记录，或产生声明的输出长度为止，两者都没有时返回 XDELTA_ERR_INVALID_ARG
+int xdelta_apply_patch_prefix(const uint8_t* old_data, size_t old_len,
+                              const uint8_t* patch_data, size_t patch_len,
+                              uint8_t** new_data, size_t* new_len,
+                              size_t* consumed);
+
+// 宽松地应用补丁：中途遇到损坏的记录时返回负的错误码，但 *new_data 仍为出错记录之前重建的输出（仅供诊断），
+// *failed_at 为出错记录在补丁中的字节偏移（补丁头出错为 0，CHECK 不符等所有记录之后的错误为 patch_len）；
+// 成功时返回0，*failed_at 不被修改；参数为 NULL 或内存不足时不返回输出
+int xdelta_apply_patch_lenient(const uint8_t* old_data, size_t old_len,
+                               const uint8_t* patch_data, size_t patch_len,
+                               uint8_t** new_data, size_t* new_len,
+                               size_t* failed_at);
+
+// 不需要旧数据、不生成输出，检查补丁结构是否有效（补丁头、记录格式、未知操作码等）；
+// COPY 范围是否超出旧数据留到应用时检查
+int xdelta_validate_patch(const uint8_t* patch_data, size_t patch_len);
+
+// opts 为 NULL 时使用默认选项（块大小同样自动选择）
+int xdelta_create_patch_data_opts(const uint8_t* old_data, size_t old_len,
+                                  const uint8_t* new_data, size_t new_len,
+                                  const XdeltaOptions* opts,
+                                  uint8_t** patch_data, size_t* patch_len);
+
+// opts->block_size 为 0（或 opts 为 NULL）时按旧数据长度自动选择块大小（约 sqrt(old_len)，取 2 的幂，限制在 512~65536），
+// 并以 BLOCK_SIZE 记录写入补丁；实际使用的块大小写入 *block_size_used（可为 NULL）
+int xdelta_create_patch_auto(const uint8_t* old_data, size_t old_len,
+                             const uint8_t* new_data, size_t new_len,
+                             const XdeltaOptions* opts,
+                             uint8_t** patch_data, size_t* patch_len,
+                             uint32_t* block_size_used);
+
+// 匹配回调：每生成一条 COPY 记录调用一次（新数据偏移、旧数据偏移、长度）
+typedef void (*xdelta_match_fn)(uint64_t new_offset, uint64_t old_offset, uint64_t len, void* ctx);
+
+// 创建补丁并报告每条 COPY 记录，供调试/可视化工具使用；on_match 与 opts 均可为 NULL
+int xdelta_create_patch_matches(const uint8_t* old_data, size_t old_len,
+                                const uint8_t* new_data, size_t new_len,
+                                const XdeltaOptions* opts,
+                                xdelta_match_fn on_match, void* ctx,
+                                uint8_t** patch_data, size_t* patch_len);
+
+// 匹配进度回调：已处理的新数据字节数与新数据总长度
+typedef void (*xdelta_progress_fn)(size_t processed, size_t total, void* ctx);
+
+// 创建补丁数据并报告匹配进度，供界面显示进度条；每 64KiB 新数据至多调用一次 progress_cb，
+// processed 递增，最后一次为 processed == new_len；progress_cb 可为 NULL
+int xdelta_create_patch_data_progress(const uint8_t* old_data, size_t old_len,
+                                      const uint8_t* new_data, size_t new_len,
+                                      uint8_t** patch_data, size_t* patch_len,
+                                      uint32_t block_size,
+                                      xdelta_progress_fn progress_cb, void* ctx);
+
+// 创建补丁数据，可由其它线程取消：*cancel_flag 非 0 时在匹配过程中（每 64KiB 新数据检查一次）停止并返回
+// XDELTA_ERR_CANCELLED，已生成的部分被丢弃；*cancel_flag 须以原子操作写入（C11 atomic_store 或
+// __atomic_store_n），cancel_flag 为 NULL 时不可取消
+int xdelta_create_patch_data_cancelable(const uint8_t* old_data, size_t old_len,
+                                        const uint8_t* new_data, size_t new_len,
+                                        uint8_t** patch_data, size_t* patch_len,
+                                        uint32_t block_size, const int* cancel_flag);
+
+// 对仅有少量字节不同的块输出逐字节差值（DIFF 记录）
+int xdelta_create_patch_data_diff(const uint8_t* old_data, size_t old_len,
+                                  const uint8_t* new_data, size_t new_len,
+                                  uint8_t** patch_data, size_t* patch_len,
+                                  uint32_t block_size);
+
+// 带输出索引的补丁，便于 xdelta_apply_patch_range 快速定位
+int xdelta_create_patch_data_indexed(const uint8_t* old_data, size_t old_len,
+                                     const uint8_t* new_data, size_t new_len,
+                                     uint8_t** patch_data, size_t* patch_len,
+                                     uint32_t block_size, uint64_t index_granularity);
+int xdelta_apply_patch_range(const uint8_t* old_data, size_t old_len,
+                             const uint8_t* patch_data, size_t patch_len,
+                             uint64_t start, size_t len,
+                             uint8_t** out_data, size_t* out_len);
+
+// 按输出位置把补丁拆分为 parts 个可独立应用的子补丁（可并行传输/应用）。
+// sub_patches/sub_lens 为调用方分配的长度为 parts 的数组；
+// 每个子补丁用 xdelta_free_data 释放，失败时不返回任何子补丁。
+// 子补丁的输出写入完整输出中 xdelta_sub_patch_offset 给出的位置。
+int xdelta_split_patch(const uint8_t* patch_data, size_t patch_len, size_t parts,
+                       uint8_t** sub_patches, size_t* sub_lens);
+int xdelta_sub_patch_offset(const uint8_t* patch_data, size_t patch_len, uint64_t* offset);
+
+// COPY/DIFF 读取旧数据范围的重叠统计（仅用于分析，如原地应用的安全检查）
+typedef struct XdeltaOverlapStats {
+    uint32_t size;                // 调用前填 sizeof(XdeltaOverlapStats)
+    uint64_t ranges;              // 非空 COPY/DIFF 记录数
+    uint64_t overlapping_ranges;  // 与其他记录范围重叠的记录数
+    uint64_t reread_bytes;        // 被重复读取的旧数据字节数
+} XdeltaOverlapStats;
+
+int xdelta_copy_overlap_stats(const uint8_t* patch_data, size_t patch_len, XdeltaOverlapStats* stats);
+
+// 补丁概要（不需要旧数据，用于部署前审核）；补丁的校验同 xdelta_validate_patch
+typedef struct XdeltaPatchInfo {
+    uint32_t size;           // 调用前填 sizeof(XdeltaPatchInfo)
+    uint8_t version;         // 补丁头版本
+    uint8_t identity;        // 为 1 时输出即旧数据，output_len 为 0
+    uint64_t num_add;        // ADD 记录数（含 ADD_SMALL）
+    uint64_t num_copy;       // COPY/DIFF/XOR_DELTA 记录数
+    uint64_t literal_bytes;  // ADD 携带的字节数
+    uint64_t copied_bytes;   // 从旧数据复制的字节数
+    uint64_t output_len;     // 输出长度（含 RUN、COPY_CONST 等产生的字节）
+    uint32_t block_size;     // 创建时的块大小；补丁头未记录时（恒等补丁、格式版本 9 之前的补丁）为 0
+    uint32_t weak_checksum;  // 创建时的弱校验和（XDELTA_WEAK_*），block_size 为 0 时无意义
+    uint32_t strong_hash;    // 创建时的强哈希（XDELTA_HASH_*），block_size 为 0 时无意义
+    uint8_t sampled;         // 为 1 时由只索引部分旧数据块的签名生成（XdeltaOptions::sample_blocks），压缩率可能略差
+} XdeltaPatchInfo;
+
+int xdelta_patch_info(const uint8_t* patch_data, size_t patch_len, XdeltaPatchInfo* info);
+
+// 列出补丁的每个操作（不需要旧数据），供排查补丁为何偏大：第一行为补丁头，每行一个操作
+// （如 "4096 COPY old=8192 len=1024"，开头为在输出中的位置，ADD 附前 16 个字节的十六进制），最后一行为 "records=操作数"；
+// *out 为以 NUL 结尾的字符串，*out_len 不含 NUL，用 xdelta_free_data 释放
+int xdelta_dump_patch(const uint8_t* patch_data, size_t patch_len, uint8_t** out, size_t* out_len);
+
+// 创建补丁并统计相似度，用于判断分发补丁还是完整文件
+typedef struct XdeltaStats {
+    uint32_t size;           // 调用前填 sizeof(XdeltaStats)
+    uint64_t matched_bytes;  // 从旧数据复制的字节数（恒等补丁为新数据长度）
+    uint64_t literal_bytes;  // ADD 携带的字节数
+    uint64_t patch_size;     // 补丁长度
+    double similarity;       // matched_bytes 占新数据的百分比，0~100
+} XdeltaStats;
+
+int xdelta_create_patch_stats(const uint8_t* old_data, size_t old_len,
+                              const uint8_t* new_data, size_t new_len,
+                              uint32_t block_size,
+                              uint8_t** patch_data, size_t* patch_len,
+                              XdeltaStats* stats);
+
+// 兼容性检查：每个已知操作码对应一位，allowed_opcodes 为最旧的应用方支持的操作码集合
+#define XDELTA_OPCODE_ADD           (1ull << 0)
+#define XDELTA_OPCODE_COPY          (1ull << 1)
+#define XDELTA_OPCODE_NOP           (1ull << 2)
+#define XDELTA_OPCODE_DIFF          (1ull << 3)
+#define XDELTA_OPCODE_COPY_CONST    (1ull << 4)
+#define XDELTA_OPCODE_COPY_HASH     (1ull << 5)
+#define XDELTA_OPCODE_INDEX         (1ull << 6)
+#define XDELTA_OPCODE_OLD_HASH      (1ull << 7)
+#define XDELTA_OPCODE_CONST_TABLE   (1ull << 8)
+#define XDELTA_OPCODE_OUTPUT_OFFSET (1ull << 9)
+#define XDELTA_OPCODE_PADDING       (1ull << 10)
+#define XDELTA_OPCODE_BLOCK_SIZE    (1ull << 11)
+#define XDELTA_OPCODE_XOR_DELTA     (1ull << 12)
+#define XDELTA_OPCODE_MIN_VERSION   (1ull << 13)
+#define XDELTA_OPCODE_RUN           (1ull << 14)  // 单字节重复（RUN），创建补丁时总会对长重复段使用
+#define XDELTA_OPCODE_COPY_TARGET   (1ull << 15)  // 从已输出的新数据复制（COPY_TARGET），仅 XDELTA_OPT_COPY_TARGET 时使用
+#define XDELTA_OPCODE_CHECK         (1ull << 16)  // 输出的 SHA-256（CHECK），仅 XDELTA_OPT_OUTPUT_CHECK 时使用
+#define XDELTA_OPCODE_ADD_SMALL     (1ull << 17)  // 单字节长度的 ADD（ADD_SMALL，操作码 0x05），创建补丁时总会对不超过 255 字节的字面数据使用
+#define XDELTA_OPCODE_ADD_ZSTD      (1ull << 18)  // zstd 压缩的 ADD（ADD_ZSTD，操作码 0x03），仅 XDELTA_CREATE_COMPRESS 时使用
+#define XDELTA_OPCODES_BASELINE     (XDELTA_OPCODE_ADD | XDELTA_OPCODE_COPY)  // 最初的格式
+
+// 补丁只使用允许的操作码时返回0；否则返回负的错误码，xdelta_last_error 给出第一个不允许（或未知）的操作码
+int xdelta_patch_uses_only(const uint8_t* patch_data, size_t patch_len, uint64_t allowed_opcodes);
+
+// 按操作码统计补丁中的记录，用于分析补丁构成（如哪类记录占主导、是否有大量很短的 COPY）
+typedef struct XdeltaOpcodeStat {
+    uint64_t count;         // 记录数
+    uint64_t patch_bytes;   // 占用的补丁字节数（含记录头）
+    uint64_t output_bytes;  // 产生的输出字节数
+} XdeltaOpcodeStat;
+#define XDELTA_OPCODE_KINDS 19  // 已知操作码个数，即 XDELTA_OPCODE_* 的位数
+// stats 为 stat_count 个元素的数组（通常为 XDELTA_OPCODE_KINDS），stats[i] 对应 XDELTA_OPCODE_* 中的 (1ull << i)，
+// 未出现的操作码为 0；未知的可跳过操作码不报告。成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+int xdelta_opcode_histogram(const uint8_t* patch_data, size_t patch_len, XdeltaOpcodeStat* stats, size_t stat_count);
+
+// 补丁格式版本：1 为最初的 ADD/COPY 格式，2 增加到 MIN_VERSION 为止的操作码，3 增加 RUN，4 增加 COPY_TARGET，5 增加 CHECK，
+// 6 在补丁头中增加输出长度，7 增加逐条记录的 CRC-32，8 增加 ADD_SMALL，9 在补丁头中增加创建参数，
+// 10 增加只向前读取旧数据的头标志，11 增加抽样签名的头标志，12 增加 ADD_ZSTD
+#define XDELTA_FORMAT_VERSION 12
+// 运行时库实现的格式版本；补丁的 MIN_VERSION 高于它时应用失败，错误为 "patch requires applier >= X, this is Y"
+uint32_t xdelta_format_version(void);
+// 库的版本号（如 "0.1.0"），静态字符串，归库所有，不要释放
+const char* xdelta_version(void);
+
+// 每个补丁以 8 字节头开始："XDR1"、头版本（XDELTA_PATCH_HEADER_VERSION）、标志字节、2 个保留的 0 字节；
+// 头过短、魔数不符、版本或标志不支持时应用失败，不会尝试解析记录。
+// 标志位 0 为恒等补丁：新旧数据相同时创建补丁只输出这 8 字节，应用时直接返回旧数据的副本
+// （要求旧数据哈希、填充、块大小或最低版本记录时不使用，内容寻址补丁也不使用）
+// 标志位 3：头后紧跟 8 字节的输出总长度（u64 小端），应用方可据此一次分配输出；除恒等补丁外新建的补丁都带此字段
+// （需格式版本 6），记录产生的长度与之不符时应用失败（XDELTA_ERR_MALFORMED_PATCH）
+// 标志位 4：每条记录后紧跟其 CRC-32（4 字节小端，覆盖操作码和操作数），见 XDELTA_OPT_RECORD_CRC（需格式版本 7）
+// 标志位 5：输出长度之后紧跟创建参数：块大小（u32 小端）、弱校验和（XDELTA_WEAK_*，u8）、强哈希（XDELTA_HASH_*，u8）、
+// 2 个保留的 0 字节；除恒等补丁外新建的补丁都带此字段（需格式版本 9）。应用时不需要这些参数、不认识的算法编号也放行，
+// 但保留字节非 0、块大小为 0 或与 BLOCK_SIZE 记录不符、BLAKE3 标志与强哈希不符时应用失败（XDELTA_ERR_MALFORMED_PATCH）；
+// 标志位 2 声明的 CHECK 记录缺失时同样失败
+// 标志位 6：记录只向前读取旧数据，见 XDELTA_OPT_FORWARD_ONLY（需格式版本 10）
+// 标志位 7：由只索引部分旧数据块的签名生成，见 XdeltaOptions::sample_blocks，应用时不需要（需格式版本 11）
+#define XDELTA_PATCH_HEADER_VERSION 1
+
+// 应用补丁，输出写入调用方提供的缓冲区（容量 out_cap），不为输出分配内存；成功时 *out_len 为输出长度
+// 缓冲区不足时不写入任何输出并返回 XDELTA_ERR_BUFFER_TOO_SMALL，*out_len 为所需长度
+int xdelta_apply_patch_into(const uint8_t* old_data, size_t old_len,
+                            const uint8_t* patch_data, size_t patch_len,
+                            uint8_t* out_buf, size_t out_cap, size_t* out_len);
+
+// 创建补丁并写入调用方提供的缓冲区（容量 out_cap），不为输出分配内存；成功时 *out_written 为补丁长度
+// 缓冲区不足时不写入任何数据并返回 XDELTA_ERR_BUFFER_TOO_SMALL，*out_written 为所需长度
+int xdelta_create_patch_data_into(const uint8_t* old_data, size_t old_len,
+                                  const uint8_t* new_data, size_t new_len,
+                                  uint8_t* out_buf, size_t out_cap, size_t* out_written,
+                                  uint32_t block_size);
+
+// 通过抽样估算补丁大小（不实际创建补丁），判断补丁是否比 new 至少小 threshold（0~1 的比例）
+// 值得创建补丁时返回1，否则返回0，参数错误返回负的错误码；结果为近似值，可能漏掉短于一个块或位于抽样点之间的匹配
+int xdelta_should_patch(const uint8_t* old_data, size_t old_len,
+                        const uint8_t* new_data, size_t new_len,
+                        uint32_t block_size, double threshold);
+
+// 估算创建补丁的峰值内存（上限估计）；block_size 非 0 时覆盖 opts 中的值
+uint64_t xdelta_estimate_memory(uint64_t old_len, uint64_t new_len,
+                                uint32_t block_size, const XdeltaOptions* opts);
+
+// 数据块的强哈希，与签名及 COPY_HASH 中保存的相同，供其他实现生成兼容的签名：
+// 块为旧数据中 block_size 对齐的 block_size 字节；末尾短块按 tail_policy 原样（AS_IS）或用 0 补齐到 block_size（PAD）
+#define XDELTA_HASH_SHA256 0  // SHA-256，输出 32 字节原始摘要
+#define XDELTA_HASH_BLAKE3 1  // BLAKE3，输出 32 字节；需以 blake3 特性编译
+// 补丁头记录的弱校验和（xdelta_patch_info 的 weak_checksum）
+#define XDELTA_WEAK_ROLLING 0  // rsync 式弱校验和（默认）
+#define XDELTA_WEAK_ADLER32 1  // 标准 Adler-32，见 XDELTA_OPT_ADLER32
+// hash_out 须能容纳 32 字节；成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+int xdelta_block_strong_hash(const uint8_t* data, size_t len, uint32_t algo, uint8_t* hash_out);
+
+// 流式应用：回调返回非 0 时中止
+typedef int (*xdelta_read_fn)(uint64_t offset, uint8_t* buf, size_t len, void* ctx);
+typedef int (*xdelta_write_fn)(const uint8_t* data, size_t len, void* ctx);
+
+#define XDELTA_VERIFY_NONE    0
+#define XDELTA_VERIFY_PARTIAL 1  // 只校验被 COPY 读取的旧数据范围
+#define XDELTA_VERIFY_FULL    2  // 应用前读取并校验全部旧数据
+
+int xdelta_apply_patch_callbacks(uint64_t old_len, xdelta_read_fn read_old,
+                                 const uint8_t* patch_data, size_t patch_len,
+                                 xdelta_write_fn write_out, void* ctx,
+                                 uint32_t verify, const XdeltaCancelToken* cancel);
+
+// 同上，但从旧数据读取的总字节数（含校验读取）不超过 max_old_bytes_read：
+// 将要超出时不再调用 read_old 并返回 XDELTA_ERR_OLD_READ_BUDGET；UINT64_MAX 表示不限制
+int xdelta_apply_patch_callbacks_budget(uint64_t old_len, xdelta_read_fn read_old,
+                                        const uint8_t* patch_data, size_t patch_len,
+                                        xdelta_write_fn write_out, void* ctx,
+                                        uint32_t verify, const XdeltaCancelToken* cancel,
+                                        uint64_t max_old_bytes_read);
+
+// 旧数据在内存中，输出按记录分块交给 write_out，不分配整个输出；
+// write_out 返回非0时立即中止并返回 XDELTA_ERR_IO；含 COPY_TARGET 记录的补丁会被拒绝
+int xdelta_apply_patch_stream(const uint8_t* old_data, size_t old_len,
+                              const uint8_t* patch_data, size_t patch_len,
+                              xdelta_write_fn write_out, void* ctx);
+
+// 输出按 alignment 字节分块写出；pad_final 为 0..255 时用该字节补齐最后一块，负数表示不补齐
+int xdelta_apply_patch_aligned(uint64_t old_len, xdelta_read_fn read_old,
+                               const uint8_t* patch_data, size_t patch_len,
+                               xdelta_write_fn write_out, void* ctx,
+                               size_t alignment, int pad_final);
+
+// 推送式应用：补丁边接收边送入，完整的记录立即校验并应用，不完整的记录缓存到下次送入；
+// old_data 在句柄释放前须保持有效；verify 为 XDELTA_VERIFY_*（FULL 在首个输出前校验，PARTIAL 在 finish 时校验）
+typedef struct XdeltaApplyFeed XdeltaApplyFeed;
+XdeltaApplyFeed* xdelta_apply_feed_new(const uint8_t* old_data, size_t old_len,
+                                       xdelta_write_fn write_out, void* ctx, uint32_t verify);
+int xdelta_apply_feed(XdeltaApplyFeed* feed, const uint8_t* data, size_t len);
+// 结束并释放句柄：补丁在记录中间结束或校验失败时返回负的错误码
+int xdelta_apply_feed_finish(XdeltaApplyFeed* feed);
+// 中途放弃并释放句柄
+void xdelta_apply_feed_free(XdeltaApplyFeed* feed);
+
+// 内容寻址应用：按 32 字节强哈希（SHA-256，补丁头标明时为 BLAKE3）查找数据块，成功时写入 *data/*len 并返回 0，
+// 数据块须在 xdelta_apply_patch_cas 返回前保持有效
+typedef int (*xdelta_resolve_fn)(const uint8_t* hash, const uint8_t** data, size_t* len, void* ctx);
+
+int xdelta_apply_patch_cas(const uint8_t* patch_data, size_t patch_len,
+                           xdelta_resolve_fn resolve, void* ctx,
+                           uint8_t** new_data, size_t* new_len);
+
+// 输出直接写入调用方缓冲区 new_data（容量 new_cap），旧数据经 read_old 读取；
+// 每次读取的偏移和长度均为 read_alignment 的整数倍（如 XIP 闪存要求字对齐），
+// 相邻的小块读取会合并；仅读到旧数据末尾且其长度非整数倍时长度可能不足
+int xdelta_apply_patch_read_aligned(uint64_t old_len, xdelta_read_fn read_old, void* ctx,
+                                    const uint8_t* patch_data, size_t patch_len,
+                                    size_t read_alignment,
+                                    uint8_t* new_data, size_t new_cap, size_t* new_len);
+
+// 应用补丁并与参考数据逐字节比较（不缓存输出），报告第一个不同字节；
+// 任一方数据已结束时对应字节报告为 0
+typedef struct XdeltaCompareResult {
+    uint32_t size;               // 调用前填 sizeof(XdeltaCompareResult)
+    uint8_t matched;             // 1 表示完全一致
+    uint8_t expected_byte;
+    uint8_t actual_byte;
+    uint64_t first_diff_offset;
+} XdeltaCompareResult;
+
+// 比较完成（无论是否一致）返回 0，补丁无法应用返回负的错误码
+int xdelta_apply_compare(const uint8_t* old_data, size_t old_len,
+                         const uint8_t* patch_data, size_t patch_len,
+                         const uint8_t* expected, size_t expected_len,
+                         XdeltaCompareResult* result);
+
+XdeltaCancelToken* xdelta_cancel_token_new(void);
+void xdelta_cancel_token_cancel(const XdeltaCancelToken* token);
+void xdelta_cancel_token_free(XdeltaCancelToken* token);
+
+// 可复用的差异上下文：批量创建小补丁时复用内部缓冲区，避免反复分配。
+// 同一上下文不可被多个线程同时使用。
+typedef struct XdeltaContext XdeltaContext;
+
+XdeltaContext* xdelta_context_new(void);
+void xdelta_context_free(XdeltaContext* ctx);
+int xdelta_create_patch_ctx(XdeltaContext* ctx,
+                            const uint8_t* old_data, size_t old_len,
+                            const uint8_t* new_data, size_t new_len,
+                            uint32_t block_size,
+                            uint8_t** patch_data, size_t* patch_len);
+
+// 可复用的应用上下文：大量应用小补丁时复用输出缓冲区，缓冲区只在需要更大容量时重新分配。
+// 同一上下文不可被多个线程同时使用。
+typedef struct XdeltaApplyContext XdeltaApplyContext;
+
+XdeltaApplyContext* xdelta_apply_context_new(void);
+void xdelta_apply_context_free(XdeltaApplyContext* ctx);
+// 成功时 *new_data 指向上下文内部的缓冲区：归上下文所有，不要用 xdelta_free_data 释放，
+// 只在同一上下文的下一次调用或 xdelta_apply_context_free 之前有效，需要保留时请先复制
+int xdelta_apply_patch_ctx(XdeltaApplyContext* ctx,
+                           const uint8_t* old_data, size_t old_len,
+                           const uint8_t* patch_data, size_t patch_len,
+                           const uint8_t** new_data, size_t* new_len);
+
+// 旧数据的块签名（不透明句柄），用于诊断
+typedef struct XdeltaSignature XdeltaSignature;
+
+// 签名表统计；调用前填写 size = sizeof(XdeltaSigStats)
+// 平均桶大小偏高说明弱校验效果差或数据高度重复，可据此调整块大小
+typedef struct XdeltaSigStats {
+    uint32_t size;
+    uint32_t most_collided_weak;  // 桶最大的弱校验值
+    uint64_t block_count;         // 块数
+    uint64_t distinct_weak;       // 不同弱校验值个数
+    uint64_t max_bucket;          // 最大桶大小
+    double avg_bucket;            // 平均桶大小
+} XdeltaSigStats;
+
+// 失败时返回 NULL
+XdeltaSignature* xdelta_signature_new(const uint8_t* old_data, size_t old_len, uint32_t block_size);
+void xdelta_signature_free(XdeltaSignature* sig);
+int xdelta_signature_stats(const XdeltaSignature* sig, XdeltaSigStats* stats);
+
+// 序列化块签名，输出用 xdelta_free_data 释放；成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+int xdelta_signature_serialize(const XdeltaSignature* sig, uint8_t** out_data, size_t* out_len);
+
+// 计算并序列化旧数据的块签名（等同 xdelta_signature_new 后 xdelta_signature_serialize），输出用 xdelta_free_data 释放
+int xdelta_build_signature(const uint8_t* old_data, size_t old_len, uint32_t block_size,
+                           uint8_t** sig_data, size_t* sig_len);
+
+// 只凭序列化的签名创建补丁，不需要旧数据本身（rsync 式流程：旧数据不离开接收方，只发送签名）；
+// 只按整块匹配旧数据，补丁照常以旧数据应用
+int xdelta_create_patch_from_signature(const uint8_t* sig_data, size_t sig_len,
+                                       const uint8_t* new_data, size_t new_len,
+                                       uint8_t** patch_data, size_t* patch_len);
+
+// 只凭签名句柄创建补丁（不需要旧数据本身）；同一句柄可对多个新数据重复使用，签名只计算一次
+// 与 xdelta_create_patch_from_signature 相同只按整块匹配旧数据，补丁照常以旧数据应用，输出用 xdelta_free_data 释放
+// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+int xdelta_signature_create_patch(const XdeltaSignature* sig,
+                                  const uint8_t* new_data, size_t new_len,
+                                  uint8_t** patch_data, size_t* patch_len);
+
+// 解析序列化的块签名（可来自不可信来源，所有计数和长度均经校验）
+// 用完后用 xdelta_signature_free 释放；失败时返回 NULL
+XdeltaSignature* xdelta_signature_deserialize(const uint8_t* data, size_t len);
+
+// 比较两个块签名的块大小、数据长度及每个块的弱/强校验值（按块序比较，与内部存储顺序无关）
+// 相同返回 1，不同返回 0，参数为 NULL 时返回 XDELTA_ERR_NULL_POINTER
+int xdelta_signatures_equal(const XdeltaSignature* sig_a, const XdeltaSignature* sig_b);
+
+// 应用补丁并同时计算输出的块签名（补丁须以 XDELTA_OPT_EMBED_BLOCK_SIZE 创建）
+// 成功时 *sig 为新签名，用 xdelta_signature_free 释放；输出用 xdelta_free_data 释放
+// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+int xdelta_apply_patch_signature(const uint8_t* old_data, size_t old_len,
+                                 const uint8_t* patch_data, size_t patch_len,
+                                 uint8_t** new_data, size_t* new_len,
+                                 XdeltaSignature** sig);
+
+// 使用已有的旧数据签名创建补丁（签名须由同一 old 计算），块大小取自签名；opts 可为 NULL
+// 成功时返回0，失败返回负的错误码（XDELTA_ERR_*）
+int xdelta_create_patch_signature(const XdeltaSignature* sig,
+                                  const uint8_t* old_data, size_t old_len,
+                                  const uint8_t* new_data, size_t new_len,
+                                  const XdeltaOptions* opts,
+                                  uint8_t** patch_data, size_t* patch_len);
+
+// pack：在一个数据块中保存同一文件的多个版本，可计算任意两个版本间的补丁
+int xdelta_pack_build(const uint64_t* rev_ids, const uint8_t* const* datas, const size_t* lens,
+                      size_t count, uint8_t** pack_data, size_t* pack_len);
+int xdelta_pack_diff(const uint8_t* pack, size_t pack_len,
+                     uint64_t from_rev, uint64_t to_rev,
+                     const XdeltaOptions* opts,
+                     uint8_t** patch_data, size_t* patch_len);
+
+// 批量补丁：从同一 base 到多个新版本，相同的补丁片段只保存一次；news/new_lens 为长度 count 的数组
+int xdelta_batch_create(const uint8_t* base, size_t base_len,
+                        const uint8_t* const* news, const size_t* new_lens, size_t count,
+                        const XdeltaOptions* opts,
+                        uint8_t** bundle_data, size_t* bundle_len);
+// 从批量补丁包中取出第 index 个补丁
+int xdelta_batch_patch(const uint8_t* bundle, size_t bundle_len, size_t index,
+                       uint8_t** patch_data, size_t* patch_len);
+
+// 将 patch_a 应用到旧数据，并在同一遍中与 new_data 比较生成补丁 B（B 以 A 的输出为旧数据）
+// 不生成中间结果：内存为 A 的每条记录一个索引项加上块签名；A 不能含 COPY_HASH 记录
+int xdelta_apply_then_diff(const uint8_t* old_data, size_t old_len,
+                           const uint8_t* patch_a, size_t patch_a_len,
+                           const uint8_t* new_data, size_t new_len,
+                           uint32_t block_size,
+                           uint8_t** patch_data, size_t* patch_len);
+
+// 同时创建正向补丁（旧→新）和反向补丁（新→旧，以新数据为基准应用），用于回滚
+// 两个补丁都用 xdelta_free_data 释放；失败时不返回任何补丁
+int xdelta_create_patch_bidirectional(const uint8_t* old_data, size_t old_len,
+                                      const uint8_t* new_data, size_t new_len,
+                                      uint32_t block_size,
+                                      uint8_t** forward_data, size_t* forward_len,
+                                      uint8_t** reverse_data, size_t* reverse_len);
+
+// 流式创建补丁：新数据通过 read_new 分块读取，每次最多 len 字节，读到的字节数写入 *got，*got 为 0 表示结束；
+// 补丁边生成边交给 write_patch，内存只需旧数据及其签名加上新数据的几个块；两个回调共用 ctx
+// 记录与 xdelta_create_patch 相同，但补丁头不含输出长度；回调返回非0时中止并返回 XDELTA_ERR_IO
+typedef int (*xdelta_read_next_fn)(uint8_t* buf, size_t len, size_t* got, void* ctx);
+int xdelta_create_patch_streaming(const uint8_t* old_data, size_t old_len,
+                                  xdelta_read_next_fn read_new, xdelta_write_fn write_patch,
+                                  void* ctx, uint32_t block_size);
+
+// 以共享字典为基准创建补丁，适合大量相似的小数据（配置、JSON、protobuf 消息）；补丁格式与普通补丁相同
+// 字典只读，不会被修改或保留，同一字典可供任意多次调用（包括多线程同时调用）复用；
+// block_size 为 0 时按字典长度自动选择，小消息宜用小块
+int xdelta_create_patch_with_dictionary(const uint8_t* dict_data, size_t dict_len,
+                                        const uint8_t* new_data, size_t new_len,
+                                        uint8_t** patch_data, size_t* patch_len,
+                                        uint32_t block_size);
+// 用同一字典应用上述补丁，字典只读；输出用 xdelta_free_data 释放
+int xdelta_apply_patch_with_dictionary(const uint8_t* dict_data, size_t dict_len,
+                                       const uint8_t* patch_data, size_t patch_len,
+                                       uint8_t** new_data, size_t* new_len);
+
+// 稀疏旧数据：old_base 起 old_len 字节中只有 extents 所列范围存在，其余视为 0 且不会被读取
+typedef struct XdeltaExtent {
+    uint64_t offset; // 相对 old_base 的偏移
+    uint64_t len;
+} XdeltaExtent;
+// 不支持 XDELTA_OPT_OLD_HASH；opts 可为 NULL
+int xdelta_create_patch_sparse(const uint8_t* old_base, uint64_t old_len,
+                               const XdeltaExtent* extents, size_t extent_count,
+                               const uint8_t* new_data, size_t new_len,
+                               const XdeltaOptions* opts,
+                               uint8_t** patch_data, size_t* patch_len);
+int xdelta_apply_patch_sparse(const uint8_t* old_base, uint64_t old_len,
+                              const XdeltaExtent* extents, size_t extent_count,
+                              const uint8_t* patch_data, size_t patch_len,
+                              uint8_t** new_data, size_t* new_len);
+
+// 补丁读取的旧数据范围（COPY/DIFF/XOR_DELTA），合并重叠、相邻及间隔不超过 gap_tolerance 字节的范围，按偏移排序，供预读使用；
+// gap_tolerance 越大读取次数越少，但会多读间隔中用不到的字节。*ranges 为 range_count 个元素的数组，用 xdelta_free_data 释放
+int xdelta_patch_old_ranges_merged(const uint8_t* patch_data, size_t patch_len, uint64_t gap_tolerance,
+                                   XdeltaExtent** ranges, size_t* range_count);
+
+// 不生成补丁，只找出新数据中哪些块（按 block_size 对齐，最后一块可以较短）的内容在旧数据的任何对齐块中都不存在，
+// 即增量备份需要保存的块。*blocks 为 block_count 个升序块序号组成的数组，用 xdelta_free_data 释放；
+// 没有变化的块时 block_count 为 0，*blocks 可能为 NULL。block_size 为 0 返回 XDELTA_ERR_INVALID_ARG
+int xdelta_changed_blocks(const uint8_t* old_data, size_t old_len, const uint8_t* new_data, size_t new_len,
+                          uint32_t block_size, uint64_t** blocks, size_t* block_count);
+
+// VCDIFF（RFC 3284）格式的补丁，供解码端使用 xdelta3 -d -s old 的场景；只含 ADD/COPY/RUN 指令，
+// 默认指令表，无二级压缩。仅在以 vcdiff 特性构建时导出。输出用 xdelta_free_data 释放
+int xdelta_create_patch_vcdiff(const uint8_t* old_data, size_t old_len,
+                               const uint8_t* new_data, size_t new_len,
+                               uint32_t block_size,
+                               uint8_t** patch_data, size_t* patch_len);
+// 应用 VCDIFF 补丁，如 xdelta3 -e -S none -s old 生成的补丁；二级压缩和自定义指令表返回 XDELTA_ERR_INVALID_ARG。
+// 仅在以 vcdiff 特性构建时导出。输出用 xdelta_free_data 释放
+int xdelta_apply_patch_vcdiff(const uint8_t* old_data, size_t old_len,
+                              const uint8_t* patch_data, size_t patch_len,
+                              uint8_t** new_data, size_t* new_len);
+
+// 将整个补丁封装为 gzip 容器（RFC 1952，可用 gzip -d 解开），适合作为单个压缩分发文件；
+// xdelta_apply_patch_data 按 gzip 魔数（1f 8b）自动识别并先解压。仅在以 gzip 特性构建时导出。输出用 xdelta_free_data 释放
+int xdelta_compress_patch(const uint8_t* patch_data, size_t patch_len, uint8_t** blob_data, size_t* blob_len);
+// 解开 gzip 容器取出补丁，接受任意 gzip 数据（含多个成员）；损坏或 CRC-32/长度不符返回 XDELTA_ERR_MALFORMED_PATCH。
+// 仅在以 gzip 特性构建时导出。输出用 xdelta_free_data 释放
+int xdelta_decompress_patch(const uint8_t* blob_data, size_t blob_len, uint8_t** patch_data, size_t* patch_len);
+
+// 按路径（以 NUL 结尾）创建补丁：旧文件与新文件以内存映射读取，不整体读入内存，补丁写入 patch_path。
+// 文件读写失败返回 XDELTA_ERR_IO，xdelta_last_error 给出路径和系统错误信息
+int xdelta_create_patch_file(const char* old_path, const char* new_path, const char* patch_path, uint32_t block_size);
+// 按路径应用补丁：旧文件按需读取，输出边生成边写入 out_path（失败时可能只写了一部分）；
+// 含 COPY_TARGET 记录的补丁会被拒绝。文件读写失败返回 XDELTA_ERR_IO
+int xdelta_apply_patch_file(const char* old_path, const char* patch_path, const char* out_path);
+// 按路径应用内存中的补丁（需以 mmap 特性构建）：旧文件以只读内存映射读取，COPY 记录直接读映射，适合数 GB 的旧文件；
+// 无法映射时（不支持映射的设备或文件系统）改为按需读取。补丁头无效时不创建 out_path，其他失败时 out_path 可能只写了一部分；
+// 含 COPY_TARGET 记录的补丁会被拒绝。文件读写失败返回 XDELTA_ERR_IO
+int xdelta_apply_patch_mmap(const char* old_path, const uint8_t* patch_data, size_t patch_len, const char* out_path);
+
+// 内置自检：补丁创建/应用往返及滚动校验一致性，
+// 全部通过返回 0，否则返回负的错误码并可通过 xdelta_last_error 查看失败项
+int xdelta_self_test(void);
+
+// 本线程最近一次失败的错误信息（以 NUL 结尾），尚无失败或已清除时返回 NULL；
+// 指针只在本线程下一次可能失败的调用（或 xdelta_clear_last_error）之前有效，需要保留时请复制
 const char* xdelta_last_error(void);
+// 上述错误信息的字节数（不含结尾的 NUL），没有错误信息时为 0，可据此复制而不必查找 NUL
+size_t xdelta_last_error_len(void);
+// 清除本线程的错误信息，之后 xdelta_last_error 返回 NULL，直到下一次失败
+void xdelta_clear_last_error(void);
+// 同时返回本线程最近一次失败的错误信息和错误码（写入 *code_out，可为 NULL），两者来自同一次失败；
+// 尚无失败时返回 NULL 并写入 XDELTA_OK
+const char* xdelta_last_error_detail(int* code_out);
 
 #ifdef __cplusplus
 }